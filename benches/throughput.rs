@@ -0,0 +1,71 @@
+// Instructions per second on two small programs, with the decode cache (the default) and without it. Run with
+// `cargo bench`; criterion compares against the previous run, so a change to the execute path shows up as a
+// change in the numbers.
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use lc3_sim::components::assembler::assemble;
+use lc3_sim::components::object::write_v2;
+use lc3_sim::components::vm::{ExecState, VM};
+
+// trips round each loop
+const TRIPS: u16 = 10000;
+
+// arithmetic and a branch: 3 instructions a trip
+const COUNT: &str = "
+            .ORIG x3000
+            LD R1, TRIPS
+            AND R2, R2, #0
+LOOP        ADD R2, R2, #3
+            ADD R1, R1, #-1
+            BRp LOOP
+            HALT
+TRIPS       .FILL #10000
+            .END";
+
+// a load and a store every trip, walking a 16-word buffer: 7 instructions a trip
+const MEMORY: &str = "
+            .ORIG x3000
+            LD R1, TRIPS
+            LEA R3, BUFFER
+LOOP        AND R4, R1, #15
+            ADD R4, R4, R3
+            LDR R5, R4, #0
+            ADD R5, R5, #1
+            STR R5, R4, #0
+            ADD R1, R1, #-1
+            BRp LOOP
+            HALT
+TRIPS       .FILL #10000
+BUFFER      .BLKW 16
+            .END";
+
+// boxed, as the machine is too big to pass around by value
+fn machine(object: &[u8], cached: bool) -> Box<VM> {
+    let mut vm = Box::new(VM::from_obj_bytes(object).unwrap());
+    vm.console.echo = false;
+    if !cached {
+        vm.decode_cache = None;
+    }
+    vm
+}
+
+fn throughput(c: &mut Criterion) {
+    for (name, source, per_trip) in [("count", COUNT, 3), ("memory", MEMORY, 7)] {
+        let object = write_v2(&assemble(source).unwrap());
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(per_trip * TRIPS as u64));
+        for (label, cached) in [("cached", true), ("uncached", false)] {
+            group.bench_function(label, |b| {
+                b.iter_batched(
+                    || machine(&object, cached),
+                    |mut vm| assert_eq!(vm.run(), Ok(ExecState::Halted)),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
@@ -0,0 +1,36 @@
+// Device plugins: every enabled cargo feature named `device-<crate>` stands for an optional dependency on a device
+// crate of that name, and gets a call to its `register` in the generated `register_plugins` that
+// devices::register_all runs. Adding a device crate takes two lines in Cargo.toml and no change to the source:
+//
+//   lc3_dev_led = { path = "../lc3_dev_led", optional = true }
+//   [features] device-lc3_dev_led = ["dep:lc3_dev_led"]
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    // Cargo passes enabled features as CARGO_FEATURE_<NAME>, upper-cased with `-` turned into `_`
+    let mut crates: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_DEVICE_")
+                .map(str::to_ascii_lowercase)
+        })
+        .collect();
+    crates.sort();
+
+    let mut code = String::from(
+        "// Generated by build.rs: one call per device crate enabled with a `device-<crate>` feature\n\
+         pub fn register_plugins(registry: &mut lc3_device::Registry) {\n",
+    );
+    if crates.is_empty() {
+        code.push_str("    let _ = registry;\n");
+    }
+    for name in &crates {
+        code.push_str(&format!("    ::{}::register(registry);\n", name));
+    }
+    code.push_str("}\n");
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("device_plugins.rs");
+    fs::write(out, code).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,295 @@
+//! The interface between lc3_sim and memory-mapped devices.
+//!
+//! A device crate implements [`Device`] and exposes a `register(&mut Registry)` function that adds its
+//! constructors by name. lc3_sim takes the crate as an optional dependency enabled by a cargo feature named
+//! `device-<crate>`; its build script calls `register` for every such feature at startup, and devices are then
+//! created by the names given with `--device`, so the simulator's source doesn't need to know about them.
+
+use std::cell::Cell;
+use std::ops::RangeInclusive;
+
+pub trait Device {
+    /// Name used in diagnostics
+    fn name(&self) -> &str;
+
+    /// Addresses the device answers to. Reads and writes in this range go to the device instead of memory.
+    fn range(&self) -> RangeInclusive<u16>;
+
+    fn read(&mut self, address: u16) -> u16;
+
+    fn write(&mut self, address: u16, value: u16);
+
+    /// Registers with their bit fields, for inspectors. Devices with nothing worth decoding keep the default.
+    fn registers(&self) -> Vec<RegisterInfo> {
+        Vec::new()
+    }
+
+    /// A register's value without the side effects a read may have, or None when it can't be known without
+    /// reading (a register that returns fresh data each time). A register `peek` answers for must read without
+    /// side effects, since a loop only polling it can be skipped (see `next_event`).
+    fn peek(&self, _address: u16) -> Option<u16> {
+        None
+    }
+
+    /// Set a register by hand, as an inspector editing it does, without the side effects a program's store has.
+    /// False when the device keeps nothing there that can be set.
+    fn poke(&mut self, _address: u16, _value: u16) -> bool {
+        false
+    }
+
+    /// Back to the state the device was created in, when the machine is cleared for another program
+    fn reset(&mut self) {}
+
+    /// Called once before every instruction, for devices that keep time in instructions executed
+    fn tick(&mut self) {}
+
+    /// Instructions until the device next changes by itself (a timer running out), for skipping loops that only
+    /// poll it. None when it only changes when the program or the host acts on it.
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+
+    /// Let `instructions` instructions pass at once, short of the next event, as that many `tick`s would
+    fn skip(&mut self, instructions: u64) {
+        for _ in 0..instructions {
+            self.tick();
+        }
+    }
+
+    /// An interrupt the device is asking for. It's asked before every instruction and taken once the program runs
+    /// below its priority, so a device keeps asking until the program acknowledges it through a register.
+    fn interrupt(&self) -> Option<Interrupt> {
+        None
+    }
+}
+
+/// Where an interrupt goes (entry x0100 + vector of the vector table) and the priority it runs at, 0-7
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupt {
+    pub vector: u8,
+    pub priority: u8,
+}
+
+/// A named group of bits in a register, high bit first, e.g. READY 15:15 or CHAR 7:0
+pub struct Field {
+    pub name: &'static str,
+    pub high: u8,
+    pub low: u8,
+}
+
+impl Field {
+    pub fn new(name: &'static str, high: u8, low: u8) -> Field {
+        Field { name, high, low }
+    }
+
+    pub fn extract(&self, value: u16) -> u16 {
+        let width = self.high - self.low + 1;
+        let mask = if width >= 16 { u16::MAX } else { (1 << width) - 1 };
+        (value >> self.low) & mask
+    }
+}
+
+pub struct RegisterInfo {
+    pub name: String,
+    pub address: u16,
+    pub fields: Vec<Field>,
+}
+
+/// What a constructor gets to know about the machine it is building for
+pub struct Setup {
+    /// devices that need randomness must derive it from this so `--seed` reproduces them
+    pub seed: u64,
+    /// where device registers are mapped; default addresses should be offsets into it
+    pub io_page: RangeInclusive<u16>,
+}
+
+/// Builds a device from the argument text after `NAME:` (empty when none was given)
+pub type Constructor = fn(&str, &Setup) -> Result<Box<dyn Device>, String>;
+
+pub struct Registry {
+    entries: Vec<(String, Constructor)>,
+    seed: u64,
+    io_page: RangeInclusive<u16>,
+    created: Cell<u64>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            entries: Vec::new(),
+            seed: 0,
+            io_page: 0xFE00..=0xFFFF,
+            created: Cell::new(0),
+        }
+    }
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// A later registration under the same name replaces the earlier one
+    pub fn register(&mut self, name: &str, constructor: Constructor) {
+        self.entries.retain(|(n, _)| n != name);
+        self.entries.push((name.to_string(), constructor));
+    }
+
+    /// Base for the seeds handed to constructors; each device created gets a different one
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Devices must live inside this range; the simulator sets it from its configured I/O page
+    pub fn set_io_page(&mut self, io_page: RangeInclusive<u16>) {
+        self.io_page = io_page;
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(n, _)| n.as_str())
+    }
+
+    /// Create a device from a `NAME` or `NAME:ARGS` spec
+    pub fn create(&self, spec: &str) -> Result<Box<dyn Device>, String> {
+        let (name, args) = spec.split_once(':').unwrap_or((spec, ""));
+        let (_, constructor) = self
+            .entries
+            .iter()
+            .find(|(n, _)| n == name)
+            .ok_or_else(|| {
+                let known: Vec<&str> = self.names().collect();
+                format!("unknown device `{}` (available: {})", name, known.join(", "))
+            })?;
+        let n = self.created.get();
+        self.created.set(n + 1);
+        let setup = Setup {
+            seed: self.seed.wrapping_add(n.wrapping_mul(0x9E37_79B9_7F4A_7C15)),
+            io_page: self.io_page.clone(),
+        };
+        let device = constructor(args, &setup)?;
+
+        let range = device.range();
+        if !self.io_page.contains(range.start()) || !self.io_page.contains(range.end()) {
+            return Err(format!(
+                "device `{}` at x{:04X}-x{:04X} is outside the I/O page x{:04X}-x{:04X}",
+                device.name(),
+                range.start(),
+                range.end(),
+                self.io_page.start(),
+                self.io_page.end()
+            ));
+        }
+        Ok(device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One register that keeps whatever is written to it
+    struct Latch {
+        address: u16,
+        value: u16,
+    }
+
+    impl Device for Latch {
+        fn name(&self) -> &str {
+            "latch"
+        }
+
+        fn range(&self) -> RangeInclusive<u16> {
+            self.address..=self.address
+        }
+
+        fn read(&mut self, _address: u16) -> u16 {
+            self.value
+        }
+
+        fn write(&mut self, _address: u16, value: u16) {
+            self.value = value;
+        }
+    }
+
+    // `latch` or `latch:ADDRESS`, e.g. `latch:xFE30`
+    fn latch(args: &str, setup: &Setup) -> Result<Box<dyn Device>, String> {
+        let address = match args {
+            "" => setup.io_page.start() + 0x20,
+            _ => u16::from_str_radix(args.trim_start_matches('x'), 16)
+                .map_err(|e| format!("bad address `{}`: {}", args, e))?,
+        };
+        Ok(Box::new(Latch { address, value: 0 }))
+    }
+
+    #[test]
+    fn devices_are_created_by_name_with_their_arguments() {
+        let mut registry = Registry::new();
+        registry.register("latch", latch);
+        let mut device = registry.create("latch:xFE30").unwrap();
+        assert_eq!(device.range(), 0xFE30..=0xFE30);
+        device.write(0xFE30, 7);
+        assert_eq!(device.read(0xFE30), 7);
+
+        assert_eq!(registry.create("latch").unwrap().range(), 0xFE20..=0xFE20);
+        let error = registry.create("latch:xZZ").err().unwrap();
+        assert_eq!(error, "bad address `xZZ`: invalid digit found in string");
+    }
+
+    #[test]
+    fn unknown_names_list_the_registered_ones() {
+        let mut registry = Registry::new();
+        registry.register("latch", latch);
+        registry.register("lamp", latch);
+        let error = registry.create("lump").err().unwrap();
+        assert_eq!(error, "unknown device `lump` (available: latch, lamp)");
+    }
+
+    #[test]
+    fn a_later_registration_replaces_the_earlier_one() {
+        fn fixed(_: &str, _: &Setup) -> Result<Box<dyn Device>, String> {
+            Ok(Box::new(Latch { address: 0xFE40, value: 0 }))
+        }
+
+        let mut registry = Registry::new();
+        registry.register("latch", latch);
+        registry.register("lamp", latch);
+        registry.register("latch", fixed);
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["lamp", "latch"]);
+        assert_eq!(registry.create("latch").unwrap().range(), 0xFE40..=0xFE40);
+    }
+
+    #[test]
+    fn each_device_gets_its_own_seed() {
+        // keeps the low bits of its seed in the register
+        fn seeded(_: &str, setup: &Setup) -> Result<Box<dyn Device>, String> {
+            Ok(Box::new(Latch { address: 0xFE20, value: setup.seed as u16 }))
+        }
+
+        let mut registry = Registry::new();
+        registry.register("seeded", seeded);
+        registry.set_seed(42);
+        let seeds: Vec<u16> = (0..3).map(|_| registry.create("seeded").unwrap().read(0xFE20)).collect();
+        assert_eq!(seeds[0], 42);
+        assert!(seeds[1] != seeds[0] && seeds[2] != seeds[1] && seeds[2] != seeds[0]);
+    }
+
+    #[test]
+    fn devices_outside_the_io_page_are_refused() {
+        let mut registry = Registry::new();
+        registry.register("latch", latch);
+        registry.set_io_page(0xFD00..=0xFDFF);
+        assert_eq!(registry.create("latch").unwrap().range(), 0xFD20..=0xFD20);
+        registry.set_io_page(0xFE00..=0xFE1F);
+        let error = registry.create("latch:xFE20").err().unwrap();
+        assert_eq!(error, "device `latch` at xFE20-xFE20 is outside the I/O page xFE00-xFE1F");
+        assert!(registry.create("latch:xFE1F").is_ok());
+    }
+
+    #[test]
+    fn fields_pick_out_their_bits() {
+        let status = 0x8041;
+        assert_eq!(Field::new("READY", 15, 15).extract(status), 1);
+        assert_eq!(Field::new("CHAR", 7, 0).extract(status), 0x41);
+        assert_eq!(Field::new("WORD", 15, 0).extract(status), status);
+    }
+}
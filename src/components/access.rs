@@ -0,0 +1,43 @@
+// Per-address memory access accounting: how often each word was read and written by the program, and which
+// instruction touched it last (PC and step number), to answer "who clobbered my variable?". Off unless something
+// asks for it (e.g. `--query`, `--who`), since it touches every load and store. Instruction fetches and loading
+// the program don't count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub pc: u16,
+    pub step: u64, // 1 for the first instruction of the run
+}
+
+pub struct AccessLog {
+    pub reads: Vec<u32>,
+    pub writes: Vec<u32>,
+    pub last_write: Vec<Option<Access>>,
+    pub last_read: Vec<Option<Access>>,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AccessLog {
+    pub fn new() -> AccessLog {
+        AccessLog {
+            reads: vec![0; 1 << 16],
+            writes: vec![0; 1 << 16],
+            last_write: vec![None; 1 << 16],
+            last_read: vec![None; 1 << 16],
+        }
+    }
+
+    pub fn read(&mut self, address: u16, by: Access) {
+        self.reads[address as usize] = self.reads[address as usize].saturating_add(1);
+        self.last_read[address as usize] = Some(by);
+    }
+
+    pub fn write(&mut self, address: u16, by: Access) {
+        self.writes[address as usize] = self.writes[address as usize].saturating_add(1);
+        self.last_write[address as usize] = Some(by);
+    }
+}
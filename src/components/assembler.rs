@@ -0,0 +1,645 @@
+// LC-3 assembler (`lc3_sim asm prog.asm -o prog.obj`). Two passes over the source: the first lays out addresses
+// and collects labels, the second encodes each statement now that every label is known. Errors carry the source
+// line and all of them are reported at once, so a student sees every typo in one go rather than one per run.
+//
+// The syntax is the textbook's: `.ORIG`, `.FILL`, `.BLKW`, `.STRINGZ` and `.END`, the 15 instructions (BR with any
+// of n/z/p, JSR/JSRR, RET and NOP), `TRAP` and the trap aliases the simulator knows (GETC ... HALT, BKPT and the
+// extensions). Numbers are `x1F`, `#31` or `31`; labels may end in a colon; `;` starts a comment.
+use std::collections::HashMap;
+
+use super::instruction::trap_name;
+use super::object::{Metadata, ObjectImage};
+
+// A source line with something on it, split into its parts
+struct Statement {
+    line: usize,
+    label: Option<String>,
+    op: Option<String>,
+    operands: Vec<String>,
+    // the quoted text of a .STRINGZ, escapes already resolved
+    text: Option<String>,
+}
+
+// Assemble a whole file into an object image: one .ORIG block, with its labels as symbols
+pub fn assemble(source: &str) -> Result<ObjectImage, String> {
+    assemble_listing(source).map(|(image, _)| image)
+}
+
+// `assemble`, also giving the address and source line of each instruction, so tools that change or report on
+// single instructions can tell code from data
+pub fn assemble_listing(source: &str) -> Result<(ObjectImage, Vec<(u16, usize)>), String> {
+    let mut errors = Vec::new();
+    let statements: Vec<Statement> = source
+        .lines()
+        .enumerate()
+        .filter_map(|(n, line)| match parse_line(n + 1, line) {
+            Ok(statement) => statement,
+            Err(e) => {
+                errors.push((n + 1, e));
+                None
+            }
+        })
+        .collect();
+
+    // pass 1: addresses and labels
+    let mut origin = None;
+    let mut address: u32 = 0;
+    let mut ended = false;
+    let mut labels: HashMap<String, u16> = HashMap::new();
+    let mut placed = Vec::new();
+    for statement in &statements {
+        let at = |e: String| (statement.line, e);
+        let op = statement.op.as_deref().map(str::to_ascii_uppercase);
+        if op.as_deref() == Some(".ORIG") {
+            if origin.is_some() {
+                errors.push(at("only one .ORIG per file".to_string()));
+                continue;
+            }
+            match operand_count(statement, 1).and_then(|_| number(&statement.operands[0])) {
+                Ok(value) if (0..=0xFFFF).contains(&value) => {
+                    origin = Some(value as u16);
+                    address = value as u32;
+                }
+                Ok(value) => errors.push(at(format!(".ORIG x{:X} is outside memory", value))),
+                Err(e) => errors.push(at(e)),
+            }
+            continue;
+        }
+        if origin.is_none() {
+            errors.push(at("expected .ORIG before anything else".to_string()));
+            break;
+        }
+        if op.as_deref() == Some(".END") {
+            ended = true;
+            break;
+        }
+        if let Some(label) = &statement.label {
+            if address > 0xFFFF {
+                errors.push(at(format!("label `{}` is past the end of memory", label)));
+            } else if labels.insert(label.clone(), address as u16).is_some() {
+                errors.push(at(format!("label `{}` is defined twice", label)));
+            }
+        }
+        if op.is_none() {
+            continue;
+        }
+        match size(statement) {
+            Ok(words) => {
+                placed.push((address as u16, statement));
+                address += words;
+                if address > 0x10000 {
+                    errors.push(at("the program runs past the end of memory".to_string()));
+                    break;
+                }
+            }
+            Err(e) => errors.push(at(e)),
+        }
+    }
+    if origin.is_some() && !ended && errors.is_empty() {
+        errors.push((0, "missing .END".to_string()));
+    }
+    let origin = match origin {
+        Some(origin) => origin,
+        None if errors.is_empty() => return Err("no .ORIG in the file".to_string()),
+        None => return Err(report(errors)),
+    };
+
+    // pass 2: encoding
+    let mut words = Vec::new();
+    let mut instructions = Vec::new();
+    for (address, statement) in placed {
+        if !statement.op.as_deref().unwrap_or_default().starts_with('.') {
+            instructions.push((address, statement.line));
+        }
+        match encode(statement, address, &labels) {
+            Ok(encoded) => words.extend(encoded),
+            Err(e) => errors.push((statement.line, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(report(errors));
+    }
+
+    let mut symbols: Vec<(u16, String)> = labels.into_iter().map(|(name, a)| (a, name)).collect();
+    symbols.sort();
+    let image = ObjectImage {
+        origin,
+        words,
+        metadata: Metadata {
+            entry: Some(origin),
+            isa: Some("lc3".to_string()),
+            symbols,
+            ..Metadata::default()
+        },
+    };
+    Ok((image, instructions))
+}
+
+// One error per line of text, in source order; line 0 is about the whole file
+fn report(mut errors: Vec<(usize, String)>) -> String {
+    errors.sort_by_key(|(line, _)| *line);
+    let lines: Vec<String> = errors
+        .into_iter()
+        .map(|(line, e)| match line {
+            0 => e,
+            line => format!("line {}: {}", line, e),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+// FNV-1a over the source text, for the object's source record
+pub fn source_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn parse_line(line: usize, text: &str) -> Result<Option<Statement>, String> {
+    let (code, text) = split_string(strip_comment(text))?;
+    let mut tokens: Vec<String> = code
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect();
+    if tokens.is_empty() {
+        return match text {
+            Some(_) => Err("a string needs .STRINGZ".to_string()),
+            None => Ok(None),
+        };
+    }
+
+    let label = if is_mnemonic(&tokens[0]) {
+        None
+    } else {
+        let label = tokens.remove(0);
+        let label = label.strip_suffix(':').unwrap_or(&label).to_string();
+        check_label(&label)?;
+        Some(label)
+    };
+    let op = if tokens.is_empty() {
+        None
+    } else {
+        let op = tokens.remove(0);
+        if !is_mnemonic(&op) {
+            // `FOO R1`: FOO was meant as the instruction, not a label
+            return Err(match &label {
+                Some(label) if register(&op).is_ok() || number(&op).is_ok() => {
+                    format!("unknown instruction `{}`", label)
+                }
+                _ => format!("unknown instruction or directive `{}`", op),
+            });
+        }
+        Some(op)
+    };
+    let is_stringz = op.as_deref().map(str::to_ascii_uppercase).as_deref() == Some(".STRINGZ");
+    if text.is_some() != is_stringz {
+        return Err(match text {
+            Some(_) => "a string needs .STRINGZ".to_string(),
+            None => ".STRINGZ needs a quoted string".to_string(),
+        });
+    }
+    Ok(Some(Statement {
+        line,
+        label,
+        op,
+        operands: tokens,
+        text,
+    }))
+}
+
+// `;` starts a comment unless it's inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+// The code before a quoted string, and the string itself with its escapes resolved
+fn split_string(line: &str) -> Result<(&str, Option<String>), String> {
+    let start = match line.find('"') {
+        Some(start) => start,
+        None => return Ok((line, None)),
+    };
+    let mut text = String::new();
+    let mut chars = line[start + 1..].chars();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".to_string()),
+            Some('"') => break,
+            Some('\\') => text.push(match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some('e') => '\x1B',
+                Some(c @ ('"' | '\\')) => c,
+                Some(c) => return Err(format!("unknown escape `\\{}`", c)),
+                None => return Err("unterminated string".to_string()),
+            }),
+            Some(c) => text.push(c),
+        }
+    }
+    if !chars.as_str().trim().is_empty() {
+        return Err(format!(
+            "unexpected `{}` after the string",
+            chars.as_str().trim()
+        ));
+    }
+    Ok((&line[..start], Some(text)))
+}
+
+fn check_label(label: &str) -> Result<(), String> {
+    let mut chars = label.chars();
+    let starts_well = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !starts_well || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("`{}` isn't a valid label", label));
+    }
+    if register(label).is_ok() || number(label).is_ok() {
+        return Err(format!(
+            "`{}` can't be a label, it reads as an operand",
+            label
+        ));
+    }
+    Ok(())
+}
+
+fn is_mnemonic(word: &str) -> bool {
+    let word = word.to_ascii_uppercase();
+    matches!(
+        word.as_str(),
+        "ADD"
+            | "AND"
+            | "NOT"
+            | "JMP"
+            | "RET"
+            | "JSR"
+            | "JSRR"
+            | "LD"
+            | "LDI"
+            | "LDR"
+            | "LEA"
+            | "ST"
+            | "STI"
+            | "STR"
+            | "TRAP"
+            | "RTI"
+            | "NOP"
+            | ".ORIG"
+            | ".FILL"
+            | ".BLKW"
+            | ".STRINGZ"
+            | ".END"
+    ) || branch_flags(&word).is_some()
+        || trap_vector(&word).is_some()
+}
+
+// The n/z/p bits of a BR mnemonic; plain BR branches always
+fn branch_flags(word: &str) -> Option<u16> {
+    let flags = word.strip_prefix("BR")?;
+    if flags.is_empty() {
+        return Some(0x7);
+    }
+    let mut bits = 0;
+    let mut rest = flags;
+    for (letter, bit) in [('N', 0x4), ('Z', 0x2), ('P', 0x1)] {
+        if let Some(r) = rest.strip_prefix(letter) {
+            bits |= bit;
+            rest = r;
+        }
+    }
+    rest.is_empty().then_some(bits)
+}
+
+fn trap_vector(word: &str) -> Option<u16> {
+    (0..=0xFF).find(|&vector| trap_name(vector) == Some(word))
+}
+
+// Words a statement occupies, known in the first pass
+fn size(statement: &Statement) -> Result<u32, String> {
+    let op = statement
+        .op
+        .as_deref()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    match op.as_str() {
+        ".BLKW" => {
+            operand_count(statement, 1)?;
+            match number(&statement.operands[0])? {
+                count @ 1..=0xFFFF => Ok(count as u32),
+                count => Err(format!(
+                    ".BLKW needs a count from 1 to 65535, got {}",
+                    count
+                )),
+            }
+        }
+        ".STRINGZ" => Ok(statement.text.as_ref().map_or(0, |t| t.chars().count()) as u32 + 1),
+        _ => Ok(1),
+    }
+}
+
+fn operand_count(statement: &Statement, expected: usize) -> Result<(), String> {
+    let found = statement.operands.len();
+    if found == expected {
+        return Ok(());
+    }
+    let op = statement
+        .op
+        .as_deref()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let plural = if expected == 1 { "" } else { "s" };
+    Err(format!(
+        "{} takes {} operand{}, got {}",
+        op, expected, plural, found
+    ))
+}
+
+fn encode(
+    statement: &Statement,
+    address: u16,
+    labels: &HashMap<String, u16>,
+) -> Result<Vec<u16>, String> {
+    let op = statement
+        .op
+        .as_deref()
+        .unwrap_or_default()
+        .to_ascii_uppercase();
+    let operands = &statement.operands;
+    let count = |n| operand_count(statement, n);
+    let reg = |i: usize| register(&operands[i]);
+    // a label or a literal offset, PC-relative to the next instruction
+    let offset = |i: usize, bits: u32| -> Result<u16, String> {
+        let operand = &operands[i];
+        let value = match labels.get(operand.as_str()) {
+            Some(&target) => target as i32 - (address as i32 + 1),
+            None if number(operand).is_ok() => number(operand)?,
+            None if check_label(operand).is_ok() => {
+                return Err(format!("undefined label `{}`", operand))
+            }
+            None => return Err(format!("expected a label or offset, got `{}`", operand)),
+        };
+        signed(value, bits).map_err(|range| match labels.get(operand.as_str()) {
+            Some(_) => format!(
+                "`{}` is too far away for a {}-bit offset ({})",
+                operand, bits, range
+            ),
+            None => format!("offset {} doesn't fit in {} bits ({})", value, bits, range),
+        })
+    };
+    let immediate = |i: usize, bits: u32| -> Result<u16, String> {
+        let value = number(&operands[i])?;
+        signed(value, bits)
+            .map_err(|range| format!("#{} doesn't fit in {} bits ({})", value, bits, range))
+    };
+    // ADD and AND: a register or a 5-bit immediate last
+    let arithmetic = |opcode: u16| -> Result<u16, String> {
+        count(3)?;
+        let base = opcode | reg(0)? << 9 | reg(1)? << 6;
+        match register(&operands[2]) {
+            Ok(sr2) => Ok(base | sr2),
+            Err(_) => Ok(base | 0x20 | immediate(2, 5)?),
+        }
+    };
+    let pc_relative = |opcode: u16| -> Result<u16, String> {
+        count(2)?;
+        Ok(opcode | reg(0)? << 9 | offset(1, 9)?)
+    };
+    let base_offset = |opcode: u16| -> Result<u16, String> {
+        count(3)?;
+        Ok(opcode | reg(0)? << 9 | reg(1)? << 6 | immediate(2, 6)?)
+    };
+
+    let word = match op.as_str() {
+        ".FILL" => {
+            count(1)?;
+            let value = match labels.get(operands[0].as_str()) {
+                Some(&target) => target as i32,
+                None => number(&operands[0])?,
+            };
+            if !(-0x8000..=0xFFFF).contains(&value) {
+                return Err(format!(".FILL value {} doesn't fit in 16 bits", value));
+            }
+            value as u16
+        }
+        ".BLKW" => return Ok(vec![0; size(statement)? as usize]),
+        ".STRINGZ" => {
+            count(0)?;
+            let text = statement.text.as_deref().unwrap_or_default();
+            return Ok(text
+                .chars()
+                .map(|c| c as u16)
+                .chain(std::iter::once(0))
+                .collect());
+        }
+        "ADD" => arithmetic(0x1000)?,
+        "AND" => arithmetic(0x5000)?,
+        "NOT" => {
+            count(2)?;
+            0x903F | reg(0)? << 9 | reg(1)? << 6
+        }
+        "LD" => pc_relative(0x2000)?,
+        "LDI" => pc_relative(0xA000)?,
+        "LEA" => pc_relative(0xE000)?,
+        "ST" => pc_relative(0x3000)?,
+        "STI" => pc_relative(0xB000)?,
+        "LDR" => base_offset(0x6000)?,
+        "STR" => base_offset(0x7000)?,
+        "JMP" => {
+            count(1)?;
+            0xC000 | reg(0)? << 6
+        }
+        "RET" => {
+            count(0)?;
+            0xC1C0
+        }
+        "JSR" => {
+            count(1)?;
+            0x4800 | offset(0, 11)?
+        }
+        "JSRR" => {
+            count(1)?;
+            0x4000 | reg(0)? << 6
+        }
+        "RTI" => {
+            count(0)?;
+            0x8000
+        }
+        "NOP" => {
+            count(0)?;
+            0x0000
+        }
+        "TRAP" => {
+            count(1)?;
+            match number(&operands[0])? {
+                vector @ 0..=0xFF => 0xF000 | vector as u16,
+                vector => return Err(format!("trap vector x{:X} doesn't fit in 8 bits", vector)),
+            }
+        }
+        _ => {
+            if let Some(flags) = branch_flags(&op) {
+                count(1)?;
+                flags << 9 | offset(0, 9)?
+            } else if let Some(vector) = trap_vector(&op) {
+                count(0)?;
+                0xF000 | vector
+            } else {
+                return Err(format!("{} can't be used here", op));
+            }
+        }
+    };
+    Ok(vec![word])
+}
+
+fn register(operand: &str) -> Result<u16, String> {
+    match operand.as_bytes() {
+        [b'R' | b'r', n @ b'0'..=b'7'] => Ok((n - b'0') as u16),
+        _ => Err(format!("expected a register R0-R7, got `{}`", operand)),
+    }
+}
+
+// `x1F`, `0x1F`, `#31` or `31`, each optionally negative
+fn number(operand: &str) -> Result<i32, String> {
+    let invalid = || format!("invalid number `{}`", operand);
+    let (radix, digits) = if let Some(hex) = operand.strip_prefix(['x', 'X']) {
+        (16, hex)
+    } else if let Some(hex) = operand.strip_prefix("0x") {
+        (16, hex)
+    } else {
+        (10, operand.strip_prefix('#').unwrap_or(operand))
+    };
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(invalid());
+    }
+    let value = i32::from_str_radix(digits, radix).map_err(|_| invalid())?;
+    Ok(if negative { -value } else { value })
+}
+
+// Two's complement in `bits` bits, or the allowed range
+fn signed(value: i32, bits: u32) -> Result<u16, String> {
+    let (low, high) = (-(1 << (bits - 1)), (1 << (bits - 1)) - 1);
+    if value < low || value > high {
+        return Err(format!("{} to {}", low, high));
+    }
+    Ok(value as u16 & ((1 << bits) - 1) as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        assemble(source).unwrap().words
+    }
+
+    #[test]
+    fn encodes_every_opcode() {
+        let source = "
+            .ORIG x3000
+    TOP     ADD R1, R2, R3
+            ADD R1, R2, #-16
+            AND R0, R0, #0
+            NOT R4, R5
+            BRnzp TOP
+            BRz TOP
+            JMP R6
+            RET
+            JSR TOP
+            JSRR R3
+            LD R0, DATA
+            LDI R1, DATA
+            LDR R2, R6, #-32
+            LEA R3, DATA
+            ST R4, DATA
+            STI R5, DATA
+            STR R7, R6, #31
+            TRAP x25
+            RTI
+    DATA    .FILL xBEEF
+            .END";
+        assert_eq!(
+            words(source),
+            [
+                0x1283, 0x12B0, 0x5020, 0x997F, 0x0FFB, 0x05FA, 0xC180, 0xC1C0, 0x4FF7, 0x40C0,
+                0x2008, 0xA207, 0x65A0, 0xE605, 0x3804, 0xBA03, 0x7F9F, 0xF025, 0x8000, 0xBEEF,
+            ]
+        );
+    }
+
+    #[test]
+    fn directives_and_aliases() {
+        let image = assemble(
+            ".ORIG x3000\nMSG .STRINGZ \"hi\\n\" ; greeting\nBUF .BLKW 2\nGETC\nHALT\nBKPT\n.END\nignored",
+        )
+        .unwrap();
+        assert_eq!(image.origin, 0x3000);
+        assert_eq!(
+            image.words,
+            [0x68, 0x69, 0x0A, 0, 0, 0, 0xF020, 0xF025, 0xF026]
+        );
+        assert_eq!(
+            image.metadata.symbols,
+            [(0x3000, "MSG".to_string()), (0x3004, "BUF".to_string())]
+        );
+        assert_eq!(image.metadata.entry, Some(0x3000));
+    }
+
+    #[test]
+    fn reports_every_error_in_line_order() {
+        let errors = assemble(
+            ".ORIG x3000\nADD R1, R2\nBR NOWHERE\nADD R0, R0, #16\nX .FILL 1\nX .FILL 2\n.END",
+        )
+        .unwrap_err();
+        let lines: Vec<&str> = errors.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "line 2: ADD takes 3 operands, got 2",
+                "line 3: undefined label `NOWHERE`",
+                "line 4: #16 doesn't fit in 5 bits (-16 to 15)",
+                "line 6: label `X` is defined twice",
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_must_reach() {
+        let far = ".ORIG x3000\nLD R0, FAR\n.BLKW 300\nFAR .FILL 0\n.END";
+        assert_eq!(
+            assemble(far).unwrap_err(),
+            "line 2: `FAR` is too far away for a 9-bit offset (-256 to 255)"
+        );
+    }
+
+    #[test]
+    fn needs_orig_and_end() {
+        assert_eq!(
+            assemble("ADD R0, R0, R0").unwrap_err(),
+            "line 1: expected .ORIG before anything else"
+        );
+        assert_eq!(assemble(".ORIG x3000\nHALT").unwrap_err(), "missing .END");
+    }
+
+    #[test]
+    fn the_listing_tells_code_from_data() {
+        let (image, instructions) =
+            assemble_listing(".ORIG x3000\nLD R0, N\n\nHALT\nN .FILL #3\n.STRINGZ \"ab\"\n.END").unwrap();
+        assert_eq!(image.words.len(), 6);
+        assert_eq!(instructions, [(0x3000, 2), (0x3001, 4)]);
+    }
+}
@@ -0,0 +1,82 @@
+// Second console device pair, mapped next to the keyboard registers (offsets x10-x16 into the I/O page), for
+// assembly-written test harnesses.
+// The harness exchanges data with the host through this channel while the primary console stays with the user.
+//
+// xFE10 AKBSR  bit 15 set when a byte is waiting in AKBDR
+// xFE12 AKBDR  next input byte, reading it clears AKBSR
+// xFE14 ADSR   bit 15 set when the output side is attached
+// xFE16 ADDR   writing the low byte sends it to the output side
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const READY: u16 = 1 << 15;
+
+#[derive(Default)]
+pub struct AuxConsole {
+    input: Option<Box<dyn Read>>,
+    output: Option<Box<dyn Write>>,
+    pending: Option<u8>,
+}
+
+impl AuxConsole {
+    pub fn new() -> AuxConsole {
+        AuxConsole::default()
+    }
+
+    pub fn attach_input(&mut self, path: &Path) -> io::Result<()> {
+        self.input = Some(Box::new(BufReader::new(File::open(path)?)));
+        Ok(())
+    }
+
+    pub fn attach_output(&mut self, path: &Path) -> io::Result<()> {
+        self.output = Some(Box::new(BufWriter::new(File::create(path)?)));
+        Ok(())
+    }
+
+    // AKBSR: pull the next byte if none is waiting. End of input simply leaves the ready bit clear.
+    pub fn status(&mut self) -> u16 {
+        if self.pending.is_none() {
+            if let Some(input) = self.input.as_mut() {
+                let mut buffer = [0; 1];
+                if let Ok(1) = input.read(&mut buffer) {
+                    self.pending = Some(buffer[0]);
+                }
+            }
+        }
+        if self.pending.is_some() {
+            READY
+        } else {
+            0
+        }
+    }
+
+    // AKBDR
+    pub fn data(&mut self) -> u16 {
+        self.pending.take().map_or(0, |b| b as u16)
+    }
+
+    // ADSR
+    pub fn display_status(&self) -> u16 {
+        if self.output.is_some() {
+            READY
+        } else {
+            0
+        }
+    }
+
+    // ADDR
+    pub fn write(&mut self, value: u16) {
+        if let Some(output) = self.output.as_mut() {
+            output
+                .write_all(&[value as u8])
+                .expect("failed to write aux output");
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(output) = self.output.as_mut() {
+            output.flush().expect("failed to flush aux output");
+        }
+    }
+}
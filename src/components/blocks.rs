@@ -0,0 +1,177 @@
+// Basic-block profile gathered while running. Blocks are found at runtime: one ends at any control-flow
+// instruction or wherever execution doesn't fall through, and a block entered in its middle is split there.
+// Block and edge counts make up a weighted control-flow graph, exported as Graphviz DOT or JSON.
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
+
+use super::instruction::disassemble_at;
+
+#[derive(Default)]
+pub struct BlockProfile {
+    // first address of the block being executed
+    start: Option<u16>,
+    // (first, last) address of a block as executed -> times run
+    runs: HashMap<(u16, u16), u64>,
+    // (first, last, address execution continued at) -> times taken
+    exits: HashMap<(u16, u16, u16), u64>,
+}
+
+pub struct Block {
+    pub start: u16,
+    pub end: u16,
+    pub count: u64,
+}
+
+pub struct Graph {
+    pub blocks: Vec<Block>,
+    pub edges: BTreeMap<(u16, u16), u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Json,
+}
+
+impl GraphFormat {
+    // `.json` files get JSON, anything else DOT
+    pub fn from_path(path: &std::path::Path) -> GraphFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => GraphFormat::Json,
+            _ => GraphFormat::Dot,
+        }
+    }
+}
+
+impl BlockProfile {
+    pub fn new() -> BlockProfile {
+        BlockProfile::default()
+    }
+
+    // Called after each instruction with its address and where execution goes next
+    pub fn record(&mut self, pc: u16, instruction: u16, next: u16) {
+        let start = *self.start.get_or_insert(pc);
+        // BR, JSR, RTI, JMP and TRAP end a block even when this time they fell through
+        let control = matches!(instruction >> 12, 0x0 | 0x4 | 0x8 | 0xC | 0xF);
+        if control || next != pc.wrapping_add(1) {
+            *self.runs.entry((start, pc)).or_default() += 1;
+            *self.exits.entry((start, pc, next)).or_default() += 1;
+            self.start = Some(next);
+        }
+    }
+
+    // The blocks as executed, split at every address some block started at or some edge led to
+    pub fn graph(&self) -> Graph {
+        let leaders: BTreeSet<u16> = self
+            .runs
+            .keys()
+            .map(|&(start, _)| start)
+            .chain(self.exits.keys().map(|&(_, _, next)| next))
+            .collect();
+
+        let mut blocks: BTreeMap<u16, Block> = BTreeMap::new();
+        let mut edges: BTreeMap<(u16, u16), u64> = BTreeMap::new();
+        let mut add_block = |start: u16, end: u16, count: u64| {
+            blocks
+                .entry(start)
+                .or_insert(Block {
+                    start,
+                    end,
+                    count: 0,
+                })
+                .count += count;
+        };
+        for (&(start, end), &count) in &self.runs {
+            let mut first = start;
+            for &leader in leaders.range(start..=end.max(start)).skip(1) {
+                add_block(first, leader - 1, count);
+                *edges.entry((first, leader)).or_default() += count;
+                first = leader;
+            }
+            add_block(first, end, count);
+        }
+        for (&(start, end, next), &count) in &self.exits {
+            let last = *leaders.range(start..=end.max(start)).next_back().unwrap();
+            // leaving the program (HALT, running off the end) isn't an edge
+            if blocks.contains_key(&next) {
+                *edges.entry((last, next)).or_default() += count;
+            }
+        }
+
+        Graph {
+            blocks: blocks.into_values().collect(),
+            edges,
+        }
+    }
+
+    pub fn render(&self, memory: &[u16], format: GraphFormat) -> String {
+        let graph = self.graph();
+        match format {
+            GraphFormat::Dot => graph.dot(memory),
+            GraphFormat::Json => graph.json(),
+        }
+    }
+}
+
+impl Graph {
+    // Blocks are labelled with their disassembly; edge width follows how often the edge was taken
+    fn dot(&self, memory: &[u16]) -> String {
+        let heaviest = self.edges.values().copied().max().unwrap_or(1);
+        let mut out = String::from("digraph cfg {\n  node [shape=box, fontname=monospace];\n");
+        for block in &self.blocks {
+            let mut label = format!("x{:04X}  ({} runs)\\l", block.start, block.count);
+            for address in block.start..=block.end {
+                let instruction = memory.get(address as usize).copied().unwrap_or(0);
+                write!(
+                    label,
+                    "x{:04X}: {}\\l",
+                    address,
+                    disassemble_at(instruction, address).replace('"', "\\\"")
+                )
+                .unwrap();
+            }
+            writeln!(out, "  b{:04X} [label=\"{}\"];", block.start, label).unwrap();
+        }
+        for (&(from, to), &count) in &self.edges {
+            writeln!(
+                out,
+                "  b{:04X} -> b{:04X} [label=\"{}\", penwidth={:.1}];",
+                from,
+                to,
+                count,
+                1.0 + 4.0 * count as f64 / heaviest as f64
+            )
+            .unwrap();
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn json(&self) -> String {
+        let blocks: Vec<String> = self
+            .blocks
+            .iter()
+            .map(|b| {
+                format!(
+                    "{{\"start\": {}, \"end\": {}, \"count\": {}}}",
+                    b.start, b.end, b.count
+                )
+            })
+            .collect();
+        let edges: Vec<String> = self
+            .edges
+            .iter()
+            .map(|(&(from, to), count)| {
+                format!(
+                    "{{\"from\": {}, \"to\": {}, \"count\": {}}}",
+                    from, to, count
+                )
+            })
+            .collect();
+        format!(
+            "{{\"blocks\": [{}], \"edges\": [{}]}}\n",
+            blocks.join(", "),
+            edges.join(", ")
+        )
+    }
+}
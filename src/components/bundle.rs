@@ -0,0 +1,141 @@
+// Run bundles (--bundle, `lc3_sim replay`): everything needed to repeat a run on another machine in one file, for
+// bug reports and regrade requests. That is the command line, every file it names (the object, data files,
+// region and policy files, ...), the keystrokes the program read, the seed and the simulator version.
+//
+// The format is text so a bundle can be pasted into an issue: a header line, then one `version`, `arg` or `file`
+// line per entry, file contents in hex. In `arg` lines `{bundle:N}` stands for where file N is unpacked.
+use std::fs;
+use std::path::Path;
+
+const HEADER: &str = "# lc3_sim run bundle v1";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    // the simulator that wrote the bundle
+    pub version: String,
+    pub args: Vec<String>,
+    // (name, contents)
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl Bundle {
+    // Pack a command line, taking a copy of every file it names: a whole argument, the FILE of FILE@ADDRESS or
+    // the value of --flag=FILE. Done before the run so the copies are what the run started from.
+    pub fn capture(args: &[String]) -> Bundle {
+        let mut bundle = Bundle {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            ..Bundle::default()
+        };
+        for arg in args {
+            let arg = bundle
+                .pack_file(arg, "", arg, "")
+                .or_else(|| {
+                    let (path, address) = arg.rsplit_once('@')?;
+                    bundle.pack_file(arg, "", path, &format!("@{}", address))
+                })
+                .or_else(|| {
+                    let (flag, path) = arg.strip_prefix("--")?.split_once('=')?;
+                    bundle.pack_file(arg, &format!("--{}=", flag), path, "")
+                })
+                .unwrap_or_else(|| arg.clone());
+            bundle.args.push(arg);
+        }
+        bundle
+    }
+
+    // Add a file of the run's own making and pass it to the replay with `flag`
+    pub fn attach(&mut self, flag: &str, name: &str, contents: Vec<u8>) {
+        self.args.push(flag.to_string());
+        self.args.push(format!("{{bundle:{}}}", self.files.len()));
+        self.files.push((name.to_string(), contents));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = format!("{}\nversion {}\n", HEADER, self.version);
+        for arg in &self.args {
+            out.push_str(&format!("arg {}\n", arg));
+        }
+        for (name, contents) in &self.files {
+            let hex: String = contents.iter().map(|b| format!("{:02X}", b)).collect();
+            out.push_str(&format!("file {} {}\n", name, hex));
+        }
+        fs::write(path, out).map_err(|e| format!("couldn't write bundle {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Bundle, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read bundle {}: {}", path.display(), e))?;
+        parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Write the files into `dir` and return the command line with their new paths filled in
+    pub fn unpack(&self, dir: &Path) -> Result<Vec<String>, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("couldn't create {}: {}", dir.display(), e))?;
+        let mut paths = Vec::new();
+        for (n, (name, contents)) in self.files.iter().enumerate() {
+            let path = dir.join(format!("{}-{}", n, name));
+            fs::write(&path, contents)
+                .map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+            paths.push(path.display().to_string());
+        }
+        Ok(self
+            .args
+            .iter()
+            .map(|arg| {
+                paths
+                    .iter()
+                    .enumerate()
+                    .fold(arg.clone(), |arg, (n, path)| {
+                        arg.replace(&format!("{{bundle:{}}}", n), path)
+                    })
+            })
+            .collect())
+    }
+
+    // `{prefix}{bundle:N}{suffix}` for a regular file at `path`, packed as file N
+    fn pack_file(&mut self, arg: &str, prefix: &str, path: &str, suffix: &str) -> Option<String> {
+        let path = Path::new(path);
+        if arg.is_empty() || !path.is_file() {
+            return None;
+        }
+        let contents = fs::read(path).ok()?;
+        let name = path.file_name()?.to_string_lossy().replace(char::is_whitespace, "_");
+        let n = self.files.len();
+        self.files.push((name, contents));
+        Some(format!("{}{{bundle:{}}}{}", prefix, n, suffix))
+    }
+}
+
+fn parse(text: &str) -> Result<Bundle, String> {
+    let mut lines = text.lines();
+    if lines.next() != Some(HEADER) {
+        return Err("not a run bundle".to_string());
+    }
+    let mut bundle = Bundle::default();
+    for (n, line) in lines.enumerate() {
+        let at = |e: &str| format!("line {}: {}", n + 2, e);
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match kind {
+            "version" => bundle.version = rest.to_string(),
+            "arg" => bundle.args.push(rest.to_string()),
+            "file" => {
+                let (name, hex) = rest.split_once(' ').unwrap_or((rest, ""));
+                if name.is_empty() || name.contains(['/', '\\']) || name == ".." {
+                    return Err(at("bad file name"));
+                }
+                let contents = (0..hex.len())
+                    .step_by(2)
+                    .map(|i| {
+                        hex.get(i..i + 2)
+                            .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    })
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| at("bad hex in file contents"))?;
+                bundle.files.push((name.to_string(), contents));
+            }
+            "" => {}
+            _ => return Err(at(&format!("unknown entry `{}`", kind))),
+        }
+    }
+    Ok(bundle)
+}
@@ -0,0 +1,300 @@
+// Shadow call stack, maintained from JSR/JSRR and RET so per-subroutine instruction budgets can be enforced.
+// Nothing here is visible to the guest: R7 and the real stack (if any) are untouched. Traps, exceptions and
+// interrupts taken through a vector table push handler frames too, which RTI (or, for a trap returning through R7,
+// RET) pops.
+use std::collections::HashMap;
+use std::fmt;
+
+use super::instruction::trap_name;
+use super::interrupt;
+use super::regions::MemoryMap;
+
+#[derive(Clone)]
+pub struct CallFrame {
+    pub entry: u16,          // address the subroutine was entered at
+    pub call_site: u16,      // address of the JSR/JSRR
+    pub return_address: u16, // value saved in R7, a RET to it pops this frame
+    pub start: u64,          // instruction count when the call was made
+    pub handler: Option<Handler>,
+    budget: Option<u64>,
+}
+
+// What took the machine into a handler, with the vector it went through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    Trap(u16),
+    Exception(u16),
+    Interrupt(u16),
+}
+
+impl Cause {
+    // `TRAP x21 (OUT)`, `illegal opcode exception (x01)`, `keyboard interrupt (x80)`
+    pub fn describe(self) -> String {
+        match self {
+            Cause::Trap(vector) => match trap_name(vector) {
+                Some(name) => format!("TRAP x{:02X} ({})", vector, name),
+                None => format!("TRAP x{:02X}", vector),
+            },
+            Cause::Exception(vector) => match vector {
+                interrupt::PRIVILEGE_VIOLATION => "privilege mode violation exception (x00)".to_string(),
+                interrupt::ILLEGAL_OPCODE => "illegal opcode exception (x01)".to_string(),
+                _ => format!("exception x{:02X}", vector),
+            },
+            Cause::Interrupt(vector) => match vector {
+                interrupt::KEYBOARD => "keyboard interrupt (x80)".to_string(),
+                interrupt::TIMER => "timer interrupt (x81)".to_string(),
+                _ => format!("interrupt x{:02X}", vector),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handler {
+    pub cause: Cause,
+    // where the saved PC is on the supervisor stack, the saved PSR being the word above it; None for a trap that
+    // saved the PC in R7 instead
+    pub saved_at: Option<u16>,
+}
+
+// Reported when a subroutine runs longer than its budget in a single call (callees included)
+#[derive(Debug)]
+pub struct BudgetExceeded {
+    pub entry: u16,
+    pub call_site: u16,
+    pub depth: usize,
+    pub budget: u64,
+    pub used: u64,
+}
+
+impl BudgetExceeded {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        format!(
+            "subroutine at {} (called from {}, depth {}) exceeded its budget of {} instructions per call ({} executed)",
+            map.at(self.entry), map.at(self.call_site), self.depth, self.budget, self.used
+        )
+    }
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.describe(&MemoryMap::new()))
+    }
+}
+
+// Reported when the whole run reaches its instruction limit (--max-instructions) without halting
+#[derive(Debug)]
+pub struct LimitReached {
+    pub limit: u64,
+    // the instruction that would have run next
+    pub pc: u16,
+}
+
+impl LimitReached {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        format!(
+            "stopped after {} instructions without halting, next instruction at {}",
+            self.limit,
+            map.at(self.pc)
+        )
+    }
+}
+
+// A budget as --budget and test specs give it, `SUBROUTINE:MAX`, where the subroutine is a symbol or an address
+pub fn parse_budget(s: &str) -> Result<(String, u64), String> {
+    let (subroutine, max) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected SUBROUTINE:MAX, got `{}`", s))?;
+    let max = max
+        .parse()
+        .map_err(|_| format!("invalid instruction budget `{}`", max))?;
+    Ok((subroutine.to_string(), max))
+}
+
+#[derive(Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    budgets: HashMap<u16, u64>,
+    steps: u64,
+    deadline: Option<u64>, // earliest step at which some budgeted frame runs out
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack::default()
+    }
+
+    // Limit every call to the subroutine at `entry` to `max` instructions
+    pub fn set_budget(&mut self, entry: u16, max: u64) {
+        self.budgets.insert(entry, max);
+    }
+
+    // Forget every frame and the step count, keeping the budgets
+    pub fn reset(&mut self) {
+        self.frames.clear();
+        self.steps = 0;
+        self.deadline = None;
+    }
+
+    // Instructions executed since the start (or the last reset)
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn call(&mut self, call_site: u16, entry: u16, return_address: u16) {
+        self.push(call_site, entry, return_address, None);
+    }
+
+    // A trap, exception or interrupt going through its vector to `entry`
+    pub fn enter_handler(&mut self, call_site: u16, entry: u16, return_address: u16, handler: Handler) {
+        self.push(call_site, entry, return_address, Some(handler));
+    }
+
+    fn push(&mut self, call_site: u16, entry: u16, return_address: u16, handler: Option<Handler>) {
+        let budget = self.budgets.get(&entry).copied();
+        if let Some(max) = budget {
+            let deadline = self.steps + max;
+            self.deadline = Some(self.deadline.map_or(deadline, |d| d.min(deadline)));
+        }
+        self.frames.push(CallFrame {
+            entry,
+            call_site,
+            return_address,
+            start: self.steps,
+            handler,
+            budget,
+        });
+    }
+
+    // A jump through R7. Unwinds to the matching frame, anything else is just a computed jump. Handlers that
+    // saved the PC on the supervisor stack only return with RTI.
+    pub fn ret(&mut self, target: u16) {
+        if let Some(index) = self.frames.iter().rposition(|f| {
+            f.return_address == target && f.handler.is_none_or(|h| h.saved_at.is_none())
+        }) {
+            self.frames.truncate(index);
+            self.update_deadline();
+        }
+    }
+
+    // RTI popping the PC saved at `sp`. Unwinds to the handler frame that saved it.
+    pub fn rti(&mut self, sp: u16) {
+        if let Some(index) = self
+            .frames
+            .iter()
+            .rposition(|f| f.handler.is_some_and(|h| h.saved_at == Some(sp)))
+        {
+            self.frames.truncate(index);
+            self.update_deadline();
+        }
+    }
+
+    // The innermost handler frame and how many frames are below it
+    pub fn handler(&self) -> Option<(usize, &CallFrame)> {
+        let index = self.frames.iter().rposition(|f| f.handler.is_some())?;
+        Some((index, &self.frames[index]))
+    }
+
+    // Back to the frames and step count of an earlier instruction, for the debugger's undo
+    pub fn rewind(&mut self, frames: Vec<CallFrame>, steps: u64) {
+        self.frames = frames;
+        self.steps = steps;
+        self.update_deadline();
+    }
+
+    fn update_deadline(&mut self) {
+        self.deadline = self
+            .frames
+            .iter()
+            .filter_map(|f| f.budget.map(|max| f.start + max))
+            .min();
+    }
+
+    // Called once per executed instruction
+    pub fn step(&mut self) -> Result<(), BudgetExceeded> {
+        self.steps += 1;
+        match self.deadline {
+            Some(deadline) if self.steps > deadline => Err(self.exceeded()),
+            _ => Ok(()),
+        }
+    }
+
+    // Instructions skipped by idle-loop fast-forward, counted as executed
+    pub fn skip(&mut self, instructions: u64) {
+        self.steps += instructions;
+    }
+
+    // Instructions left before some budgeted frame runs out, if one has a budget
+    pub fn until_deadline(&self) -> Option<u64> {
+        self.deadline.map(|d| d.saturating_sub(self.steps))
+    }
+
+    fn exceeded(&self) -> BudgetExceeded {
+        let (depth, frame) = self
+            .frames
+            .iter()
+            .enumerate()
+            .find(|(_, f)| f.budget.is_some_and(|max| self.steps > f.start + max))
+            .expect("deadline passed without an exhausted frame");
+        BudgetExceeded {
+            entry: frame.entry,
+            call_site: frame.call_site,
+            depth: depth + 1,
+            budget: frame.budget.unwrap(),
+            used: self.steps - frame.start,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_call_that_outruns_its_budget_is_reported() {
+        let mut stack = CallStack::new();
+        stack.set_budget(0x3100, 3);
+        // an unbudgeted caller, then the budgeted subroutine it calls
+        stack.call(0x3000, 0x3050, 0x3001);
+        stack.step().unwrap();
+        stack.call(0x3051, 0x3100, 0x3052);
+        for _ in 0..3 {
+            stack.step().unwrap();
+        }
+        let exceeded = stack.step().unwrap_err();
+        assert_eq!(exceeded.entry, 0x3100);
+        assert_eq!(exceeded.call_site, 0x3051);
+        assert_eq!(exceeded.depth, 2);
+        assert_eq!((exceeded.budget, exceeded.used), (3, 4));
+    }
+
+    #[test]
+    fn returning_clears_the_deadline() {
+        let mut stack = CallStack::new();
+        stack.set_budget(0x3100, 2);
+        for _ in 0..2 {
+            // each call gets the whole budget again
+            stack.call(0x3000, 0x3100, 0x3001);
+            stack.step().unwrap();
+            stack.step().unwrap();
+            stack.ret(0x3001);
+            assert!(stack.frames().is_empty());
+        }
+        for _ in 0..10 {
+            stack.step().unwrap();
+        }
+        assert_eq!(stack.steps(), 14);
+    }
+
+    #[test]
+    fn budgets_name_a_symbol_or_an_address() {
+        assert_eq!(parse_budget("MULT:2000"), Ok(("MULT".to_string(), 2000)));
+        assert_eq!(parse_budget("x3100:5"), Ok(("x3100".to_string(), 5)));
+        assert!(parse_budget("MULT").is_err());
+        assert!(parse_budget("MULT:lots").is_err());
+    }
+}
@@ -0,0 +1,279 @@
+// Frame capture of guest console output, so interactive programs can be checked against golden output.
+// A frame is whatever the guest printed since the previous frame, stamped with the instruction count it was taken at.
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+// When a new frame is cut from the output stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameTrigger {
+    Halt,       // a single frame holding everything printed before HALT
+    Prompt,     // every time the guest waits for input, plus one at HALT
+    Steps(u64), // every N instructions, plus one at HALT
+}
+
+impl FromStr for FrameTrigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "halt" => Ok(FrameTrigger::Halt),
+            "prompt" => Ok(FrameTrigger::Prompt),
+            _ => match s.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(FrameTrigger::Steps(n)),
+                _ => Err(format!(
+                    "unknown frame trigger `{}` (expected halt, prompt or a step count)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub step: u64,
+    pub text: String,
+}
+
+pub struct FrameCapture {
+    pub trigger: FrameTrigger,
+    pub output: Option<PathBuf>, // where to write the frames at HALT
+    pub golden: Option<PathBuf>, // frames to compare against at HALT
+    frames: Vec<Frame>,
+    pending: Vec<u8>,
+    steps: u64,
+}
+
+impl FrameCapture {
+    pub fn new(trigger: FrameTrigger) -> FrameCapture {
+        FrameCapture {
+            trigger,
+            output: None,
+            golden: None,
+            frames: Vec::new(),
+            pending: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    // Record a byte exactly as it was written to the terminal
+    pub fn record(&mut self, byte: u8) {
+        self.pending.push(byte);
+    }
+
+    // Called once per executed instruction
+    pub fn step(&mut self) {
+        self.steps += 1;
+        if let FrameTrigger::Steps(n) = self.trigger {
+            if self.steps.is_multiple_of(n) {
+                self.cut();
+            }
+        }
+    }
+
+    // Called whenever the guest is about to wait for input
+    pub fn prompt(&mut self) {
+        if self.trigger == FrameTrigger::Prompt {
+            self.cut();
+        }
+    }
+
+    // Take the final frame, then write and/or compare. Returns the diff if the golden frames don't match.
+    pub fn finish(&mut self) -> Result<(), String> {
+        self.cut();
+
+        if let Some(path) = &self.output {
+            fs::write(path, render_frames(&self.frames))
+                .map_err(|e| format!("couldn't write frames to {}: {}", path.display(), e))?;
+        }
+
+        if let Some(path) = &self.golden {
+            let golden = fs::read_to_string(path)
+                .map_err(|e| format!("couldn't read golden frames {}: {}", path.display(), e))?;
+            compare_frames(&parse_frames(&golden), &self.frames)?;
+        }
+
+        Ok(())
+    }
+
+    // Empty frames are skipped so polling loops that print nothing don't produce noise
+    fn cut(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let text = String::from_utf8_lossy(&self.pending).into_owned();
+        self.pending.clear();
+        self.frames.push(Frame {
+            step: self.steps,
+            text,
+        });
+    }
+}
+
+const FRAME_HEADER: &str = "=== frame ";
+
+// Text format: a header line per frame followed by its output. The step is informative only.
+pub fn render_frames(frames: &[Frame]) -> String {
+    let mut out = String::new();
+    for (i, frame) in frames.iter().enumerate() {
+        writeln!(out, "{}{} @ step {} ===", FRAME_HEADER, i + 1, frame.step).unwrap();
+        out.push_str(&frame.text);
+        if !frame.text.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+pub fn parse_frames(text: &str) -> Vec<Frame> {
+    let mut frames: Vec<Frame> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        if let Some(header) = line.strip_prefix(FRAME_HEADER) {
+            let step = header
+                .split(" @ step ")
+                .nth(1)
+                .and_then(|rest| rest.trim_end().trim_end_matches(" ===").parse().ok())
+                .unwrap_or(0);
+            frames.push(Frame {
+                step,
+                text: String::new(),
+            });
+        } else if let Some(frame) = frames.last_mut() {
+            frame.text.push_str(line);
+        }
+    }
+    frames
+}
+
+// Compare frame texts (ignoring steps) and describe the first mismatch as a line diff
+pub fn compare_frames(expected: &[Frame], actual: &[Frame]) -> Result<(), String> {
+    for (i, (e, a)) in expected.iter().zip(actual).enumerate() {
+        if e.text.trim_end_matches('\n') != a.text.trim_end_matches('\n') {
+            let mut report = format!(
+                "frame {} differs (expected step {}, got step {}):\n",
+                i + 1,
+                e.step,
+                a.step
+            );
+            report.push_str(&line_diff(&e.text, &a.text));
+            return Err(report);
+        }
+    }
+
+    if expected.len() != actual.len() {
+        return Err(format!(
+            "expected {} frames, captured {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+
+    Ok(())
+}
+
+// Minimal LCS line diff: ` ` unchanged, `-` expected only, `+` actual only
+fn line_diff(expected: &str, actual: &str) -> String {
+    let e: Vec<&str> = expected.lines().collect();
+    let a: Vec<&str> = actual.lines().collect();
+
+    // lcs[i][j] = length of the LCS of e[i..] and a[j..]
+    let mut lcs = vec![vec![0usize; a.len() + 1]; e.len() + 1];
+    for i in (0..e.len()).rev() {
+        for j in (0..a.len()).rev() {
+            lcs[i][j] = if e[i] == a[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < e.len() || j < a.len() {
+        if i < e.len() && j < a.len() && e[i] == a[j] {
+            writeln!(out, "  {}", e[i]).unwrap();
+            i += 1;
+            j += 1;
+        } else if i < e.len() && (j == a.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            writeln!(out, "- {}", e[i]).unwrap();
+            i += 1;
+        } else {
+            writeln!(out, "+ {}", a[j]).unwrap();
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(step: u64, text: &str) -> Frame {
+        Frame {
+            step,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn line_diff_marks_removed_and_added_lines() {
+        assert_eq!(line_diff("a\nb\nc\n", "a\nb\nc\n"), "  a\n  b\n  c\n");
+        assert_eq!(
+            line_diff("Score: 1\nLives: 3\nPress a key\n", "Score: 2\nLives: 3\n"),
+            "- Score: 1\n+ Score: 2\n  Lives: 3\n- Press a key\n"
+        );
+        assert_eq!(line_diff("", "new\n"), "+ new\n");
+    }
+
+    #[test]
+    fn frames_survive_a_round_trip() {
+        let mut capture = FrameCapture::new(FrameTrigger::Steps(2));
+        for &byte in b"Enter a digit: " {
+            capture.record(byte);
+        }
+        capture.step();
+        capture.step();
+        // a step with nothing printed since the last frame cuts none
+        capture.step();
+        capture.step();
+        for &byte in b"7\nDone\n" {
+            capture.record(byte);
+        }
+        capture.step();
+        capture.cut();
+        let frames = capture.frames().to_vec();
+        assert_eq!(frames, [frame(2, "Enter a digit: "), frame(5, "7\nDone\n")]);
+
+        let text = render_frames(&frames);
+        assert_eq!(
+            text,
+            "=== frame 1 @ step 2 ===\nEnter a digit: \n=== frame 2 @ step 5 ===\n7\nDone\n"
+        );
+        let parsed = parse_frames(&text);
+        assert_eq!(parsed.iter().map(|f| f.step).collect::<Vec<_>>(), [2, 5]);
+        // the newline added after a frame that didn't end in one doesn't count as a difference
+        assert_eq!(compare_frames(&parsed, &frames), Ok(()));
+    }
+
+    #[test]
+    fn a_mismatch_names_the_frame_and_diffs_it() {
+        let golden = [frame(10, "Guess: \n"), frame(40, "Too high\nGuess: \n")];
+        let actual = [frame(10, "Guess: "), frame(38, "Too low\nGuess: ")];
+        assert_eq!(
+            compare_frames(&golden, &actual).unwrap_err(),
+            "frame 2 differs (expected step 40, got step 38):\n- Too high\n+ Too low\n  Guess: \n"
+        );
+        assert_eq!(
+            compare_frames(&golden, &actual[..1]).unwrap_err(),
+            "expected 2 frames, captured 1"
+        );
+    }
+}
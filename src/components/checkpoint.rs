@@ -0,0 +1,124 @@
+// Named machine-state checkpoints and the differences between them, to answer "what did this code change?".
+// Marks are armed at an address and taken the first time the PC reaches it.
+use std::collections::HashMap;
+
+use super::regions::MemoryMap;
+use super::register::Registers;
+use super::vm::VM;
+
+const REGISTER_NAMES: [&str; 10] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+
+pub struct Checkpoint {
+    pub registers: Registers,
+    pub memory: Vec<u16>,
+}
+
+impl Checkpoint {
+    pub fn take(vm: &VM) -> Checkpoint {
+        Checkpoint {
+            registers: vm.registers.clone(),
+            memory: vm.memory.to_vec(),
+        }
+    }
+}
+
+// One line of a diff: a register or memory word that holds a different value
+pub enum Change {
+    Register {
+        name: &'static str,
+        old: u16,
+        new: u16,
+    },
+    Memory {
+        address: u16,
+        old: u16,
+        new: u16,
+    },
+}
+
+impl Change {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        match self {
+            Change::Register { name, old, new } => {
+                format!("{}: x{:04X} -> x{:04X}", name, old, new)
+            }
+            Change::Memory { address, old, new } => {
+                format!("{}: x{:04X} -> x{:04X}", map.at(*address), old, new)
+            }
+        }
+    }
+}
+
+pub fn diff(a: &Checkpoint, b: &Checkpoint) -> Vec<Change> {
+    let registers = (0..REGISTER_NAMES.len() as u16).filter_map(|i| {
+        let (old, new) = (a.registers.get(i), b.registers.get(i));
+        (old != new).then(|| Change::Register {
+            name: REGISTER_NAMES[i as usize],
+            old,
+            new,
+        })
+    });
+    let memory = a
+        .memory
+        .iter()
+        .zip(&b.memory)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(address, (&old, &new))| Change::Memory {
+            address: address as u16,
+            old,
+            new,
+        });
+    registers.chain(memory).collect()
+}
+
+#[derive(Default)]
+pub struct Marks {
+    armed: Vec<(String, u16)>,
+    taken: HashMap<String, Checkpoint>,
+}
+
+impl Marks {
+    pub fn new() -> Marks {
+        Marks::default()
+    }
+
+    // Take checkpoint `name` the first time execution reaches `address`
+    pub fn arm(&mut self, name: &str, address: u16) {
+        self.armed.push((name.to_string(), address));
+    }
+
+    pub fn is_armed(&self) -> bool {
+        !self.armed.is_empty()
+    }
+
+    pub fn mark(&mut self, name: &str, checkpoint: Checkpoint) {
+        self.taken.insert(name.to_string(), checkpoint);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Checkpoint> {
+        self.taken.get(name)
+    }
+
+    // Names of marks armed at `pc`, disarming them
+    pub fn reached(&mut self, pc: u16) -> Vec<String> {
+        let mut names = Vec::new();
+        self.armed.retain(|(name, address)| {
+            if *address == pc {
+                names.push(name.clone());
+                false
+            } else {
+                true
+            }
+        });
+        names
+    }
+
+    pub fn diff(&self, a: &str, b: &str) -> Result<Vec<Change>, String> {
+        let lookup = |name: &str| {
+            self.get(name)
+                .ok_or_else(|| format!("checkpoint `{}` was never reached", name))
+        };
+        Ok(diff(lookup(a)?, lookup(b)?))
+    }
+}
@@ -0,0 +1,140 @@
+// Checksums for object files, so graders can be sure the binary that ran is the binary that was submitted.
+// They live in a sidecar next to the object (`prog.obj.sum`) with one `ALGORITHM HEX` line per checksum,
+// e.g. `sha256 9f86d0...` and `crc32 1a2b3c4d`. Every line present must match.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+pub fn sidecar_path(object: &Path) -> PathBuf {
+    let mut name = object.as_os_str().to_owned();
+    name.push(".sum");
+    PathBuf::from(name)
+}
+
+pub fn crc32(bytes: &[u8]) -> String {
+    format!("{:08x}", crc32fast::hash(bytes))
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// Write the sidecar for an object, returning its path
+pub fn sign(object: &Path) -> Result<PathBuf, String> {
+    let bytes =
+        fs::read(object).map_err(|e| format!("couldn't read {}: {}", object.display(), e))?;
+    let path = sidecar_path(object);
+    let text = format!("sha256 {}\ncrc32 {}\n", sha256(&bytes), crc32(&bytes));
+    fs::write(&path, text).map_err(|e| format!("couldn't write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+// Check an object against its sidecar. Ok(false) means there is no sidecar to check against.
+pub fn verify(object: &Path, bytes: &[u8]) -> Result<bool, String> {
+    let path = sidecar_path(object);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(format!("couldn't read {}: {}", path.display(), e)),
+    };
+
+    let mut checked = 0;
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (algorithm, expected) = line.split_once(char::is_whitespace).ok_or_else(|| {
+            format!(
+                "{}: expected `ALGORITHM HEX`, got `{}`",
+                path.display(),
+                line
+            )
+        })?;
+        let actual = match algorithm {
+            "sha256" => sha256(bytes),
+            "crc32" => crc32(bytes),
+            _ => {
+                return Err(format!(
+                    "{}: unknown checksum `{}`",
+                    path.display(),
+                    algorithm
+                ))
+            }
+        };
+        let expected = expected.trim().to_ascii_lowercase();
+        if actual != expected {
+            return Err(format!(
+                "{} doesn't match {}: {} is {}, expected {}",
+                object.display(),
+                path.display(),
+                algorithm,
+                actual,
+                expected
+            ));
+        }
+        checked += 1;
+    }
+
+    if checked == 0 {
+        return Err(format!("{} contains no checksums", path.display()));
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An object of its own in the temp directory, removed with its sidecar when dropped
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str, bytes: &[u8]) -> Scratch {
+            let path =
+                std::env::temp_dir().join(format!("lc3_sim-{}-{}.obj", std::process::id(), name));
+            fs::write(&path, bytes).unwrap();
+            Scratch(path)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(sidecar_path(&self.0));
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    const OBJECT: &[u8] = &[0x30, 0x00, 0xF0, 0x25];
+
+    #[test]
+    fn a_signed_object_verifies() {
+        let object = Scratch::new("signed", OBJECT);
+        assert_eq!(verify(&object.0, OBJECT), Ok(false));
+        assert_eq!(sign(&object.0), Ok(sidecar_path(&object.0)));
+        let text = fs::read_to_string(sidecar_path(&object.0)).unwrap();
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.contains(&format!("crc32 {}\n", crc32(OBJECT))));
+        assert_eq!(verify(&object.0, OBJECT), Ok(true));
+    }
+
+    #[test]
+    fn a_changed_object_is_rejected() {
+        let object = Scratch::new("tampered", OBJECT);
+        sign(&object.0).unwrap();
+        // HALT patched to a TRAP x26
+        let tampered = [0x30, 0x00, 0xF0, 0x26];
+        let e = verify(&object.0, &tampered).unwrap_err();
+        assert!(e.contains("doesn't match") && e.contains("sha256"), "{}", e);
+
+        // a sidecar holding only a CRC still has to match
+        fs::write(
+            sidecar_path(&object.0),
+            format!("crc32 {}\n", crc32(OBJECT)),
+        )
+        .unwrap();
+        assert!(verify(&object.0, &tampered).unwrap_err().contains("crc32"));
+        fs::write(sidecar_path(&object.0), "\n").unwrap();
+        assert!(verify(&object.0, OBJECT).is_err());
+    }
+}
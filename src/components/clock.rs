@@ -0,0 +1,62 @@
+// Instruction rate limit (--clock-hz): the run is held back so it executes no more than the given number of
+// instructions a second, which puts busy-wait delays and the timer device on a human time scale. Sleeping for
+// every instruction would cost far more than the instructions, so the clock only sleeps once the run is a
+// millisecond ahead of where it should be.
+use std::time::{Duration, Instant};
+
+// how far ahead of time the run may get before it sleeps
+const AHEAD: Duration = Duration::from_millis(1);
+// a run further behind than this (it was waiting for a key, or stopped in the debugger) starts counting afresh
+// instead of racing to catch up
+const BEHIND: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    hz: u64,
+    start: Option<Instant>,
+    ticks: u64,
+}
+
+impl Clock {
+    pub fn new(hz: u64) -> Clock {
+        Clock {
+            hz: hz.max(1),
+            start: None,
+            ticks: 0,
+        }
+    }
+
+    pub fn hz(&self) -> u64 {
+        self.hz
+    }
+
+    // Wait until the next instruction is due
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        let start = *self.start.get_or_insert(now);
+        let due = start + Duration::from_secs_f64(self.ticks as f64 / self.hz as f64);
+        self.ticks += 1;
+        if due > now + AHEAD {
+            std::thread::sleep(due - now);
+        } else if now > due + BEHIND {
+            self.start = Some(now);
+            self.ticks = 1;
+        }
+    }
+
+    // Instructions skipped by idle-loop fast-forward: the next one is due once their time has passed
+    pub fn skip(&mut self, instructions: u64) {
+        self.ticks += instructions;
+    }
+}
+
+// Parse a `--clock-hz` argument: a positive number of instructions a second
+pub fn parse_hz(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(0) | Err(_) => Err(format!(
+            "expected a positive number of instructions a second, got `{}`",
+            s
+        )),
+        Ok(hz) => Ok(hz),
+    }
+}
@@ -0,0 +1,282 @@
+// Guest console output. Every byte the guest prints goes through here so the ANSI policy is applied in one place.
+use super::capture::FrameCapture;
+use super::iodevice::{IoDevice, MemoryIo};
+use super::regions::MemoryMap;
+use std::io;
+use std::str::FromStr;
+
+const ESC: u8 = 0x1B;
+
+// What to do with ANSI escape sequences produced by the guest program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnsiPolicy {
+    Pass,   // write sequences untouched, guest can draw on the terminal (needs raw mode)
+    Strip,  // drop whole sequences, leaving only printable output
+    Escape, // show the escape byte visibly as `^[` so sequences can be inspected
+}
+
+impl FromStr for AnsiPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pass" => Ok(AnsiPolicy::Pass),
+            "strip" => Ok(AnsiPolicy::Strip),
+            "escape" => Ok(AnsiPolicy::Escape),
+            _ => Err(format!(
+                "unknown ANSI policy `{}` (expected pass, strip or escape)",
+                s
+            )),
+        }
+    }
+}
+
+// Raised when the device can't take the program's output for a reason other than its reader going away
+#[derive(Debug)]
+pub struct OutputError {
+    pub pc: u16,
+    pub error: io::Error,
+}
+
+impl OutputError {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        format!("couldn't write the output of the instruction at {}: {}", map.at(self.pc), self.error)
+    }
+}
+
+// Where we are inside an escape sequence, needed to strip sequences split across TRAP calls
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EscapeState {
+    Normal,
+    Esc, // seen ESC
+    Csi, // seen ESC [ — parameters until a final byte in 0x40..=0x7E
+}
+
+pub struct Console {
+    pub policy: AnsiPolicy,
+    pub capture: Option<FrameCapture>,
+    pub transcript: Option<Vec<u8>>, // everything written, kept when a report needs it
+    pub echo: bool, // write to the device; off for headless runs that only keep a transcript
+    pub io: Box<dyn IoDevice>, // where keystrokes come from and output goes, the terminal unless replaced
+    pub recording: Option<Vec<u8>>, // every keystroke the guest read, kept for --bundle
+    // the write to the device that failed, until the run picks it up; nothing is written to it after one
+    pub output_error: Option<io::Error>,
+    closed: bool,
+    state: EscapeState,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The terminal when the crate is built with it, otherwise an empty in-memory device for the host to replace
+#[cfg(feature = "terminal")]
+fn default_io() -> Box<dyn IoDevice> {
+    Box::new(super::iodevice::TerminalIo::new())
+}
+
+#[cfg(not(feature = "terminal"))]
+fn default_io() -> Box<dyn IoDevice> {
+    Box::new(MemoryIo::default())
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            policy: AnsiPolicy::Pass,
+            capture: None,
+            transcript: None,
+            echo: true,
+            io: default_io(),
+            recording: None,
+            output_error: None,
+            closed: false,
+            state: EscapeState::Normal,
+        }
+    }
+
+    pub fn write_byte(&mut self, byte: u8) {
+        let mut buffer = [0; 2];
+        let bytes = self.filter(byte, &mut buffer);
+        if self.echo && !self.closed {
+            if let Err(e) = bytes.iter().try_for_each(|&b| self.io.write_char(b)) {
+                self.close(e);
+            }
+        }
+        if let Some(capture) = self.capture.as_mut() {
+            bytes.iter().for_each(|&b| capture.record(b));
+        }
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.extend_from_slice(bytes);
+        }
+    }
+
+    // Forget what the last program printed and typed, keeping the settings and the device
+    pub fn clear(&mut self) {
+        if let Some(transcript) = self.transcript.as_mut() {
+            transcript.clear();
+        }
+        if let Some(recording) = self.recording.as_mut() {
+            recording.clear();
+        }
+        self.state = EscapeState::Normal;
+    }
+
+    // The guest is about to block on input
+    pub fn prompt(&mut self) {
+        self.flush();
+        if let Some(capture) = self.capture.as_mut() {
+            capture.prompt();
+        }
+    }
+
+    pub fn write_str(&mut self, text: &str) {
+        text.bytes().for_each(|b| self.write_byte(b));
+    }
+
+    // Screen control for the cursor traps. The sequences go through write_byte like any guest output,
+    // so the ANSI policy, frame capture and transcript all see them.
+    pub fn clear_screen(&mut self) {
+        self.write_str("\x1B[2J\x1B[1;1H");
+    }
+
+    // Zero-based row and column, like the terminal size the guest gets back
+    pub fn move_cursor(&mut self, row: u16, column: u16) {
+        self.write_str(&format!("\x1B[{};{}H", row as u32 + 1, column as u32 + 1));
+    }
+
+    // (rows, columns), 24x80 when there is no terminal to ask
+    pub fn size(&self) -> (u16, u16) {
+        match self.io.size() {
+            Some((rows, columns)) if self.echo && rows > 0 && columns > 0 => (rows, columns),
+            _ => (24, 80),
+        }
+    }
+
+    // Queue keystrokes from the host, switching the console over to queued input if it was reading the keyboard
+    pub fn push_input(&mut self, keys: &[u8]) {
+        self.io.queue(keys);
+    }
+
+    // Whether a GETC/IN would have to wait: queued input that has run dry
+    pub fn input_exhausted(&self) -> bool {
+        self.io.queued().is_some_and(|input| input.is_empty())
+    }
+
+    // Whether reading a whole line would have to wait: queued input without a line ending
+    pub fn line_pending(&self) -> bool {
+        self.io
+            .queued()
+            .is_some_and(|input| !input.iter().any(|&b| b == b'\n' || b == b'\r'))
+    }
+
+    // Whether a keystroke is waiting to be read, without waiting for one
+    pub fn key_ready(&mut self) -> bool {
+        self.io.key_ready()
+    }
+
+    // Next keystroke; x00 once queued input is used up
+    pub fn read_byte(&mut self) -> u8 {
+        let key = self.io.read_char().unwrap_or(0);
+        if let Some(recording) = self.recording.as_mut() {
+            recording.push(key);
+        }
+        key
+    }
+
+    pub fn flush(&mut self) {
+        if self.echo && !self.closed {
+            if let Err(e) = self.io.flush() {
+                self.close(e);
+            }
+        }
+    }
+
+    fn close(&mut self, error: io::Error) {
+        self.closed = true;
+        self.output_error = Some(error);
+    }
+
+    // The in-memory device, when the console is using one, e.g. to read back what the guest printed
+    pub fn memory(&self) -> Option<&MemoryIo> {
+        self.io.as_any().downcast_ref()
+    }
+
+    pub fn memory_mut(&mut self) -> Option<&mut MemoryIo> {
+        self.io.as_any_mut().downcast_mut()
+    }
+
+    // Apply the policy to one byte, returning what should actually be written
+    fn filter<'a>(&mut self, byte: u8, buffer: &'a mut [u8; 2]) -> &'a [u8] {
+        match self.policy {
+            AnsiPolicy::Pass => {
+                buffer[0] = byte;
+                &buffer[..1]
+            }
+            AnsiPolicy::Escape => {
+                if byte == ESC {
+                    *buffer = *b"^[";
+                    &buffer[..2]
+                } else {
+                    buffer[0] = byte;
+                    &buffer[..1]
+                }
+            }
+            AnsiPolicy::Strip => {
+                let (state, keep) = match (self.state, byte) {
+                    (EscapeState::Normal, ESC) => (EscapeState::Esc, false),
+                    (EscapeState::Normal, _) => (EscapeState::Normal, true),
+                    (EscapeState::Esc, b'[') => (EscapeState::Csi, false),
+                    // two-byte sequences such as ESC c
+                    (EscapeState::Esc, _) => (EscapeState::Normal, false),
+                    (EscapeState::Csi, 0x40..=0x7E) => (EscapeState::Normal, false),
+                    (EscapeState::Csi, _) => (EscapeState::Csi, false),
+                };
+                self.state = state;
+                buffer[0] = byte;
+                if keep {
+                    &buffer[..1]
+                } else {
+                    &buffer[..0]
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the policy lets through when the parts are written one after another
+    fn filtered(policy: AnsiPolicy, parts: &[&str]) -> Vec<u8> {
+        let mut console = Console::new();
+        console.policy = policy;
+        let mut out = Vec::new();
+        for byte in parts.iter().flat_map(|part| part.bytes()) {
+            let mut buffer = [0; 2];
+            out.extend_from_slice(console.filter(byte, &mut buffer));
+        }
+        out
+    }
+
+    // the way a program prints with two PUTS calls, the colour sequence cut in half between them
+    const SPLIT: [&str; 2] = ["Score: \x1B[3", "1m42\x1B[0m\n\x1Bc"];
+
+    #[test]
+    fn strip_drops_a_sequence_split_across_writes() {
+        assert_eq!(filtered(AnsiPolicy::Strip, &SPLIT), b"Score: 42\n");
+    }
+
+    #[test]
+    fn pass_leaves_every_byte_alone() {
+        assert_eq!(filtered(AnsiPolicy::Pass, &SPLIT), SPLIT.concat().into_bytes());
+    }
+
+    #[test]
+    fn escape_shows_the_escape_byte() {
+        assert_eq!(filtered(AnsiPolicy::Escape, &["\x1B[2J"]), b"^[[2J");
+    }
+}
@@ -0,0 +1,232 @@
+// Context-switch unit (--context-switch, and part of `--os scheduler`): hardware help for a thread library. The
+// supervisor keeps a table of thread contexts in memory, ten words a thread (R0-R7, PC, PSR), and works the unit
+// through three registers in the I/O page:
+//   offset x28  CSBASE  address of the context table
+//   offset x2A  CSCUR   number of the running thread, whose entry is CSBASE + 10 * CSCUR
+//   offset x2C  CSNEXT  store a thread number to switch to it; reads the pending number, or xFFFF with none
+//
+// The switch happens at the next RTI that returns to user mode. Once RTI has popped PC and PSR and gone back to
+// the user stack, the state it returned to is saved in the running thread's entry and the next thread's entry is
+// loaded in its place, with CSCUR following. So a trap or interrupt handler only picks a thread and returns, and
+// an RTI back into supervisor code (an interrupt over a trap routine) leaves the switch pending. The entries are
+// written by the unit, not stored by the program: no watches, and the debugger can't undo them. Each thread keeps
+// its own shadow call stack, so backtraces and budgets follow the thread that is running.
+//
+// The registers are the supervisor's. A store to one in user mode raises the privilege mode violation exception
+// instead, as RTI does.
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use lc3_device::{Field, RegisterInfo};
+
+use super::callstack::CallFrame;
+use super::interrupt::{self, Exception};
+use super::vm::VM;
+
+// words a thread takes in the context table
+pub const ENTRY_WORDS: u16 = 10;
+const NONE_PENDING: u16 = 0xFFFF;
+
+pub struct ContextSwitch {
+    // CSBASE's address; CSCUR and CSNEXT follow two words apart
+    address: u16,
+    // the table address to go back to on a reset
+    start_base: u16,
+    pub base: u16,
+    pub current: u16,
+    pub pending: Option<u16>,
+    // the shadow call stacks of the threads that aren't running
+    frames: HashMap<u16, Vec<CallFrame>>,
+}
+
+impl ContextSwitch {
+    pub fn new(address: u16, base: u16) -> ContextSwitch {
+        ContextSwitch {
+            address,
+            start_base: base,
+            base,
+            current: 0,
+            pending: None,
+            frames: HashMap::new(),
+        }
+    }
+
+    pub fn range(&self) -> RangeInclusive<u16> {
+        self.address..=self.address + 4
+    }
+
+    // The value of one of the unit's registers, or None for any other address
+    pub fn read(&self, address: u16) -> Option<u16> {
+        match address.checked_sub(self.address)? {
+            0 => Some(self.base),
+            2 => Some(self.current),
+            4 => Some(self.pending.unwrap_or(NONE_PENDING)),
+            _ => None,
+        }
+    }
+
+    // Set one of the unit's registers, as a store by the supervisor or an inspector editing it does
+    pub fn set(&mut self, address: u16, value: u16) {
+        match address.wrapping_sub(self.address) {
+            0 => self.base = value,
+            2 => self.current = value,
+            4 => self.pending = (value != NONE_PENDING).then_some(value),
+            _ => {}
+        }
+    }
+
+    // Back to thread 0 with nothing pending, when the machine is reset
+    pub fn reset(&mut self) {
+        self.base = self.start_base;
+        self.current = 0;
+        self.pending = None;
+        self.frames.clear();
+    }
+
+    // For the device inspectors
+    pub fn registers(&self) -> Vec<RegisterInfo> {
+        let register = |name: &str, offset: u16, field: &'static str| RegisterInfo {
+            name: name.to_string(),
+            address: self.address + offset,
+            fields: vec![Field::new(field, 15, 0)],
+        };
+        vec![
+            register("CSBASE", 0, "TABLE"),
+            register("CSCUR", 2, "THREAD"),
+            register("CSNEXT", 4, "THREAD"),
+        ]
+    }
+}
+
+// A store by the program to one of the unit's registers
+pub fn store(vm: &mut VM, address: u16, value: u16) {
+    if !vm.registers.supervisor {
+        interrupt::exception(vm, Exception::PrivilegeViolation);
+        return;
+    }
+    if let Some(unit) = vm.context.as_mut() {
+        unit.set(address, value);
+    }
+}
+
+// After RTI: make the pending switch if it went back to user mode
+pub fn returned(vm: &mut VM) {
+    if vm.registers.supervisor {
+        return;
+    }
+    let Some((base, current, next)) = vm.context.as_mut().and_then(|unit| {
+        let next = unit.pending.take()?;
+        Some((unit.base, unit.current, next))
+    }) else {
+        return;
+    };
+    let entry = |thread: u16| base.wrapping_add(thread.wrapping_mul(ENTRY_WORDS));
+
+    let saved = entry(current);
+    for r in 0..8 {
+        vm.memory[saved.wrapping_add(r) as usize] = vm.registers.get(r);
+    }
+    vm.memory[saved.wrapping_add(8) as usize] = vm.registers.pc;
+    vm.memory[saved.wrapping_add(9) as usize] = vm.registers.psr();
+    if let Some(cache) = vm.decode_cache.as_mut() {
+        for offset in 0..ENTRY_WORDS {
+            cache.invalidate(saved.wrapping_add(offset));
+        }
+    }
+
+    let loaded = entry(next);
+    let word = |offset: u16| vm.memory[loaded.wrapping_add(offset) as usize];
+    let (registers, pc, psr): (Vec<u16>, u16, u16) = ((0..8).map(word).collect(), word(8), word(9));
+    for (r, value) in registers.into_iter().enumerate() {
+        vm.registers.update(r as u16, value);
+    }
+    vm.registers.pc = pc;
+    vm.registers.set_psr(psr);
+
+    let steps = vm.call_stack.steps();
+    let Some(unit) = vm.context.as_mut() else {
+        return;
+    };
+    unit.frames.insert(current, vm.call_stack.frames().to_vec());
+    let frames = unit.frames.remove(&next).unwrap_or_default();
+    unit.current = next;
+    vm.call_stack.rewind(frames, steps);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v2;
+    use crate::components::vm::Fault;
+
+    // In supervisor mode, set the table up and ask for thread 1, then RTI into user code at USER. The switch
+    // saves that as thread 0 and runs thread 1, which records R0 and halts.
+    const SWITCH: &str = "
+            .ORIG x3000
+            LEA R0, TABLE
+            STI R0, CSBASE
+            AND R0, R0, #0
+            ADD R0, R0, #1
+            STI R0, CSNEXT
+            LD R0, PSR
+            ADD R6, R6, #-1
+            STR R0, R6, #0
+            LEA R0, USER
+            ADD R6, R6, #-1
+            STR R0, R6, #0
+            AND R0, R0, #0
+            RTI
+    USER    HALT
+    THREAD  ST R0, SEEN
+            {THREAD}
+            HALT
+    CSBASE  .FILL xFE28
+    CSNEXT  .FILL xFE2C
+    PSR     .FILL x8002
+    SEEN    .FILL #0
+    TABLE   .BLKW #10
+            .FILL #7
+            .BLKW #5
+            .FILL x5000
+            .FILL #0
+            .FILL THREAD
+            .FILL x8001
+            .END";
+
+    fn load(thread: &str) -> VM {
+        let source = SWITCH.replace("{THREAD}", thread);
+        let mut vm = VM::from_obj_bytes(&write_v2(&assemble(&source).unwrap())).unwrap();
+        vm.console.echo = false;
+        vm.registers.supervisor = true;
+        vm.registers.r6 = 0x3000;
+        vm.context = Some(ContextSwitch::new(0xFE28, 0));
+        vm
+    }
+
+    #[test]
+    fn rti_to_user_mode_switches_threads() {
+        let mut vm = load("");
+        vm.run().unwrap();
+        // thread 1 ran with its own registers
+        assert_eq!(vm.memory[0x3013], 7);
+        assert_eq!(vm.registers.r6, 0x5000);
+        let unit = vm.context.as_ref().unwrap();
+        assert_eq!((unit.current, unit.pending), (1, None));
+        // and thread 0 was saved as RTI left it: in user mode at USER, R6 back on the user stack
+        let table = 0x3014;
+        assert_eq!(vm.memory[table + 8], 0x300D);
+        assert_eq!(vm.memory[table + 9], 0x8002);
+        assert_eq!(vm.memory[table + 6], 0);
+    }
+
+    #[test]
+    fn the_registers_are_the_supervisors() {
+        let mut vm = load("STI R0, CSNEXT");
+        assert!(vm.run().is_err());
+        assert!(matches!(
+            &vm.fault,
+            Some(Fault::Exception(e)) if e.exception == Exception::PrivilegeViolation
+        ));
+    }
+}
@@ -0,0 +1,58 @@
+// Requests to a running VM from outside its run loop: another thread, a signal handler or a UI asking it to pause
+// or stop, or handing it a new set of breakpoints. The loop only looks every CONTROL_INTERVAL instructions, so a
+// request is answered within about a millisecond while instructions in between pay nothing for it.
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use super::vm::VM;
+
+// Instructions run between looks at the control flags
+pub const CONTROL_INTERVAL: u32 = 1024;
+
+#[derive(Default)]
+pub struct ControlFlags {
+    pause: AtomicBool,
+    stop: AtomicBool,
+    breakpoints_changed: AtomicBool,
+    breakpoints: Mutex<BTreeSet<u16>>,
+}
+
+impl ControlFlags {
+    pub fn new() -> ControlFlags {
+        ControlFlags::default()
+    }
+
+    // Stop at the next check; the run can be resumed. Answered once.
+    pub fn request_pause(&self) {
+        self.pause.store(true, Ordering::Relaxed);
+    }
+
+    // Stop at the next check and at every check after it
+    pub fn request_stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stop_requested(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    // Replace the VM's breakpoints at the next check
+    pub fn set_breakpoints(&self, breakpoints: BTreeSet<u16>) {
+        *self.breakpoints.lock().unwrap() = breakpoints;
+        self.breakpoints_changed.store(true, Ordering::Release);
+    }
+}
+
+// Called by the run loop between intervals. A pause or stop leaves the run stopped at the current PC.
+pub fn check(vm: &mut VM) {
+    let Some(control) = vm.control.clone() else {
+        return;
+    };
+    if control.breakpoints_changed.swap(false, Ordering::Acquire) {
+        vm.breakpoints = control.breakpoints.lock().unwrap().clone();
+    }
+    if control.stop_requested() || control.pause.swap(false, Ordering::Relaxed) {
+        vm.stopped_at = Some(vm.registers.pc);
+    }
+}
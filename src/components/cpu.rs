@@ -0,0 +1,97 @@
+// The processor core behind a trait, so an ISA variant (LC-3b, an experimental extension) can be another Cpu
+// sharing the same memory, devices, debugger and tooling. A Cpu holds no state of its own: it works on the VM's
+// registers and memory, which keeps checkpoints, resets and reports independent of which core is running.
+use super::decode;
+use super::instruction::{self, disassemble_at};
+use super::vm::{ExecState, VmError, VM};
+
+const REGISTER_NAMES: [&str; 11] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND", "PSR"];
+
+pub trait Cpu: Sync {
+    // The ISA profile name, as written in an object's ISA record
+    fn name(&self) -> &'static str;
+
+    // Read the instruction at PC and move PC past it
+    fn fetch(&self, vm: &mut VM) -> u16;
+
+    fn execute(&self, instruction: u16, vm: &mut VM) -> Result<ExecState, VmError>;
+
+    // One bare fetch and execute; `components::step` does the same with the run's instrumentation around it
+    fn step(&self, vm: &mut VM) -> Result<ExecState, VmError> {
+        let instruction = self.fetch(vm);
+        self.execute(instruction, vm)
+    }
+
+    // The register file as the tools show it, indexed the same way as `register`
+    fn register_names(&self) -> &'static [&'static str];
+
+    fn register(&self, vm: &VM, index: usize) -> u16;
+
+    fn set_register(&self, vm: &mut VM, index: usize, value: u16);
+
+    // One instruction as text, with PC-relative operands resolved against `address`
+    fn disassemble(&self, instruction: u16, address: u16) -> String;
+
+    // Which word of the VM's memory holds the instruction or data at `address`. The same on the word-addressed
+    // LC-3; on a byte-addressed core, the word the byte is in.
+    fn word_index(&self, address: u16) -> u16 {
+        address
+    }
+}
+
+pub struct Lc3;
+
+pub static LC3: Lc3 = Lc3;
+
+impl Cpu for Lc3 {
+    fn name(&self) -> &'static str {
+        "lc3"
+    }
+
+    fn fetch(&self, vm: &mut VM) -> u16 {
+        let pc = vm.registers.pc;
+        let instruction = vm.fetch(pc);
+        vm.registers.pc = pc.wrapping_add(1);
+        instruction
+    }
+
+    fn execute(&self, instruction: u16, vm: &mut VM) -> Result<ExecState, VmError> {
+        // fetch has moved the PC past it
+        let address = vm.registers.pc.wrapping_sub(1);
+        let decoded = match vm.decode_cache.as_mut() {
+            Some(cache) => cache.get(address, instruction),
+            None => decode::decode(instruction),
+        };
+        instruction::execute(decoded, vm)
+    }
+
+    fn register_names(&self) -> &'static [&'static str] {
+        &REGISTER_NAMES
+    }
+
+    fn register(&self, vm: &VM, index: usize) -> u16 {
+        vm.registers.get(index as u16)
+    }
+
+    fn set_register(&self, vm: &mut VM, index: usize, value: u16) {
+        vm.registers.update(index as u16, value);
+    }
+
+    fn disassemble(&self, instruction: u16, address: u16) -> String {
+        disassemble_at(instruction, address)
+    }
+}
+
+// Every core this build can run, by ISA profile name
+pub fn by_name(name: &str) -> Option<&'static dyn Cpu> {
+    match name {
+        "lc3" => Some(&LC3),
+        "lc3b" => Some(&super::lc3b::LC3B),
+        _ => None,
+    }
+}
+
+// `--isa NAME`
+pub fn parse_isa(s: &str) -> Result<&'static dyn Cpu, String> {
+    by_name(s).ok_or_else(|| format!("unknown ISA `{}` (expected lc3 or lc3b)", s))
+}
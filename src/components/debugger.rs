@@ -0,0 +1,811 @@
+// Commands for the interactive debugger (--debug): parsing a command line and rendering what it shows. The prompt
+// loop itself lives in the binary, which owns the terminal and has to hand it back to the guest while it runs.
+use std::fmt::Write as _;
+
+use super::checkpoint::{Change, Checkpoint};
+use super::equiv::parse_register;
+use super::query::{cell, resolve};
+use super::history;
+use super::info::{Topic, TOPICS};
+use super::vm::{ResetKind, VM};
+use super::watch::{parse_watch, Target, Watch};
+use super::{at_breakpoint, running};
+
+pub const HELP: &str = "\
+break ADDRESS     stop before the instruction at ADDRESS (an address like x3010, or a symbol); alias b
+delete ADDRESS    remove a breakpoint
+breakpoints       list breakpoints and watches
+watch ADDRESS     stop after any instruction that loads from or stores to ADDRESS; alias w
+watch TARGET when new OP VALUE
+                  stop after an instruction writes a value to TARGET (an address, MEM[ADDRESS] or a register Rn)
+                  that makes the condition true, e.g. watch R6 when new < x6000; OP is one of < <= > >= == !=, and
+                  without the when any write to MEM[ADDRESS] or Rn stops
+unwatch TARGET    remove the watches on an address or register
+step [N]          execute N instructions (default 1); alias s
+next [N]          like step, but run a subroutine call (JSR, JSRR or a TRAP through the table) to its return; alias n
+finish            run until the current subroutine returns; alias fin
+finish-handler    run until the trap, exception or interrupt handler the PC is in returns (its RTI); alias fh
+continue          run until a breakpoint, BKPT, HALT or fault; alias c
+reverse-step [N]  go back N instructions (default 1); alias rs
+undo              go back over everything the last step, next, finish or continue ran
+restart [warm]    start the program over: reload it into cleared memory, or with warm keep memory as it is
+backtrace         list the subroutine calls and handlers the PC is inside, innermost first; alias bt
+handler           show the trap, exception or interrupt handler the PC is in and the PC and PSR it saved
+regs              show the registers; alias r
+info TOPIC [json] show the registers, devices, breakpoints, symbols, images (the loaded images and memory regions)
+                  or stack; with json, as one JSON value
+mem ADDRESS [N]   show N words from ADDRESS (default 8); alias m
+whence [Rn]       show where a register's value came from (all eight without Rn), as a chain of the instructions that
+                  produced it
+whowrote ADDRESS  show which instruction last stored to ADDRESS and which last loaded from it, and at what step
+diff              show the memory words changed since the last diff (or the start of the session)
+note ADDRESS TEXT attach a note to ADDRESS, shown wherever the address is (dumps, diffs, watches)
+unnote ADDRESS    remove the note on ADDRESS
+notes             list the notes
+mark NAME         save the registers and memory as they are now as checkpoint NAME
+diff A B          show the registers and memory words that differ from checkpoint A to checkpoint B
+quit              end the session; alias q
+An empty line repeats the last step, next, finish or continue.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break(u16),
+    Delete(u16),
+    Breakpoints,
+    Watch(u16),
+    WatchValue(Watch),
+    Unwatch(Target),
+    Step(u32),
+    Next(u32),
+    Finish,
+    FinishHandler,
+    Continue,
+    ReverseStep(u32),
+    Undo,
+    Restart(ResetKind),
+    Backtrace,
+    Handler,
+    Regs,
+    Info(Topic, bool),
+    Mem(u16, u16),
+    WhoWrote(u16),
+    Whence(Option<u16>),
+    Diff,
+    Note(u16, String),
+    Unnote(u16),
+    Notes,
+    Mark(String),
+    DiffMarks(String, String),
+    Help,
+    Quit,
+}
+
+pub fn parse_command(line: &str, symbols: &[(u16, String)]) -> Result<Command, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let count = |word: Option<&&str>, default: u32| match word {
+        None => Ok(default),
+        Some(n) => n
+            .parse::<u32>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("`{}` isn't a positive count", n)),
+    };
+    let address = |word: Option<&&str>| match word {
+        None => Err("which address?".to_string()),
+        Some(target) => resolve(target, symbols),
+    };
+    match words.first().copied() {
+        Some("break" | "b") => Ok(Command::Break(address(words.get(1))?)),
+        Some("delete" | "d") => Ok(Command::Delete(address(words.get(1))?)),
+        Some("breakpoints") => Ok(Command::Breakpoints),
+        Some("watch" | "w") => watch(&words[1..], symbols),
+        Some("unwatch") => match words[1..] {
+            [target] => Ok(Command::Unwatch(watch_target(target, symbols)?)),
+            _ => Err("unwatch takes an address or register".to_string()),
+        },
+        Some("step" | "s") => Ok(Command::Step(count(words.get(1), 1)?)),
+        Some("next" | "n") => Ok(Command::Next(count(words.get(1), 1)?)),
+        Some("finish" | "fin") => Ok(Command::Finish),
+        Some("finish-handler" | "fh") => Ok(Command::FinishHandler),
+        Some("continue" | "c") => Ok(Command::Continue),
+        Some("reverse-step" | "rs") => Ok(Command::ReverseStep(count(words.get(1), 1)?)),
+        Some("undo" | "u") => Ok(Command::Undo),
+        Some("restart") => match words[1..] {
+            [] | ["cold"] => Ok(Command::Restart(ResetKind::Cold)),
+            ["warm"] => Ok(Command::Restart(ResetKind::Warm)),
+            _ => Err("restart takes cold or warm".to_string()),
+        },
+        Some("backtrace" | "bt") => Ok(Command::Backtrace),
+        Some("handler") => Ok(Command::Handler),
+        Some("regs" | "r") => Ok(Command::Regs),
+        Some("info" | "i") => match words[1..] {
+            [topic] => Ok(Command::Info(topic.parse()?, false)),
+            [topic, "json"] => Ok(Command::Info(topic.parse()?, true)),
+            _ => Err(format!("info takes a topic ({}) and optionally json", TOPICS)),
+        },
+        Some("mem" | "m") => {
+            let n = count(words.get(2), 8)?.min(u16::MAX as u32) as u16;
+            Ok(Command::Mem(address(words.get(1))?, n))
+        }
+        Some("whowrote") => Ok(Command::WhoWrote(address(words.get(1))?)),
+        Some("whence") => match words[1..] {
+            [] => Ok(Command::Whence(None)),
+            [r] => match r.strip_prefix(['R', 'r']).and_then(|n| n.parse::<u16>().ok()) {
+                Some(n) if n < 8 => Ok(Command::Whence(Some(n))),
+                _ => Err(format!("`{}` isn't a register", r)),
+            },
+            _ => Err("whence takes one register".to_string()),
+        },
+        Some("note") => match words[1..] {
+            [] | [_] => Err("note takes an address and the note".to_string()),
+            [target, ..] => Ok(Command::Note(resolve(target, symbols)?, words[2..].join(" "))),
+        },
+        Some("unnote") => Ok(Command::Unnote(address(words.get(1))?)),
+        Some("notes") => Ok(Command::Notes),
+        Some("mark") => match words[1..] {
+            [name] => Ok(Command::Mark(name.to_string())),
+            _ => Err("mark takes a checkpoint name".to_string()),
+        },
+        Some("diff") => match words[1..] {
+            [] => Ok(Command::Diff),
+            [a, b] => Ok(Command::DiffMarks(a.to_string(), b.to_string())),
+            _ => Err("diff takes no checkpoints or two".to_string()),
+        },
+        Some("help" | "h" | "?") => Ok(Command::Help),
+        Some("quit" | "q") => Ok(Command::Quit),
+        Some(other) => Err(format!("unknown command `{}` (try help)", other)),
+        None => Err("empty command".to_string()),
+    }
+}
+
+// `watch ADDRESS` stops on any load or store there. A register, `MEM[ADDRESS]` or a `when` condition makes it a
+// watch on the values written instead, the kind --watch sets (see watch.rs).
+fn watch(words: &[&str], symbols: &[(u16, String)]) -> Result<Command, String> {
+    let text = words.join(" ");
+    let (target, condition) = match text.split_once(" when ") {
+        Some((target, condition)) => (target, Some(condition)),
+        None => (text.as_str(), None),
+    };
+    if target.is_empty() {
+        return Err("which address?".to_string());
+    }
+    if parse_register(target).is_ok() || target.to_uppercase().starts_with("MEM[") {
+        return Ok(Command::WatchValue(parse_watch(&text)?));
+    }
+    let address = resolve(target, symbols)?;
+    match condition {
+        None => Ok(Command::Watch(address)),
+        Some(condition) => Ok(Command::WatchValue(parse_watch(&format!(
+            "MEM[x{:04X}] when {}",
+            address, condition
+        ))?)),
+    }
+}
+
+fn watch_target(target: &str, symbols: &[(u16, String)]) -> Result<Target, String> {
+    if let Ok(r) = parse_register(target) {
+        return Ok(Target::Register(r));
+    }
+    let address = match target
+        .strip_prefix("MEM[")
+        .or_else(|| target.strip_prefix("mem["))
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        Some(address) => address,
+        None => target,
+    };
+    Ok(Target::Memory(resolve(address, symbols)?))
+}
+
+// Drop the value watches on `target`, saying whether there were any
+pub fn unwatch(vm: &mut VM, target: Target) -> bool {
+    let before = vm.watches.len();
+    vm.watches.retain(|w| w.target != target);
+    vm.watches.len() != before
+}
+
+pub fn registers(vm: &VM) -> String {
+    let registers: Vec<String> = vm
+        .cpu
+        .register_names()
+        .iter()
+        .enumerate()
+        .map(|(i, name)| format!("{}=x{:04X}", name, vm.cpu.register(vm, i)))
+        .collect();
+    registers.join(" ")
+}
+
+// Words from `start`, eight to a line, read without going through devices. A line with notes on its words ends
+// with them: `x3000: x1234 ...  ; x3006 loop counter`.
+pub fn memory(vm: &VM, start: u16, count: u16) -> String {
+    let addresses: Vec<u16> = (start..=u16::MAX).take(count as usize).collect();
+    let mut lines = Vec::new();
+    for chunk in addresses.chunks(8) {
+        let mut line = format!("x{:04X}:", chunk[0]);
+        for &address in chunk {
+            let value = vm.memory.get(address as usize).copied().unwrap_or(0);
+            write!(line, " x{:04X}", value).unwrap();
+        }
+        let notes: Vec<String> = chunk
+            .iter()
+            .filter_map(|a| vm.regions.notes.get(a).map(|note| format!("x{:04X} {}", a, note)))
+            .collect();
+        if !notes.is_empty() {
+            write!(line, "  ; {}", notes.join(", ")).unwrap();
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+// Execute one instruction and, if it called a subroutine, the rest of the call. Breakpoints, watches and
+// anything else that stops a run stop this too.
+pub fn next(vm: &mut VM) {
+    let depth = vm.call_stack.frames().len();
+    run_while_deeper(vm, depth);
+}
+
+// Run until the innermost subroutine returns; false when the PC isn't inside one
+pub fn finish(vm: &mut VM) -> bool {
+    let depth = vm.call_stack.frames().len();
+    if depth == 0 {
+        return false;
+    }
+    run_while_deeper(vm, depth - 1);
+    true
+}
+
+// Run until the innermost handler returns; false when the PC isn't inside one
+pub fn finish_handler(vm: &mut VM) -> bool {
+    let Some((depth, _)) = vm.call_stack.handler() else {
+        return false;
+    };
+    run_while_deeper(vm, depth);
+    true
+}
+
+// At least one instruction, then on while the call stack is deeper than `depth`
+fn run_while_deeper(vm: &mut VM, depth: usize) {
+    let mut first = true;
+    while running(vm) && (first || vm.call_stack.frames().len() > depth) {
+        if !first && at_breakpoint(vm) {
+            return;
+        }
+        first = false;
+        if vm.step().is_err() {
+            return;
+        }
+    }
+}
+
+// Go back `count` instructions, saying how far it got
+pub fn reverse(vm: &mut VM, count: usize) -> String {
+    let undone = history::undo(vm, count);
+    let note = if undone == count {
+        String::new()
+    } else if vm.instructions_executed() == 0 {
+        " to the start of the run".to_string()
+    } else {
+        format!(
+            " (no further history; the last {} instructions are kept)",
+            history::CAPACITY
+        )
+    };
+    let plural = if undone == 1 { "" } else { "s" };
+    format!(
+        "back {} instruction{}{}, next {}",
+        undone,
+        plural,
+        note,
+        location(vm)
+    )
+}
+
+// The calls the PC is inside, innermost first, e.g.
+//   #0  x3012 in PRINT
+//   #1  x3003
+// Each line has where that frame is now: the PC, then the call site each frame returns to.
+pub fn backtrace(vm: &VM, symbols: &[(u16, String)]) -> String {
+    let name = |entry: u16| {
+        symbols
+            .iter()
+            .find(|(address, _)| *address == entry)
+            .map_or(format!("x{:04X}", entry), |(_, name)| name.clone())
+    };
+    let frames = vm.call_stack.frames();
+    let mut lines = Vec::new();
+    let mut at = vm.registers.pc;
+    for (n, frame) in frames.iter().rev().enumerate() {
+        let mut line = format!("#{:<2} x{:04X} in {}", n, at, name(frame.entry));
+        if let Some(handler) = frame.handler {
+            write!(line, ", {} handler", handler.cause.describe()).unwrap();
+        }
+        lines.push(line);
+        at = frame.call_site;
+    }
+    lines.push(format!("#{:<2} x{:04X}", frames.len(), at));
+    lines.join("\n")
+}
+
+// The innermost handler frame: what entered it, and the PC and PSR it will return to, as saved on the supervisor
+// stack (or the PC in R7 for a trap returning with RET)
+pub fn handler(vm: &VM, symbols: &[(u16, String)]) -> String {
+    let Some((_, frame)) = vm.call_stack.handler() else {
+        return "not in a handler".to_string();
+    };
+    let handler = frame.handler.expect("a handler frame");
+    let mut out = format!(
+        "in the {} handler at {}, entered from x{:04X}",
+        handler.cause.describe(),
+        symbolic(frame.entry, symbols),
+        frame.call_site
+    );
+    match handler.saved_at {
+        Some(sp) => write!(
+            out,
+            "; saved at x{:04X} on the supervisor stack: PC x{:04X}, PSR x{:04X}",
+            sp,
+            vm.memory[sp as usize],
+            vm.memory[sp.wrapping_add(1) as usize]
+        )
+        .unwrap(),
+        None => write!(out, "; returns to x{:04X} (in R7 when it was entered)", frame.return_address).unwrap(),
+    }
+    out
+}
+
+// Each word that differs from the snapshot `before`, as `x4000: x0000 -> x0007`. Device registers aren't in memory,
+// so they never show up.
+pub fn diff(before: &[u16], vm: &VM) -> String {
+    let changed: Vec<String> = before
+        .iter()
+        .zip(vm.memory.iter())
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(address, (old, new))| {
+            format!("x{:04X}{}: x{:04X} -> x{:04X}", address, note(vm, address as u16), old, new)
+        })
+        .collect();
+    if changed.is_empty() {
+        "no changes".to_string()
+    } else {
+        changed.join("\n")
+    }
+}
+
+// From the access log the session keeps, so it covers only what ran since loading (or the last restart)
+pub fn who_wrote(vm: &VM, address: u16, symbols: &[(u16, String)]) -> String {
+    cell(vm, symbols, address).who(vm)
+}
+
+// Tracked from the start of the session (or the last restart); older values show as not written
+pub fn whence(vm: &VM, register: Option<u16>) -> String {
+    let Some(provenance) = &vm.provenance else {
+        return "register provenance isn't being tracked".to_string();
+    };
+    match register {
+        Some(r) => provenance.describe(r),
+        None => (0..8).map(|r| provenance.describe(r)).collect::<Vec<_>>().join("\n"),
+    }
+}
+
+// Save the machine as checkpoint `name`, replacing one of that name
+pub fn mark(vm: &mut VM, name: &str) -> String {
+    let checkpoint = Checkpoint::take(vm);
+    vm.marks.mark(name, checkpoint);
+    format!("checkpoint {} at x{:04X}", name, vm.registers.pc)
+}
+
+// What changed from checkpoint `a` to `b`, one register or word a line, with addresses named from `symbols`:
+//   R0: x0000 -> x0002
+//   x3009 SAVE: x0000 -> x3001
+pub fn diff_marks(vm: &VM, a: &str, b: &str, symbols: &[(u16, String)]) -> Result<String, String> {
+    let changes = vm.marks.diff(a, b)?;
+    if changes.is_empty() {
+        return Ok("no changes".to_string());
+    }
+    let lines: Vec<String> = changes
+        .iter()
+        .map(|change| match *change {
+            Change::Memory { address, old, new } => {
+                format!("{}{}: x{:04X} -> x{:04X}", symbolic(address, symbols), note(vm, address), old, new)
+            }
+            Change::Register { .. } => change.describe(&vm.regions),
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+// An address with the nearest symbol at or before it, e.g. `x300A SAVE` or `x4003 ARRAY+3`
+fn symbolic(address: u16, symbols: &[(u16, String)]) -> String {
+    let nearest = symbols
+        .iter()
+        .filter(|(at, _)| *at <= address)
+        .max_by_key(|(at, _)| *at);
+    match nearest {
+        Some((at, name)) if *at == address => format!("x{:04X} {}", address, name),
+        Some((at, name)) => format!("x{:04X} {}+{}", address, name, address - at),
+        None => format!("x{:04X}", address),
+    }
+}
+
+// ` [loop counter]` for an address with a note, nothing otherwise
+fn note(vm: &VM, address: u16) -> String {
+    vm.regions
+        .notes
+        .get(&address)
+        .map_or(String::new(), |note| format!(" [{}]", note))
+}
+
+pub fn notes(vm: &VM, symbols: &[(u16, String)]) -> String {
+    if vm.regions.notes.is_empty() {
+        return "no notes".to_string();
+    }
+    let lines: Vec<String> = vm
+        .regions
+        .notes
+        .iter()
+        .map(|(&address, note)| format!("{}: {}", symbolic(address, symbols), note))
+        .collect();
+    lines.join("\n")
+}
+
+// A session file: the breakpoints, watches and notes as the commands that set them, one a line, for
+// --session to read back at the start of the next session
+pub fn session(vm: &VM, access_watches: &[u16]) -> String {
+    let mut out = String::from("# lc3_sim debugger session\n");
+    for address in &vm.breakpoints {
+        writeln!(out, "break x{:04X}", address).unwrap();
+    }
+    for address in access_watches {
+        writeln!(out, "watch x{:04X}", address).unwrap();
+    }
+    for watch in &vm.watches {
+        writeln!(out, "watch {}", watch).unwrap();
+    }
+    for (address, note) in &vm.regions.notes {
+        writeln!(out, "note x{:04X} {}", address, note).unwrap();
+    }
+    out
+}
+
+// The instruction about to run, e.g. `x3010: ADD R1, R1, #-1`
+pub fn location(vm: &VM) -> String {
+    let pc = vm.registers.pc;
+    let instruction = vm.memory[vm.cpu.word_index(pc) as usize];
+    format!("x{:04X}: {}", pc, vm.cpu.disassemble(instruction, pc))
+}
+
+// Back to the start of the program; the breakpoints, watches and checkpoints stay
+pub fn restart(vm: &mut VM, kind: ResetKind) -> String {
+    vm.reset(kind);
+    let how = match kind {
+        ResetKind::Warm => " (memory kept)",
+        _ => "",
+    };
+    format!("restarted{}, next {}", how, location(vm))
+}
+
+// Why the last step or continue came back
+pub fn stop_reason(vm: &VM) -> String {
+    if let Some(fault) = &vm.fault {
+        fault.describe(&vm.regions)
+    } else if let Some(hit) = &vm.watch_hit {
+        format!("{}, next {}", hit.describe(&vm.regions), location(vm))
+    } else if let Some(event) = &vm.paused_on {
+        format!("watch: {}, next {}", event.describe(&vm.regions), location(vm))
+    } else if vm.halted {
+        "program halted".to_string()
+    } else if let Some(address) = vm.stopped_at.filter(|&a| a != vm.registers.pc) {
+        format!("BKPT at x{:04X}, next {}", address, location(vm))
+    } else if vm.breakpoints.contains(&vm.registers.pc) {
+        format!("breakpoint at {}", location(vm))
+    } else {
+        location(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::access::AccessLog;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v2;
+    use crate::components::provenance::Provenance;
+    use crate::components::vm::ExecState;
+
+    // MAIN calls OUTER, which calls INNER twice
+    const CALLS: &str = "
+            .ORIG x3000
+    MAIN    JSR OUTER
+            HALT
+    OUTER   ST R7, SAVE
+            JSR INNER
+            JSR INNER
+            LD R7, SAVE
+            RET
+    INNER   ADD R0, R0, #1
+            RET
+    SAVE    .BLKW 1
+            .END";
+
+    #[test]
+    fn next_and_finish_run_whole_calls() {
+        let image = assemble(CALLS).unwrap();
+        let symbols = &image.metadata.symbols;
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+
+        next(&mut vm);
+        assert_eq!((vm.registers.pc, vm.registers.r0), (0x3001, 2));
+        assert!(vm.call_stack.frames().is_empty());
+        assert!(!finish(&mut vm));
+
+        // into OUTER, then INNER, then back out to OUTER
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        vm.step().unwrap();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(
+            backtrace(&vm, symbols),
+            "#0  x3007 in INNER\n#1  x3003 in OUTER\n#2  x3000"
+        );
+        assert!(finish(&mut vm));
+        assert_eq!((vm.registers.pc, vm.registers.r0), (0x3004, 1));
+
+        // a breakpoint inside the call stops a next
+        vm.breakpoints.insert(0x3008);
+        next(&mut vm);
+        assert_eq!(vm.registers.pc, 0x3008);
+        assert_eq!(vm.stopped_at, Some(0x3008));
+    }
+
+    #[test]
+    fn marks_diff_by_name() {
+        let image = assemble(CALLS).unwrap();
+        let symbols = &image.metadata.symbols;
+        assert_eq!(
+            parse_command("mark A", symbols),
+            Ok(Command::Mark("A".to_string()))
+        );
+        assert_eq!(
+            parse_command("diff A B", symbols),
+            Ok(Command::DiffMarks("A".to_string(), "B".to_string()))
+        );
+        assert_eq!(parse_command("diff", symbols), Ok(Command::Diff));
+        assert!(parse_command("diff A", symbols).is_err());
+        assert!(parse_command("mark", symbols).is_err());
+
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        assert_eq!(mark(&mut vm, "A"), "checkpoint A at x3000");
+        // into OUTER, past the ST R7, SAVE and the first call to INNER
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        mark(&mut vm, "B");
+        assert_eq!(
+            diff_marks(&vm, "A", "B", symbols).unwrap(),
+            "R0: x0000 -> x0001\n\
+             R7: x0000 -> x3004\n\
+             PC: x3000 -> x3004\n\
+             COND: x0002 -> x0001\n\
+             x3009 SAVE: x0000 -> x3001"
+        );
+        assert_eq!(diff_marks(&vm, "B", "B", symbols).unwrap(), "no changes");
+        assert!(diff_marks(&vm, "A", "C", symbols).is_err());
+        assert_eq!(symbolic(0x300A, symbols), "x300A SAVE+1");
+    }
+
+    #[test]
+    fn restart_starts_the_program_over() {
+        let image = assemble(CALLS).unwrap();
+        let symbols = &image.metadata.symbols;
+        assert_eq!(parse_command("restart", symbols), Ok(Command::Restart(ResetKind::Cold)));
+        assert_eq!(parse_command("restart warm", symbols), Ok(Command::Restart(ResetKind::Warm)));
+        assert!(parse_command("restart hot", symbols).is_err());
+
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        vm.breakpoints.insert(0x3004);
+        assert_eq!(vm.run(), Ok(ExecState::Running));
+        // OUTER has saved R7 by now
+        assert_eq!(vm.memory[0x3009], 0x3001);
+
+        assert_eq!(restart(&mut vm, ResetKind::Warm), "restarted (memory kept), next x3000: JSR x3002");
+        assert_eq!((vm.registers.r0, vm.memory[0x3009]), (0, 0x3001));
+        assert!(vm.call_stack.frames().is_empty());
+
+        vm.run().unwrap();
+        assert_eq!(restart(&mut vm, ResetKind::Cold), "restarted, next x3000: JSR x3002");
+        assert_eq!(vm.memory[0x3009], 0);
+        assert!(vm.breakpoints.contains(&0x3004));
+    }
+
+    #[test]
+    fn whowrote_names_the_last_store() {
+        let image = assemble(CALLS).unwrap();
+        let symbols = &image.metadata.symbols;
+        assert_eq!(parse_command("whowrote SAVE", symbols), Ok(Command::WhoWrote(0x3009)));
+        assert!(parse_command("whowrote", symbols).is_err());
+
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        vm.access = Some(AccessLog::new());
+        assert_eq!(
+            who_wrote(&vm, 0x3009, symbols),
+            "x3009 (SAVE) = x0000: not written since loading; not read since loading"
+        );
+        vm.breakpoints.insert(0x3005);
+        vm.run().unwrap();
+        assert_eq!(
+            who_wrote(&vm, 0x3009, symbols),
+            "x3009 (SAVE) = x3001: last written by x3002 `ST R7, x3009` at step 2; not read since loading"
+        );
+
+        // a restart starts the log over along with the step count
+        restart(&mut vm, ResetKind::Warm);
+        assert!(who_wrote(&vm, 0x3009, symbols).contains("not written since loading"));
+    }
+
+    #[test]
+    fn a_watch_stops_at_the_prompt() {
+        let image = assemble(CALLS).unwrap();
+        let symbols = &image.metadata.symbols;
+        assert_eq!(parse_command("watch SAVE", symbols), Ok(Command::Watch(0x3009)));
+        assert_eq!(
+            parse_command("watch R6 when new < x6000", symbols),
+            Ok(Command::WatchValue(parse_watch("R6 when new < x6000").unwrap()))
+        );
+        assert_eq!(
+            parse_command("watch SAVE when new == x3001", symbols),
+            Ok(Command::WatchValue(parse_watch("MEM[x3009] when new == x3001").unwrap()))
+        );
+        assert_eq!(
+            parse_command("watch mem[x3009]", symbols),
+            Ok(Command::WatchValue(parse_watch("mem[x3009]").unwrap()))
+        );
+        assert!(parse_command("watch SAVE when new ~ 1", symbols).is_err());
+        assert_eq!(parse_command("unwatch r6", symbols), Ok(Command::Unwatch(Target::Register(6))));
+        assert_eq!(parse_command("unwatch MEM[SAVE]", symbols), Ok(Command::Unwatch(Target::Memory(0x3009))));
+
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        vm.pause_on_watch = true;
+        vm.watches.push(parse_watch("MEM[x3009] when new == x3001").unwrap());
+        vm.watches.push(parse_watch("R0 when new > 1").unwrap());
+        assert_eq!(vm.run(), Ok(ExecState::Running));
+        assert_eq!(
+            stop_reason(&vm),
+            "watch `MEM[x3009] when new == x3001` triggered by the instruction at x3002: x0000 -> x3001, \
+             next x3003: JSR x3007"
+        );
+
+        // going on stops again at the next one, and the program still finishes
+        vm.stopped_at = None;
+        vm.run().unwrap();
+        assert_eq!(
+            stop_reason(&vm),
+            "watch `R0 when new > 1` triggered by the instruction at x3007: x0001 -> x0002, next x3008: RET"
+        );
+        assert!(unwatch(&mut vm, Target::Register(0)));
+        assert!(!unwatch(&mut vm, Target::Register(0)));
+        vm.stopped_at = None;
+        vm.run().unwrap();
+        assert!(vm.halted && vm.fault.is_none());
+    }
+
+    #[test]
+    fn handler_frames_show_what_was_saved() {
+        let image = assemble(
+            "
+            .ORIG x3000
+            LD R6, USP
+            .FILL xD000
+            ADD R2, R1, #0
+            HALT
+    USP     .FILL xFE00
+    HANDLER ADD R1, R1, #1
+            RTI
+            .END",
+        )
+        .unwrap();
+        let symbols = &image.metadata.symbols;
+        assert_eq!(parse_command("fh", symbols), Ok(Command::FinishHandler));
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        vm.memory[0x0101] = 0x3005;
+        assert_eq!(handler(&vm, symbols), "not in a handler");
+
+        // the reserved opcode goes through the table to HANDLER
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(
+            backtrace(&vm, symbols),
+            "#0  x3005 in HANDLER, illegal opcode exception (x01) handler\n#1  x3001"
+        );
+        assert_eq!(
+            handler(&vm, symbols),
+            "in the illegal opcode exception (x01) handler at x3005 HANDLER, entered from x3001; \
+             saved at x2FFE on the supervisor stack: PC x3002, PSR x8004"
+        );
+        assert!(finish_handler(&mut vm));
+        assert_eq!((vm.registers.pc, vm.registers.r1), (0x3002, 1));
+        assert!(vm.call_stack.frames().is_empty());
+        assert!(!finish_handler(&mut vm));
+    }
+
+    #[test]
+    fn notes_show_in_dumps_and_diffs_and_save_with_the_session() {
+        let image = assemble(CALLS).unwrap();
+        let symbols = &image.metadata.symbols;
+        assert_eq!(
+            parse_command("note SAVE  R7 of OUTER", symbols),
+            Ok(Command::Note(0x3009, "R7 of OUTER".to_string()))
+        );
+        assert!(parse_command("note SAVE", symbols).is_err());
+        assert_eq!(parse_command("unnote SAVE", symbols), Ok(Command::Unnote(0x3009)));
+
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        assert_eq!(notes(&vm, symbols), "no notes");
+        vm.regions.annotate(0x3009, "R7 of OUTER");
+        let before = vm.memory.to_vec();
+        mark(&mut vm, "A");
+        vm.step().unwrap();
+        vm.step().unwrap();
+        mark(&mut vm, "B");
+
+        assert_eq!(notes(&vm, symbols), "x3009 SAVE: R7 of OUTER");
+        assert_eq!(
+            memory(&vm, 0x3008, 2),
+            "x3008: xC1C0 x3001  ; x3009 R7 of OUTER"
+        );
+        assert_eq!(diff(&before, &vm), "x3009 [R7 of OUTER]: x0000 -> x3001");
+        assert!(diff_marks(&vm, "A", "B", symbols)
+            .unwrap()
+            .ends_with("x3009 SAVE [R7 of OUTER]: x0000 -> x3001"));
+
+        vm.breakpoints.insert(0x3004);
+        vm.watches.push(parse_watch("R0 when new > 1").unwrap());
+        assert_eq!(
+            session(&vm, &[0x3009]),
+            "# lc3_sim debugger session\n\
+             break x3004\n\
+             watch x3009\n\
+             watch R0 when new > 1\n\
+             note x3009 R7 of OUTER\n"
+        );
+        // and each line reads back as the command it was
+        for line in session(&vm, &[0x3009]).lines().skip(1) {
+            assert!(parse_command(line, symbols).is_ok(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn whence_follows_a_register_back() {
+        let image = assemble(CALLS).unwrap();
+        let symbols = &image.metadata.symbols;
+        assert_eq!(parse_command("whence r7", symbols), Ok(Command::Whence(Some(7))));
+        assert_eq!(parse_command("whence", symbols), Ok(Command::Whence(None)));
+        assert!(parse_command("whence R8", symbols).is_err());
+        assert!(parse_command("whence SAVE", symbols).is_err());
+
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        assert_eq!(whence(&vm, Some(7)), "register provenance isn't being tracked");
+        vm.provenance = Some(Provenance::new());
+        // OUTER's LD R7, SAVE brings back what its ST R7, SAVE stored
+        vm.breakpoints.insert(0x3006);
+        vm.run().unwrap();
+        assert_eq!(
+            whence(&vm, Some(7)),
+            "R7 ← MEM[x3009] at x3005 (step 9) ← ST at x3002 (step 2) ← JSR at x3000 (step 1)"
+        );
+        assert_eq!(whence(&vm, None).lines().count(), 8);
+
+        restart(&mut vm, ResetKind::Warm);
+        assert_eq!(whence(&vm, Some(7)), "R7 ← (not written)");
+    }
+}
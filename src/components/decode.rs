@@ -0,0 +1,212 @@
+// Pre-decoded instructions. Splitting a word into its opcode and fields is done once per address and kept in a
+// cache, so a loop that runs millions of times doesn't pick the same bits apart on every trip. An entry remembers
+// the word it was decoded from and a store to the address drops it, so self-modifying code (or a debugger poke,
+// a reset, a restored checkpoint) is decoded again before it runs.
+use super::instruction::sign_extend;
+
+// An LC-3 instruction with its fields extracted and offsets sign-extended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Add { dr: u16, sr1: u16, operand: Operand },
+    And { dr: u16, sr1: u16, operand: Operand },
+    Not { dr: u16, sr: u16 },
+    // `mask` is the nzp bits
+    Br { mask: u16, offset: u16 },
+    Jmp { base: u16 },
+    Jsr { offset: u16 },
+    Jsrr { base: u16 },
+    Ld { dr: u16, offset: u16 },
+    Ldi { dr: u16, offset: u16 },
+    Ldr { dr: u16, base: u16, offset: u16 },
+    Lea { dr: u16, offset: u16 },
+    St { sr: u16, offset: u16 },
+    Sti { sr: u16, offset: u16 },
+    Str { sr: u16, base: u16, offset: u16 },
+    Trap { vector: u16 },
+    Rti,
+    Reserved,
+}
+
+// The second operand of ADD and AND
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u16),
+    Immediate(u16),
+}
+
+pub fn decode(word: u16) -> Instruction {
+    let dr = (word >> 9) & 0x7;
+    let sr1 = (word >> 6) & 0x7;
+    let offset6 = sign_extend(word & 0x3F, 6);
+    let offset9 = sign_extend(word & 0x1FF, 9);
+    let operand = || {
+        if (word >> 5) & 1 == 1 {
+            Operand::Immediate(sign_extend(word & 0x1F, 5))
+        } else {
+            Operand::Register(word & 0x7)
+        }
+    };
+    match word >> 12 {
+        0 => Instruction::Br {
+            mask: dr,
+            offset: offset9,
+        },
+        1 => Instruction::Add {
+            dr,
+            sr1,
+            operand: operand(),
+        },
+        2 => Instruction::Ld {
+            dr,
+            offset: offset9,
+        },
+        3 => Instruction::St {
+            sr: dr,
+            offset: offset9,
+        },
+        4 if (word >> 11) & 1 == 1 => Instruction::Jsr {
+            offset: sign_extend(word & 0x7FF, 11),
+        },
+        4 => Instruction::Jsrr { base: sr1 },
+        5 => Instruction::And {
+            dr,
+            sr1,
+            operand: operand(),
+        },
+        6 => Instruction::Ldr {
+            dr,
+            base: sr1,
+            offset: offset6,
+        },
+        7 => Instruction::Str {
+            sr: dr,
+            base: sr1,
+            offset: offset6,
+        },
+        8 => Instruction::Rti,
+        9 => Instruction::Not { dr, sr: sr1 },
+        10 => Instruction::Ldi {
+            dr,
+            offset: offset9,
+        },
+        11 => Instruction::Sti {
+            sr: dr,
+            offset: offset9,
+        },
+        12 => Instruction::Jmp { base: sr1 },
+        14 => Instruction::Lea {
+            dr,
+            offset: offset9,
+        },
+        15 => Instruction::Trap {
+            vector: word & 0xFF,
+        },
+        _ => Instruction::Reserved,
+    }
+}
+
+// words to a page of the cache
+const PAGE: usize = 256;
+
+type Slot = Option<(u16, Instruction)>;
+
+// Decoded instructions by address, each with the word it came from
+#[derive(Default)]
+pub struct DecodeCache {
+    // a page is only allocated once an instruction in it runs, so a small program costs a few pages
+    pages: Vec<Option<Box<[Slot; PAGE]>>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache::default()
+    }
+
+    // The instruction `word` at `address`, decoded now if it wasn't already
+    pub fn get(&mut self, address: u16, word: u16) -> Instruction {
+        if self.pages.is_empty() {
+            self.pages.resize_with(super::MEMORY_SIZE / PAGE, || None);
+        }
+        let page =
+            self.pages[address as usize / PAGE].get_or_insert_with(|| Box::new([None; PAGE]));
+        let slot = &mut page[address as usize % PAGE];
+        match *slot {
+            Some((cached, instruction)) if cached == word => {
+                self.hits += 1;
+                instruction
+            }
+            _ => {
+                self.misses += 1;
+                let instruction = decode(word);
+                *slot = Some((word, instruction));
+                instruction
+            }
+        }
+    }
+
+    // A store to `address`: whatever was decoded there is stale
+    pub fn invalidate(&mut self, address: u16) {
+        if let Some(Some(page)) = self.pages.get_mut(address as usize / PAGE) {
+            page[address as usize % PAGE] = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v2;
+    use crate::components::vm::{ExecState, VM};
+
+    #[test]
+    fn fields_are_extracted_and_sign_extended() {
+        assert_eq!(
+            decode(0x1261), // ADD R1, R1, #1
+            Instruction::Add {
+                dr: 1,
+                sr1: 1,
+                operand: Operand::Immediate(1)
+            }
+        );
+        assert_eq!(
+            decode(0x0BFD), // BRnp #-3
+            Instruction::Br {
+                mask: 0b101,
+                offset: 0xFFFD
+            }
+        );
+        assert_eq!(decode(0x4FFF), Instruction::Jsr { offset: 0xFFFF });
+        assert_eq!(decode(0xC1C0), Instruction::Jmp { base: 7 });
+        assert_eq!(decode(0xD000), Instruction::Reserved);
+    }
+
+    #[test]
+    fn a_store_over_a_cached_instruction_runs_the_new_one() {
+        // the loop's ADD is cached on the first trip, then overwritten with ADD R1, R1, #2
+        let image = assemble(
+            "
+            .ORIG x3000
+            AND R1, R1, #0
+            AND R2, R2, #0
+            ADD R2, R2, #2
+    LOOP    ADD R1, R1, #1
+            LD R3, PATCH
+            ST R3, LOOP
+            ADD R2, R2, #-1
+            BRp LOOP
+            HALT
+    PATCH   ADD R1, R1, #2
+            .END",
+        )
+        .unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.registers.r1, 3);
+        let cache = vm.decode_cache.as_ref().unwrap();
+        assert_eq!(cache.misses, 10);
+        assert_eq!(cache.hits, 4);
+    }
+}
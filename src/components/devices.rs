@@ -0,0 +1,439 @@
+// Devices built into the simulator and made available by name through the lc3_device registry.
+// Third-party device crates follow the same pattern: a `register` function adding their constructors, called
+// from register_all when the crate is enabled with a `device-<crate>` feature (see build.rs).
+use std::ops::RangeInclusive;
+
+use lc3_device::{Device, Field, Interrupt, RegisterInfo, Registry, Setup};
+
+use super::interrupt;
+use super::iopage;
+use super::parse_address;
+use super::rng::Rng;
+
+include!(concat!(env!("OUT_DIR"), "/device_plugins.rs"));
+
+// The built-in devices, then every device crate this build was compiled with
+pub fn register_all(registry: &mut Registry) {
+    register_builtin(registry);
+    register_plugins(registry);
+}
+
+pub fn register_builtin(registry: &mut Registry) {
+    registry.register("random", RandomDevice::create);
+    registry.register("timer", Timer::create);
+}
+
+// `random[:ADDRESS]` — every read of the register (default x20 into the I/O page, xFE20) returns a fresh
+// pseudo-random word
+pub struct RandomDevice {
+    address: u16,
+    rng: Rng,
+}
+
+impl RandomDevice {
+    fn create(args: &str, setup: &Setup) -> Result<Box<dyn Device>, String> {
+        let address = if args.is_empty() {
+            setup.io_page.start() + iopage::RANDOM
+        } else {
+            parse_address(args)?
+        };
+        Ok(Box::new(RandomDevice {
+            address,
+            rng: Rng::new(setup.seed),
+        }))
+    }
+}
+
+impl Device for RandomDevice {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        self.address..=self.address
+    }
+
+    fn read(&mut self, _address: u16) -> u16 {
+        self.rng.next_u16()
+    }
+
+    fn write(&mut self, _address: u16, _value: u16) {}
+
+    fn registers(&self) -> Vec<RegisterInfo> {
+        vec![RegisterInfo {
+            name: "RANDOM".to_string(),
+            address: self.address,
+            fields: vec![Field::new("VALUE", 15, 0)],
+        }]
+    }
+}
+
+// `timer[:ADDRESS]` — counts down once per instruction. TMCR (default x24 into the I/O page, xFE24) holds
+// DONE (bit 15), IE (bit 14) and ENABLE (bit 0); TMCNT two words on is the count. Storing to TMCNT sets both the
+// count and the period it reloads with; reaching zero sets DONE and reloads. With IE set, DONE interrupts through
+// vector x81 at priority 5 until the program stores to TMCR with bit 15 clear.
+pub struct Timer {
+    address: u16,
+    control: u16,
+    count: u16,
+    period: u16,
+}
+
+const TIMER_DONE: u16 = 1 << 15;
+const TIMER_IE: u16 = 1 << 14;
+const TIMER_ENABLE: u16 = 1;
+
+impl Timer {
+    fn create(args: &str, setup: &Setup) -> Result<Box<dyn Device>, String> {
+        let address = if args.is_empty() {
+            setup.io_page.start() + iopage::TIMER
+        } else {
+            parse_address(args)?
+        };
+        if address > u16::MAX - 2 {
+            return Err(format!("the timer needs two registers after x{:04X}", address));
+        }
+        Ok(Box::new(Timer {
+            address,
+            control: 0,
+            count: 0,
+            period: 0,
+        }))
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "timer"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        self.address..=self.address + 2
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        self.peek(address).unwrap_or(0)
+    }
+
+    fn write(&mut self, address: u16, value: u16) {
+        if address == self.address {
+            // DONE can only be cleared by the program, never set
+            let done = self.control & value & TIMER_DONE;
+            self.control = done | value & (TIMER_IE | TIMER_ENABLE);
+        } else if address == self.address + 2 {
+            self.count = value;
+            self.period = value;
+        }
+    }
+
+    fn registers(&self) -> Vec<RegisterInfo> {
+        vec![
+            RegisterInfo {
+                name: "TMCR".to_string(),
+                address: self.address,
+                fields: vec![
+                    Field::new("DONE", 15, 15),
+                    Field::new("IE", 14, 14),
+                    Field::new("ENABLE", 0, 0),
+                ],
+            },
+            RegisterInfo {
+                name: "TMCNT".to_string(),
+                address: self.address + 2,
+                fields: vec![Field::new("COUNT", 15, 0)],
+            },
+        ]
+    }
+
+    fn peek(&self, address: u16) -> Option<u16> {
+        if address == self.address {
+            Some(self.control)
+        } else if address == self.address + 2 {
+            Some(self.count)
+        } else {
+            Some(0)
+        }
+    }
+
+    // DONE can be set by hand too; the count is set like a store sets it, period and all
+    fn poke(&mut self, address: u16, value: u16) -> bool {
+        if address == self.address {
+            self.control = value & (TIMER_DONE | TIMER_IE | TIMER_ENABLE);
+        } else if address == self.address + 2 {
+            self.write(address, value);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn reset(&mut self) {
+        self.control = 0;
+        self.count = 0;
+        self.period = 0;
+    }
+
+    fn tick(&mut self) {
+        if self.control & TIMER_ENABLE == 0 || self.period == 0 {
+            return;
+        }
+        self.count -= 1;
+        if self.count == 0 {
+            self.control |= TIMER_DONE;
+            self.count = self.period;
+        }
+    }
+
+    fn next_event(&self) -> Option<u64> {
+        (self.control & TIMER_ENABLE != 0 && self.period != 0).then_some(self.count as u64)
+    }
+
+    fn skip(&mut self, instructions: u64) {
+        if self.control & TIMER_ENABLE == 0 || self.period == 0 {
+            return;
+        }
+        if instructions < self.count as u64 {
+            self.count -= instructions as u16;
+            return;
+        }
+        // ran out, and maybe reloaded and went round again
+        let past = (instructions - self.count as u64) % self.period as u64;
+        self.control |= TIMER_DONE;
+        self.count = self.period - past as u16;
+    }
+
+    fn interrupt(&self) -> Option<Interrupt> {
+        (self.control & (TIMER_DONE | TIMER_IE) == TIMER_DONE | TIMER_IE).then_some(Interrupt {
+            vector: interrupt::TIMER as u8,
+            priority: interrupt::TIMER_PRIORITY as u8,
+        })
+    }
+}
+
+// Read-only string table built from `--config-rom key=value` pairs, for OS images to query device-like settings
+// at boot. Entries are null-terminated `key=value` strings, one character per word like .STRINGZ, and an empty
+// string ends the table.
+pub struct ConfigRom {
+    range: RangeInclusive<u16>,
+    words: Vec<u16>,
+}
+
+impl ConfigRom {
+    // `io_base` is the start of the I/O page; the ROM takes offsets x40-xFF of it (xFE40-xFEFF by default)
+    pub fn new(entries: &[(String, String)], io_base: u16) -> Result<ConfigRom, String> {
+        let range = io_base + iopage::CONFIG_ROM.start()..=io_base + iopage::CONFIG_ROM.end();
+        let mut words: Vec<u16> = Vec::new();
+        for (key, value) in entries {
+            words.extend(key.bytes().chain(Some(b'=')).chain(value.bytes()).map(u16::from));
+            words.push(0);
+        }
+        words.push(0);
+        let capacity = range.len();
+        if words.len() > capacity {
+            return Err(format!(
+                "config ROM needs {} words but only {} fit at x{:04X}-x{:04X}",
+                words.len(),
+                capacity,
+                range.start(),
+                range.end()
+            ));
+        }
+        Ok(ConfigRom { range, words })
+    }
+}
+
+impl Device for ConfigRom {
+    fn name(&self) -> &str {
+        "config-rom"
+    }
+
+    fn range(&self) -> RangeInclusive<u16> {
+        self.range.clone()
+    }
+
+    fn read(&mut self, address: u16) -> u16 {
+        self.words
+            .get((address - self.range.start()) as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // it's a ROM: stores are ignored
+    fn write(&mut self, _address: u16, _value: u16) {}
+
+    fn peek(&self, address: u16) -> Option<u16> {
+        let offset = address.checked_sub(*self.range.start())?;
+        Some(self.words.get(offset as usize).copied().unwrap_or(0))
+    }
+}
+
+// Parse a `--config-rom` argument: KEY=VALUE
+pub fn parse_config_entry(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", s))?;
+    if key.is_empty() || !key.is_ascii() || !value.is_ascii() {
+        return Err(format!("config entries must be ASCII with a key, got `{}`", s));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v1;
+    use crate::components::vm::{ExecState, VM};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A counter going up once per read at xFE30, and a display that keeps what it's sent
+    struct Ticker(u16);
+
+    impl Device for Ticker {
+        fn name(&self) -> &str {
+            "ticker"
+        }
+
+        fn range(&self) -> RangeInclusive<u16> {
+            0xFE30..=0xFE30
+        }
+
+        fn read(&mut self, _address: u16) -> u16 {
+            self.0 += 1;
+            self.0
+        }
+
+        fn write(&mut self, _address: u16, value: u16) {
+            self.0 = value;
+        }
+    }
+
+    struct Display(Rc<RefCell<Vec<u16>>>);
+
+    impl Device for Display {
+        fn name(&self) -> &str {
+            "display"
+        }
+
+        fn range(&self) -> RangeInclusive<u16> {
+            0xFE04..=0xFE06
+        }
+
+        fn read(&mut self, _address: u16) -> u16 {
+            0x8000
+        }
+
+        fn write(&mut self, address: u16, value: u16) {
+            if address == 0xFE06 {
+                self.0.borrow_mut().push(value);
+            }
+        }
+    }
+
+    #[test]
+    fn attached_devices_answer_loads_and_stores() {
+        let image = assemble(
+            "
+            .ORIG x3000
+            AND R0, R0, #0
+            ADD R0, R0, #9
+            STI R0, TIMER
+            LDI R1, TIMER
+            LDI R2, TIMER
+            STI R2, DDR
+            HALT
+    TIMER   .FILL xFE30
+    DDR     .FILL xFE06
+            .END",
+        )
+        .unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        let shown = Rc::new(RefCell::new(Vec::new()));
+        vm.attach(Box::new(Ticker(0))).unwrap();
+        vm.attach(Box::new(Display(shown.clone()))).unwrap();
+        let clash = vm.attach(Box::new(Ticker(0))).unwrap_err();
+        assert_eq!(
+            clash,
+            "device `ticker` at xFE30-xFE30 overlaps `ticker` at xFE30-xFE30"
+        );
+
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!((vm.registers.r1, vm.registers.r2), (10, 11));
+        // the display took DDR over from the console
+        assert_eq!(*shown.borrow(), [11]);
+        assert_eq!(vm.console.transcript.as_deref(), Some(&[][..]));
+    }
+
+    #[test]
+    fn timer_interrupts_until_acknowledged() {
+        // count to 20 with interrupts on, spinning until the handler has run once
+        let image = assemble(
+            "
+            .ORIG x3000
+            LEA R0, ISR
+            STI R0, VECTOR
+            AND R0, R0, #0
+            ADD R0, R0, #10
+            ADD R0, R0, R0
+            STI R0, TMCNT
+            LD R0, START
+            STI R0, TMCR
+            AND R3, R3, #0
+    SPIN    ADD R3, R3, #0
+            BRz SPIN
+            HALT
+    ISR     ADD R3, R3, #1
+            AND R1, R1, #0
+            STI R1, TMCR
+            RTI
+    VECTOR  .FILL x0181
+    TMCR    .FILL xFE24
+    TMCNT   .FILL xFE26
+    START   .FILL x4001
+            .END",
+        )
+        .unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        vm.console.echo = false;
+        let setup = Setup {
+            seed: 0,
+            io_page: 0xFE00..=0xFFFF,
+        };
+        vm.attach(Timer::create("", &setup).unwrap()).unwrap();
+
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.registers.r3, 1);
+        let timer = &vm.devices[0];
+        // acknowledged and stopped by the handler, with the count part way through the next period
+        assert_eq!(timer.peek(0xFE24), Some(0));
+        assert!(timer.peek(0xFE26).unwrap() < 20);
+        // back in user mode at the priority the program ran at
+        assert_eq!(vm.registers.priority, 0);
+    }
+
+    #[test]
+    fn a_timer_skip_matches_ticking() {
+        let setup = Setup {
+            seed: 0,
+            io_page: 0xFE00..=0xFFFF,
+        };
+        for instructions in [0, 3, 6, 7, 20] {
+            let mut ticked = Timer::create("", &setup).unwrap();
+            let mut skipped = Timer::create("", &setup).unwrap();
+            for timer in [&mut ticked, &mut skipped] {
+                timer.write(0xFE26, 7);
+                timer.write(0xFE24, 1);
+            }
+            assert_eq!(skipped.next_event(), Some(7));
+            for _ in 0..instructions {
+                ticked.tick();
+            }
+            skipped.skip(instructions);
+            assert_eq!(ticked.peek(0xFE24), skipped.peek(0xFE24));
+            assert_eq!(ticked.peek(0xFE26), skipped.peek(0xFE26));
+        }
+    }
+}
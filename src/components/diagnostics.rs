@@ -0,0 +1,138 @@
+// The channel the simulator's own warnings go through. A warning repeated for the same category and PC is shown
+// once, a flood is cut to RATE_LIMIT printed warnings per RATE_WINDOW instructions, and whole categories can be
+// silenced with --no-warn. What was held back is counted and summarized at exit.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+// At most this many warnings are printed per window of instructions; later ones are still kept for reports
+pub const RATE_LIMIT: u32 = 10;
+pub const RATE_WINDOW: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Category {
+    // base+offset accesses that leave their region (see regions.rs)
+    Region,
+    // loads into the I/O page
+    Load,
+    // objects that look byte-swapped
+    Endian,
+}
+
+const CATEGORIES: [Category; 3] = [Category::Region, Category::Load, Category::Endian];
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Region => "region",
+            Category::Load => "load",
+            Category::Endian => "endian",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Category {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CATEGORIES
+            .into_iter()
+            .find(|c| c.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = CATEGORIES.iter().map(|c| c.name()).collect();
+                format!(
+                    "unknown warning category `{}` (expected one of {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+// What to do with a warning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Show,
+    // over the rate limit: kept for reports but not printed
+    Keep,
+    Drop,
+}
+
+#[derive(Default)]
+pub struct Diagnostics {
+    pub silenced: HashSet<Category>,
+    seen: HashSet<(Category, u16)>,
+    window: u64,
+    shown_in_window: u32,
+    repeated: u64,
+    rate_limited: u64,
+    // category -> warnings dropped because it was silenced
+    dropped: BTreeMap<Category, u64>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    // Start counting afresh for another program; silenced categories stay silenced
+    pub fn clear(&mut self) {
+        *self = Diagnostics {
+            silenced: std::mem::take(&mut self.silenced),
+            ..Diagnostics::default()
+        };
+    }
+
+    // Decide on a warning of this category raised at `pc` (or the load address, for loader warnings) during
+    // instruction `step`
+    pub fn admit(&mut self, category: Category, pc: u16, step: u64) -> Verdict {
+        if self.silenced.contains(&category) {
+            *self.dropped.entry(category).or_default() += 1;
+            return Verdict::Drop;
+        }
+        if !self.seen.insert((category, pc)) {
+            self.repeated += 1;
+            return Verdict::Drop;
+        }
+        if step / RATE_WINDOW != self.window {
+            self.window = step / RATE_WINDOW;
+            self.shown_in_window = 0;
+        }
+        if self.shown_in_window >= RATE_LIMIT {
+            self.rate_limited += 1;
+            return Verdict::Keep;
+        }
+        self.shown_in_window += 1;
+        Verdict::Show
+    }
+
+    // `warnings held back: 12 repeated, 30 over the rate limit, 5 silenced (region: 5)`, or None when nothing was
+    pub fn summary(&self) -> Option<String> {
+        let silenced: u64 = self.dropped.values().sum();
+        if self.repeated + self.rate_limited + silenced == 0 {
+            return None;
+        }
+        let mut parts = Vec::new();
+        if self.repeated > 0 {
+            parts.push(format!("{} repeated", self.repeated));
+        }
+        if self.rate_limited > 0 {
+            parts.push(format!("{} over the rate limit", self.rate_limited));
+        }
+        if silenced > 0 {
+            let counts: Vec<String> = self
+                .dropped
+                .iter()
+                .map(|(category, count)| format!("{}: {}", category, count))
+                .collect();
+            parts.push(format!("{} silenced ({})", silenced, counts.join(", ")));
+        }
+        Some(format!("warnings held back: {}", parts.join(", ")))
+    }
+}
@@ -0,0 +1,234 @@
+// Equivalence checking between two objects meant to implement the same contract. Both run headless on the
+// same inputs and their observable behavior (console output, chosen registers and memory) is compared.
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use super::iodevice::MemoryIo;
+use super::loader::{self, Endian};
+use super::parse_address;
+use super::rng::Rng;
+use super::vm::{ResetKind, VM};
+
+// A program that hasn't halted after this many instructions is treated as looping
+pub const STEP_LIMIT: u64 = 1_000_000;
+
+// One input: initial register values and the keystrokes the program will read
+#[derive(Debug, Clone, Default)]
+pub struct Case {
+    pub registers: Vec<(u16, u16)>,
+    pub input: Vec<u8>,
+    pub text: String,
+}
+
+// What counts as observable besides console output
+pub struct Contract {
+    pub registers: Vec<u16>,
+    pub regions: Vec<RangeInclusive<u16>>,
+}
+
+struct Observation {
+    output: Vec<u8>,
+    registers: Vec<u16>,
+    memory: Vec<Vec<u16>>,
+    ending: String,
+}
+
+// Parse one case: `R0=x5 R1=#-2 input=text`, where `input=` takes the rest of the line and understands `\n`
+pub fn parse_case(line: &str) -> Result<Case, String> {
+    let (assignments, input) = match line.split_once("input=") {
+        Some((before, input)) => (before, unescape(input)),
+        None => (line, Vec::new()),
+    };
+    let mut registers = Vec::new();
+    for field in assignments.split_whitespace() {
+        let (register, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("expected Rn=VALUE, got `{}`", field))?;
+        registers.push((parse_register(register)?, parse_word(value)?));
+    }
+    Ok(Case {
+        registers,
+        input,
+        text: line.trim().to_string(),
+    })
+}
+
+// A word written as an address would be, or negative: `-5`, `#-5`
+pub fn parse_word(s: &str) -> Result<u16, String> {
+    match s.strip_prefix('-').or_else(|| s.strip_prefix("#-")) {
+        Some(magnitude) => Ok(parse_address(magnitude)?.wrapping_neg()),
+        None => parse_address(s),
+    }
+}
+
+pub fn parse_register(s: &str) -> Result<u16, String> {
+    match s
+        .strip_prefix(['R', 'r'])
+        .and_then(|n| n.parse::<u16>().ok())
+    {
+        Some(n) if n < 8 => Ok(n),
+        _ => Err(format!("expected a register R0-R7, got `{}`", s)),
+    }
+}
+
+fn unescape(text: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut bytes = text.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'\\' => match bytes.next() {
+                Some(b'n') => out.push(b'\n'),
+                Some(b'r') => out.push(b'\r'),
+                Some(b't') => out.push(b'\t'),
+                Some(other) => out.push(other),
+                None => out.push(b'\\'),
+            },
+            b => out.push(b),
+        }
+    }
+    out
+}
+
+// One case per non-empty line; lines starting with `#` are comments
+pub fn load_cases(path: &Path) -> Result<Vec<Case>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            parse_case(line).map_err(|e| format!("{}:{}: {}", path.display(), i + 1, e))
+        })
+        .collect()
+}
+
+// Cases with random values in R0-R5, leaving the stack pointer and return address alone
+pub fn generate_cases(count: usize, rng: &mut Rng) -> Vec<Case> {
+    (0..count)
+        .map(|_| {
+            let registers: Vec<(u16, u16)> = (0..6).map(|r| (r, rng.next_u16())).collect();
+            let text = registers
+                .iter()
+                .map(|(r, v)| format!("R{}=x{:04X}", r, v))
+                .collect::<Vec<_>>()
+                .join(" ");
+            Case {
+                registers,
+                input: Vec::new(),
+                text,
+            }
+        })
+        .collect()
+}
+
+// A headless machine with the object loaded, reset before each case
+fn boot(path: &Path) -> Result<VM, String> {
+    let mut vm = VM::new();
+    vm.console.echo = false;
+    let image = loader::load_object(&mut vm, path, false, Endian::Big)?;
+    if let Some(entry) = image.metadata.entry {
+        vm.registers.pc = entry;
+    }
+    vm.record_start();
+    Ok(vm)
+}
+
+fn observe(vm: &mut VM, case: &Case, contract: &Contract) -> Observation {
+    vm.reset(ResetKind::Cold);
+    vm.console.transcript = Some(Vec::new());
+    vm.console.io = Box::new(MemoryIo::new(&case.input));
+    for &(register, value) in &case.registers {
+        vm.registers.update(register, value);
+    }
+
+    let mut steps = 0;
+    while super::running(vm) && steps < STEP_LIMIT {
+        steps += 1;
+        if super::step(vm).is_err() {
+            break;
+        }
+    }
+    let ending = match &vm.fault {
+        Some(fault) => fault.describe(&vm.regions),
+        None if vm.halted => "halted".to_string(),
+        None => format!("still running after {} instructions", STEP_LIMIT),
+    };
+
+    Observation {
+        output: vm.console.transcript.take().unwrap_or_default(),
+        registers: contract
+            .registers
+            .iter()
+            .map(|&r| vm.registers.get(r))
+            .collect(),
+        memory: contract
+            .regions
+            .iter()
+            .map(|range| vm.memory[*range.start() as usize..=*range.end() as usize].to_vec())
+            .collect(),
+        ending,
+    }
+}
+
+// Run every case on both objects, returning a description of the first case that tells them apart
+pub fn check(
+    a: &Path,
+    b: &Path,
+    cases: &[Case],
+    contract: &Contract,
+) -> Result<Option<String>, String> {
+    let (name_a, name_b) = (a.display(), b.display());
+    let (mut vm_a, mut vm_b) = (boot(a)?, boot(b)?);
+    for (i, case) in cases.iter().enumerate() {
+        let left = observe(&mut vm_a, case, contract);
+        let right = observe(&mut vm_b, case, contract);
+
+        let difference = if left.ending != right.ending {
+            Some(format!(
+                "{} {} but {} {}",
+                name_a, left.ending, name_b, right.ending
+            ))
+        } else if left.output != right.output {
+            Some(format!(
+                "{} printed {:?} but {} printed {:?}",
+                name_a,
+                String::from_utf8_lossy(&left.output),
+                name_b,
+                String::from_utf8_lossy(&right.output)
+            ))
+        } else if let Some(n) =
+            (0..left.registers.len()).find(|&n| left.registers[n] != right.registers[n])
+        {
+            Some(format!(
+                "R{} is x{:04X} in {} but x{:04X} in {}",
+                contract.registers[n], left.registers[n], name_a, right.registers[n], name_b
+            ))
+        } else {
+            contract
+                .regions
+                .iter()
+                .zip(left.memory.iter().zip(&right.memory))
+                .find_map(|(range, (l, r))| {
+                    let offset = (0..l.len()).find(|&o| l[o] != r[o])?;
+                    Some(format!(
+                        "x{:04X} is x{:04X} in {} but x{:04X} in {}",
+                        *range.start() as usize + offset,
+                        l[offset],
+                        name_a,
+                        r[offset],
+                        name_b
+                    ))
+                })
+        };
+
+        if let Some(difference) = difference {
+            return Ok(Some(format!(
+                "case {} `{}`: {}",
+                i + 1,
+                case.text,
+                difference
+            )));
+        }
+    }
+    Ok(None)
+}
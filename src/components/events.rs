@@ -0,0 +1,201 @@
+// Input event log: every value a device hands the program and every key the keyboard does, keyed to the
+// instruction that read it, so a run with devices whose answers can't be reproduced (hardware bridges, clocks,
+// anything outside --seed) or a user typing at it can be repeated exactly. Keys are logged as reads of KBDR,
+// whichever way the program took them (KBDR, GETC, IN, GETD or the keyboard interrupt), at the instruction that
+// took them, so a KBSR poll that found no key needs no entry. Replay answers each read from the log without
+// asking the device or the keyboard and stops the run if the program reads something the recording didn't,
+// since from there on the runs have diverged.
+//
+// The file is text: a header line, then `STEP ADDRESS VALUE` per event, e.g. `1042 xFE20 x5A3C`.
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use super::parse_address;
+use super::regions::MemoryMap;
+
+const HEADER: &str = "# lc3_sim input events v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub step: u64,
+    pub address: u16,
+    pub value: u16,
+}
+
+pub enum EventLog {
+    Record(Vec<Event>),
+    Replay(VecDeque<Event>),
+}
+
+// Raised when a replayed run makes a device read the recording doesn't have next
+#[derive(Debug)]
+pub struct Divergence {
+    pub step: u64,
+    pub address: u16,
+    pub expected: Option<Event>,
+}
+
+impl Divergence {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        let expected = match self.expected {
+            Some(e) => format!("a read of {} at step {}", map.at(e.address), e.step),
+            None => "no more device reads".to_string(),
+        };
+        format!(
+            "replay diverged: step {} read {}, but the recording has {}",
+            self.step,
+            map.at(self.address),
+            expected
+        )
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.describe(&MemoryMap::new()))
+    }
+}
+
+impl EventLog {
+    pub fn record() -> EventLog {
+        EventLog::Record(Vec::new())
+    }
+
+    pub fn load(path: &Path) -> Result<EventLog, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read events {}: {}", path.display(), e))?;
+        let mut events = VecDeque::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let event =
+                parse_event(line).map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e))?;
+            events.push_back(event);
+        }
+        Ok(EventLog::Replay(events))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let EventLog::Record(events) = self else {
+            return Ok(());
+        };
+        let mut out = format!("{}\n", HEADER);
+        for e in events {
+            writeln!(out, "{} x{:04X} x{:04X}", e.step, e.address, e.value).unwrap();
+        }
+        fs::write(path, out).map_err(|e| format!("couldn't write events {}: {}", path.display(), e))
+    }
+
+    pub fn replaying(&self) -> bool {
+        matches!(self, EventLog::Replay(_))
+    }
+
+    // Whether the next recorded read is this one, without taking it
+    pub fn has_next(&self, step: u64, address: u16) -> bool {
+        match self {
+            EventLog::Replay(events) => events
+                .front()
+                .is_some_and(|e| e.step == step && e.address == address),
+            EventLog::Record(_) => false,
+        }
+    }
+
+    // The recorded answer to a device read when replaying; None when the device should be asked
+    pub fn replay(&mut self, step: u64, address: u16) -> Option<Result<u16, Divergence>> {
+        let EventLog::Replay(events) = self else {
+            return None;
+        };
+        Some(match events.front() {
+            Some(e) if e.step == step && e.address == address => {
+                Ok(events.pop_front().unwrap().value)
+            }
+            expected => Err(Divergence {
+                step,
+                address,
+                expected: expected.copied(),
+            }),
+        })
+    }
+
+    pub fn observe(&mut self, step: u64, address: u16, value: u16) {
+        if let EventLog::Record(events) = self {
+            events.push(Event {
+                step,
+                address,
+                value,
+            });
+        }
+    }
+}
+
+fn parse_event(line: &str) -> Result<Event, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [step, address, value] = fields[..] else {
+        return Err(format!("expected STEP ADDRESS VALUE, got `{}`", line));
+    };
+    Ok(Event {
+        step: step.parse().map_err(|_| format!("bad step `{}`", step))?,
+        address: parse_address(address)?,
+        value: parse_address(value)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::iodevice::MemoryIo;
+    use crate::components::object::write_v1;
+    use crate::components::vm::{ExecState, VM};
+
+    fn machine(input: &[u8]) -> VM {
+        // a key by polling KBSR, then one from GETC
+        let image = assemble(
+            "
+            .ORIG x3000
+    POLL    LDI R1, KBSR
+            BRzp POLL
+            LDI R1, KBDR
+            GETC
+            HALT
+    KBSR    .FILL xFE00
+    KBDR    .FILL xFE02
+            .END",
+        )
+        .unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        vm.console.echo = false;
+        vm.console.io = Box::new(MemoryIo::new(input));
+        vm
+    }
+
+    #[test]
+    fn keys_replay_without_the_keyboard() {
+        let mut vm = machine(b"ab");
+        vm.events = Some(EventLog::record());
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        let Some(EventLog::Record(events)) = vm.events.take() else {
+            panic!("expected a recording");
+        };
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].address, events[0].value), (0xFE02, u16::from(b'a')));
+
+        // nothing to type this time: the keys come from the log, at the same instructions
+        let mut replayed = machine(b"");
+        replayed.events = Some(EventLog::Replay(events.iter().copied().collect()));
+        assert_eq!(replayed.run(), Ok(ExecState::Halted));
+        assert_eq!(replayed.registers.r1, u16::from(b'a'));
+        assert_eq!(replayed.registers.r0, u16::from(b'b'));
+        assert_eq!(replayed.instructions_executed(), vm.instructions_executed());
+
+        // a recording that runs out is a divergence, not a hang
+        let mut short = machine(b"");
+        short.events = Some(EventLog::Replay(events[..1].iter().copied().collect()));
+        assert_eq!(short.run().unwrap_err().exit_code, 8);
+    }
+}
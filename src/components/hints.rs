@@ -0,0 +1,250 @@
+// Teaching feedback from the basic-block profile (--hints). Loops are found from the profile's back edges, and
+// a few patterns students commonly leave in hot loops are matched against their instructions: a constant or
+// address reloaded every iteration, multiplication by repeated addition, a string printed with one OUT per
+// character. The checks are deliberately conservative: a wrong hint costs more trust than a missed one.
+use super::blocks::Graph;
+use super::instruction::sign_extend;
+
+// loops that ran fewer iterations than this aren't worth a hint
+const HOT: u64 = 100;
+// at most this many hints, hottest loops first
+const LIMIT: usize = 5;
+// a repeated-addition loop averaging fewer iterations than this is as fast as shift-and-add anyway
+const MULTIPLY_ITERATIONS: u64 = 16;
+
+struct Loop {
+    // first and last address of the loop body
+    start: u16,
+    end: u16,
+    // times the body ran, and times the loop was entered from outside
+    runs: u64,
+    entries: u64,
+}
+
+pub fn hints(graph: &Graph, memory: &[u16], symbols: &[(u16, String)]) -> Vec<String> {
+    let mut loops = find_loops(graph);
+    loops.sort_by(|a, b| b.runs.cmp(&a.runs).then(a.start.cmp(&b.start)));
+
+    let mut hints = Vec::new();
+    for found in &loops {
+        let body: Vec<(u16, u16)> = (found.start..=found.end)
+            .map(|a| (a, memory.get(a as usize).copied().unwrap_or(0)))
+            .collect();
+        let name = format!(
+            "loop at x{:04X}{}",
+            found.start,
+            within(found.start, symbols)
+        );
+        let runs = human(found.runs);
+        for (address, instruction, loaded) in reloads(&body) {
+            let what = match instruction >> 12 {
+                0xE => format!("recomputes the address x{:04X} with LEA", loaded),
+                _ => format!("reloads the constant at x{:04X} with LD", loaded),
+            };
+            hints.push(format!(
+                "{} runs {} times and {} (x{:04X}) every iteration, though nothing in the loop changes it; \
+                 consider hoisting it above the loop",
+                name, runs, what, address
+            ));
+        }
+        let average = found.runs / found.entries;
+        if average >= MULTIPLY_ITERATIONS && multiplies_by_adding(&body) {
+            hints.push(format!(
+                "{} multiplies by repeated ADD, {} iterations per entry on average; shift-and-add (ADD R, R, R \
+                 doubles a register) needs at most 16",
+                name, average
+            ));
+        }
+        if prints_characters(&body) {
+            hints.push(format!(
+                "{} prints a string one OUT per character over {} iterations; PUTS prints a null-terminated string in one",
+                name, runs
+            ));
+        }
+    }
+    hints.truncate(LIMIT);
+    hints
+}
+
+// A loop for every back edge: a jump from the end of a block to the start of a block at or before it
+fn find_loops(graph: &Graph) -> Vec<Loop> {
+    let block = |start: u16| graph.blocks.iter().find(|b| b.start == start);
+    graph
+        .edges
+        .iter()
+        .filter(|(&(from, to), &count)| to <= from && count >= HOT)
+        .filter_map(|(&(from, to), &count)| {
+            let (head, tail) = (block(to)?, block(from)?);
+            Some(Loop {
+                start: to,
+                end: tail.end,
+                runs: head.count,
+                entries: head.count.saturating_sub(count).max(1),
+            })
+        })
+        .collect()
+}
+
+// `LD R, X` or `LEA R, X` whose register nothing else in the loop writes and whose word nothing stores to:
+// (address, instruction, address loaded)
+fn reloads(body: &[(u16, u16)]) -> Vec<(u16, u16, u16)> {
+    // a call or an indirect store could change anything
+    if body
+        .iter()
+        .any(|&(_, i)| matches!(i >> 12, 0x4 | 0x7 | 0xB))
+    {
+        return Vec::new();
+    }
+    let target = |address: u16, instruction: u16| {
+        address
+            .wrapping_add(1)
+            .wrapping_add(sign_extend(instruction & 0x1FF, 9))
+    };
+    body.iter()
+        .filter(|&&(_, i)| matches!(i >> 12, 0x2 | 0xE))
+        .filter_map(|&(address, instruction)| {
+            let register = (instruction >> 9) & 0x7;
+            let loaded = target(address, instruction);
+            let rewritten = body
+                .iter()
+                .any(|&(a, i)| a != address && written(i).contains(&register));
+            let stored = body
+                .iter()
+                .any(|&(a, i)| i >> 12 == 0x3 && target(a, i) == loaded);
+            (!rewritten && !stored).then_some((address, instruction, loaded))
+        })
+        .collect()
+}
+
+// A short loop adding one register into another and counting a third down: `ADD R0, R0, R1 / ADD R2, R2, #-1 / BRp`
+fn multiplies_by_adding(body: &[(u16, u16)]) -> bool {
+    let accumulates = body.iter().find_map(|&(_, i)| {
+        let (dr, sr1, sr2) = ((i >> 9) & 0x7, (i >> 6) & 0x7, i & 0x7);
+        let adds_into_itself = (dr == sr1) != (dr == sr2);
+        (i >> 12 == 0x1 && i & 0x38 == 0 && adds_into_itself).then_some(dr)
+    });
+    let counter = body.iter().find_map(|&(_, i)| {
+        let (dr, sr1) = ((i >> 9) & 0x7, (i >> 6) & 0x7);
+        (i >> 12 == 0x1 && i & 0x3F == 0x3F && dr == sr1).then_some(dr)
+    });
+    let branches = body.last().is_some_and(|&(_, i)| i >> 12 == 0x0);
+    match (accumulates, counter) {
+        (Some(total), Some(count)) => body.len() <= 4 && total != count && branches,
+        _ => false,
+    }
+}
+
+// OUT on a character loaded through a pointer that the loop steps forward
+fn prints_characters(body: &[(u16, u16)]) -> bool {
+    let outputs = body.iter().any(|&(_, i)| i == 0xF021);
+    let pointer = body.iter().find_map(|&(_, i)| {
+        (i >> 12 == 0x6 && (i >> 9) & 0x7 == 0 && i & 0x3F == 0).then_some((i >> 6) & 0x7)
+    });
+    let steps = |p: u16| body.iter().any(|&(_, i)| i == 0x1021 | p << 9 | p << 6);
+    outputs && pointer.is_some_and(steps)
+}
+
+// Registers an instruction writes
+fn written(instruction: u16) -> Vec<u16> {
+    match instruction >> 12 {
+        0x1 | 0x2 | 0x5 | 0x6 | 0x9 | 0xA | 0xE => vec![(instruction >> 9) & 0x7],
+        0x4 => vec![7],
+        0xF => vec![0, 7],
+        _ => Vec::new(),
+    }
+}
+
+// ` (in NAME)` for the nearest symbol at or before the address
+fn within(address: u16, symbols: &[(u16, String)]) -> String {
+    symbols
+        .iter()
+        .filter(|(a, _)| *a <= address)
+        .max_by_key(|(a, _)| *a)
+        .map(|(_, name)| format!(" (in {})", name))
+        .unwrap_or_default()
+}
+
+// 950, 12.3K, 1.2M
+fn human(n: u64) -> String {
+    match n {
+        0..=9_999 => n.to_string(),
+        10_000..=999_999 => format!("{:.1}K", n as f64 / 1e3),
+        _ => format!("{:.1}M", n as f64 / 1e6),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::blocks::BlockProfile;
+    use crate::components::object::write_v1;
+    use crate::components::vm::VM;
+
+    fn run(source: &str) -> Vec<String> {
+        let image = assemble(source).unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.blocks = Some(BlockProfile::new());
+        vm.run().unwrap();
+        let graph = vm.blocks.as_ref().unwrap().graph();
+        hints(&graph, &vm.memory, &image.metadata.symbols)
+    }
+
+    #[test]
+    fn constant_reloaded_in_a_loop() {
+        let hints = run("
+            .ORIG x3000
+            AND R1, R1, #0
+            LD R2, COUNT
+    LOOP    LD R3, MASK
+            ADD R1, R1, #1
+            AND R1, R1, R3
+            ADD R2, R2, #-1
+            BRp LOOP
+            HALT
+    COUNT   .FILL #500
+    MASK    .FILL x000F
+            .END");
+        assert_eq!(
+            hints,
+            ["loop at x3002 (in LOOP) runs 500 times and reloads the constant at x3009 with LD (x3002) every \
+              iteration, though nothing in the loop changes it; consider hoisting it above the loop"]
+        );
+    }
+
+    #[test]
+    fn multiplication_by_repeated_addition() {
+        let hints = run("
+            .ORIG x3000
+            LD R2, TIMES
+            AND R4, R4, #0
+    OUTER   LD R1, A
+            LD R3, B
+            AND R0, R0, #0
+    MULT    ADD R0, R0, R1
+            ADD R3, R3, #-1
+            BRp MULT
+            ADD R2, R2, #-1
+            BRp OUTER
+            HALT
+    A       .FILL #7
+    B       .FILL #31
+    TIMES   .FILL #10
+            .END");
+        assert_eq!(
+            hints,
+            ["loop at x3005 (in MULT) multiplies by repeated ADD, 31 iterations per entry on average; \
+              shift-and-add (ADD R, R, R doubles a register) needs at most 16"]
+        );
+    }
+
+    #[test]
+    fn quiet_loops_get_no_hints() {
+        assert!(run(
+            ".ORIG x3000\nLD R0, N\nLOOP ADD R0, R0, #-1\nBRp LOOP\nHALT\nN .FILL #50\n.END"
+        )
+        .is_empty());
+    }
+}
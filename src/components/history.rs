@@ -0,0 +1,129 @@
+// Going back in the debugger (`reverse-step`, `undo`). Before each instruction the registers and the shadow call
+// stack are kept, and each store records the word it replaced, for the last CAPACITY instructions. Going back
+// puts them back newest first. Only the machine state is rewound: what the program printed stays printed, keys it
+// read aren't typed again, and devices keep their own state.
+use std::collections::VecDeque;
+
+use super::callstack::CallFrame;
+use super::register::Registers;
+use super::vm::VM;
+
+// instructions a debugger can go back over
+pub const CAPACITY: usize = 1000;
+
+struct Entry {
+    registers: Registers,
+    frames: Vec<CallFrame>,
+    steps: u64,
+    halted: bool,
+    // (address, word before the store), oldest first
+    stores: Vec<(u16, u16)>,
+}
+
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    // Instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    // A store about to overwrite `old` at `address`, during the instruction begun last
+    pub fn stored(&mut self, address: u16, old: u16) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.stores.push((address, old));
+        }
+    }
+}
+
+// Called before each instruction; the oldest instruction is forgotten once the history is full
+pub fn begin(vm: &mut VM) {
+    if vm.history.is_none() {
+        return;
+    }
+    let entry = Entry {
+        registers: vm.registers.clone(),
+        frames: vm.call_stack.frames().to_vec(),
+        steps: vm.call_stack.steps(),
+        halted: vm.halted,
+        stores: Vec::new(),
+    };
+    let history = vm.history.as_mut().unwrap();
+    if history.entries.len() == history.capacity {
+        history.entries.pop_front();
+    }
+    history.entries.push_back(entry);
+}
+
+// Take back up to `count` instructions, returning how many were
+pub fn undo(vm: &mut VM, count: usize) -> usize {
+    let mut undone = 0;
+    while undone < count {
+        let Some(entry) = vm.history.as_mut().and_then(|h| h.entries.pop_back()) else {
+            break;
+        };
+        for &(address, old) in entry.stores.iter().rev() {
+            vm.memory[address as usize] = old;
+        }
+        vm.registers = entry.registers;
+        vm.call_stack.rewind(entry.frames, entry.steps);
+        vm.halted = entry.halted;
+        // nothing had gone wrong before the instruction, or it wouldn't have run
+        vm.fault = None;
+        vm.paused_on = None;
+        vm.stopped_at = None;
+        undone += 1;
+    }
+    undone
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::testing::VmBuilder;
+
+    #[test]
+    fn undo_puts_back_registers_memory_and_calls() {
+        // ADD R0, R0, #5 / ST R0, x3005 / JSR +1 / HALT / RET
+        let mut vm = VmBuilder::new()
+            .with_memory(0x3000, &[0x1025, 0x3003, 0x4801, 0xF025, 0xC1C0])
+            .with_pc(0x3000)
+            .build();
+        vm.history = Some(History::new(2));
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!((vm.registers.pc, vm.memory[0x3005]), (0x3004, 5));
+        assert_eq!(vm.call_stack.frames().len(), 1);
+
+        // only the last two were kept
+        assert_eq!(undo(&mut vm, 5), 2);
+        assert_eq!(vm.registers.pc, 0x3001);
+        assert_eq!(vm.registers.r0, 5);
+        assert_eq!(vm.memory[0x3005], 0);
+        assert!(vm.call_stack.frames().is_empty());
+        assert_eq!(vm.instructions_executed(), 1);
+
+        // and it runs on the same way again
+        vm.run().unwrap();
+        assert_eq!((vm.memory[0x3005], vm.instructions_executed()), (5, 5));
+    }
+}
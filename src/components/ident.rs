@@ -0,0 +1,41 @@
+// Read-only identification block in the I/O page, so a guest program or OS image can check what it is running on
+// and fail with a clear message when an extension it needs is off, instead of crashing on an unknown trap:
+//   offset x30  IDMAGIC  x4C33 ("L3"); anything else means there is no ID block
+//   offset x32  IDVER    simulator version, major in the high byte and minor in the low
+//   offset x34  IDEXT    enabled extensions, bit n for EXTENSIONS[n]
+//   offset x36  IDISA    ISA profile, 0 for LC-3
+// Stores to the block are ignored.
+use super::iopage::MemoryMappedReg;
+use super::vm::VM;
+
+pub const MAGIC: u16 = 0x4C33;
+
+// Extension bits in IDEXT. New extensions are added at the end so existing bits keep their meaning.
+pub const EXTENSIONS: [&str; 3] = ["screen", "numeric", "chain"];
+
+pub const ISA_LC3: u16 = 0;
+
+pub fn version() -> u16 {
+    let major: u16 = env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0);
+    let minor: u16 = env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0);
+    (major.min(0xFF) << 8) | minor.min(0xFF)
+}
+
+pub fn extensions(vm: &VM) -> u16 {
+    EXTENSIONS
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| vm.has_capability(name))
+        .fold(0, |bits, (n, _)| bits | 1 << n)
+}
+
+// The value of an ID register, or None for any other register
+pub fn read(vm: &VM, register: MemoryMappedReg) -> Option<u16> {
+    match register {
+        MemoryMappedReg::IdMagic => Some(MAGIC),
+        MemoryMappedReg::IdVersion => Some(version()),
+        MemoryMappedReg::IdExtensions => Some(extensions(vm)),
+        MemoryMappedReg::IdIsa => Some(ISA_LC3),
+        _ => None,
+    }
+}
@@ -0,0 +1,237 @@
+// Idle-loop fast-forward (--fast-forward). A program waiting on a device polls a status register (KBSR, DSR,
+// the timer's TMCR) in a loop that does nothing else, and every time round it comes back to the loop head with
+// the same registers. Once an iteration is seen to do that with nothing but register arithmetic, branches and
+// reads that change nothing, the machine is provably idle until a device changes what the loop reads, so whole
+// iterations are skipped up to just before the next device event (see Device::next_event). The skipped
+// instructions count as executed: the instruction count, the devices' time, the --max-instructions limit,
+// budgets and the --clock-hz pace all move on as if they had run, so the run ends the same way it would have,
+// only sooner. The per-instruction views (--trace, --stats, profiles) don't see the skipped iterations.
+//
+// A loop waiting on the keyboard alone has no event to skip to: keys come in the host's time, not the machine's.
+use super::iopage::MemoryMappedReg;
+use super::register::Registers;
+use super::vm::{KBSR_READY, VM};
+
+// LC-3 opcodes an idle loop may use: BR, ADD, LD, AND, LDR, NOT, LDI and LEA. Anything that stores, calls,
+// traps or returns from an interrupt ends the iteration's claim to being idle.
+const QUIET_OPCODES: [u16; 8] = [0x0, 0x1, 0x2, 0x5, 0x6, 0x9, 0xA, 0xE];
+
+// With --clock-hz a skip is slept through, so it is cut into pieces no longer than this fraction of a second,
+// leaving the run to notice a key typed meanwhile
+const CLOCKED_SKIP_PER_SECOND: u64 = 20;
+
+#[derive(Default)]
+pub struct IdleLoop {
+    // the registers and step count when a backward branch last arrived at its target, and the step the next
+    // device event was due at then
+    head: Option<(Registers, u64, Option<u64>)>,
+    // nothing since then but quiet instructions and reads that change nothing
+    quiet: bool,
+    // instructions skipped so far
+    pub skipped: u64,
+}
+
+impl IdleLoop {
+    pub fn new() -> IdleLoop {
+        IdleLoop::default()
+    }
+
+    // Forget the loop being watched, when the machine is reset or the program's state was changed from outside
+    pub fn reset(&mut self) {
+        self.head = None;
+        self.quiet = false;
+        self.skipped = 0;
+    }
+}
+
+// Before each instruction
+pub fn before(vm: &mut VM, instruction: u16) {
+    let quiet = vm.cpu.name() == "lc3" && QUIET_OPCODES.contains(&(instruction >> 12));
+    if let Some(idle) = vm.idle.as_mut().filter(|_| !quiet) {
+        idle.quiet = false;
+    }
+}
+
+// A load by the program that read `value` from `address`
+pub fn read(vm: &mut VM, address: u16, value: u16) {
+    if vm.idle.is_none() || changes_nothing(vm, address, value) {
+        return;
+    }
+    if let Some(idle) = vm.idle.as_mut() {
+        idle.quiet = false;
+    }
+}
+
+// Whether reading `address` left everything as it was. Memory can't have changed under a quiet loop, and of the
+// console's registers only KBSR with no key, DSR, the MCR and the identification block read without taking
+// anything. A device's register counts when `peek` answers for it, which devices only do without side effects.
+fn changes_nothing(vm: &VM, address: u16, value: u16) -> bool {
+    if let Some(device) = vm.devices.iter().find(|d| d.range().contains(&address)) {
+        return device.peek(address) == Some(value);
+    }
+    if address == vm.io_page.mcr() {
+        return true;
+    }
+    match vm.io_page.register_at(address) {
+        None => !vm.io_page.contains(address),
+        Some(MemoryMappedReg::Kbsr) => value & KBSR_READY == 0,
+        Some(
+            MemoryMappedReg::Dsr
+            | MemoryMappedReg::IdMagic
+            | MemoryMappedReg::IdVersion
+            | MemoryMappedReg::IdExtensions
+            | MemoryMappedReg::IdIsa,
+        ) => true,
+        Some(_) => false,
+    }
+}
+
+// After each instruction at `pc`; a taken backward branch arrives at a loop head
+pub fn after(vm: &mut VM, pc: u16, instruction: u16) {
+    let target = vm.registers.pc;
+    if instruction >> 12 != 0 || target > pc {
+        return;
+    }
+    let steps = vm.call_stack.steps();
+    let event = next_event(vm).map(|next| steps + next);
+    let Some(idle) = vm.idle.as_mut() else {
+        return;
+    };
+    // an event that came due during the iteration may not have been read yet, so the next time round can differ
+    let repeated = idle.quiet
+        && idle
+            .head
+            .as_ref()
+            .is_some_and(|(registers, _, due)| *registers == vm.registers && *due == event);
+    let length = idle.head.as_ref().map_or(0, |(_, start, _)| steps - start);
+    let skipped = if repeated && length > 0 {
+        fast_forward(vm, length)
+    } else {
+        0
+    };
+    if let Some(idle) = vm.idle.as_mut() {
+        idle.head = Some((vm.registers.clone(), steps + skipped, event));
+        idle.quiet = true;
+    }
+}
+
+// Instructions until the first device event
+fn next_event(vm: &VM) -> Option<u64> {
+    vm.devices.iter().filter_map(|d| d.next_event()).min()
+}
+
+// Skip as many whole iterations of `length` instructions as fit before the next device event, the instruction
+// limit, a budget running out or (with a clock) a slice of a second. Returns the instructions skipped.
+fn fast_forward(vm: &mut VM, length: u64) -> u64 {
+    // a replay's keys come at recorded steps, which a skip could jump past
+    if vm.events.as_ref().is_some_and(|log| log.replaying()) {
+        return 0;
+    }
+    let Some(next) = next_event(vm) else {
+        return 0;
+    };
+    // the instruction being executed is counted once it's done
+    let steps = vm.call_stack.steps() + 1;
+    let mut room = next.saturating_sub(1);
+    if let Some(max) = vm.max_instructions {
+        room = room.min(max.saturating_sub(steps));
+    }
+    if let Some(left) = vm.call_stack.until_deadline() {
+        room = room.min(left.saturating_sub(1));
+    }
+    if let Some(clock) = vm.clock.as_ref() {
+        room = room.min(clock.hz() / CLOCKED_SKIP_PER_SECOND);
+    }
+    let skip = room / length * length;
+    if skip == 0 {
+        return 0;
+    }
+    vm.call_stack.skip(skip);
+    for device in &mut vm.devices {
+        device.skip(skip);
+    }
+    if let Some(clock) = vm.clock.as_mut() {
+        clock.skip(skip);
+    }
+    if let Some(idle) = vm.idle.as_mut() {
+        idle.skipped += skip;
+    }
+    skip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::devices;
+    use crate::components::object::write_v2;
+    use lc3_device::Registry;
+
+    // Starts the timer and polls TMCR until DONE, then counts to three in memory
+    const WAIT: &str = "
+            .ORIG x3000
+            LD R1, COUNT
+            STI R1, TMCNT
+            LD R1, ON
+            STI R1, TMCR
+    WAIT    LDI R0, TMCR
+            BRzp WAIT
+            AND R2, R2, #0
+            ADD R2, R2, #3
+            ST R2, DONE
+            HALT
+    TMCR    .FILL xFE24
+    TMCNT   .FILL xFE26
+    COUNT   .FILL #5000
+    ON      .FILL x0001
+    DONE    .FILL #0
+            .END";
+
+    fn run(program: &str, fast_forward: bool) -> VM {
+        let mut vm = VM::from_obj_bytes(&write_v2(&assemble(program).unwrap())).unwrap();
+        vm.console.echo = false;
+        let mut registry = Registry::new();
+        devices::register_builtin(&mut registry);
+        vm.attach(registry.create("timer").unwrap()).unwrap();
+        if fast_forward {
+            vm.idle = Some(IdleLoop::new());
+        }
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn a_timer_poll_is_skipped_and_counted() {
+        let plain = run(WAIT, false);
+        let skipped = run(WAIT, true);
+        assert_eq!(skipped.memory[0x300E], 3);
+        assert_eq!(
+            skipped.instructions_executed(),
+            plain.instructions_executed()
+        );
+        assert!(skipped.idle.as_ref().unwrap().skipped > 4900);
+    }
+
+    #[test]
+    fn a_loop_that_stores_is_not_idle() {
+        let busy = WAIT.replace("BRzp WAIT", "ST R0, DONE\n            BRzp WAIT");
+        let vm = run(&busy, true);
+        assert_eq!(vm.idle.as_ref().unwrap().skipped, 0);
+        assert_eq!(
+            vm.instructions_executed(),
+            run(&busy, false).instructions_executed()
+        );
+    }
+
+    #[test]
+    fn the_instruction_limit_still_stops_the_run() {
+        let mut vm = VM::from_obj_bytes(&write_v2(&assemble(WAIT).unwrap())).unwrap();
+        let mut registry = Registry::new();
+        devices::register_builtin(&mut registry);
+        vm.attach(registry.create("timer").unwrap()).unwrap();
+        vm.idle = Some(IdleLoop::new());
+        vm.max_instructions = Some(1000);
+        assert!(vm.run().is_err());
+        assert_eq!(vm.instructions_executed(), 1000);
+    }
+}
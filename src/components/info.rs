@@ -0,0 +1,282 @@
+// The debugger's `info TOPIC [json]`: one view of each part of the machine, as text for the prompt or as JSON for
+// tools driving the debugger. Built on the views the command-line flags print (--layout, --inspect-devices).
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+use super::debugger::registers;
+use super::inspect;
+use super::results::json_string;
+use super::vm::VM;
+
+// Words of the stack `info stack` shows, from R6 up
+const STACK_WORDS: u16 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    Registers,
+    Devices,
+    Breakpoints,
+    Symbols,
+    Images,
+    Stack,
+}
+
+pub const TOPICS: &str = "registers, devices, breakpoints, symbols, images or stack";
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "registers" | "regs" => Ok(Topic::Registers),
+            "devices" => Ok(Topic::Devices),
+            "breakpoints" | "watches" => Ok(Topic::Breakpoints),
+            "symbols" => Ok(Topic::Symbols),
+            "images" | "map" => Ok(Topic::Images),
+            "stack" => Ok(Topic::Stack),
+            _ => Err(format!("unknown topic `{}` (expected {})", s, TOPICS)),
+        }
+    }
+}
+
+// `access_watches` are the debugger's `watch ADDRESS` hooks, which only the session knows about
+pub fn render(
+    vm: &VM,
+    topic: Topic,
+    json: bool,
+    symbols: &[(u16, String)],
+    access_watches: &[u16],
+) -> String {
+    match (topic, json) {
+        (Topic::Registers, false) => registers(vm),
+        (Topic::Registers, true) => {
+            let fields: Vec<String> = vm
+                .cpu
+                .register_names()
+                .iter()
+                .enumerate()
+                .map(|(i, name)| format!("{}: {}", json_string(name), vm.cpu.register(vm, i)))
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        (Topic::Devices, false) => inspect::render(vm).trim_end().to_string(),
+        (Topic::Devices, true) => inspect::render_json(vm).trim_end().to_string(),
+        (Topic::Breakpoints, false) => {
+            let mut lines: Vec<String> = vm.breakpoints.iter().map(|&a| name(a, symbols)).collect();
+            lines.extend(
+                access_watches
+                    .iter()
+                    .map(|&a| format!("{} (watch)", name(a, symbols))),
+            );
+            lines.extend(vm.watches.iter().map(|w| format!("{} (watch)", w)));
+            if lines.is_empty() {
+                "no breakpoints".to_string()
+            } else {
+                lines.join("\n")
+            }
+        }
+        (Topic::Breakpoints, true) => {
+            let breakpoints: Vec<String> = vm.breakpoints.iter().map(|a| a.to_string()).collect();
+            let mut watches: Vec<String> = access_watches
+                .iter()
+                .map(|a| format!("{{\"address\": {}, \"on\": \"access\"}}", a))
+                .collect();
+            watches.extend(vm.watches.iter().map(|w| {
+                format!(
+                    "{{\"watch\": {}, \"on\": \"write\"}}",
+                    json_string(&w.to_string())
+                )
+            }));
+            format!(
+                "{{\"breakpoints\": [{}], \"watches\": [{}]}}",
+                breakpoints.join(", "),
+                watches.join(", ")
+            )
+        }
+        (Topic::Symbols, false) if symbols.is_empty() => "no symbols".to_string(),
+        (Topic::Symbols, false) => sorted(symbols)
+            .iter()
+            .map(|(address, name)| format!("x{:04X} {}", address, name))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (Topic::Symbols, true) => {
+            let symbols: Vec<String> = sorted(symbols)
+                .iter()
+                .map(|(address, name)| {
+                    format!(
+                        "{{\"name\": {}, \"address\": {}}}",
+                        json_string(name),
+                        address
+                    )
+                })
+                .collect();
+            format!("[{}]", symbols.join(", "))
+        }
+        (Topic::Images, false) => {
+            let mut out = vm.layout.render_text(vm.start.pc);
+            for region in &vm.regions.regions {
+                writeln!(
+                    out,
+                    "  region {} x{:04X}-x{:04X}",
+                    region.name,
+                    region.range.start(),
+                    region.range.end()
+                )
+                .unwrap();
+            }
+            out.trim_end().to_string()
+        }
+        (Topic::Images, true) => {
+            let regions: Vec<String> = vm
+                .regions
+                .regions
+                .iter()
+                .map(|r| {
+                    format!(
+                        "{{\"name\": {}, \"start\": {}, \"end\": {}}}",
+                        json_string(&r.name),
+                        r.range.start(),
+                        r.range.end()
+                    )
+                })
+                .collect();
+            format!(
+                "{{\"layout\": {}, \"regions\": [{}]}}",
+                vm.layout.render_json(vm.start.pc).trim_end(),
+                regions.join(", ")
+            )
+        }
+        (Topic::Stack, json) => stack(vm, json, symbols),
+    }
+}
+
+// The words from R6 up and the subroutine calls the shadow call stack has, innermost first
+fn stack(vm: &VM, json: bool, symbols: &[(u16, String)]) -> String {
+    let top = vm.registers.r6;
+    let words: Vec<(u16, u16)> = (top..=u16::MAX)
+        .take(STACK_WORDS as usize)
+        .map(|address| (address, vm.memory[address as usize]))
+        .collect();
+    let frames = vm.call_stack.frames().iter().rev();
+    if json {
+        let words: Vec<String> = words
+            .iter()
+            .map(|(address, value)| format!("{{\"address\": {}, \"value\": {}}}", address, value))
+            .collect();
+        let frames: Vec<String> = frames
+            .map(|f| {
+                format!(
+                    "{{\"entry\": {}, \"call_site\": {}, \"return_address\": {}}}",
+                    f.entry, f.call_site, f.return_address
+                )
+            })
+            .collect();
+        return format!(
+            "{{\"r6\": {}, \"words\": [{}], \"frames\": [{}]}}",
+            top,
+            words.join(", "),
+            frames.join(", ")
+        );
+    }
+    let mut out = format!("R6 = x{:04X}", top);
+    for (address, value) in words {
+        write!(out, "\n  x{:04X}: x{:04X}", address, value).unwrap();
+    }
+    for frame in frames {
+        write!(
+            out,
+            "\n  call to {} from x{:04X}, returns to x{:04X}",
+            name(frame.entry, symbols),
+            frame.call_site,
+            frame.return_address
+        )
+        .unwrap();
+    }
+    out
+}
+
+fn name(address: u16, symbols: &[(u16, String)]) -> String {
+    match symbols.iter().find(|(a, _)| *a == address) {
+        Some((_, symbol)) => format!("x{:04X} ({})", address, symbol),
+        None => format!("x{:04X}", address),
+    }
+}
+
+fn sorted(symbols: &[(u16, String)]) -> Vec<&(u16, String)> {
+    let mut sorted: Vec<&(u16, String)> = symbols.iter().collect();
+    sorted.sort();
+    sorted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v2;
+    use crate::components::watch::parse_watch;
+
+    const PROGRAM: &str = "
+            .ORIG x3000
+    MAIN    LD R6, TOP
+            JSR PUSH
+            HALT
+    PUSH    ADD R6, R6, #-1
+            STR R7, R6, #0
+            RET
+    TOP     .FILL xFE00
+            .END";
+
+    #[test]
+    fn each_topic_renders_as_text_and_json() {
+        assert_eq!("map".parse(), Ok(Topic::Images));
+        assert!("heap".parse::<Topic>().is_err());
+
+        let image = assemble(PROGRAM).unwrap();
+        let symbols = &image.metadata.symbols;
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        // inside PUSH, after it pushed R7
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        vm.breakpoints.insert(0x3005);
+        vm.watches.push(parse_watch("R6 when new < xFD00").unwrap());
+
+        assert!(render(&vm, Topic::Registers, true, symbols, &[]).starts_with("{\"R0\": 0, "));
+        assert!(render(&vm, Topic::Devices, false, symbols, &[]).starts_with("console\n  KBSR"));
+        assert!(render(&vm, Topic::Devices, true, symbols, &[]).starts_with(
+            "[{\"name\": \"console\", \"range\": null, \"registers\": [{\"name\": \"KBSR\""
+        ));
+        assert_eq!(
+            render(&vm, Topic::Breakpoints, false, symbols, &[0x3006]),
+            "x3005\nx3006 (TOP) (watch)\nR6 when new < xFD00 (watch)"
+        );
+        assert_eq!(
+            render(&vm, Topic::Breakpoints, true, symbols, &[0x3006]),
+            "{\"breakpoints\": [12293], \"watches\": [{\"address\": 12294, \"on\": \"access\"}, \
+             {\"watch\": \"R6 when new < xFD00\", \"on\": \"write\"}]}"
+        );
+        assert_eq!(
+            render(&vm, Topic::Symbols, false, symbols, &[]),
+            "x3000 MAIN\nx3003 PUSH\nx3006 TOP"
+        );
+        assert_eq!(
+            render(&vm, Topic::Symbols, true, symbols, &[]),
+            "[{\"name\": \"MAIN\", \"address\": 12288}, {\"name\": \"PUSH\", \"address\": 12291}, \
+             {\"name\": \"TOP\", \"address\": 12294}]"
+        );
+        assert!(render(&vm, Topic::Images, true, symbols, &[]).ends_with("\"regions\": []}"));
+        assert_eq!(
+            render(&vm, Topic::Stack, false, symbols, &[])
+                .lines()
+                .take(2)
+                .collect::<Vec<_>>(),
+            ["R6 = xFDFF", "  xFDFF: x3002"]
+        );
+        assert!(render(&vm, Topic::Stack, false, symbols, &[])
+            .ends_with("\n  call to x3003 (PUSH) from x3001, returns to x3002"));
+        assert!(render(&vm, Topic::Stack, true, symbols, &[]).ends_with(
+            "\"frames\": [{\"entry\": 12291, \"call_site\": 12289, \"return_address\": 12290}]}"
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+// Decoded view of the device registers: the console registers built into the simulator plus whatever the attached
+// devices describe, one line per register with its fields, e.g. `KBSR  xFE00 = x8000  READY=1 IE=0`.
+// Built-in registers show the value the program last saw; device registers show a side-effect-free peek.
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
+
+use lc3_device::{Field, RegisterInfo};
+
+use super::iopage::MemoryMappedReg;
+use super::results::json_string;
+use super::vm::VM;
+
+fn builtin(vm: &VM) -> Vec<RegisterInfo> {
+    let register = |name: &str, reg: MemoryMappedReg, fields: Vec<Field>| RegisterInfo {
+        name: name.to_string(),
+        address: vm.io_page.address(reg),
+        fields,
+    };
+    vec![
+        register(
+            "KBSR",
+            MemoryMappedReg::Kbsr,
+            vec![Field::new("READY", 15, 15), Field::new("IE", 14, 14)],
+        ),
+        register(
+            "KBDR",
+            MemoryMappedReg::Kbdr,
+            vec![Field::new("CHAR", 7, 0)],
+        ),
+        register(
+            "DSR",
+            MemoryMappedReg::Dsr,
+            vec![Field::new("READY", 15, 15)],
+        ),
+        register(
+            "DDR",
+            MemoryMappedReg::Ddr,
+            vec![Field::new("CHAR", 7, 0)],
+        ),
+        register(
+            "AKBSR",
+            MemoryMappedReg::AuxKbsr,
+            vec![Field::new("READY", 15, 15)],
+        ),
+        register(
+            "AKBDR",
+            MemoryMappedReg::AuxKbdr,
+            vec![Field::new("CHAR", 7, 0)],
+        ),
+        register(
+            "ADSR",
+            MemoryMappedReg::AuxDsr,
+            vec![Field::new("READY", 15, 15)],
+        ),
+    ]
+}
+
+fn line(out: &mut String, info: &RegisterInfo, value: Option<u16>) {
+    let value_text = value.map_or("?????".to_string(), |v| format!("x{:04X}", v));
+    write!(
+        out,
+        "  {:<8} x{:04X} = {}",
+        info.name, info.address, value_text
+    )
+    .unwrap();
+    if let Some(value) = value {
+        for field in &info.fields {
+            write!(out, "  {}=", field.name).unwrap();
+            if field.high == field.low {
+                write!(out, "{}", field.extract(value)).unwrap();
+            } else {
+                write!(out, "x{:X}", field.extract(value)).unwrap();
+            }
+        }
+    }
+    out.push('\n');
+}
+
+// Each group of registers with the values to show: the console first, then each device with its range
+pub type Group = (String, Option<RangeInclusive<u16>>, Vec<(RegisterInfo, Option<u16>)>);
+
+pub fn groups(vm: &VM) -> Vec<Group> {
+    // registers a device has taken over are listed under the device
+    let console = builtin(vm)
+        .into_iter()
+        .filter(|info| !vm.devices.iter().any(|d| d.range().contains(&info.address)))
+        .map(|info| {
+            let value = vm.memory[info.address as usize];
+            (info, Some(value))
+        })
+        .collect();
+    let mut groups = vec![("console".to_string(), None, console)];
+    if let Some(unit) = &vm.context {
+        let registers = unit
+            .registers()
+            .into_iter()
+            .map(|info| {
+                let value = unit.read(info.address);
+                (info, value)
+            })
+            .collect();
+        groups.push(("context-switch".to_string(), Some(unit.range()), registers));
+    }
+    for device in &vm.devices {
+        let registers = device
+            .registers()
+            .into_iter()
+            .map(|info| {
+                let value = device.peek(info.address);
+                (info, value)
+            })
+            .collect();
+        groups.push((device.name().to_string(), Some(device.range()), registers));
+    }
+    groups
+}
+
+pub fn render(vm: &VM) -> String {
+    let mut out = String::new();
+    for (name, range, registers) in groups(vm) {
+        match range {
+            Some(range) => writeln!(out, "{} (x{:04X}-x{:04X})", name, range.start(), range.end()).unwrap(),
+            None => writeln!(out, "{}", name).unwrap(),
+        }
+        for (info, value) in &registers {
+            line(&mut out, info, *value);
+        }
+    }
+    out
+}
+
+// The same as a JSON array, one object per device with its registers and their fields
+pub fn render_json(vm: &VM) -> String {
+    let devices: Vec<String> = groups(vm)
+        .iter()
+        .map(|(name, range, registers)| {
+            let range = range.as_ref().map_or("null".to_string(), |r| {
+                format!("{{\"start\": {}, \"end\": {}}}", r.start(), r.end())
+            });
+            let registers: Vec<String> = registers
+                .iter()
+                .map(|(info, value)| {
+                    let fields: Vec<String> = value
+                        .iter()
+                        .flat_map(|&v| info.fields.iter().map(move |f| (f, v)))
+                        .map(|(f, v)| format!("{}: {}", json_string(f.name), f.extract(v)))
+                        .collect();
+                    format!(
+                        "{{\"name\": {}, \"address\": {}, \"value\": {}, \"fields\": {{{}}}}}",
+                        json_string(&info.name),
+                        info.address,
+                        value.map_or("null".to_string(), |v| v.to_string()),
+                        fields.join(", ")
+                    )
+                })
+                .collect();
+            format!(
+                "{{\"name\": {}, \"range\": {}, \"registers\": [{}]}}",
+                json_string(name),
+                range,
+                registers.join(", ")
+            )
+        })
+        .collect();
+    format!("[{}]\n", devices.join(", "))
+}
+
+// Flip one bit of a register by hand, as the TUI's device pane does, and give the new value. The console's
+// registers are the words the program last saw, so the next read finds the bit as set here (until the keyboard or
+// display updates it); a device register goes through the device's `poke`.
+pub fn toggle(vm: &mut VM, address: u16, bit: u8) -> Result<u16, String> {
+    let mask = 1u16 << bit;
+    if let Some(device) = vm.devices.iter_mut().find(|d| d.range().contains(&address)) {
+        let value = device
+            .peek(address)
+            .ok_or_else(|| format!("x{:04X} can't be read without side effects", address))?;
+        if !device.poke(address, value ^ mask) {
+            return Err(format!("{} can't set x{:04X} by hand", device.name(), address));
+        }
+        return Ok(device.peek(address).unwrap_or(value ^ mask));
+    }
+    if let Some(unit) = vm.context.as_mut() {
+        if let Some(value) = unit.read(address) {
+            unit.set(address, value ^ mask);
+            return Ok(unit.read(address).unwrap_or(value ^ mask));
+        }
+    }
+    if !builtin(vm).iter().any(|info| info.address == address) {
+        return Err(format!("x{:04X} isn't a device register", address));
+    }
+    vm.memory[address as usize] ^= mask;
+    Ok(vm.memory[address as usize])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::devices;
+    use lc3_device::Registry;
+
+    #[test]
+    fn toggle_flips_console_and_device_bits() {
+        let mut vm = VM::new();
+        let mut registry = Registry::new();
+        devices::register_builtin(&mut registry);
+        vm.attach(registry.create("timer").unwrap()).unwrap();
+
+        assert_eq!(toggle(&mut vm, 0xFE00, 14), Ok(0x4000));
+        assert_eq!(toggle(&mut vm, 0xFE00, 15), Ok(0xC000));
+        assert_eq!(toggle(&mut vm, 0xFE00, 14), Ok(0x8000));
+        // the timer's DONE bit, which a store can only clear
+        assert_eq!(toggle(&mut vm, 0xFE24, 15), Ok(0x8000));
+        // bits the timer doesn't keep stay clear
+        assert_eq!(toggle(&mut vm, 0xFE24, 3), Ok(0x8000));
+        assert!(toggle(&mut vm, 0x3000, 0).is_err());
+    }
+}
@@ -1,13 +1,16 @@
-/// LC-3 has 16 opcodes, each instruction 16 bits long — first 4 bits store the OpCode, rest are saved for parameters
-
-/// This file includes every single instruction: br, add, ld, st, jsr, and, ldr, str, rti, not, ldi, sti, jmp, res, lea, trap
-
-use super::vm::VM; 
-
-use std::io;
-use std::io::Read;
-use std::io::Write;
-use std::process;
+//! LC-3 has 16 opcodes, each instruction 16 bits long — first 4 bits store the OpCode, rest are saved for parameters
+//!
+//! This file includes every single instruction: br, add, ld, st, jsr, and, ldr, str, rti, not, ldi, sti, jmp, res, lea, trap
+
+use super::decode::{decode, Instruction, Operand};
+use super::memo;
+use super::diagnostics::Category;
+use super::interrupt::{self, Exception};
+use super::loader;
+use super::numeric::{self, Radix};
+use super::os::{self, TrapMode};
+use super::regions::MemoryMap;
+use super::vm::{ExecState, Fault, VmError, VM};
 
 #[derive(Debug)] // default debug functionality
 pub enum OpCode {
@@ -19,12 +22,12 @@ pub enum OpCode {
     AND,    // bitwise and
     LDR,    // load register
     STR,    // store register
-    RTI,    // unused
+    RTI,    // return from interrupt
     NOT,    // bitwise not
     LDI,    // load indirect
     STI,    // store indirect
     JMP,    // jump
-    RES,    // reserved (unused)
+    RES,    // reserved, raises an illegal opcode exception
     LEA,    // load effective address
     TRAP,   // execute trap
 }
@@ -43,31 +46,69 @@ pub enum TrapCode {
     Putsp = 0x24,
     // halts program
     Halt = 0x25,
+    // guest breakpoint: stops under the debugger, NOP otherwise
+    Bkpt = 0x26,
+    // screen control, only with the `screen` capability
+    Clear = 0x27,
+    SetCursor = 0x28,
+    TermSize = 0x29,
+    // print R0, only with the `numeric` capability
+    PrintSigned = 0x2A,
+    PrintUnsigned = 0x2B,
+    PrintHex = 0x2C,
+    // read a signed decimal line into R0, only with the `numeric` capability
+    GetDecimal = 0x2D,
+    // load the object named by the string at R0 and jump to it, only with the `chain` capability
+    Chain = 0x2E,
+}
+
+// Longest file name TRAP x2E reads from guest memory
+const CHAIN_NAME_LIMIT: usize = 255;
+// What TRAP x23 (IN) prints before reading
+const IN_PROMPT: &str = "\nInput a character> ";
+
+// A TRAP to a vector the simulator doesn't implement (or whose capability is off). Stops the run.
+#[derive(Debug)]
+pub struct UnknownTrap {
+    pub vector: u16,
+    // the TRAP instruction
+    pub pc: u16,
 }
 
-pub fn execute_instruction(instr: u16, vm: &mut VM) {
-    // Extract OpCode from instruction
-    let op_code = get_opcode(&instr);
-
-    match op_code {
-        Some(OpCode::ADD) => add(instr, vm),
-        Some(OpCode::AND) => and(instr, vm),
-        Some(OpCode::NOT) => not(instr, vm),
-        Some(OpCode::BR) => br(instr, vm),
-        Some(OpCode::JMP) => jmp(instr, vm),
-        Some(OpCode::JSR) => jsr(instr, vm),
-        Some(OpCode::LD) => ld(instr, vm),
-        Some(OpCode::LDI) => ldi(instr, vm),
-        Some(OpCode::LDR) => ldr(instr, vm),
-        Some(OpCode::LEA) => lea(instr, vm),
-        Some(OpCode::ST) => st(instr, vm),
-        Some(OpCode::STI) => sti(instr, vm),
-        Some(OpCode::STR) => str(instr, vm),
-        Some(OpCode::TRAP) => trap(instr, vm),
-        _ => {}
+impl UnknownTrap {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        format!("unknown trap x{:02X} at {}", self.vector, map.at(self.pc))
     }
 }
 
+pub fn execute_instruction(instr: u16, vm: &mut VM) -> Result<ExecState, VmError> {
+    execute(decode(instr), vm)
+}
+
+// Run an instruction that's already been decoded, see decode.rs
+pub fn execute(instruction: Instruction, vm: &mut VM) -> Result<ExecState, VmError> {
+    match instruction {
+        Instruction::Add { dr, sr1, operand } => add(dr, sr1, operand, vm),
+        Instruction::And { dr, sr1, operand } => and(dr, sr1, operand, vm),
+        Instruction::Not { dr, sr } => not(dr, sr, vm),
+        Instruction::Br { mask, offset } => br(mask, offset, vm),
+        Instruction::Jmp { base } => jmp(base, vm),
+        Instruction::Jsr { offset } => jsr(vm.registers.pc.wrapping_add(offset), vm),
+        Instruction::Jsrr { base } => jsr(vm.registers.get(base), vm),
+        Instruction::Ld { dr, offset } => ld(dr, offset, vm),
+        Instruction::Ldi { dr, offset } => ldi(dr, offset, vm),
+        Instruction::Ldr { dr, base, offset } => ldr(dr, base, offset, vm),
+        Instruction::Lea { dr, offset } => lea(dr, offset, vm),
+        Instruction::St { sr, offset } => st(sr, offset, vm),
+        Instruction::Sti { sr, offset } => sti(sr, offset, vm),
+        Instruction::Str { sr, base, offset } => str(sr, base, offset, vm),
+        Instruction::Trap { vector } => trap(vector, vm),
+        Instruction::Rti => interrupt::rti(vm),
+        Instruction::Reserved => interrupt::exception(vm, Exception::IllegalOpcode),
+    }
+    vm.state()
+}
+
 // Each instruction is 16 bits long, l4 store opcode, rest store parameters
 pub fn get_opcode(instruction: &u16) -> Option<OpCode> {
     match instruction >> 12 {
@@ -91,34 +132,20 @@ pub fn get_opcode(instruction: &u16) -> Option<OpCode> {
     }
 }
 
-pub fn add(instruction: u16, vm: &mut VM) {
-    // Get destination address using bitwise operation to shift binary for DR.
-    let dr = (instruction >> 9) & 0x7;
-
-    // First operand — move 6
-    let sr1 = (instruction >> 6) & 0x7;
-
-    // Check if we're in immediate mode or register mode (imm_flag)
-    let imm_flag = (instruction >> 5) & 0x1;
-
-    if imm_flag == 1 {
-        let imm5 = sign_extend(instruction & 0x1F, 5);
-
-        // set as u32 to prevent overflow
-        let val: u32 = imm5 as u32 + vm.registers.get(sr1) as u32;
-
-        // result of sum set from target register
-        vm.registers.update(dr, val as u16);
-    } else {
-        // 2nd needs to be extracted in this case
-        let sr2 = instruction & 0x7;
+// The second operand of ADD/AND: a register, or an immediate already sign-extended
+fn operand_value(operand: Operand, vm: &VM) -> u16 {
+    match operand {
+        Operand::Register(sr2) => vm.registers.get(sr2),
+        Operand::Immediate(imm5) => imm5,
+    }
+}
 
-        // rest is normal
-        
-        let val: u32 = vm.registers.get(sr1) as u32 + vm.registers.get(sr2) as u32;
+pub fn add(dr: u16, sr1: u16, operand: Operand, vm: &mut VM) {
+    // two's complement: the sum wraps at 16 bits
+    let val = vm.registers.get(sr1).wrapping_add(operand_value(operand, vm));
 
-        vm.registers.update(dr, val as u16);
-    }
+    // result of sum set from target register
+    vm.registers.update(dr, val);
 
     // dr last operation
     vm.registers.update_r_cond_register(dr);
@@ -127,14 +154,9 @@ pub fn add(instruction: u16, vm: &mut VM) {
 /* 
 The address is determined by sign-extending bits [8:0] to 16 bits and adding it to the incremented PC. The content stored in memory at this computed address represents the data to be loaded into DR, with condition codes set accordingly.
 */
-pub fn ldi(instruction: u16, vm: &mut VM) {
-    // Get the direct register
-    let dr = (instruction >> 9) & 0x7;
-
-    let pc_offset = sign_extend(instruction & 0x1ff, 9);
-
+pub fn ldi(dr: u16, pc_offset: u16, vm: &mut VM) {
     // This sum addresses a location in memory — contains another value: the address of the value to load
-    let first_read = vm.read_memory(vm.registers.pc + pc_offset);
+    let first_read = vm.read_memory(vm.registers.pc.wrapping_add(pc_offset));
 
     // Read the resulting address and update the DR.
     let resulting_address = vm.read_memory(first_read);
@@ -143,94 +165,59 @@ pub fn ldi(instruction: u16, vm: &mut VM) {
 }
 
 // Normal `and` functionality
-pub fn and(instruction: u16, vm: &mut VM) {
-    // Get the direct register encoded in the instruction
-    let dr = (instruction >> 9) & 0x7;
-
-    let sr1 = (instruction >> 6) & 0x7;
-    let imm_flag = (instruction >> 5) & 0x1;
-
-    if imm_flag == 1 {
-        let imm5 = sign_extend(instruction & 0x1F, 5);
-        // execute and store bitwise value in the DR.
-        vm.registers.update(dr, vm.registers.get(sr1) & imm5);
-    } else {
-        let sr2 = instruction & 0x7;
-        // same as above
-        vm.registers.update(dr, vm.registers.get(sr1) & vm.registers.get(sr2));
-    }
+pub fn and(dr: u16, sr1: u16, operand: Operand, vm: &mut VM) {
+    // execute and store bitwise value in the DR.
+    vm.registers.update(dr, vm.registers.get(sr1) & operand_value(operand, vm));
 
     vm.registers.update_r_cond_register(dr);
 }
 
 // Binary negation
-pub fn not(instruction: u16, vm: &mut VM) {
-    let dr = (instruction >> 9) & 0x7;
-    let sr1 = (instruction >> 6) & 0x7;
+pub fn not(dr: u16, sr1: u16, vm: &mut VM) {
     vm.registers.update(dr, !vm.registers.get(sr1));
 
     vm.registers.update_r_cond_register(dr);
 }
 
 // The branching operation: redirect a location within assembly code depending on bit conditions [11:9]
-pub fn br(instruction: u16, vm: &mut VM) {
-    let pc_offset = sign_extend((instruction) & 0x1ff, 9);
-
-    let cond_flag = (instruction >> 9) & 0x7;
-
+pub fn br(cond_flag: u16, pc_offset: u16, vm: &mut VM) {
     // combine '001', xor '010', xor '100' stored in the condition register w/ instruction
     if cond_flag & vm.registers.cond != 0 {
-        let val: u32 = vm.registers.pc as u32 + pc_offset as u32;
-        vm.registers.pc = val as u16;
+        vm.registers.pc = vm.registers.pc.wrapping_add(pc_offset);
     }
-
 }
 
 // The program unconditionally jumps to the location specified by the contents of the base register.
 
 // typical assembly classifications
-pub fn jmp(instruction: u16, vm: &mut VM) {
+pub fn jmp(base_reg: u16, vm: &mut VM) {
     // base_reg will either be an arbitrary register or the register 7 (`111`) — `RET` operation.
-    let base_reg = (instruction >> 6) & 0x7;
     vm.registers.pc = vm.registers.get(base_reg);
+    vm.call_stack.ret(vm.registers.pc);
+    memo::leave(vm);
 }
 
-// Save the he incremented PC in R7, load with subroutine instruction to cause unconditional jump
-pub fn jsr(instruction: u16, vm: &mut VM) {
-    // base register
-    let base_reg = (instruction >> 6) & 0x7;
-
-    let long_pc_offset = sign_extend(instruction & 0x7ff, 11);
-
-    let long_flag = (instruction >> 11) & 1;
-
+// Save the he incremented PC in R7, load with subroutine instruction to cause unconditional jump. The target
+// (PC + PCOffset11 for JSR, the base register for JSRR) is worked out first, so JSRR R7 jumps to where R7 pointed
+// before it's overwritten.
+pub fn jsr(target: u16, vm: &mut VM) {
     // Save the incremented PC in R7
     vm.registers.r7 = vm.registers.pc;
+    vm.registers.pc = target;
 
-    if long_flag != 0 {
-        // the address to jump from PCOffset11
-        let val: u32 = vm.registers.pc as u32 + long_pc_offset as u32;
-        vm.registers.pc = val as u16;
-    } else {
-        // address to jump to in the base register
-        vm.registers.pc = vm.registers.get(base_reg);
-    }
+    let call_site = vm.registers.r7.wrapping_sub(1);
+    vm.call_stack.call(call_site, vm.registers.pc, vm.registers.r7);
+    memo::enter(vm);
 }
 
 /* 
 An address is computed by sign-extending bits [8:0] to 16 bits and adding this value to the incremented PC: contents into DR, condition codes set.
 */
-pub fn ld(instruction: u16, vm: &mut VM) {
-    // Get the direct register encoded in the instruction (see `add` fn for more in-depth details)
-    let dr = (instruction >> 9) & 0x7;
-
-    // Grab the PCOffset and sign extend it
-    let pc_offset = sign_extend(instruction & 0x1ff, 9);
-
-    let mem: u32 = pc_offset as u32 + vm.registers.pc as u32;
+pub fn ld(dr: u16, pc_offset: u16, vm: &mut VM) {
+    let mem = vm.registers.pc.wrapping_add(pc_offset);
 
     // Read the value from the place where the memory above was computed
-    let value = vm.read_memory(mem as u16);
+    let value = vm.read_memory(mem);
 
     // Save that value to the direct register and update the condition register
     vm.registers.update(dr, value);
@@ -238,141 +225,180 @@ pub fn ld(instruction: u16, vm: &mut VM) {
 }
 
 // Load base + offset
-pub fn ldr(instruction: u16, vm: &mut VM) {
-    // Get the direct register encoded in the instruction (see `add` fn for more in-depth details)
-    let dr = (instruction >> 9) & 0x7;
-
-    // Grab the base register
-    let base_reg = (instruction >> 6) & 0x7;
-
-    // Grab the offset and sign extend it
-    let offset = sign_extend(instruction & 0x3F, 6);
-
+pub fn ldr(dr: u16, base_reg: u16, offset: u16, vm: &mut VM) {
     // Compute the memory location to be loaded
-    let val: u32 = vm.registers.get(base_reg) as u32 + offset as u32;
+    let val = vm.registers.get(base_reg).wrapping_add(offset);
+    vm.check_region_access(vm.registers.get(base_reg), val);
 
     // Read the value at that memory location
-    let mem_value = vm.read_memory(val as u16).clone();
+    let mem_value = vm.read_memory(val);
 
     // Update the register with the loaded value and update the condition register
     vm.registers.update(dr, mem_value);
     vm.registers.update_r_cond_register(dr);
 }
 
-pub fn lea(instruction: u16, vm: &mut VM) {
-    let dr = (instruction >> 9) & 0x7;
-
-    let pc_offset = sign_extend(instruction & 0x1ff, 9);
-
-    let val: u32 = vm.registers.pc as u32 + pc_offset as u32;
+pub fn lea(dr: u16, pc_offset: u16, vm: &mut VM) {
+    let val = vm.registers.pc.wrapping_add(pc_offset);
 
-    vm.registers.update(dr, val as u16);
+    vm.registers.update(dr, val);
 
     vm.registers.update_r_cond_register(dr);
 }
 
-pub fn st(instruction: u16, vm: &mut VM) {
-    let sr = (instruction >> 9) & 0x7;
-
-    // Grab the PC offset and sign extend it
-    let pc_offset = sign_extend(instruction & 0x1ff, 9);
-
-    // add current PC to PC offset and convert to avoid overflow
-    let val: u32 = vm.registers.pc as u32 + pc_offset as u32;
-    let val: u16 = val as u16;
+pub fn st(sr: u16, pc_offset: u16, vm: &mut VM) {
+    // add current PC to PC offset, wrapping at the top of memory
+    let val = vm.registers.pc.wrapping_add(pc_offset);
 
     // Store the value in the register being passed at above instructed address
-    vm.write_memory(val as usize, vm.registers.get(sr));
+    vm.write_memory(val, vm.registers.get(sr));
 }
 
 
-pub fn sti(instruction: u16, vm: &mut VM) {
-    let sr = (instruction >> 9) & 0x7;
-
-    let pc_offset = sign_extend(instruction & 0x1ff, 9);
-
-    let val: u32 = vm.registers.pc as u32 + pc_offset as u32;
-    let val: u16 = val as u16;
+pub fn sti(sr: u16, pc_offset: u16, vm: &mut VM) {
+    let val = vm.registers.pc.wrapping_add(pc_offset);
 
     // This is the difference between STI and ST
-    let address = vm.read_memory(val) as usize;
+    let address = vm.read_memory(val);
 
     vm.write_memory(address, vm.registers.get(sr));
 }
 
-pub fn str(instruction: u16, vm: &mut VM) {
-    let dr = (instruction >> 9) & 0x7;
-
-    let base_reg = (instruction >> 6) & 0x7;
-
-    let offset = sign_extend(instruction & 0x3F, 6);
-
-    let val: u32 = vm.registers.get(base_reg) as u32 + offset as u32;
-    let val: u16 = val as u16;
-    vm.write_memory(val as usize, vm.registers.get(dr));
+pub fn str(sr: u16, base_reg: u16, offset: u16, vm: &mut VM) {
+    let val = vm.registers.get(base_reg).wrapping_add(offset);
+    vm.check_region_access(vm.registers.get(base_reg), val);
+    vm.write_memory(val, vm.registers.get(sr));
 }
 
 // I/O device interaction
 
 // figure out what exactly is accessed and how the parts work together
 pub fn trap(instruction: u16, vm: &mut VM) {
+    if let Some(stats) = vm.stats.as_mut() {
+        stats.record_trap(vm.registers.pc.wrapping_sub(1), instruction & 0xFF, vm.registers.r0);
+    }
+    if vm.traps != TrapMode::Native && os::dispatch(vm, instruction & 0xFF) {
+        return;
+    }
+
     match instruction & 0xFF {
         0x20 => {
-            // Get character
-            let mut buffer = [0; 1];
-            std::io::stdin().read_exact(&mut buffer).unwrap();
-            vm.registers.r0 = buffer[0] as u16;
+            // Get character, not echoed. Every byte is a key, so a program can read the escape sequences
+            // arrows and function keys send.
+            vm.console.prompt();
+            vm.registers.r0 = vm.read_byte() as u16;
         }
         0x21 => {
             // Write out character
             let c = vm.registers.r0 as u8;
-            print!("{}", c as char);
+            vm.console.write_byte(c);
         }
         0x22 => {
             let mut index = vm.registers.r0;
             let mut c = vm.read_memory(index);
             while c != 0x0000 {
-                print!("{}", (c as u8) as char);
-                index += 1;
+                vm.console.write_byte(c as u8);
+                index = index.wrapping_add(1);
                 c = vm.read_memory(index);
             }
-            io::stdout().flush().expect("failed to flush");
+            vm.console.flush();
         }
         0x23 => {
-            // take input, print prompt and read a char (y/n typically), ASCII encoded into R0 + clear the high 8bits of R0
-            print!("Enter a  character : ");
-            io::stdout().flush().expect("failed to flush");
-            let char = std::io::stdin()
-                .bytes()
-                .next()
-                .and_then(|result| result.ok())
-                .map(|byte| byte as u16)
-                .unwrap();
-            vm.registers.update(0, char);
+            // prompt, read a character into R0 (high 8 bits clear) and echo it, as the textbook's OS does
+            vm.console.write_str(IN_PROMPT);
+            vm.console.prompt();
+            let key = vm.read_key();
+            vm.registers.update(0, key as u16);
+            // nothing to echo once scripted input has run out
+            if key != 0 {
+                vm.console.write_byte(key);
+            }
+            vm.console.write_byte(b'\n');
+            vm.console.flush();
         }
         0x24 => {
             // Putsp — packed string
             let mut index = vm.registers.r0;
             let mut c = vm.read_memory(index);
             while c != 0x0000 {
-                let c1 = ((c & 0xFF) as u8) as char;
-                print!("{}", c1);
-                let c2 = ((c >> 8) as u8) as char;
-                if c2 != '\0' {
-                    print!("{}", c2);
+                let c1 = (c & 0xFF) as u8;
+                vm.console.write_byte(c1);
+                let c2 = (c >> 8) as u8;
+                if c2 != 0 {
+                    vm.console.write_byte(c2);
                 }
-                index += 1;
+                index = index.wrapping_add(1);
                 c = vm.read_memory(index);
             }
-            io::stdout().flush().expect("failed to flush");
+            vm.console.flush();
         }
         0x25 => {
-            println!("HALT detected");
-            io::stdout().flush().expect("failed to flush");
-            process::exit(1);
+            vm.console.flush();
+            // stop the execute loop, the caller decides what happens after the run (and what to print)
+            vm.halted = true;
         }
-        _ => {
-            process::exit(1);
+        0x26 => {
+            if vm.break_on_bkpt {
+                vm.stopped_at = Some(vm.registers.pc.wrapping_sub(1));
+            }
+        }
+        0x27 if vm.has_capability("screen") => {
+            vm.console.clear_screen();
+            vm.console.flush();
+        }
+        0x28 if vm.has_capability("screen") => {
+            // R0 row, R1 column, both from 0
+            vm.console.move_cursor(vm.registers.r0, vm.registers.r1);
+            vm.console.flush();
+        }
+        0x29 if vm.has_capability("screen") => {
+            let (rows, columns) = vm.console.size();
+            vm.registers.r0 = rows;
+            vm.registers.r1 = columns;
+        }
+        0x2A..=0x2C if vm.has_capability("numeric") => {
+            let radix = match instruction & 0xFF {
+                0x2A => Radix::Signed,
+                0x2B => Radix::Unsigned,
+                _ => Radix::Hex,
+            };
+            vm.console.write_str(&numeric::format(vm.registers.r0, radix));
+            vm.console.flush();
+        }
+        0x2D if vm.has_capability("numeric") => {
+            // ask again until the line is a number that fits; running out of scripted input gives 0
+            vm.console.prompt();
+            let value = loop {
+                let Some(line) = numeric::read_line(vm) else {
+                    break 0;
+                };
+                match numeric::parse_signed(&line) {
+                    Ok(value) => break value,
+                    Err(e) => {
+                        vm.console.write_str(&format!("{}\n{}", e, numeric::RETRY_PROMPT));
+                        vm.console.prompt();
+                    }
+                }
+            };
+            vm.registers.update(0, value);
+        }
+        0x2E if vm.has_capability("chain") => {
+            // on failure the program carries on with R0 = -1, so it can report the problem itself
+            let name = vm.read_string(vm.registers.r0, CHAIN_NAME_LIMIT);
+            match loader::chain_load(vm, &name) {
+                Ok(entry) => vm.registers.pc = entry,
+                Err(e) => {
+                    let pc = vm.registers.pc.wrapping_sub(1);
+                    vm.warn(Category::Load, pc, format!("warning: TRAP x2E at x{:04X}: {}", pc, e));
+                    vm.registers.update(0, 0xFFFF);
+                }
+            }
+        }
+        vector => {
+            vm.fault.get_or_insert(Fault::UnknownTrap(UnknownTrap {
+                vector,
+                pc: vm.registers.pc.wrapping_sub(1),
+            }));
         }
     }
 }
@@ -386,4 +412,403 @@ pub fn sign_extend(mut x: u16, bit_count: u8) -> u16 {
     }
     // return as is given positive
     x
-}
\ No newline at end of file
+}
+
+// Disassembly
+
+// Render an instruction in assembler syntax, PC-relative operands shown as offsets (`BRz #-3`)
+pub fn disassemble(instruction: u16) -> String {
+    render(instruction, None)
+}
+
+// Same as `disassemble`, but PC-relative operands are resolved against the instruction's address (`BRz x3004`)
+pub fn disassemble_at(instruction: u16, address: u16) -> String {
+    render(instruction, Some(address))
+}
+
+// A listing of an object: address, word and instruction on each line, with the object's symbols as labels and
+// in place of the PC-relative targets they name. Words that are printable characters show them, so strings
+// read as strings rather than as the nonsense instructions they decode to.
+pub fn listing(origin: u16, words: &[u16], symbols: &[(u16, String)]) -> String {
+    let name = |address: u16| symbols.iter().find(|(a, _)| *a == address).map(|(_, n)| n.as_str());
+    let mut out = String::new();
+    for (address, &word) in (origin..=u16::MAX).zip(words) {
+        if let Some(label) = name(address) {
+            out.push_str(&format!("{}:\n", label));
+        }
+        let mut text = disassemble_at(word, address);
+        if let Some(target) = pc_target(word, address) {
+            if let Some(label) = name(target) {
+                let hex = format!("x{:04X}", target);
+                text = format!("{}{}", text.strip_suffix(&hex).unwrap_or(&text), label);
+            }
+        }
+        match word {
+            0x20..=0x7E => out.push_str(&format!(
+                "x{:04X}  {:04X}  {:<24}'{}'\n",
+                address, word, text, word as u8 as char
+            )),
+            _ => out.push_str(&format!("x{:04X}  {:04X}  {}\n", address, word, text)),
+        }
+    }
+    out
+}
+
+// Where a PC-relative instruction points
+fn pc_target(instruction: u16, address: u16) -> Option<u16> {
+    let bits = match get_opcode(&instruction)? {
+        OpCode::BR if (instruction >> 9) & 0x7 != 0 => 9,
+        OpCode::LD | OpCode::LDI | OpCode::LEA | OpCode::ST | OpCode::STI => 9,
+        OpCode::JSR if (instruction >> 11) & 1 == 1 => 11,
+        _ => return None,
+    };
+    let offset = sign_extend(instruction & ((1 << bits) - 1), bits);
+    Some(address.wrapping_add(1).wrapping_add(offset))
+}
+
+fn render(instruction: u16, address: Option<u16>) -> String {
+    let dr = (instruction >> 9) & 0x7;
+    let sr1 = (instruction >> 6) & 0x7;
+
+    // a PC-relative operand of `bits` width, as an absolute target when the address is known
+    let target = |bits: u8| {
+        let offset = sign_extend(instruction & ((1 << bits) - 1), bits);
+        match address {
+            Some(address) => format!("x{:04X}", address.wrapping_add(1).wrapping_add(offset)),
+            None => format!("#{}", offset as i16),
+        }
+    };
+    // the second operand of ADD/AND: a register or a 5-bit immediate
+    let operand2 = || {
+        if (instruction >> 5) & 0x1 == 1 {
+            format!("#{}", sign_extend(instruction & 0x1F, 5) as i16)
+        } else {
+            format!("R{}", instruction & 0x7)
+        }
+    };
+
+    match get_opcode(&instruction) {
+        Some(OpCode::BR) => {
+            let flags: String = [(0x4, 'n'), (0x2, 'z'), (0x1, 'p')]
+                .iter()
+                .filter(|(bit, _)| dr & bit != 0)
+                .map(|(_, c)| *c)
+                .collect();
+            if flags.is_empty() {
+                "NOP".to_string()
+            } else {
+                format!("BR{} {}", flags, target(9))
+            }
+        }
+        Some(OpCode::ADD) => format!("ADD R{}, R{}, {}", dr, sr1, operand2()),
+        Some(OpCode::AND) => format!("AND R{}, R{}, {}", dr, sr1, operand2()),
+        Some(OpCode::NOT) => format!("NOT R{}, R{}", dr, sr1),
+        Some(OpCode::LD) => format!("LD R{}, {}", dr, target(9)),
+        Some(OpCode::LDI) => format!("LDI R{}, {}", dr, target(9)),
+        Some(OpCode::LEA) => format!("LEA R{}, {}", dr, target(9)),
+        Some(OpCode::ST) => format!("ST R{}, {}", dr, target(9)),
+        Some(OpCode::STI) => format!("STI R{}, {}", dr, target(9)),
+        Some(OpCode::LDR) => format!(
+            "LDR R{}, R{}, #{}",
+            dr,
+            sr1,
+            sign_extend(instruction & 0x3F, 6) as i16
+        ),
+        Some(OpCode::STR) => format!(
+            "STR R{}, R{}, #{}",
+            dr,
+            sr1,
+            sign_extend(instruction & 0x3F, 6) as i16
+        ),
+        Some(OpCode::JMP) if sr1 == 7 => "RET".to_string(),
+        Some(OpCode::JMP) => format!("JMP R{}", sr1),
+        Some(OpCode::JSR) if (instruction >> 11) & 1 == 1 => format!("JSR {}", target(11)),
+        Some(OpCode::JSR) => format!("JSRR R{}", sr1),
+        Some(OpCode::RTI) => "RTI".to_string(),
+        Some(OpCode::TRAP) => match trap_name(instruction & 0xFF) {
+            Some(name) => name.to_string(),
+            None => format!("TRAP x{:02X}", instruction & 0xFF),
+        },
+        Some(OpCode::RES) | None => format!(".FILL x{:04X}", instruction),
+    }
+}
+
+// Assembler alias for a trap vector
+pub fn trap_name(vector: u16) -> Option<&'static str> {
+    match vector {
+        0x20 => Some("GETC"),
+        0x21 => Some("OUT"),
+        0x22 => Some("PUTS"),
+        0x23 => Some("IN"),
+        0x24 => Some("PUTSP"),
+        0x25 => Some("HALT"),
+        0x26 => Some("BKPT"),
+        0x27 => Some("CLEAR"),
+        0x28 => Some("SETCUR"),
+        0x29 => Some("TERMSIZE"),
+        0x2A => Some("PUTSD"),
+        0x2B => Some("PUTUD"),
+        0x2C => Some("PUTX"),
+        0x2D => Some("GETD"),
+        0x2E => Some("CHAIN"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::iodevice::MemoryIo;
+    use crate::components::register::Registers;
+    use crate::components::testing::{output, VmBuilder, N, P, Z};
+
+    // A machine with `words` at `origin` and the device page moved down to xFD00-xFDFF, so xFFFF is plain memory
+    fn machine(origin: u16, words: &[u16]) -> Box<VM> {
+        VmBuilder::new()
+            .with_io_page(0xFD00..=0xFDFF)
+            .with_memory(origin, words)
+            .with_pc(origin)
+            .build()
+    }
+
+    // (registers before, instruction at x3000, register written, its value, condition codes after)
+    type Case = (&'static [(u16, u16)], u16, u16, u16, u16);
+
+    // Step each case and check the destination, the condition codes, and that no other register moved
+    fn check(cases: &[Case]) {
+        for &(registers, instruction, dr, value, cond) in cases {
+            let mut before = Registers::new();
+            registers.iter().for_each(|&(r, value)| before.update(r, value));
+            let vm = VmBuilder::new()
+                .with_registers(registers)
+                .step_one(instruction);
+            let name = disassemble(instruction);
+            assert_eq!(vm.registers.get(dr), value, "{}: R{}", name, dr);
+            assert_eq!(vm.registers.cond, cond, "{}: condition codes", name);
+            for r in (0..8).filter(|&r| r != dr) {
+                assert_eq!(vm.registers.get(r), before.get(r), "{}: R{} changed", name, r);
+            }
+            assert_eq!(vm.registers.pc, 0x3001, "{}: PC", name);
+        }
+    }
+
+    #[test]
+    fn add_wraps_and_sets_the_condition_codes() {
+        check(&[
+            // x7FFF + 1 overflows into the sign bit
+            (&[(1, 0x7FFF)], 0x1261, 1, 0x8000, N),
+            (&[(1, 0xFFFF)], 0x1261, 1, 0x0000, Z),
+            // x8000 - 1 overflows the other way
+            (&[(1, 0x8000)], 0x127F, 1, 0x7FFF, P),
+            // ADD R2, R1, #-16, the most negative immediate
+            (&[(1, 5)], 0x1470, 2, 0xFFF5, N),
+            // ADD R3, R1, R2
+            (&[(1, 0x4000), (2, 0x4000)], 0x1642, 3, 0x8000, N),
+            // ADD R1, R1, R1
+            (&[(1, 3)], 0x1241, 1, 6, P),
+        ]);
+    }
+
+    #[test]
+    fn and_and_not() {
+        check(&[
+            // AND R1, R1, #0 clears
+            (&[(1, 0xFFFF)], 0x5260, 1, 0, Z),
+            // AND R2, R1, #-1 sign-extends to xFFFF and copies
+            (&[(1, 0x8001)], 0x547F, 2, 0x8001, N),
+            // AND R2, R1, #15
+            (&[(1, 0xFFF0)], 0x546F, 2, 0, Z),
+            // AND R3, R1, R2
+            (&[(1, 0x0F0F), (2, 0x00FF)], 0x5642, 3, 0x000F, P),
+            // NOT R2, R1
+            (&[(1, 0x0000)], 0x947F, 2, 0xFFFF, N),
+            (&[(1, 0xFFFF)], 0x947F, 2, 0x0000, Z),
+            (&[(1, 0x8000)], 0x947F, 2, 0x7FFF, P),
+        ]);
+    }
+
+    #[test]
+    fn loads_set_the_condition_codes() {
+        for &(registers, instruction, dr, value, cond) in &[
+            // LD R1, #-17 reaches x2FF0
+            (&[][..], 0x23EF, 1, 0x8000, N),
+            // LDI R2, #1 through x3002 = x4000, which holds zero
+            (&[(2, 7)][..], 0xA401, 2, 0x0000, Z),
+            // LDR R1, R2, #-32 and #31 from x4020 and x3FE2
+            (&[(2, 0x4020)][..], 0x62A0, 1, 0x0000, Z),
+            (&[(2, 0x3FE2)][..], 0x629F, 1, 0x1234, P),
+            // LEA R3, #-256; as in the second edition of the book, LEA sets them too
+            (&[][..], 0xE700, 3, 0x2F01, P),
+        ] {
+            let mut vm = VmBuilder::new()
+                .with_memory(0x2FF0, &[0x8000])
+                .with_memory(0x3002, &[0x4000])
+                .with_memory(0x4000, &[0x0000, 0x1234])
+                .with_registers(registers)
+                .build();
+            vm.memory[0x3000] = instruction;
+            vm.step().unwrap();
+            assert_eq!(vm.registers.get(dr), value, "{}", disassemble(instruction));
+            assert_eq!(vm.registers.cond, cond, "{}", disassemble(instruction));
+        }
+    }
+
+    #[test]
+    fn stores_leave_the_registers_alone() {
+        // ST R1, #-2 to x2FFF; a store of zero doesn't set Z
+        let vm = VmBuilder::new()
+            .with_registers(&[(1, 0), (9, P)])
+            .step_one(0x33FE);
+        assert_eq!(vm.memory[0x2FFF], 0);
+        assert_eq!(vm.registers.cond, P);
+
+        // STR R1, R2, #31 and #-32
+        let vm = VmBuilder::new()
+            .with_registers(&[(1, 0xBEEF), (2, 0x4000)])
+            .step_one(0x729F);
+        assert_eq!(vm.memory[0x401F], 0xBEEF);
+        let vm = VmBuilder::new()
+            .with_registers(&[(1, 0xBEEF), (2, 0x4000)])
+            .step_one(0x72A0);
+        assert_eq!(vm.memory[0x3FE0], 0xBEEF);
+        assert_eq!(vm.registers.cond, Z);
+    }
+
+    #[test]
+    fn sti_and_ldi_chain_through_pointers() {
+        // STI R1 then LDI R2 through the same pointer at x3002
+        let mut vm = VmBuilder::new()
+            .with_memory(0x3000, &[0xB201, 0xA400, 0x4000])
+            .with_registers(&[(1, 0xABCD)])
+            .build();
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0x4000], 0xABCD);
+        assert_eq!((vm.registers.r2, vm.registers.cond), (0xABCD, N));
+
+        // pointers into the I/O page reach the devices: LDI from DSR, STI to DDR
+        let mut vm = VmBuilder::new()
+            .with_memory(0x3000, &[0xA402, 0xB002, 0xF025, 0xFE04, 0xFE06])
+            .with_registers(&[(0, u16::from(b'A'))])
+            .build();
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.registers.r2, 0x8000);
+        assert_eq!(output(&vm), "A");
+    }
+
+    #[test]
+    fn br_tests_every_mask_against_every_condition() {
+        for mask in 0..8 {
+            for cond in [N, Z, P] {
+                // BR<mask> #5
+                let vm = VmBuilder::new()
+                    .with_registers(&[(9, cond)])
+                    .step_one(mask << 9 | 5);
+                let taken = mask & cond != 0;
+                assert_eq!(
+                    vm.registers.pc,
+                    if taken { 0x3006 } else { 0x3001 },
+                    "mask {:03b}, condition {:03b}",
+                    mask,
+                    cond
+                );
+                assert_eq!(vm.registers.cond, cond);
+            }
+        }
+        // the reach of PCoffset9 both ways
+        let vm = VmBuilder::new().step_one(0x0F00);
+        assert_eq!(vm.registers.pc, 0x2F01);
+        let vm = VmBuilder::new().step_one(0x0EFF);
+        assert_eq!(vm.registers.pc, 0x3100);
+    }
+
+    #[test]
+    fn jumps_and_subroutine_calls() {
+        // JMP R3 and RET leave R7 and the condition codes alone
+        let vm = VmBuilder::new()
+            .with_registers(&[(3, 0x4000), (7, 0x5000), (9, N)])
+            .step_one(0xC0C0);
+        assert_eq!((vm.registers.pc, vm.registers.r7, vm.registers.cond), (0x4000, 0x5000, N));
+        let vm = VmBuilder::new()
+            .with_registers(&[(7, 0x5000)])
+            .step_one(0xC1C0);
+        assert_eq!(vm.registers.pc, 0x5000);
+
+        // JSR reaches -1024 and +1023 and saves the return address
+        let vm = VmBuilder::new().step_one(0x4C00);
+        assert_eq!((vm.registers.pc, vm.registers.r7), (0x2C01, 0x3001));
+        let vm = VmBuilder::new().step_one(0x4BFF);
+        assert_eq!((vm.registers.pc, vm.registers.r7), (0x3400, 0x3001));
+
+        // JSRR R3, and JSRR R7, which jumps to R7 as it was before the call overwrote it
+        let vm = VmBuilder::new()
+            .with_registers(&[(3, 0x4000)])
+            .step_one(0x40C0);
+        assert_eq!((vm.registers.pc, vm.registers.r7), (0x4000, 0x3001));
+        let vm = VmBuilder::new()
+            .with_registers(&[(7, 0x5000)])
+            .step_one(0x41C0);
+        assert_eq!((vm.registers.pc, vm.registers.r7), (0x5000, 0x3001));
+    }
+
+    #[test]
+    fn pc_wraps_from_xffff_to_x0000() {
+        // ADD R1, R1, #1 at xFFFF and again at x0000
+        let mut vm = machine(0xFFFF, &[0x1261, 0x1261]);
+        vm.step().unwrap();
+        assert_eq!(vm.registers.pc, 0x0000);
+        vm.step().unwrap();
+        assert_eq!((vm.registers.pc, vm.registers.r1), (0x0001, 2));
+
+        // BRnzp #1 at xFFFE lands on x0000
+        let mut vm = machine(0xFFFE, &[0x0E01]);
+        vm.step().unwrap();
+        assert_eq!(vm.registers.pc, 0x0000);
+    }
+
+    #[test]
+    fn negative_offsets_reach_backwards() {
+        // x3000: .FILL x3005, x3001: LDI R0, #-2, x3002: LD R1, #-3, x3003: LEA R2, #-4, x3004: BRnzp #-5
+        let mut vm = machine(0x3000, &[0x3005, 0xA1FE, 0x23FD, 0xE5FC, 0x0FFB, 0x1234]);
+        vm.registers.pc = 0x3001;
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.registers.r0, 0x1234);
+        assert_eq!(vm.registers.r1, 0x3005);
+        assert_eq!(vm.registers.r2, 0x3000);
+        assert_eq!(vm.registers.pc, 0x3000);
+
+        // STR R2, R1, #-1 with R1 = x0000 wraps to the top of memory
+        let mut vm = machine(0x3000, &[0x747F]);
+        vm.registers.r1 = 0x0000;
+        vm.registers.r2 = 0x00AA;
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0xFFFF], 0x00AA);
+    }
+
+    #[test]
+    fn getc_reads_every_byte_and_in_skips_escape_sequences() {
+        // GETC twice, then IN, then HALT; the GETCs get the two bytes of ESC [ and IN skips F1 (ESC O P)
+        let mut vm = machine(0x3000, &[0xF020, 0x1220, 0xF020, 0x1420, 0xF023, 0xF025]);
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.console.io = Box::new(MemoryIo::new(b"\x1B[\x1BOPn"));
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!((vm.registers.r1, vm.registers.r2), (0x1B, u16::from(b'[')));
+        assert_eq!(vm.registers.r0, u16::from(b'n'));
+        assert_eq!(vm.console.transcript.as_deref(), Some(&b"\nInput a character> n\n"[..]));
+    }
+
+    #[test]
+    fn loads_and_stores_at_xffff() {
+        // ST R0, #1 at xFFFD writes xFFFF, then LD R1, #0 at xFFFE reads it back
+        let mut vm = machine(0xFFFD, &[0x3001, 0x2200]);
+        vm.registers.r0 = 0x5A5A;
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.memory[0xFFFF], 0x5A5A);
+        assert_eq!(vm.registers.r1, 0x5A5A);
+        assert_eq!(vm.registers.pc, 0xFFFF);
+    }
+}
@@ -0,0 +1,141 @@
+// Starter-code integrity checks: protected address ranges paired with the hash their contents must keep.
+// Manifest lines look like `x3000:x30FF 9c1f0e2b8a7d6c54`, `#` starts a comment.
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+
+use super::parse_range;
+use super::regions::MemoryMap;
+
+pub struct ProtectedRegion {
+    pub range: RangeInclusive<u16>,
+    pub hash: u64,
+}
+
+#[derive(Debug)]
+pub enum IntegrityError {
+    // the region no longer hashes to the manifest value (checked after loading)
+    Modified {
+        start: u16,
+        end: u16,
+        expected: u64,
+        actual: u64,
+    },
+    // a store changed a protected word while running
+    Overwritten {
+        address: u16,
+        pc: u16,
+        old: u16,
+        new: u16,
+    },
+}
+
+impl IntegrityError {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        match self {
+            IntegrityError::Modified {
+                start,
+                end,
+                expected,
+                actual,
+            } => format!(
+                "protected region {}:{} was modified (expected hash {:016x}, found {:016x})",
+                map.at(*start),
+                map.at(*end),
+                expected,
+                actual
+            ),
+            IntegrityError::Overwritten {
+                address,
+                pc,
+                old,
+                new,
+            } => format!(
+                "instruction at {} overwrote protected {} (x{:04X} -> x{:04X})",
+                map.at(*pc),
+                map.at(*address),
+                old,
+                new
+            ),
+        }
+    }
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.describe(&MemoryMap::new()))
+    }
+}
+
+#[derive(Default)]
+pub struct Integrity {
+    pub regions: Vec<ProtectedRegion>,
+    pub continuous: bool, // also reject stores into protected regions during execution
+}
+
+impl Integrity {
+    pub fn new() -> Integrity {
+        Integrity::default()
+    }
+
+    pub fn load_manifest(&mut self, path: &std::path::Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read manifest {}: {}", path.display(), e))?;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (range, hash) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(range), Some(hash), None) => (range, hash),
+                _ => {
+                    return Err(format!(
+                        "{}:{}: expected `START:END HASH`",
+                        path.display(),
+                        number + 1
+                    ))
+                }
+            };
+            let range = parse_range(range)
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+            let hash = u64::from_str_radix(hash, 16).map_err(|_| {
+                format!("{}:{}: invalid hash `{}`", path.display(), number + 1, hash)
+            })?;
+            self.regions.push(ProtectedRegion { range, hash });
+        }
+        Ok(())
+    }
+
+    pub fn covers(&self, address: u16) -> bool {
+        self.regions.iter().any(|r| r.range.contains(&address))
+    }
+
+    // Check every region against the given memory, reporting the first mismatch
+    pub fn verify(&self, memory: &[u16]) -> Result<(), IntegrityError> {
+        for region in &self.regions {
+            let actual =
+                hash_words(&memory[*region.range.start() as usize..=*region.range.end() as usize]);
+            if actual != region.hash {
+                return Err(IntegrityError::Modified {
+                    start: *region.range.start(),
+                    end: *region.range.end(),
+                    expected: region.hash,
+                    actual,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// FNV-1a over the big-endian bytes of each word, the same bytes an .obj file stores
+pub fn hash_words(words: &[u16]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in words.iter().flat_map(|w| w.to_be_bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,221 @@
+// Privilege, exceptions and interrupts as in Patt & Patel: the PSR (register.rs) says whether the program runs in
+// user or supervisor mode, and an exception or interrupt saves PSR and PC on the supervisor stack, switching R6
+// to it first if the program was in user mode, then jumps through the vector table at x0100. RTI undoes that.
+//
+// A vector whose table entry is still zero has no handler, and taking it stops the run with a fault: without an
+// OS loaded that's far more useful than jumping to x0000. Memory access control (the ACV exception) is not
+// modelled; user programs may touch system space and the I/O page as they always could here.
+use super::callstack::{Cause, Handler};
+use super::context;
+use super::regions::MemoryMap;
+use super::vm::{Fault, VM};
+
+// where the vector table starts; entry N holds the handler address for vector N
+pub const TABLE: u16 = 0x0100;
+pub const PRIVILEGE_VIOLATION: u16 = 0x00;
+pub const ILLEGAL_OPCODE: u16 = 0x01;
+pub const KEYBOARD: u16 = 0x80;
+pub const TIMER: u16 = 0x81;
+
+// the priority the keyboard interrupts at; it is only taken while the program runs below it
+pub const KEYBOARD_PRIORITY: u16 = 4;
+pub const TIMER_PRIORITY: u16 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    PrivilegeViolation, // RTI, or a store to the context-switch unit, in user mode
+    IllegalOpcode,      // the reserved opcode 1101
+}
+
+impl Exception {
+    pub fn vector(self) -> u16 {
+        match self {
+            Exception::PrivilegeViolation => PRIVILEGE_VIOLATION,
+            Exception::IllegalOpcode => ILLEGAL_OPCODE,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Exception::PrivilegeViolation => "privilege mode violation",
+            Exception::IllegalOpcode => "illegal opcode",
+        }
+    }
+}
+
+// Raised when an exception has no handler in the vector table
+#[derive(Debug)]
+pub struct Unhandled {
+    pub exception: Exception,
+    // the instruction that raised it
+    pub pc: u16,
+}
+
+impl Unhandled {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        format!(
+            "{} at {} and no handler at x{:04X} in the vector table",
+            self.exception.name(),
+            map.at(self.pc),
+            TABLE + self.exception.vector()
+        )
+    }
+}
+
+// Save PSR and PC on the supervisor stack and jump to the handler for `vector`, at `priority` for an interrupt
+// or the current priority for an exception (None)
+pub fn enter(vm: &mut VM, vector: u16, priority: Option<u16>) {
+    let pc = vm.registers.pc;
+    let (cause, site) = match priority {
+        Some(_) => (Cause::Interrupt(vector), pc),
+        // the exception's instruction has run
+        None => (Cause::Exception(vector), pc.wrapping_sub(1)),
+    };
+    save_context(vm, cause, site, vm.memory[(TABLE + vector) as usize]);
+    if let Some(priority) = priority {
+        vm.registers.priority = priority & 0x7;
+    }
+}
+
+// Switch to supervisor mode and its stack, push PSR and PC there for RTI to pop, and go to the handler at `entry`.
+// `site` is the instruction that raised it, or for an interrupt the one it came in before.
+pub fn save_context(vm: &mut VM, cause: Cause, site: u16, entry: u16) {
+    let psr = vm.registers.psr();
+    if !vm.registers.supervisor {
+        vm.registers.saved_usp = vm.registers.r6;
+        vm.registers.r6 = vm.registers.saved_ssp;
+        vm.registers.supervisor = true;
+    }
+    push(vm, psr);
+    let pc = vm.registers.pc;
+    push(vm, pc);
+    let handler = Handler {
+        cause,
+        saved_at: Some(vm.registers.r6),
+    };
+    vm.call_stack.enter_handler(site, entry, pc, handler);
+    vm.registers.pc = entry;
+}
+
+// Take an exception raised by the instruction just executed (PC already points past it)
+pub fn exception(vm: &mut VM, exception: Exception) {
+    let vector = exception.vector();
+    if vm.memory[(TABLE + vector) as usize] == 0 {
+        vm.fault.get_or_insert(Fault::Exception(Unhandled {
+            exception,
+            pc: vm.registers.pc.wrapping_sub(1),
+        }));
+        return;
+    }
+    enter(vm, vector, None);
+}
+
+// RTI: pop PC and PSR, and go back to the user stack if the PSR returns to user mode
+pub fn rti(vm: &mut VM) {
+    if !vm.registers.supervisor {
+        exception(vm, Exception::PrivilegeViolation);
+        return;
+    }
+    vm.call_stack.rti(vm.registers.r6);
+    let pc = pop(vm);
+    let psr = pop(vm);
+    vm.registers.pc = pc;
+    vm.registers.set_psr(psr);
+    if !vm.registers.supervisor {
+        vm.registers.saved_ssp = vm.registers.r6;
+        vm.registers.r6 = vm.registers.saved_usp;
+    }
+    if vm.context.is_some() {
+        context::returned(vm);
+    }
+}
+
+fn push(vm: &mut VM, value: u16) {
+    vm.registers.r6 = vm.registers.r6.wrapping_sub(1);
+    vm.write_memory(vm.registers.r6, value);
+}
+
+fn pop(vm: &mut VM) -> u16 {
+    let value = vm.read_memory(vm.registers.r6);
+    vm.registers.r6 = vm.registers.r6.wrapping_add(1);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::iodevice::MemoryIo;
+    use crate::components::object::write_v1;
+    use crate::components::vm::ExecState;
+
+    fn load(source: &str) -> VM {
+        let image = assemble(source).unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm
+    }
+
+    #[test]
+    fn illegal_opcode_goes_through_the_table_and_rti_returns() {
+        // the handler counts in R1 and returns past the bad word; R6 is back on the user stack afterwards
+        let mut vm = load("
+            .ORIG x3000
+            LD R6, USP
+            .FILL xD000
+            ADD R2, R1, #0
+            HALT
+    USP     .FILL xFE00
+    HANDLER ADD R1, R1, #1
+            RTI
+            .END");
+        vm.memory[(TABLE + ILLEGAL_OPCODE) as usize] = 0x3005;
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.registers.r2, 1);
+        assert_eq!(vm.registers.r6, 0xFE00);
+        assert!(!vm.registers.supervisor);
+        assert_eq!(vm.memory[0x2FFF], 0x8004);
+    }
+
+    #[test]
+    fn rti_in_user_mode_without_a_handler_faults() {
+        let mut vm = load(".ORIG x3000\nRTI\nHALT\n.END");
+        assert_eq!(vm.run().unwrap_err().exit_code, 9);
+        match &vm.fault {
+            Some(Fault::Exception(e)) => {
+                assert_eq!(e.exception, Exception::PrivilegeViolation);
+                assert_eq!(e.pc, 0x3000);
+            }
+            _ => panic!("expected an unhandled exception"),
+        }
+    }
+
+    #[test]
+    fn keyboard_interrupt_delivers_a_key() {
+        // enable keyboard interrupts and spin; the handler stores the key it took from KBDR and returns
+        let mut vm = load("
+            .ORIG x3000
+            LD R6, USP
+            LD R0, IE
+            STI R0, KBSR
+    SPIN    LDI R1, KEY
+            BRz SPIN
+            HALT
+    USP     .FILL xFE00
+    IE      .FILL x4000
+    KBSR    .FILL xFE00
+    KEY     .FILL x3100
+    HANDLER LDI R2, KBDR
+            STI R2, KEY
+            RTI
+    KBDR    .FILL xFE02
+            .END");
+        vm.memory[(TABLE + KEYBOARD) as usize] = 0x300A;
+        vm.console.io = Box::new(MemoryIo::new(b"k"));
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.registers.r1, b'k' as u16);
+        assert_eq!(vm.registers.priority, 0);
+        assert_eq!(vm.memory[0xFE00], 0x4000);
+    }
+}
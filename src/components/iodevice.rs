@@ -0,0 +1,302 @@
+// Where the guest console's bytes actually come from and go to. The console (console.rs) applies the ANSI policy,
+// frame capture and transcript; an IoDevice only moves bytes. The terminal is the default; MemoryIo keeps both
+// directions in memory for tests and for hosts that draw the console themselves (a GUI, a web page).
+//
+// TerminalIo and read_line need stdin, stdout and threads, so they only exist with the `terminal` feature.
+use std::any::Any;
+use std::collections::VecDeque;
+use std::io;
+#[cfg(feature = "terminal")]
+use std::io::{Read, Write};
+#[cfg(feature = "terminal")]
+use std::sync::mpsc::{self, Receiver};
+#[cfg(feature = "terminal")]
+use std::sync::{Mutex, OnceLock};
+#[cfg(feature = "terminal")]
+use std::thread;
+
+// stdin read a byte at a time on its own thread, started the first time anything asks whether a key is waiting.
+// There is one stdin, so there is one reader, and everything reading stdin after that goes through it.
+#[cfg(feature = "terminal")]
+static READER: OnceLock<Mutex<Receiver<u8>>> = OnceLock::new();
+
+pub trait IoDevice: Any {
+    // Next keystroke, waiting for one. None when no key will ever come, e.g. queued input that has run out.
+    fn read_char(&mut self) -> Option<u8>;
+
+    // Whether read_char would return a key straight away. Never waits.
+    fn key_ready(&mut self) -> bool {
+        self.queued().is_some_and(|keys| !keys.is_empty())
+    }
+
+    // Output that can't be written ends the run: a reader that went away (`| head`) halts it as though the
+    // program had, any other error is a fault
+    fn write_char(&mut self, byte: u8) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // (rows, columns) of the screen output goes to, when there is one
+    fn size(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    // Keys waiting to be read, for a device whose input is queued ahead of time; None for a live keyboard
+    fn queued(&self) -> Option<&VecDeque<u8>>;
+
+    // Queue keys ahead of the program, switching to queued input if the device was reading a live keyboard
+    fn queue(&mut self, keys: &[u8]);
+
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+// stdin and stdout. Keys queued with `queue` (--input, VM::poll) are read instead of stdin once there are any.
+// The first key_ready moves stdin onto the reader thread, so asking whether a key was typed (a KBSR read) never
+// blocks; until then keys are read from stdin directly.
+#[cfg(feature = "terminal")]
+#[derive(Default)]
+pub struct TerminalIo {
+    keys: Option<VecDeque<u8>>,
+    // taken off the reader by key_ready and not read yet
+    waiting: Option<u8>,
+}
+
+#[cfg(feature = "terminal")]
+impl TerminalIo {
+    pub fn new() -> TerminalIo {
+        TerminalIo::default()
+    }
+}
+
+#[cfg(feature = "terminal")]
+impl IoDevice for TerminalIo {
+    fn read_char(&mut self) -> Option<u8> {
+        if let Some(keys) = self.keys.as_mut() {
+            return keys.pop_front();
+        }
+        if let Some(key) = self.waiting.take() {
+            return Some(key);
+        }
+        if let Some(reader) = READER.get() {
+            // the reader hangs up at the end of stdin
+            return reader.lock().unwrap().recv().ok();
+        }
+        // None at the end of piped input, like queued keys running out
+        let mut buffer = [0; 1];
+        loop {
+            io::stdin().read_exact(&mut buffer).ok()?;
+            if let Some(key) = translate(buffer[0]) {
+                return Some(key);
+            }
+        }
+    }
+
+    fn key_ready(&mut self) -> bool {
+        if let Some(keys) = self.keys.as_ref() {
+            return !keys.is_empty();
+        }
+        if self.waiting.is_none() {
+            let reader = READER.get_or_init(|| Mutex::new(spawn_reader()));
+            self.waiting = reader.lock().unwrap().try_recv().ok();
+        }
+        self.waiting.is_some()
+    }
+
+    fn write_char(&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+
+    fn size(&self) -> Option<(u16, u16)> {
+        crossterm::terminal::size()
+            .ok()
+            .map(|(columns, rows)| (rows, columns))
+    }
+
+    fn queued(&self) -> Option<&VecDeque<u8>> {
+        self.keys.as_ref()
+    }
+
+    fn queue(&mut self, keys: &[u8]) {
+        self.keys.get_or_insert_with(VecDeque::new).extend(keys);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Read stdin a byte at a time on its own thread, until it ends
+#[cfg(feature = "terminal")]
+fn spawn_reader() -> Receiver<u8> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 1];
+        while io::stdin().read_exact(&mut buffer).is_ok() {
+            let Some(key) = translate(buffer[0]) else {
+                continue;
+            };
+            if sender.send(key).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// A byte of stdin as a key. Windows consoles send CR for Enter even in raw mode, where termios would have turned it
+// into the x0A LC-3 programs look for, and piped text there ends its lines with CRLF; both read as a single x0A.
+#[cfg(all(feature = "terminal", windows))]
+fn translate(byte: u8) -> Option<u8> {
+    use std::io::IsTerminal;
+    match byte {
+        b'\r' if io::stdin().is_terminal() => Some(b'\n'),
+        b'\r' => None,
+        _ => Some(byte),
+    }
+}
+
+#[cfg(all(feature = "terminal", not(windows)))]
+fn translate(byte: u8) -> Option<u8> {
+    Some(byte)
+}
+
+// A line of stdin for the host rather than the guest, e.g. a debugger command. None at the end of stdin.
+#[cfg(feature = "terminal")]
+pub fn read_line() -> Option<String> {
+    let Some(reader) = READER.get() else {
+        let mut line = String::new();
+        return match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line),
+        };
+    };
+    let reader = reader.lock().unwrap();
+    let mut line = Vec::new();
+    while let Ok(byte) = reader.recv() {
+        line.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+    }
+    (!line.is_empty()).then(|| String::from_utf8_lossy(&line).into_owned())
+}
+
+// Input from a queue and output into a buffer, nothing touching the terminal
+#[derive(Default)]
+pub struct MemoryIo {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl MemoryIo {
+    pub fn new(input: &[u8]) -> MemoryIo {
+        MemoryIo {
+            input: input.iter().copied().collect(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl IoDevice for MemoryIo {
+    fn read_char(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn write_char(&mut self, byte: u8) -> io::Result<()> {
+        self.output.push(byte);
+        Ok(())
+    }
+
+    fn queued(&self) -> Option<&VecDeque<u8>> {
+        Some(&self.input)
+    }
+
+    fn queue(&mut self, keys: &[u8]) {
+        self.input.extend(keys);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::vm::{ExecState, VM};
+
+    #[test]
+    fn a_program_talks_to_memory() {
+        // GETC, OUT, loop until the character was a newline, then PUTS a greeting and HALT
+        let mut words = vec![
+            0x3000, 0xF020, 0xF021, 0x1236, 0x0BFC, 0xE002, 0xF022, 0xF025,
+        ];
+        words.extend(b"ok\n\0".iter().map(|&c| c as u16));
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        let mut vm = VM::from_obj_bytes(&bytes).unwrap();
+        vm.console.io = Box::new(MemoryIo::new(b"hi\n"));
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.console.memory().unwrap().output, b"hi\nok\n");
+    }
+
+    // Takes no output at all, failing every write the same way
+    struct Refusing(io::ErrorKind);
+
+    impl IoDevice for Refusing {
+        fn read_char(&mut self) -> Option<u8> {
+            None
+        }
+
+        fn write_char(&mut self, _byte: u8) -> io::Result<()> {
+            Err(self.0.into())
+        }
+
+        fn queued(&self) -> Option<&VecDeque<u8>> {
+            None
+        }
+
+        fn queue(&mut self, _keys: &[u8]) {}
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+    }
+
+    #[test]
+    fn output_that_cannot_be_written_ends_the_run() {
+        // OUT, then an endless loop back to it
+        let program = [0x3000, 0xF021, 0x0FFE];
+        let bytes: Vec<u8> = program.iter().flat_map(|w: &u16| w.to_be_bytes()).collect();
+
+        // a closed pipe halts
+        let mut vm = VM::from_obj_bytes(&bytes).unwrap();
+        vm.console.io = Box::new(Refusing(io::ErrorKind::BrokenPipe));
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.registers.pc, 0x3001);
+
+        let mut vm = VM::from_obj_bytes(&bytes).unwrap();
+        vm.console.io = Box::new(Refusing(io::ErrorKind::StorageFull));
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.exit_code, 13);
+        assert!(error.message.starts_with("couldn't write the output of the instruction at x3000"));
+    }
+}
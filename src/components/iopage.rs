@@ -0,0 +1,141 @@
+// The memory-mapped I/O page: where the device registers live. It is xFE00-xFFFF by default and can be moved or
+// made larger with --io-page for courses using their own address map. Register addresses are offsets into it.
+use std::ops::RangeInclusive;
+
+use super::parse_range;
+use super::regions::MemoryMap;
+
+// Every standard register and built-in device fits in this many words from the start of the page
+pub const MIN_SIZE: usize = 0x100;
+
+// Offsets from the start of the page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryMappedReg {
+    // key presses
+    Kbsr = 0x00,
+
+    // identify key
+    Kbdr = 0x02,
+
+    // the display: always ready, a character stored to DDR is printed like TRAP x21
+    Dsr = 0x04,
+    Ddr = 0x06,
+
+    // auxiliary console for test harnesses, see auxconsole.rs
+    AuxKbsr = 0x10,
+    AuxKbdr = 0x12,
+    AuxDsr = 0x14,
+    AuxDdr = 0x16,
+
+    // probe registers: the guest marks phases of its own run in the statistics (see README)
+    Probe = 0x18,     // address of a null-terminated marker label
+    ProbeCode = 0x1A, // a number, recorded as the marker `probe N`
+
+    // read-only identification block, see ident.rs
+    IdMagic = 0x30,
+    IdVersion = 0x32,
+    IdExtensions = 0x34,
+    IdIsa = 0x36,
+}
+
+const REGISTERS: [MemoryMappedReg; 14] = [
+    MemoryMappedReg::Kbsr,
+    MemoryMappedReg::Kbdr,
+    MemoryMappedReg::Dsr,
+    MemoryMappedReg::Ddr,
+    MemoryMappedReg::AuxKbsr,
+    MemoryMappedReg::AuxKbdr,
+    MemoryMappedReg::AuxDsr,
+    MemoryMappedReg::AuxDdr,
+    MemoryMappedReg::Probe,
+    MemoryMappedReg::ProbeCode,
+    MemoryMappedReg::IdMagic,
+    MemoryMappedReg::IdVersion,
+    MemoryMappedReg::IdExtensions,
+    MemoryMappedReg::IdIsa,
+];
+
+// Default offsets of the built-in devices
+pub const RANDOM: u16 = 0x20;
+pub const TIMER: u16 = 0x24;
+pub const CONTEXT_SWITCH: u16 = 0x28;
+pub const CONFIG_ROM: RangeInclusive<u16> = 0x40..=0xFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoPage {
+    pub range: RangeInclusive<u16>,
+}
+
+impl Default for IoPage {
+    fn default() -> Self {
+        IoPage {
+            range: 0xFE00..=0xFFFF,
+        }
+    }
+}
+
+impl IoPage {
+    pub fn new(range: RangeInclusive<u16>) -> Result<IoPage, String> {
+        if range.len() < MIN_SIZE {
+            return Err(format!(
+                "the I/O page x{:04X}-x{:04X} is smaller than the x{:X} words the device registers need",
+                range.start(),
+                range.end(),
+                MIN_SIZE
+            ));
+        }
+        Ok(IoPage { range })
+    }
+
+    pub fn base(&self) -> u16 {
+        *self.range.start()
+    }
+
+    pub fn address(&self, register: MemoryMappedReg) -> u16 {
+        self.base() + register as u16
+    }
+
+    // The machine control register, the page's last word but one (xFFFE by default) wherever the page is
+    pub fn mcr(&self) -> u16 {
+        self.range.end() - 1
+    }
+
+    // The standard register at this address, if any
+    pub fn register_at(&self, address: u16) -> Option<MemoryMappedReg> {
+        let offset = address.checked_sub(self.base())?;
+        REGISTERS.into_iter().find(|&r| r as u16 == offset)
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.range.contains(&address)
+    }
+
+    pub fn overlaps(&self, range: &RangeInclusive<u16>) -> bool {
+        range.start() <= self.range.end() && self.range.start() <= range.end()
+    }
+}
+
+// Under --strict, a load or store in the I/O page where there's no register or device. Stops the run.
+#[derive(Debug)]
+pub struct Unmapped {
+    pub address: u16,
+    // the load or store instruction
+    pub pc: u16,
+    pub store: bool,
+}
+
+impl Unmapped {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        format!(
+            "{} unmapped I/O address x{:04X} at {}",
+            if self.store { "store to" } else { "load from" },
+            self.address,
+            map.at(self.pc)
+        )
+    }
+}
+
+// Parse an `--io-page` argument: START:END
+pub fn parse_io_page(s: &str) -> Result<IoPage, String> {
+    IoPage::new(parse_range(s)?)
+}
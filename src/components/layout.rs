@@ -0,0 +1,131 @@
+// The address-space layout built up while loading: which ranges are occupied and by what.
+// Overlapping loads are refused unless explicitly allowed, since they silently clobber each other otherwise.
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
+
+use super::results::json_string;
+
+pub struct Segment {
+    pub range: RangeInclusive<u16>,
+    pub source: String,
+}
+
+#[derive(Default)]
+pub struct Layout {
+    pub segments: Vec<Segment>,
+    pub allow_overlap: bool,
+}
+
+impl Layout {
+    pub fn new() -> Layout {
+        Layout::default()
+    }
+
+    // Record a load of `len` words at `start`. Empty loads occupy nothing.
+    pub fn add(&mut self, start: u16, len: usize, source: &str) -> Result<(), String> {
+        if len == 0 {
+            return Ok(());
+        }
+        let range = start..=(start as usize + len - 1) as u16;
+        if !self.allow_overlap {
+            if let Some(other) = self
+                .segments
+                .iter()
+                .find(|s| s.range.start() <= range.end() && range.start() <= s.range.end())
+            {
+                return Err(format!(
+                    "{} (x{:04X}-x{:04X}) overlaps {} (x{:04X}-x{:04X}); pass --allow-overlap to load anyway",
+                    source,
+                    range.start(),
+                    range.end(),
+                    other.source,
+                    other.range.start(),
+                    other.range.end()
+                ));
+            }
+        }
+        self.segments.push(Segment {
+            range,
+            source: source.to_string(),
+        });
+        Ok(())
+    }
+
+    fn sorted(&self) -> Vec<&Segment> {
+        let mut segments: Vec<&Segment> = self.segments.iter().collect();
+        segments.sort_by_key(|s| *s.range.start());
+        segments
+    }
+
+    // Unoccupied ranges between the first and last segment
+    pub fn gaps(&self) -> Vec<RangeInclusive<u16>> {
+        let mut gaps = Vec::new();
+        let mut next_free: Option<u32> = None;
+        for segment in self.sorted() {
+            let start = *segment.range.start() as u32;
+            if let Some(free) = next_free {
+                if start > free {
+                    gaps.push(free as u16..=(start - 1) as u16);
+                }
+            }
+            let end = *segment.range.end() as u32 + 1;
+            next_free = Some(next_free.map_or(end, |f| f.max(end)));
+        }
+        gaps
+    }
+
+    // Segments in address order with the gaps between them
+    pub fn render_text(&self, entry: u16) -> String {
+        let mut out = String::from("address space layout:\n");
+        let mut gaps = self.gaps().into_iter().peekable();
+        for segment in self.sorted() {
+            while let Some(gap) = gaps.next_if(|g| g.start() < segment.range.start()) {
+                writeln!(
+                    out,
+                    "  x{:04X}-x{:04X}  {:>5} words  (gap)",
+                    gap.start(),
+                    gap.end(),
+                    gap.len()
+                )
+                .unwrap();
+            }
+            writeln!(
+                out,
+                "  x{:04X}-x{:04X}  {:>5} words  {}",
+                segment.range.start(),
+                segment.range.end(),
+                segment.range.len(),
+                segment.source
+            )
+            .unwrap();
+        }
+        writeln!(out, "  entry PC x{:04X}", entry).unwrap();
+        out
+    }
+
+    pub fn render_json(&self, entry: u16) -> String {
+        let segments: Vec<String> = self
+            .sorted()
+            .iter()
+            .map(|s| {
+                format!(
+                    "{{\"start\": {}, \"end\": {}, \"source\": {}}}",
+                    s.range.start(),
+                    s.range.end(),
+                    json_string(&s.source)
+                )
+            })
+            .collect();
+        let gaps: Vec<String> = self
+            .gaps()
+            .iter()
+            .map(|g| format!("{{\"start\": {}, \"end\": {}}}", g.start(), g.end()))
+            .collect();
+        format!(
+            "{{\"entry\": {}, \"segments\": [{}], \"gaps\": [{}]}}\n",
+            entry,
+            segments.join(", "),
+            gaps.join(", ")
+        )
+    }
+}
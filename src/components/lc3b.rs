@@ -0,0 +1,355 @@
+// The LC-3b (--isa lc3b): the byte-addressable variant of the LC-3 some courses teach. Addresses are bytes and
+// words are two bytes, low byte first, so the PC moves by 2 and PC-relative offsets are counted in words. LD/ST
+// and their indirect forms give way to LDB/STB (a sign-extended byte) and LDW/STW (a word), NOT is XOR with #-1,
+// and opcode 1101 is SHF (LSHF, RSHFL, RSHFA). LEA leaves the condition codes alone.
+//
+// The VM's memory stays an array of words: the byte at address A is one half of word A/2, which is also how
+// loaded images, dumps, watches and --region addresses number memory under this ISA. The I/O page is the
+// exception: device registers keep their addresses (KBSR at xFE00, DDR at xFE06), which the LC-3b's byte addresses
+// for them already are. Interrupts, exceptions and RTI push single words as on the LC-3, so an LC-3b OS that uses
+// them won't find its stack where it expects it.
+use super::cpu::Cpu;
+use super::instruction::{self, sign_extend, trap_name};
+use super::interrupt::{self, Exception};
+use super::os::TrapMode;
+use super::vm::{ExecState, VmError, VM};
+
+pub struct Lc3b;
+
+pub static LC3B: Lc3b = Lc3b;
+
+impl Cpu for Lc3b {
+    fn name(&self) -> &'static str {
+        "lc3b"
+    }
+
+    fn fetch(&self, vm: &mut VM) -> u16 {
+        let pc = vm.registers.pc;
+        let instruction = if vm.io_page.contains(pc) {
+            vm.fetch(pc & !1)
+        } else {
+            vm.fetch(pc >> 1)
+        };
+        vm.registers.pc = pc.wrapping_add(2);
+        instruction
+    }
+
+    fn execute(&self, instruction: u16, vm: &mut VM) -> Result<ExecState, VmError> {
+        execute_instruction(instruction, vm);
+        vm.state()
+    }
+
+    fn register_names(&self) -> &'static [&'static str] {
+        super::cpu::LC3.register_names()
+    }
+
+    fn register(&self, vm: &VM, index: usize) -> u16 {
+        vm.registers.get(index as u16)
+    }
+
+    fn set_register(&self, vm: &mut VM, index: usize, value: u16) {
+        vm.registers.update(index as u16, value);
+    }
+
+    fn disassemble(&self, instruction: u16, address: u16) -> String {
+        disassemble_at(instruction, address)
+    }
+
+    fn word_index(&self, address: u16) -> u16 {
+        address >> 1
+    }
+}
+
+fn execute_instruction(instruction: u16, vm: &mut VM) {
+    let dr = (instruction >> 9) & 0x7;
+    let sr1 = (instruction >> 6) & 0x7;
+    let base = vm.registers.get(sr1);
+    // the second ALU operand: a register or a 5-bit immediate
+    let operand2 = if (instruction >> 5) & 1 == 1 {
+        sign_extend(instruction & 0x1F, 5)
+    } else {
+        vm.registers.get(instruction & 0x7)
+    };
+    let offset6 = sign_extend(instruction & 0x3F, 6);
+    let set = |vm: &mut VM, value: u16| {
+        vm.registers.update(dr, value);
+        vm.registers.update_r_cond_register(dr);
+    };
+
+    match instruction >> 12 {
+        0x0 => {
+            if dr & vm.registers.cond != 0 {
+                vm.registers.pc = pc_relative(vm, instruction, 9);
+            }
+        }
+        0x1 => set(vm, base.wrapping_add(operand2)),
+        0x2 => {
+            let byte = load_byte(vm, base.wrapping_add(offset6));
+            set(vm, sign_extend(byte, 8));
+        }
+        0x3 => store_byte(vm, base.wrapping_add(offset6), vm.registers.get(dr) as u8),
+        0x4 => {
+            let target = if (instruction >> 11) & 1 == 1 {
+                pc_relative(vm, instruction, 11)
+            } else {
+                base
+            };
+            let pc = vm.registers.pc;
+            vm.registers.r7 = pc;
+            vm.registers.pc = target;
+            vm.call_stack.call(pc.wrapping_sub(2), target, pc);
+        }
+        0x5 => set(vm, base & operand2),
+        0x6 => {
+            let word = load_word(vm, base.wrapping_add(offset6 << 1));
+            set(vm, word);
+        }
+        0x7 => store_word(vm, base.wrapping_add(offset6 << 1), vm.registers.get(dr)),
+        0x8 => interrupt::rti(vm),
+        0x9 => set(vm, base ^ operand2),
+        0xC => {
+            vm.registers.pc = base;
+            vm.call_stack.ret(base);
+        }
+        0xD => {
+            let amount = instruction & 0xF;
+            let value = match (instruction >> 4) & 0x3 {
+                0 | 2 => base << amount,
+                1 => base >> amount,
+                _ => ((base as i16) >> amount) as u16,
+            };
+            set(vm, value);
+        }
+        0xE => {
+            let address = pc_relative(vm, instruction, 9);
+            vm.registers.update(dr, address);
+        }
+        0xF => trap(instruction, vm),
+        // 1010 and 1011 are unused
+        _ => interrupt::exception(vm, Exception::IllegalOpcode),
+    }
+}
+
+// PC plus a sign-extended word offset of `bits` bits
+fn pc_relative(vm: &VM, instruction: u16, bits: u8) -> u16 {
+    let offset = sign_extend(instruction & ((1 << bits) - 1), bits);
+    vm.registers.pc.wrapping_add(offset << 1)
+}
+
+// The vector table holds byte addresses at x0000-x01FF, a word a vector, and --os routines are reached through it
+// as on the LC-3. Without one, PUTS prints a byte a character and the other traps are the LC-3's.
+fn trap(instruction: u16, vm: &mut VM) {
+    let vector = instruction & 0xFF;
+    let routine = vm.traps != TrapMode::Native && vm.memory[vector as usize] != 0;
+    if vector != 0x22 || routine {
+        instruction::trap(instruction, vm);
+        return;
+    }
+    if let Some(stats) = vm.stats.as_mut() {
+        stats.record_trap(vm.registers.pc.wrapping_sub(2), vector, vm.registers.r0);
+    }
+    let mut address = vm.registers.r0;
+    loop {
+        let byte = load_byte(vm, address);
+        if byte == 0 {
+            break;
+        }
+        vm.console.write_byte(byte as u8);
+        address = address.wrapping_add(1);
+    }
+    vm.console.flush();
+}
+
+fn load_word(vm: &mut VM, address: u16) -> u16 {
+    if vm.io_page.contains(address) {
+        vm.read_memory(address & !1)
+    } else {
+        vm.read_memory(address >> 1)
+    }
+}
+
+fn store_word(vm: &mut VM, address: u16, value: u16) {
+    if vm.io_page.contains(address) {
+        vm.write_memory(address & !1, value);
+    } else {
+        vm.write_memory(address >> 1, value);
+    }
+}
+
+fn load_byte(vm: &mut VM, address: u16) -> u16 {
+    let word = load_word(vm, address);
+    if address & 1 == 1 {
+        word >> 8
+    } else {
+        word & 0xFF
+    }
+}
+
+// The other half of the word is left as it is; a device register just gets the byte
+fn store_byte(vm: &mut VM, address: u16, byte: u8) {
+    if vm.io_page.contains(address) {
+        vm.write_memory(address & !1, byte as u16);
+        return;
+    }
+    let word = vm.memory[(address >> 1) as usize];
+    let word = if address & 1 == 1 {
+        word & 0x00FF | (byte as u16) << 8
+    } else {
+        word & 0xFF00 | byte as u16
+    };
+    vm.write_memory(address >> 1, word);
+}
+
+pub fn disassemble_at(instruction: u16, address: u16) -> String {
+    let dr = (instruction >> 9) & 0x7;
+    let sr1 = (instruction >> 6) & 0x7;
+    let target = |bits: u8| {
+        let offset = sign_extend(instruction & ((1 << bits) - 1), bits);
+        format!("x{:04X}", address.wrapping_add(2).wrapping_add(offset << 1))
+    };
+    let operand2 = || {
+        if (instruction >> 5) & 1 == 1 {
+            format!("#{}", sign_extend(instruction & 0x1F, 5) as i16)
+        } else {
+            format!("R{}", instruction & 0x7)
+        }
+    };
+    let offset6 = sign_extend(instruction & 0x3F, 6) as i16;
+
+    match instruction >> 12 {
+        0x0 => {
+            let flags: String = [(0x4, 'n'), (0x2, 'z'), (0x1, 'p')]
+                .iter()
+                .filter(|(bit, _)| dr & bit != 0)
+                .map(|(_, c)| *c)
+                .collect();
+            if flags.is_empty() {
+                "NOP".to_string()
+            } else {
+                format!("BR{} {}", flags, target(9))
+            }
+        }
+        0x1 => format!("ADD R{}, R{}, {}", dr, sr1, operand2()),
+        0x2 => format!("LDB R{}, R{}, #{}", dr, sr1, offset6),
+        0x3 => format!("STB R{}, R{}, #{}", dr, sr1, offset6),
+        0x4 if (instruction >> 11) & 1 == 1 => format!("JSR {}", target(11)),
+        0x4 => format!("JSRR R{}", sr1),
+        0x5 => format!("AND R{}, R{}, {}", dr, sr1, operand2()),
+        0x6 => format!("LDW R{}, R{}, #{}", dr, sr1, offset6),
+        0x7 => format!("STW R{}, R{}, #{}", dr, sr1, offset6),
+        0x8 => "RTI".to_string(),
+        0x9 if instruction & 0x3F == 0x3F => format!("NOT R{}, R{}", dr, sr1),
+        0x9 => format!("XOR R{}, R{}, {}", dr, sr1, operand2()),
+        0xC if sr1 == 7 => "RET".to_string(),
+        0xC => format!("JMP R{}", sr1),
+        0xD => {
+            let name = match (instruction >> 4) & 0x3 {
+                0 | 2 => "LSHF",
+                1 => "RSHFL",
+                _ => "RSHFA",
+            };
+            format!("{} R{}, R{}, #{}", name, dr, sr1, instruction & 0xF)
+        }
+        0xE => format!("LEA R{}, {}", dr, target(9)),
+        0xF => match trap_name(instruction & 0xFF) {
+            Some(name) => name.to_string(),
+            None => format!("TRAP x{:02X}", instruction & 0xFF),
+        },
+        _ => format!(".FILL x{:04X}", instruction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::testing::{output, VmBuilder, N, P, Z};
+    use crate::components::vm::PollResult;
+
+    // a program at byte address x3000, which is word x1800
+    fn machine(words: &[u16]) -> Box<VM> {
+        let mut vm = VmBuilder::new()
+            .with_memory(0x1800, words)
+            .with_pc(0x3000)
+            .build();
+        vm.cpu = &LC3B;
+        vm
+    }
+
+    #[test]
+    fn bytes_and_words() {
+        let mut vm = machine(&[
+            0xE208, // LEA R1, x3012 (the data, 8 words on)
+            0x2440, // LDB R2, R1, #0
+            0x2641, // LDB R3, R1, #1
+            0x6841, // LDW R4, R1, #1
+            0x3841, // STB R4, R1, #1
+            0x7641, // STW R3, R1, #1
+            0xF025, // HALT
+        ]);
+        vm.memory[0x1809] = 0x80FF;
+        vm.memory[0x180A] = 0x1234;
+        vm.run().unwrap();
+        assert_eq!(vm.registers.r1, 0x3012);
+        // bytes sign-extend: xFF is -1, x80 is -128
+        assert_eq!((vm.registers.r2, vm.registers.r3), (0xFFFF, 0xFF80));
+        assert_eq!(vm.registers.r4, 0x1234);
+        assert_eq!(vm.registers.cond, P);
+        // STB replaced only the high byte of the word at x3012; STW wrote all of x3014
+        assert_eq!(vm.memory[0x1809], 0x34FF);
+        assert_eq!(vm.memory[0x180A], 0xFF80);
+        assert_eq!(vm.registers.pc, 0x300E);
+    }
+
+    #[test]
+    fn shifts_xor_and_branches() {
+        let mut vm = machine(&[
+            0x1260, // ADD R1, R1, #0
+            0x127F, // ADD R1, R1, #-1
+            0xD443, // LSHF R2, R1, #3
+            0xD654, // RSHFL R3, R1, #4
+            0xD8B2, // RSHFA R4, R2, #2
+            0x9A7F, // NOT R5, R1
+            0x0BFB, // BRnp back to x3004: not taken, Z set by NOT
+            0xF025, // HALT
+        ]);
+        vm.run().unwrap();
+        assert_eq!(vm.registers.r2, 0xFFF8);
+        assert_eq!(vm.registers.r3, 0x0FFF);
+        assert_eq!(vm.registers.r4, 0xFFFE);
+        assert_eq!(vm.registers.r5, 0);
+        assert_eq!(vm.registers.cond, Z);
+        assert_eq!(disassemble_at(0x0BFB, 0x300C), "BRnp x3004");
+        assert_eq!(disassemble_at(0xD8B2, 0x3000), "RSHFA R4, R2, #2");
+
+        // a taken branch counts its offset in words
+        let mut vm = machine(&[0x1FFF, 0x0801, 0xF025, 0xF025]);
+        vm.step().unwrap();
+        assert_eq!(vm.registers.cond, N);
+        vm.step().unwrap();
+        assert_eq!(vm.registers.pc, 0x3006);
+    }
+
+    #[test]
+    fn puts_prints_a_byte_a_character() {
+        let mut vm = machine(&[0xE002, 0xF022, 0xF025, 0x6968, 0x0021]);
+        vm.run().unwrap();
+        assert_eq!(output(&vm), "hi!");
+    }
+
+    #[test]
+    fn run_for_waits_at_the_getc_the_pc_is_on() {
+        let mut vm = machine(&[
+            0x1261, // ADD R1, R1, #1
+            0xF020, // GETC
+            0xF025, // HALT
+        ]);
+        // word x3000 isn't the instruction at byte address x3000, so it mustn't count as a GETC
+        vm.memory[0x3000] = 0xF020;
+        assert_eq!(vm.run_for(10), PollResult::NeedsInput);
+        assert_eq!((vm.registers.pc, vm.registers.r1), (0x3002, 1));
+
+        vm.console.push_input(b"k");
+        assert_eq!(vm.run_for(10), PollResult::Halted);
+        assert_eq!(vm.registers.r0, b'k' as u16);
+    }
+}
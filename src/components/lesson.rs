@@ -0,0 +1,325 @@
+// Lessons (`lc3_sim lesson intro.lesson`, or --lesson with --debug): a guided walk through a program at the
+// debugger prompt. A lesson file names the program and lists its checkpoints in order, one step a line:
+//
+//   # multiplying by adding
+//   program multiply.obj           # relative to the lesson
+//   break AGAIN                    # wait until the program stops at AGAIN (an address or a symbol)
+//   explain R0 is the running total; each time round adds R2 to it.
+//   predict R0                     # ask for R0's value before showing it
+//   verify R1=#0 PRODUCT=#12       # wait until these hold, as a spec's registers and memory say them
+//
+// Explanations and questions come as soon as the steps before them are done. A break or a verify waits for the
+// learner to get there with the usual commands (continue, step, next ...), and the lesson takes up again at the
+// prompt after the command that did it.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::equiv::{parse_register, parse_word};
+use super::query::resolve;
+use super::vm::VM;
+use super::watch::Target;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    Break(u16),
+    Explain(String),
+    Predict(u16),
+    Verify(Vec<(Target, u16)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lesson {
+    pub program: PathBuf,
+    pub steps: Vec<Step>,
+    // the step the lesson is at
+    next: usize,
+    // what the current step waits for has been said, and that the program stopped before it
+    announced: bool,
+    stranded: bool,
+    // a breakpoint the lesson set for its break, taken away again once it's reached
+    placed: Option<u16>,
+}
+
+// The program a lesson file names, relative to the lesson
+pub fn program(path: &Path) -> Result<PathBuf, String> {
+    let text = read(path)?;
+    let program = lines(&text)
+        .find(|(_, keyword, _)| *keyword == "program")
+        .map(|(_, _, rest)| PathBuf::from(rest))
+        .ok_or_else(|| format!("{}: no `program` to run", path.display()))?;
+    Ok(path
+        .parent()
+        .map_or(program.clone(), |dir| dir.join(program)))
+}
+
+// Load a lesson, naming addresses from the program's symbols
+pub fn load(path: &Path, symbols: &[(u16, String)]) -> Result<Lesson, String> {
+    let mut lesson =
+        parse(&read(path)?, symbols).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if let Some(dir) = path.parent() {
+        lesson.program = dir.join(&lesson.program);
+    }
+    Ok(lesson)
+}
+
+pub fn parse(text: &str, symbols: &[(u16, String)]) -> Result<Lesson, String> {
+    let mut program = None;
+    let mut steps = Vec::new();
+    for (n, keyword, rest) in lines(text) {
+        let at = |e: String| format!("line {}: {}", n, e);
+        match keyword {
+            "program" => program = Some(PathBuf::from(rest)),
+            "break" => steps.push(Step::Break(resolve(rest, symbols).map_err(at)?)),
+            "explain" => steps.push(Step::Explain(rest.to_string())),
+            "predict" => steps.push(Step::Predict(parse_register(rest).map_err(at)?)),
+            "verify" => steps.push(Step::Verify(checks(rest, symbols).map_err(at)?)),
+            _ => return Err(at(format!("unknown step `{}`", keyword))),
+        }
+    }
+    Ok(Lesson {
+        program: program.ok_or("no `program` to run")?,
+        steps,
+        next: 0,
+        announced: false,
+        stranded: false,
+        placed: None,
+    })
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))
+}
+
+// The line number, keyword and the rest of each line with a step on it. `#` starts a comment at the start of a
+// line or after a space, except in an explanation, which is all text.
+fn lines(text: &str) -> impl Iterator<Item = (usize, &str, &str)> {
+    text.lines().enumerate().filter_map(|(n, line)| {
+        let line = line.trim();
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let rest = match rest.find(" #") {
+            Some(comment) if keyword != "explain" => rest[..comment].trim_end(),
+            _ => rest,
+        };
+        (!keyword.is_empty() && !keyword.starts_with('#')).then_some((n + 1, keyword, rest))
+    })
+}
+
+// `TARGET=VALUE` pairs: a register, or an address or symbol for a word of memory
+fn checks(text: &str, symbols: &[(u16, String)]) -> Result<Vec<(Target, u16)>, String> {
+    if text.is_empty() {
+        return Err("nothing to verify".to_string());
+    }
+    text.split_whitespace()
+        .map(|field| {
+            let (left, right) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected TARGET=VALUE, got `{}`", field))?;
+            let target = match parse_register(left) {
+                Ok(r) => Target::Register(r),
+                Err(_) => Target::Memory(resolve(left, symbols)?),
+            };
+            Ok((target, parse_word(right)?))
+        })
+        .collect()
+}
+
+fn value(vm: &VM, target: Target) -> u16 {
+    match target {
+        Target::Register(r) => vm.registers.get(r),
+        Target::Memory(address) => vm.memory[address as usize],
+    }
+}
+
+fn word(value: u16) -> String {
+    format!("x{:04X} (#{})", value, value as i16)
+}
+
+impl Lesson {
+    // Do every step that can be done now and return what the lesson says. Stops at a question, at a break the
+    // program hasn't reached or at a verify that doesn't hold yet.
+    pub fn advance(&mut self, vm: &mut VM) -> String {
+        let mut said = Vec::new();
+        let start = self.next;
+        while let Some(step) = self.steps.get(self.next) {
+            let waiting = match step {
+                Step::Explain(text) => {
+                    said.push(text.clone());
+                    None
+                }
+                Step::Predict(_) => break,
+                Step::Break(address) if vm.registers.pc == *address => {
+                    if let Some(placed) = self.placed.take() {
+                        vm.breakpoints.remove(&placed);
+                    }
+                    None
+                }
+                Step::Break(address) => {
+                    if vm.breakpoints.insert(*address) {
+                        self.placed = Some(*address);
+                    }
+                    Some(format!("(run to x{:04X} to go on)", address))
+                }
+                Step::Verify(checks) => {
+                    let wrong: Vec<String> = checks
+                        .iter()
+                        .filter(|&&(target, expected)| value(vm, target) != expected)
+                        .map(|&(target, expected)| format!("{} = {}", target, word(expected)))
+                        .collect();
+                    if wrong.is_empty() {
+                        said.push("(verified)".to_string());
+                        None
+                    } else {
+                        Some(format!("(go on until {})", wrong.join(" and ")))
+                    }
+                }
+            };
+            if let Some(waiting) = waiting {
+                if !self.announced {
+                    said.push(waiting);
+                    self.announced = true;
+                } else if (vm.halted || vm.fault.is_some()) && !self.stranded {
+                    self.stranded = true;
+                    said.push(
+                        "(the program has stopped short of this: restart to try again)".to_string(),
+                    );
+                }
+                break;
+            }
+            self.next += 1;
+            self.announced = false;
+            self.stranded = false;
+        }
+        if self.finished() && self.next > start {
+            said.push("(end of the lesson)".to_string());
+        }
+        said.join("\n")
+    }
+
+    // The prompt for a prediction the lesson is waiting on
+    pub fn question(&self) -> Option<String> {
+        match self.steps.get(self.next) {
+            Some(Step::Predict(r)) => Some(format!("R{} = ? ", r)),
+            _ => None,
+        }
+    }
+
+    // Take the answer to the question and say whether it was right; the next advance goes on from there
+    pub fn answer(&mut self, vm: &VM, line: &str) -> String {
+        let Some(Step::Predict(r)) = self.steps.get(self.next) else {
+            return String::new();
+        };
+        let actual = vm.registers.get(*r);
+        let said = match parse_word(line.trim()) {
+            Err(_) => return format!("expected a value like x000C or #12, got `{}`", line.trim()),
+            Ok(guess) if guess == actual => format!("Right: R{} is {}.", r, word(actual)),
+            Ok(guess) => format!(
+                "Not quite: R{} is {}, not {}.",
+                r,
+                word(actual),
+                word(guess)
+            ),
+        };
+        self.next += 1;
+        self.announced = false;
+        said
+    }
+
+    pub fn finished(&self) -> bool {
+        self.next >= self.steps.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v2;
+
+    const MULTIPLY: &str = "
+            .ORIG x3000
+            LD R1, A
+            LD R2, B
+            AND R0, R0, #0
+    AGAIN   ADD R0, R0, R2
+            ADD R1, R1, #-1
+            BRp AGAIN
+            ST R0, PRODUCT
+            HALT
+    A       .FILL #3
+    B       .FILL #4
+    PRODUCT .BLKW 1
+            .END";
+
+    const LESSON: &str = "
+        # multiplying by adding
+        program multiply.obj
+        explain R2 is added to R0 three times.
+        break AGAIN   # the loop
+        predict R0
+        verify PRODUCT=#12
+        explain That's 3 times 4.";
+
+    #[test]
+    fn steps_parse_with_symbols() {
+        let image = assemble(MULTIPLY).unwrap();
+        let lesson = parse(LESSON, &image.metadata.symbols).unwrap();
+        assert_eq!(lesson.program, PathBuf::from("multiply.obj"));
+        assert_eq!(
+            lesson.steps,
+            [
+                Step::Explain("R2 is added to R0 three times.".to_string()),
+                Step::Break(0x3003),
+                Step::Predict(0),
+                Step::Verify(vec![(Target::Memory(0x300A), 12)]),
+                Step::Explain("That's 3 times 4.".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse("program a.obj\nask R0", &[]).unwrap_err(),
+            "line 2: unknown step `ask`"
+        );
+        assert!(parse("program a.obj\nbreak LOOP", &[]).is_err());
+        assert!(parse("break x3000", &[]).is_err());
+    }
+
+    #[test]
+    fn checkpoints_wait_for_the_program() {
+        let image = assemble(MULTIPLY).unwrap();
+        let mut lesson = parse(LESSON, &image.metadata.symbols).unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+
+        assert_eq!(
+            lesson.advance(&mut vm),
+            "R2 is added to R0 three times.\n(run to x3003 to go on)"
+        );
+        assert!(vm.breakpoints.contains(&0x3003));
+        // said once, however many commands it takes to get there
+        assert_eq!(lesson.advance(&mut vm), "");
+        vm.run().unwrap();
+        assert_eq!(lesson.advance(&mut vm), "");
+        assert!(!vm.breakpoints.contains(&0x3003));
+        assert_eq!(lesson.question().as_deref(), Some("R0 = ? "));
+
+        // an answer that isn't a number is asked for again
+        assert_eq!(
+            lesson.answer(&vm, "four"),
+            "expected a value like x000C or #12, got `four`"
+        );
+        assert!(lesson.question().is_some());
+        assert_eq!(
+            lesson.answer(&vm, "#4"),
+            "Not quite: R0 is x0000 (#0), not x0004 (#4)."
+        );
+        assert_eq!(lesson.advance(&mut vm), "(go on until x300A = x000C (#12))");
+        vm.stopped_at = None;
+        vm.run().unwrap();
+        assert_eq!(
+            lesson.advance(&mut vm),
+            "(verified)\nThat's 3 times 4.\n(end of the lesson)"
+        );
+        assert!(lesson.finished());
+        assert_eq!(lesson.advance(&mut vm), "");
+    }
+}
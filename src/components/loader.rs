@@ -0,0 +1,518 @@
+// Loading program objects and data files into memory ahead of execution.
+// Programs are .obj files (v1 or v2), the .hex and .bin text that lc3as-compatible tools write (one word per line,
+// the first being the origin), or raw memory images (bare words from x0000 on), see Format.
+// For data files the format follows the extension: `.txt` is a null-terminated string (one character per word, like .STRINGZ),
+// `.csv` is words separated by commas/whitespace (x-hex, #decimal or plain decimal), anything else is raw big-endian words.
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+use super::checksum;
+use super::diagnostics::Category;
+use super::cpu;
+use super::instruction::trap_name;
+use super::object::{self, Metadata, ObjectImage};
+use super::{parse_address, parse_range};
+use super::vm::{LoadedImage, VM};
+use super::MEMORY_SIZE;
+
+// Byte order of the words in a plain (v1) object. v2 objects are always big-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+    Auto, // whichever order gives the more plausible program
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "big" => Ok(Endian::Big),
+            "little" => Ok(Endian::Little),
+            "auto" => Ok(Endian::Auto),
+            _ => Err(format!(
+                "unknown byte order `{}` (expected big, little or auto)",
+                s
+            )),
+        }
+    }
+}
+
+// How a program file is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Auto, // by extension: .hex, .bin, anything else an object
+    Obj,
+    Hex, // a word per line in hex, origin first
+    Bin, // a word per line in binary, origin first
+    Raw, // bare words in the --endian order, loaded from x0000
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Format::Auto),
+            "obj" => Ok(Format::Obj),
+            "hex" => Ok(Format::Hex),
+            "bin" => Ok(Format::Bin),
+            "raw" => Ok(Format::Raw),
+            _ => Err(format!(
+                "unknown format `{}` (expected auto, obj, hex, bin or raw)",
+                s
+            )),
+        }
+    }
+}
+
+impl Format {
+    pub fn for_path(self, path: &Path) -> Format {
+        if self != Format::Auto {
+            return self;
+        }
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("hex") => Format::Hex,
+            Some("bin") => Format::Bin,
+            _ => Format::Obj,
+        }
+    }
+}
+
+// Load a v1 or v2 object file at its origin, returning the image with any metadata it carried.
+// A checksum sidecar is verified when present (against the file as stored), and required when `require_checksum` is set.
+pub fn load_object(
+    vm: &mut VM,
+    path: &Path,
+    require_checksum: bool,
+    endian: Endian,
+) -> Result<ObjectImage, String> {
+    load_program(vm, path, require_checksum, endian, Format::Obj)
+}
+
+// Load a program file in any of the formats, checked the same way as an object
+pub fn load_program(
+    vm: &mut VM,
+    path: &Path,
+    require_checksum: bool,
+    endian: Endian,
+    format: Format,
+) -> Result<ObjectImage, String> {
+    let bytes = fs::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    if !checksum::verify(path, &bytes)? && require_checksum {
+        return Err(format!(
+            "{} has no checksum sidecar ({})",
+            path.display(),
+            checksum::sidecar_path(path).display()
+        ));
+    }
+    let format = format.for_path(path);
+    if matches!(format, Format::Hex | Format::Bin) && endian == Endian::Little {
+        return Err(format!(
+            "{}: text formats have no byte order, --endian little doesn't apply",
+            path.display()
+        ));
+    }
+    let mut image = match format {
+        Format::Obj | Format::Auto => return load_object_bytes(vm, path, bytes, endian),
+        Format::Hex => parse_text(&bytes, 16),
+        Format::Bin => parse_text(&bytes, 2),
+        Format::Raw => parse_raw(&bytes, endian),
+    }
+    .map_err(|e| format!("{}: {}", path.display(), e))?;
+    if format == Format::Raw && image.words.len() > vm.io_page.base() as usize {
+        // a dump of the whole address space: what it holds for the I/O page is device state, not program
+        vm.warn(
+            Category::Load,
+            0,
+            format!(
+                "warning: {} runs into the I/O page at x{:04X}; the words from there on are skipped",
+                path.display(),
+                vm.io_page.base()
+            ),
+        );
+        image.words.truncate(vm.io_page.base() as usize);
+    }
+    load_object_bytes(vm, path, object::write_v1(&image), Endian::Big)
+}
+
+// A .hex (radix 16) or .bin (radix 2) file: one word per line, the first line being the origin. Blank lines and
+// `;` comments are skipped.
+fn parse_text(bytes: &[u8], radix: u32) -> Result<ObjectImage, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "not a text file".to_string())?;
+    let (name, digits) = if radix == 16 { ("hex", 4) } else { ("binary", 16) };
+    let mut words = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let number = if radix == 16 {
+            line.strip_prefix("0x")
+                .or_else(|| line.strip_prefix(['x', 'X']))
+                .unwrap_or(line)
+        } else {
+            line
+        };
+        if number.is_empty() || number.len() > digits {
+            return Err(format!(
+                "line {}: `{}` isn't a {} word of up to {} digits",
+                n + 1,
+                line,
+                name,
+                digits
+            ));
+        }
+        let word = u16::from_str_radix(number, radix)
+            .map_err(|_| format!("line {}: `{}` isn't a {} word", n + 1, line, name))?;
+        words.push(word);
+    }
+    if words.is_empty() {
+        return Err("no origin: the file is empty".to_string());
+    }
+    let origin = words.remove(0);
+    Ok(ObjectImage {
+        origin,
+        words,
+        metadata: Metadata::default(),
+    })
+}
+
+// The other way: `words` at `origin` as .hex or .bin text that parse_text reads back
+pub fn format_text(origin: u16, words: &[u16], radix: u32) -> String {
+    std::iter::once(&origin)
+        .chain(words)
+        .map(|word| {
+            if radix == 16 {
+                format!("{:04X}\n", word)
+            } else {
+                format!("{:016b}\n", word)
+            }
+        })
+        .collect()
+}
+
+// A memory image: words with no origin, starting at x0000
+fn parse_raw(bytes: &[u8], endian: Endian) -> Result<ObjectImage, String> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(format!(
+            "a memory image must be whole 16-bit words, found {} bytes",
+            bytes.len()
+        ));
+    }
+    let word = match endian {
+        Endian::Big => u16::from_be_bytes,
+        Endian::Little => u16::from_le_bytes,
+        // without an origin to go by there's nothing to guess from
+        Endian::Auto => {
+            return Err("--endian auto can't tell a memory image's byte order; give big or little".to_string())
+        }
+    };
+    Ok(ObjectImage {
+        origin: 0,
+        words: bytes.chunks(2).map(|pair| word([pair[0], pair[1]])).collect(),
+        metadata: Metadata::default(),
+    })
+}
+
+// Load an object already in memory; `path` only names it in messages and the layout
+pub fn load_object_bytes(
+    vm: &mut VM,
+    path: &Path,
+    bytes: Vec<u8>,
+    endian: Endian,
+) -> Result<ObjectImage, String> {
+    let bytes = byte_order(vm, path, bytes, endian)?;
+    let image = object::parse(&bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if let Some(isa) = &image.metadata.isa {
+        if cpu::by_name(isa).is_none() {
+            return Err(format!(
+                "{}: built for ISA profile `{}`, which this simulator doesn't support",
+                path.display(),
+                isa
+            ));
+        }
+        if isa != vm.cpu.name() {
+            return Err(format!(
+                "{}: built for ISA profile `{}`, but the machine runs `{}`",
+                path.display(),
+                isa,
+                vm.cpu.name()
+            ));
+        }
+    }
+    // the origin is a byte address on the LC-3b
+    let start = vm.cpu.word_index(image.origin);
+    if start as usize + image.words.len() > vm.memory.len() {
+        return Err(format!(
+            "{}: {} words at x{:04X} run past the end of memory",
+            path.display(),
+            image.words.len(),
+            image.origin
+        ));
+    }
+
+    vm.layout
+        .add(start, image.words.len(), &path.display().to_string())?;
+    warn_io_page(vm, path, start, image.words.len());
+    for (offset, word) in image.words.iter().enumerate() {
+        vm.write_memory(start + offset as u16, *word);
+    }
+    vm.loaded.push(LoadedImage {
+        origin: start,
+        words: image.words.clone(),
+    });
+    Ok(image)
+}
+
+// Load another object from inside a run (TRAP x2E) and return where to continue: its entry point, or its origin.
+// The name is confined to the directory the program was loaded from, and the load goes through the same layout,
+// protection and watch checks as any store.
+pub fn chain_load(vm: &mut VM, name: &str) -> Result<u16, String> {
+    let dir = vm
+        .load_dir
+        .clone()
+        .ok_or_else(|| "chain loading isn't available in this run".to_string())?;
+    let relative = Path::new(name);
+    if name.is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(format!(
+            "`{}` isn't a plain relative path inside {}",
+            name,
+            dir.display()
+        ));
+    }
+    let image = load_object(vm, &dir.join(relative), false, Endian::Big)?;
+    Ok(image.metadata.entry.unwrap_or(image.origin))
+}
+
+// The object's bytes in big-endian order, swapping them when the file holds little-endian words
+fn byte_order(vm: &mut VM, path: &Path, bytes: Vec<u8>, endian: Endian) -> Result<Vec<u8>, String> {
+    if bytes.starts_with(object::MAGIC) {
+        if endian == Endian::Little {
+            return Err(format!(
+                "{}: v2 objects are always big-endian, --endian little doesn't apply",
+                path.display()
+            ));
+        }
+        return Ok(bytes);
+    }
+
+    let straight = plausibility(&bytes);
+    let swapped = plausibility(&swap_bytes(&bytes));
+    let little = match endian {
+        Endian::Big => {
+            if swapped > straight && straight < 0 {
+                // keyed on x0000: a run loads a single object
+                vm.warn(Category::Endian, 0, format!(
+                    "warning: {} looks byte-swapped; if it was written little-endian, pass --endian little (or auto)",
+                    path.display()
+                ));
+            }
+            false
+        }
+        Endian::Little => true,
+        Endian::Auto => {
+            let little = swapped > straight;
+            if !vm.quiet {
+                eprintln!(
+                    "{}: words look {}-endian, loading them as such",
+                    path.display(),
+                    if little { "little" } else { "big" }
+                );
+            }
+            little
+        }
+    };
+    Ok(if little { swap_bytes(&bytes) } else { bytes })
+}
+
+fn swap_bytes(bytes: &[u8]) -> Vec<u8> {
+    let mut swapped = bytes.to_vec();
+    for pair in swapped.chunks_exact_mut(2) {
+        pair.swap(0, 1);
+    }
+    swapped
+}
+
+// How much a big-endian v1 object looks like a real program: a usual origin, and opening words that decode to
+// instructions a program would start with. Higher is more plausible.
+fn plausibility(bytes: &[u8]) -> i32 {
+    let Ok(image) = object::parse(bytes) else {
+        return i32::MIN;
+    };
+    if image.origin as usize + image.words.len() > MEMORY_SIZE {
+        return i32::MIN;
+    }
+    let mut score = match image.origin {
+        0x3000..=0xFDFF => 4,
+        0x0200..=0x2FFF => 0, // operating system space
+        _ => -4,              // trap vector table or device page
+    };
+    for &word in image.words.iter().take(16) {
+        score += match word >> 12 {
+            0xD => -3, // reserved opcode
+            0x8 => -2, // RTI has no business in a user program
+            0xF if word & 0x0F00 != 0 => -3,
+            0xF if trap_name(word & 0xFF).is_none() => -1,
+            0xF => 2,
+            _ => 0,
+        };
+    }
+    score
+}
+
+// Place a data file at `address`, returning how many words were written
+pub fn load_data(vm: &mut VM, path: &Path, address: u16) -> Result<usize, String> {
+    let bytes = fs::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+
+    let words = match path.extension().and_then(|e| e.to_str()) {
+        Some("txt") => bytes
+            .iter()
+            .map(|&b| b as u16)
+            .chain(std::iter::once(0))
+            .collect(),
+        Some("csv") => parse_csv(&String::from_utf8_lossy(&bytes))
+            .map_err(|e| format!("{}: {}", path.display(), e))?,
+        _ => {
+            if bytes.len() % 2 != 0 {
+                return Err(format!(
+                    "{}: raw data must be whole 16-bit words, found {} bytes",
+                    path.display(),
+                    bytes.len()
+                ));
+            }
+            bytes
+                .chunks(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect::<Vec<u16>>()
+        }
+    };
+
+    if address as usize + words.len() > vm.memory.len() {
+        return Err(format!(
+            "{}: {} words at x{:04X} run past the end of memory",
+            path.display(),
+            words.len(),
+            address
+        ));
+    }
+
+    vm.layout
+        .add(address, words.len(), &path.display().to_string())?;
+    warn_io_page(vm, path, address, words.len());
+    for (offset, word) in words.iter().enumerate() {
+        vm.write_memory(address + offset as u16, *word);
+    }
+    let count = words.len();
+    vm.loaded.push(LoadedImage {
+        origin: address,
+        words,
+    });
+    Ok(count)
+}
+
+// Words loaded into the I/O page land in device registers rather than memory, which is rarely what was meant
+fn warn_io_page(vm: &mut VM, path: &Path, start: u16, len: usize) {
+    if len == 0 {
+        return;
+    }
+    let range = start..=(start as usize + len - 1) as u16;
+    if vm.io_page.overlaps(&range) {
+        let warning = format!(
+            "warning: {} (x{:04X}-x{:04X}) loads into the I/O page x{:04X}-x{:04X}",
+            path.display(),
+            range.start(),
+            range.end(),
+            vm.io_page.range.start(),
+            vm.io_page.range.end()
+        );
+        vm.warn(Category::Load, start, warning);
+    }
+}
+
+fn parse_csv(text: &str) -> Result<Vec<u16>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|field| !field.is_empty())
+        .map(|field| {
+            // negative values are stored two's complement, as .FILL #-1 would be
+            match field.strip_prefix('-').or_else(|| field.strip_prefix("#-")) {
+                Some(magnitude) => magnitude
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|&m| m <= 0x8000)
+                    .map(|m| m.wrapping_neg())
+                    .ok_or_else(|| format!("invalid word `{}`", field)),
+                None => parse_address(field).map_err(|_| format!("invalid word `{}`", field)),
+            }
+        })
+        .collect()
+}
+
+// Parse a `--load-data` argument: FILE@ADDRESS
+pub fn parse_data_arg(s: &str) -> Result<(std::path::PathBuf, u16), String> {
+    let (path, address) = s
+        .rsplit_once('@')
+        .ok_or_else(|| format!("expected FILE@ADDRESS, got `{}`", s))?;
+    Ok((path.into(), parse_address(address)?))
+}
+
+// `--dump-range START:END=FILE`
+pub fn parse_dump_arg(s: &str) -> Result<(RangeInclusive<u16>, std::path::PathBuf), String> {
+    let (range, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected START:END=FILE, got `{}`", s))?;
+    if path.is_empty() {
+        return Err(format!("no file to dump `{}` to", range));
+    }
+    Ok((parse_range(range)?, path.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_formats_start_with_the_origin() {
+        let hex = parse_text(b"3000\nx1021 ; ADD R0, R0, #1\n\n0xF025\n", 16).unwrap();
+        let bin = parse_text(
+            b"0011000000000000\n0001000000100001\n1111000000100101\n",
+            2,
+        )
+        .unwrap();
+        assert_eq!((hex.origin, &hex.words[..]), (0x3000, &[0x1021, 0xF025][..]));
+        assert_eq!((bin.origin, bin.words), (hex.origin, hex.words));
+
+        assert_eq!(
+            parse_text(b"3000\n1021\n10210\n", 16).unwrap_err(),
+            "line 3: `10210` isn't a hex word of up to 4 digits"
+        );
+        assert_eq!(
+            parse_text(b"0011000000000000\n0001000000100002\n", 2).unwrap_err(),
+            "line 2: `0001000000100002` isn't a binary word"
+        );
+        assert!(parse_text(b"; nothing\n", 16).is_err());
+
+        // dumps read back as they were written
+        let text = format_text(0x3000, &[0x1021, 0xF025], 2);
+        assert_eq!(parse_text(text.as_bytes(), 2).unwrap().words, [0x1021, 0xF025]);
+        assert_eq!(format_text(0x3000, &[0x1021], 16), "3000\n1021\n");
+    }
+
+    #[test]
+    fn raw_images_load_from_zero_in_either_order() {
+        let bytes = [0x12, 0x34, 0xAB, 0xCD];
+        assert_eq!(parse_raw(&bytes, Endian::Big).unwrap().words, [0x1234, 0xABCD]);
+        assert_eq!(parse_raw(&bytes, Endian::Little).unwrap().words, [0x3412, 0xCDAB]);
+        assert_eq!(parse_raw(&bytes, Endian::Big).unwrap().origin, 0);
+        assert!(parse_raw(&bytes[..3], Endian::Big).is_err());
+        assert!(parse_raw(&bytes, Endian::Auto).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+// Memoization of subroutines declared pure: their results depend only on the given input registers and
+// memory ranges, so a repeated call with identical inputs can be answered from a cache instead of being run.
+// Only the declared output registers are replayed. Memory the subroutine writes (its stack frame) is treated
+// as scratch and the condition codes are left as the caller had them. Verify mode runs every call anyway and
+// faults when a cached result disagrees with the real one.
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::equiv::parse_register;
+use super::parse_range;
+use super::regions::MemoryMap;
+use super::vm::{Fault, VM};
+
+// What a pure subroutine reads and what it produces
+#[derive(Clone)]
+pub struct Signature {
+    pub inputs: Vec<u16>,
+    pub regions: Vec<RangeInclusive<u16>>,
+    pub outputs: Vec<u16>,
+}
+
+// A call whose result will be cached (or checked) when it returns
+struct Pending {
+    entry: u16,
+    key: Vec<u16>,
+    depth: usize,
+    cached: Option<Vec<u16>>,
+}
+
+// Raised in verify mode when a call's real result differs from the cached one
+#[derive(Debug)]
+pub struct Impure {
+    pub entry: u16,
+    pub register: u16,
+    pub cached: u16,
+    pub actual: u16,
+}
+
+impl Impure {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        format!(
+            "subroutine at {} is not pure: a call with the same inputs as an earlier one returned R{}=x{:04X}, not x{:04X}",
+            map.at(self.entry),
+            self.register,
+            self.actual,
+            self.cached
+        )
+    }
+}
+
+impl fmt::Display for Impure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.describe(&MemoryMap::new()))
+    }
+}
+
+#[derive(Default)]
+pub struct Memo {
+    pub verify: bool,
+    pub hits: u64,
+    pub misses: u64,
+    subroutines: HashMap<u16, Signature>,
+    // (entry, input values) -> output values
+    cache: HashMap<(u16, Vec<u16>), Vec<u16>>,
+    pending: Vec<Pending>,
+}
+
+impl Memo {
+    pub fn new() -> Memo {
+        Memo::default()
+    }
+
+    pub fn declare(&mut self, entry: u16, signature: Signature) {
+        self.subroutines.insert(entry, signature);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subroutines.is_empty()
+    }
+
+    // Forget calls in progress. Cached results stay: they depend only on their inputs, not on the run.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+
+    // Forget the declarations and every cached result, which belong to the program that was loaded
+    pub fn clear(&mut self) {
+        self.subroutines.clear();
+        self.cache.clear();
+        self.pending.clear();
+    }
+}
+
+// Parse a `--pure` argument: SUBROUTINE=INPUTS[->OUTPUTS], comma-separated, where an input is a register (R0) or
+// a range (x4000:x40FF) and an output is a register. Outputs default to R0. The subroutine is left unresolved
+// since it may be a symbol.
+pub fn parse_pure_arg(s: &str) -> Result<(String, Signature), String> {
+    let (subroutine, rest) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected SUBROUTINE=INPUTS[->OUTPUTS], got `{}`", s))?;
+    let (inputs, outputs) = rest.split_once("->").unwrap_or((rest, "R0"));
+
+    let mut signature = Signature {
+        inputs: Vec::new(),
+        regions: Vec::new(),
+        outputs: Vec::new(),
+    };
+    for input in fields(inputs) {
+        if input.contains(':') {
+            signature.regions.push(parse_range(input)?);
+        } else {
+            signature.inputs.push(parse_register(input)?);
+        }
+    }
+    for output in fields(outputs) {
+        signature.outputs.push(parse_register(output)?);
+    }
+    Ok((subroutine.to_string(), signature))
+}
+
+fn fields(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|f| !f.is_empty())
+}
+
+// Called by JSR/JSRR once the call has been made. A cache hit returns straight to the caller.
+pub fn enter(vm: &mut VM) {
+    let entry = vm.registers.pc;
+    let Some(signature) = vm.memo.subroutines.get(&entry) else {
+        return;
+    };
+    let memory = &vm.memory;
+    let key: Vec<u16> = signature
+        .inputs
+        .iter()
+        .map(|&r| vm.registers.get(r))
+        .chain(signature.regions.iter().flat_map(|range| {
+            memory[*range.start() as usize..=*range.end() as usize]
+                .iter()
+                .copied()
+        }))
+        .collect();
+
+    match vm.memo.cache.get(&(entry, key.clone())).cloned() {
+        Some(outputs) if !vm.memo.verify => {
+            for (&r, &value) in signature.outputs.iter().zip(&outputs) {
+                vm.registers.update(r, value);
+            }
+            vm.memo.hits += 1;
+            // R7 still holds the return address, as a RET would find it
+            vm.registers.pc = vm.registers.r7;
+            vm.call_stack.ret(vm.registers.pc);
+        }
+        cached => {
+            if cached.is_some() {
+                vm.memo.hits += 1;
+            } else {
+                vm.memo.misses += 1;
+            }
+            vm.memo.pending.push(Pending {
+                entry,
+                key,
+                depth: vm.call_stack.frames().len(),
+                cached,
+            });
+        }
+    }
+}
+
+// Called by JMP after the shadow call stack has unwound; finishes every pending call whose frame is gone
+pub fn leave(vm: &mut VM) {
+    while vm
+        .memo
+        .pending
+        .last()
+        .is_some_and(|p| p.depth > vm.call_stack.frames().len())
+    {
+        let pending = vm.memo.pending.pop().unwrap();
+        let registers = &vm.memo.subroutines[&pending.entry].outputs;
+        let outputs: Vec<u16> = registers.iter().map(|&r| vm.registers.get(r)).collect();
+        match pending.cached {
+            Some(cached) => {
+                if let Some(n) = (0..outputs.len()).find(|&n| cached[n] != outputs[n]) {
+                    vm.fault = Some(Fault::Impure(Impure {
+                        entry: pending.entry,
+                        register: registers[n],
+                        cached: cached[n],
+                        actual: outputs[n],
+                    }));
+                }
+            }
+            None => {
+                vm.memo.cache.insert((pending.entry, pending.key), outputs);
+            }
+        }
+    }
+}
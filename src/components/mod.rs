@@ -1,18 +1,241 @@
+pub mod access;
+pub mod assembler;
+pub mod auxconsole;
+pub mod blocks;
+pub mod bundle;
+pub mod callstack;
+pub mod capture;
+pub mod checkpoint;
+pub mod checksum;
+pub mod clock;
+pub mod console;
+pub mod context;
+pub mod control;
+pub mod cpu;
+pub mod debugger;
+pub mod decode;
+pub mod devices;
+pub mod diagnostics;
+pub mod equiv;
+pub mod events;
+pub mod hints;
+pub mod history;
+pub mod ident;
+pub mod idle;
+pub mod info;
+pub mod inspect;
 pub mod instruction;
+pub mod integrity;
+pub mod interrupt;
+pub mod iodevice;
+pub mod iopage;
+pub mod layout;
+pub mod lesson;
+pub mod lc3b;
+pub mod loader;
+pub mod memo;
+pub mod mutate;
+pub mod numeric;
+pub mod object;
+pub mod os;
+pub mod policy;
+pub mod profile;
+pub mod provenance;
+pub mod query;
+pub mod regions;
 pub mod register;
+pub mod report;
+pub mod results;
+pub mod rng;
+pub mod snapshot;
+pub mod spec;
+pub mod stats;
+pub mod summary;
+pub mod symbols;
+pub mod task;
+#[cfg(test)]
+pub mod testing;
+pub mod trace;
 pub mod vm;
+pub mod watch;
 
-use vm::VM;
+use console::OutputError;
+use vm::{ExecState, Fault, VmError, VM};
+use std::io;
 
-pub const MEMORY_SIZE: usize = std::u16::MAX as usize;
+// Embed an object file in the binary at compile time and build a VM from it, so the program needs no file at
+// run time: `let vm = include_lc3!("prog.obj")?;`. The path is relative to the file using it, as with
+// include_bytes!.
+#[macro_export]
+macro_rules! include_lc3 {
+    ($path:expr) => {
+        $crate::components::vm::VM::from_obj_bytes(include_bytes!($path))
+    };
+}
 
-pub fn execute_program(vm: &mut VM) {
-    while vm.registers.pc < MEMORY_SIZE as u16 {
-        let instruction = vm.read_memory(vm.registers.pc);
+// Every address a u16 can hold, x0000 through xFFFF
+pub const MEMORY_SIZE: usize = 1 << 16;
 
-        // increment program counter
-        vm.registers.pc += 1;
+// Run until the program halts, faults or is stopped: by a BKPT, a breakpoint or a request through vm.control.
+// The instruction the run starts (or resumes) at doesn't stop it again. A fault comes back as the error;
+// Running means the run was stopped (see vm.stopped_at).
+pub fn execute_program(vm: &mut VM) -> Result<ExecState, VmError> {
+    let mut first = true;
+    while running(vm) {
+        for _ in 0..control::CONTROL_INTERVAL {
+            if !running(vm) || (!first && at_breakpoint(vm)) {
+                return vm.state();
+            }
+            first = false;
+            step(vm)?;
+            // a slow clock would leave requests waiting for seconds between the usual checks
+            if vm.clock.is_some() {
+                control::check(vm);
+            }
+        }
+        control::check(vm);
+    }
+    vm.state()
+}
+
+// Stop before an instruction with a breakpoint on it
+pub fn at_breakpoint(vm: &mut VM) -> bool {
+    let pc = vm.registers.pc;
+    if vm.breakpoints.is_empty() || !vm.breakpoints.contains(&pc) {
+        return false;
+    }
+    vm.stopped_at = Some(pc);
+    true
+}
+
+// Whether the machine can execute another instruction
+pub fn running(vm: &VM) -> bool {
+    !vm.halted && vm.fault.is_none() && vm.stopped_at.is_none()
+}
+
+// Fetch and execute a single instruction
+pub fn step(vm: &mut VM) -> Result<ExecState, VmError> {
+    vm.paused_on = None;
+    vm.watch_hit = None;
+    if let Some(limit) = vm.max_instructions.filter(|&max| vm.call_stack.steps() >= max) {
+        let pc = vm.registers.pc;
+        vm.fault.get_or_insert(Fault::Limit(callstack::LimitReached { limit, pc }));
+        return vm.state();
+    }
+    history::begin(vm);
+    if vm.marks.is_armed() {
+        for name in vm.marks.reached(vm.registers.pc) {
+            let checkpoint = checkpoint::Checkpoint::take(vm);
+            vm.marks.mark(&name, checkpoint);
+        }
+    }
+
+    if let Some(clock) = vm.clock.as_mut() {
+        clock.tick();
+    }
+    vm.keyboard_interrupt();
+    vm.device_interrupts();
+    let cpu = vm.cpu;
+    let pc = vm.registers.pc;
+    let instruction = cpu.fetch(vm);
+
+    if let Some(stats) = vm.stats.as_mut() {
+        stats.record(pc, instruction);
+    }
+
+    if let Some(capture) = vm.console.capture.as_mut() {
+        capture.step();
+    }
+
+    let plan = provenance::plan(vm, pc, instruction);
+    let watched = vm
+        .watches
+        .iter()
+        .any(|w| matches!(w.target, watch::Target::Register(_)))
+        .then(|| vm.registers.clone());
+
+    if vm.idle.is_some() {
+        idle::before(vm, instruction);
+    }
+
+    // the hooks below can fault too, so the state is taken once they've run
+    let _ = cpu.execute(instruction, vm);
+
+    if vm.idle.is_some() {
+        idle::after(vm, pc, instruction);
+    }
+
+    if let Some(mut trace) = vm.trace.take() {
+        trace.record(vm, pc, instruction);
+        vm.trace = Some(trace);
+    }
+
+    if let Some(plan) = plan {
+        provenance::commit(vm, plan);
+    }
+
+    if let Some(before) = watched {
+        check_register_watches(vm, pc, &before);
+    }
+    if vm.watch_hit.is_some() {
+        vm.stopped_at = Some(vm.registers.pc);
+    }
+
+    if let Some(blocks) = vm.blocks.as_mut() {
+        blocks.record(pc, instruction, vm.registers.pc);
+    }
+
+    if let Err(e) = vm.call_stack.step() {
+        vm.fault.get_or_insert(Fault::Budget(e));
+    }
+
+    if let Some(error) = vm.console.output_error.take() {
+        // nobody is reading any more (`lc3_sim prog.obj | head`), which ends the run like HALT
+        if error.kind() == io::ErrorKind::BrokenPipe {
+            vm.halted = true;
+        } else {
+            vm.fault.get_or_insert(Fault::Output(OutputError { pc, error }));
+        }
+    }
+    vm.state()
+}
+
+fn check_register_watches(vm: &mut VM, pc: u16, before: &register::Registers) {
+    let hit = vm.watches.iter().find_map(|w| match w.target {
+        watch::Target::Register(r) => {
+            let (old, new) = (before.get(r), vm.registers.get(r));
+            (old != new && w.triggers(old, new)).then(|| w.hit(pc, old, new))
+        }
+        watch::Target::Memory(_) => None,
+    });
+    if let Some(hit) = hit {
+        vm.watch_triggered(hit);
+    }
+}
+
+// Parse an address written LC-3 style (`x3000`, `#12288`) or as `0x3000` / plain decimal
+pub fn parse_address(s: &str) -> Result<u16, String> {
+    let s = s.trim();
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix('x'))
+        .or_else(|| s.strip_prefix('X'));
+    let parsed = if let Some(hex) = hex {
+        u16::from_str_radix(hex, 16)
+    } else {
+        s.strip_prefix('#').unwrap_or(s).parse::<u16>()
+    };
+    parsed.map_err(|_| format!("invalid address `{}`", s))
+}
 
-        instruction::execute_instruction(instruction, vm)
+// Parse an inclusive address range written START:END, e.g. `x3000:x30FF`
+pub fn parse_range(s: &str) -> Result<std::ops::RangeInclusive<u16>, String> {
+    let (start, end) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected START:END, got `{}`", s))?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if start > end {
+        return Err(format!("range `{}` ends before it starts", s));
     }
-}
\ No newline at end of file
+    Ok(start..=end)
+}
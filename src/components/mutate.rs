@@ -0,0 +1,209 @@
+// Mutation testing (`lc3_sim test --mutate solution.asm specs...`): how good a set of specs is at catching
+// mistakes. Each instruction of a reference solution is changed the way a student might get it wrong, one
+// change at a time - a branch on the other conditions, another register, an immediate off by one, ADD for AND,
+// LD for LDI, the instruction left out - and every spec is run against each changed program in place of its
+// object. A change that no spec fails on survives: the specs can't tell that program from the right one.
+use std::time::{Duration, Instant};
+
+use super::instruction::disassemble_at;
+use super::object::ObjectImage;
+use super::spec::{self, Spec};
+
+// What a left-out instruction becomes: a branch on no conditions
+const NOP: u16 = 0x0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mutant {
+    pub address: u16,
+    // the source line of the instruction
+    pub line: usize,
+    pub original: u16,
+    pub word: u16,
+}
+
+impl Mutant {
+    pub fn describe(&self) -> String {
+        format!(
+            "line {} x{:04X}: {} -> {}",
+            self.line,
+            self.address,
+            disassemble_at(self.original, self.address),
+            disassemble_at(self.word, self.address)
+        )
+    }
+
+    // The reference with this one change
+    pub fn apply(&self, image: &ObjectImage) -> ObjectImage {
+        let mut changed = image.clone();
+        changed.words[self.address.wrapping_sub(image.origin) as usize] = self.word;
+        changed
+    }
+}
+
+// A mutant and the first spec that failed on it, or None if it survived
+pub struct Outcome {
+    pub mutant: Mutant,
+    pub killed_by: Option<String>,
+    pub elapsed: Duration,
+}
+
+// Every change to the instructions at `instructions`, the addresses and source lines the assembler listed
+pub fn mutants(image: &ObjectImage, instructions: &[(u16, usize)]) -> Vec<Mutant> {
+    let mut mutants = Vec::new();
+    for &(address, line) in instructions {
+        let Some(&original) = image.words.get(address.wrapping_sub(image.origin) as usize) else {
+            continue;
+        };
+        for word in changes(original) {
+            mutants.push(Mutant {
+                address,
+                line,
+                original,
+                word,
+            });
+        }
+    }
+    mutants
+}
+
+// The changed words for one instruction, without repeats or the instruction itself
+fn changes(word: u16) -> Vec<u16> {
+    // the register in the 3-bit field at `shift` replaced by the next one up
+    let register =
+        |shift: u16| word & !(0x7 << shift) | ((word >> shift).wrapping_add(1) & 0x7) << shift;
+    let immediate = |delta: u16| word & !0x1F | (word & 0x1F).wrapping_add(delta) & 0x1F;
+    let mut changed = vec![NOP];
+    match word >> 12 {
+        // BR: the conditions it doesn't branch on, unless that's none, which is leaving it out again
+        0x0 if word & 0x0E00 != 0x0E00 => changed.push(word ^ 0x0E00),
+        // ADD and AND: each other, another register, or the immediate one off
+        0x1 | 0x5 => {
+            changed.extend([word ^ 0x4000, register(9), register(6)]);
+            if word & 0x20 == 0 {
+                changed.push(register(0));
+            } else {
+                changed.extend([immediate(1), immediate(0xFFFF)]);
+            }
+        }
+        0x9 => changed.extend([register(9), register(6)]),
+        // LD and LDI, ST and STI: each other, or another register
+        0x2 | 0xA | 0x3 | 0xB => changed.extend([word ^ 0x8000, register(9)]),
+        0x6 | 0x7 => changed.extend([register(9), register(6)]),
+        0xE => changed.push(register(9)),
+        _ => {}
+    }
+    let mut unique = Vec::new();
+    for w in changed {
+        if w != word && !unique.contains(&w) {
+            unique.push(w);
+        }
+    }
+    unique
+}
+
+// Run the specs against the reference and then against each of its mutants. The reference has to pass them
+// all, or there is nothing to measure the mutants against.
+pub fn run(
+    image: &ObjectImage,
+    instructions: &[(u16, usize)],
+    specs: &[Spec],
+) -> Result<Vec<Outcome>, String> {
+    for spec in specs {
+        if let Some(failure) = spec::run_image(spec, image)?.first() {
+            return Err(format!("the reference fails {}: {}", spec.name, failure));
+        }
+    }
+    Ok(mutants(image, instructions)
+        .into_iter()
+        .map(|mutant| {
+            let start = Instant::now();
+            let changed = mutant.apply(image);
+            let killed_by = specs
+                .iter()
+                .find(|spec| spec::run_image(spec, &changed).map_or(true, |f| !f.is_empty()))
+                .map(|spec| spec.name.clone());
+            Outcome {
+                mutant,
+                killed_by,
+                elapsed: start.elapsed(),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble_listing;
+
+    // Counts down from R1 to zero, adding R2 each time round, and stores the product
+    const MULTIPLY: &str = "
+            .ORIG x3000
+            LD R1, A
+            LD R2, B
+            AND R0, R0, #0
+    AGAIN   ADD R0, R0, R2
+            ADD R1, R1, #-1
+            BRp AGAIN
+            ST R0, PRODUCT
+            HALT
+    A       .FILL #3
+    B       .FILL #4
+    PRODUCT .BLKW 1
+            .END";
+
+    fn spec(name: &str, memory: &str) -> Spec {
+        spec::parse(
+            &format!(
+                "name = \"{}\"\nobject = \"multiply.obj\"\nmemory = \"{}\"\nmax_instructions = 1000",
+                name, memory
+            ),
+            false,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn each_instruction_changes_a_few_ways() {
+        assert_eq!(changes(0x0BFC), [NOP, 0x05FC]); // BRnp -> BRz
+        assert_eq!(changes(0x0FFC), [NOP]); // BRnzp has only being left out
+                                            // ADD R0, R0, #-1: AND, R1 for the destination and the source, #0, #-2
+        assert_eq!(
+            changes(0x103F),
+            [NOP, 0x503F, 0x123F, 0x107F, 0x1020, 0x103E]
+        );
+        assert_eq!(changes(0xF025), [NOP]);
+    }
+
+    #[test]
+    fn a_spec_that_checks_less_lets_more_survive() {
+        let (image, instructions) = assemble_listing(MULTIPLY).unwrap();
+        let mutants = mutants(&image, &instructions);
+        // only the eight instructions, never the data
+        assert!(mutants.iter().all(|m| m.address < 0x3008));
+        assert_eq!(mutants[0].describe(), "line 3 x3000: LD R1, x3008 -> NOP");
+
+        let strict = run(&image, &instructions, &[spec("product", "x300A=#12")]).unwrap();
+        assert_eq!(strict.len(), mutants.len());
+        assert_eq!(strict[0].killed_by.as_deref(), Some("product"));
+        // R0 starts out zero on a fresh machine, so nothing the spec checks needs the AND that clears it
+        let survivors = |outcomes: &[Outcome]| {
+            outcomes
+                .iter()
+                .filter(|o| o.killed_by.is_none())
+                .map(|o| o.mutant.line)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(survivors(&strict), [5; 5]);
+
+        // a spec that doesn't look at the product catches only the changes that break the run
+        let loose = run(&image, &instructions, &[spec("halts", "")]).unwrap();
+        assert!(survivors(&loose).len() > 5);
+
+        let wrong = spec("wrong", "x300A=#13");
+        assert!(run(&image, &instructions, &[wrong])
+            .err()
+            .unwrap()
+            .starts_with("the reference fails"));
+    }
+}
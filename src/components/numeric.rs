@@ -0,0 +1,137 @@
+// Number formatting and parsing behind the `numeric` capability traps, so programs can print and read values
+// before they have written their own conversion routines.
+use super::vm::VM;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    Signed,   // two's complement decimal, -32768..=32767
+    Unsigned, // decimal, 0..=65535
+    Hex,      // LC-3 style, x0000..=xFFFF
+}
+
+pub fn format(value: u16, radix: Radix) -> String {
+    match radix {
+        Radix::Signed => (value as i16).to_string(),
+        Radix::Unsigned => value.to_string(),
+        Radix::Hex => format!("x{:04X}", value),
+    }
+}
+
+// What GETD prints after rejecting a line
+pub const RETRY_PROMPT: &str = "Enter a number from -32768 to 32767: ";
+
+// Longest line GETD keeps; a sign, five digits and some spaces fit easily
+const LINE_LIMIT: usize = 32;
+
+// Parse what GETD read: optional sign, decimal digits, surrounding spaces allowed. Anything outside
+// -32768..=32767 is an error rather than wrapping, so the trap can ask again.
+pub fn parse_signed(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let digits = text.strip_prefix(['+', '-']).unwrap_or(text);
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("`{}` is not a number", text));
+    }
+    text.parse::<i16>()
+        .map(|n| n as u16)
+        .map_err(|_| format!("{} is out of range (-32768 to 32767)", text))
+}
+
+// Read a line for GETD, echoing keys (the terminal is raw) and handling backspace.
+// None when scripted input runs out before the line ends.
+pub fn read_line(vm: &mut VM) -> Option<String> {
+    let mut line = String::new();
+    loop {
+        match vm.read_byte() {
+            0 => return None,
+            b'\n' | b'\r' => {
+                vm.console.write_byte(b'\n');
+                return Some(line);
+            }
+            0x08 | 0x7F if line.pop().is_some() => vm.console.write_str("\x08 \x08"),
+            0x08 | 0x7F => {}
+            key if line.len() < LINE_LIMIT => {
+                line.push(key as char);
+                vm.console.write_byte(key);
+            }
+            _ => {}
+        }
+        vm.console.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::instruction::trap;
+    use crate::components::vm::VM;
+
+    fn run_trap(vector: u16, r0: u16) -> Vec<u8> {
+        let mut vm = VM::new();
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.capabilities.push("numeric".to_string());
+        vm.registers.r0 = r0;
+        trap(0xF000 | vector, &mut vm);
+        vm.console.transcript.unwrap()
+    }
+
+    #[test]
+    fn signed_boundaries() {
+        assert_eq!(format(0x0000, Radix::Signed), "0");
+        assert_eq!(format(0x7FFF, Radix::Signed), "32767");
+        assert_eq!(format(0x8000, Radix::Signed), "-32768");
+        assert_eq!(format(0xFFFF, Radix::Signed), "-1");
+    }
+
+    #[test]
+    fn unsigned_boundaries() {
+        assert_eq!(format(0x0000, Radix::Unsigned), "0");
+        assert_eq!(format(0x8000, Radix::Unsigned), "32768");
+        assert_eq!(format(0xFFFF, Radix::Unsigned), "65535");
+    }
+
+    #[test]
+    fn hex_is_padded_and_upper_case() {
+        assert_eq!(format(0x0000, Radix::Hex), "x0000");
+        assert_eq!(format(0x00AB, Radix::Hex), "x00AB");
+        assert_eq!(format(0x8000, Radix::Hex), "x8000");
+    }
+
+    #[test]
+    fn traps_print_r0_through_the_console() {
+        assert_eq!(run_trap(0x2A, 0x8000), b"-32768");
+        assert_eq!(run_trap(0x2B, 0x8000), b"32768");
+        assert_eq!(run_trap(0x2C, 0x8000), b"x8000");
+    }
+
+    #[test]
+    fn parse_accepts_signs_and_boundaries() {
+        assert_eq!(parse_signed("42"), Ok(42));
+        assert_eq!(parse_signed(" +7 "), Ok(7));
+        assert_eq!(parse_signed("-1"), Ok(0xFFFF));
+        assert_eq!(parse_signed("32767"), Ok(0x7FFF));
+        assert_eq!(parse_signed("-32768"), Ok(0x8000));
+    }
+
+    #[test]
+    fn parse_rejects_garbage_and_overflow() {
+        assert!(parse_signed("").is_err());
+        assert!(parse_signed("-").is_err());
+        assert!(parse_signed("12a").is_err());
+        assert!(parse_signed("32768").is_err());
+        assert!(parse_signed("-32769").is_err());
+    }
+
+    #[test]
+    fn getd_retries_until_a_valid_number() {
+        let mut vm = VM::new();
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.capabilities.push("numeric".to_string());
+        vm.console.push_input(b"99999\nabc\n-12\n");
+        trap(0xF02D, &mut vm);
+        assert_eq!(vm.registers.r0, (-12i16) as u16);
+        let transcript = String::from_utf8(vm.console.transcript.unwrap()).unwrap();
+        assert_eq!(transcript.matches(RETRY_PROMPT).count(), 2);
+    }
+}
@@ -0,0 +1,262 @@
+// Object file formats.
+//
+// v1 (plain .obj): origin word followed by the program words, all big-endian.
+// v2: the magic `LC3OBJv2`, a list of tagged metadata records, then a complete v1 body. Every record is
+// `tag: u16, length: u16, payload` (length in bytes) and the list ends with tag 0. Unknown tags are skipped
+// so older loaders of v2 can still read newer files; anything without the magic is treated as v1.
+use std::fmt;
+
+pub const MAGIC: &[u8; 8] = b"LC3OBJv2";
+
+const TAG_END: u16 = 0;
+const TAG_ENTRY: u16 = 1; // u16 entry point
+const TAG_DEVICE: u16 = 2; // device spec the program needs, as given to --device
+const TAG_ISA: u16 = 3; // ISA profile name, e.g. "lc3"
+const TAG_SYMBOL: u16 = 4; // u16 address + name
+const TAG_SOURCE: u16 = 5; // u64 FNV-1a hash of the source + path
+const TAG_CAPABILITY: u16 = 6; // optional extension the program uses, as given to --capability
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub entry: Option<u16>,
+    pub devices: Vec<String>,
+    pub isa: Option<String>,
+    pub symbols: Vec<(u16, String)>,
+    pub sources: Vec<(u64, String)>,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectImage {
+    pub origin: u16,
+    pub words: Vec<u16>,
+    pub metadata: Metadata,
+}
+
+#[derive(Debug)]
+pub struct ObjectError(pub String);
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> Result<ObjectImage, ObjectError> {
+    match bytes.strip_prefix(MAGIC.as_slice()) {
+        Some(rest) => {
+            let (metadata, body) = parse_metadata(rest)?;
+            let mut image = parse_body(body)?;
+            image.metadata = metadata;
+            Ok(image)
+        }
+        None => parse_body(bytes),
+    }
+}
+
+fn parse_body(bytes: &[u8]) -> Result<ObjectImage, ObjectError> {
+    if bytes.len() < 2 {
+        return Err(ObjectError("object file has no origin".to_string()));
+    }
+    if bytes.len() % 2 == 1 {
+        return Err(ObjectError(
+            "object file is truncated: it ends halfway through a word".to_string(),
+        ));
+    }
+    let mut words = bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+    let origin = words.next().unwrap();
+    Ok(ObjectImage {
+        origin,
+        words: words.collect(),
+        metadata: Metadata::default(),
+    })
+}
+
+fn parse_metadata(mut bytes: &[u8]) -> Result<(Metadata, &[u8]), ObjectError> {
+    let truncated = || ObjectError("truncated v2 object header".to_string());
+    let mut metadata = Metadata::default();
+
+    loop {
+        if bytes.len() < 4 {
+            return Err(truncated());
+        }
+        let tag = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let length = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        bytes = &bytes[4..];
+        if tag == TAG_END {
+            return Ok((metadata, bytes));
+        }
+        if bytes.len() < length {
+            return Err(truncated());
+        }
+        let (payload, rest) = bytes.split_at(length);
+        bytes = rest;
+
+        let text = |payload: &[u8]| {
+            String::from_utf8(payload.to_vec())
+                .map_err(|_| ObjectError(format!("record {} is not valid UTF-8", tag)))
+        };
+        let word = |payload: &[u8]| {
+            payload
+                .get(..2)
+                .map(|w| u16::from_be_bytes([w[0], w[1]]))
+                .ok_or_else(|| ObjectError(format!("record {} is too short", tag)))
+        };
+
+        match tag {
+            TAG_ENTRY => metadata.entry = Some(word(payload)?),
+            TAG_DEVICE => metadata.devices.push(text(payload)?),
+            TAG_ISA => metadata.isa = Some(text(payload)?),
+            TAG_SYMBOL => metadata
+                .symbols
+                .push((word(payload)?, text(&payload[2..])?)),
+            TAG_SOURCE => {
+                let hash = payload
+                    .get(..8)
+                    .map(|h| u64::from_be_bytes(h.try_into().unwrap()))
+                    .ok_or_else(|| ObjectError(format!("record {} is too short", tag)))?;
+                metadata.sources.push((hash, text(&payload[8..])?));
+            }
+            TAG_CAPABILITY => metadata.capabilities.push(text(payload)?),
+            _ => {} // written by a newer tool, safe to ignore
+        }
+    }
+}
+
+// Plain .obj bytes
+pub fn write_v1(image: &ObjectImage) -> Vec<u8> {
+    std::iter::once(image.origin)
+        .chain(image.words.iter().copied())
+        .flat_map(u16::to_be_bytes)
+        .collect()
+}
+
+// v2 bytes; metadata that is empty is simply left out
+pub fn write_v2(image: &ObjectImage) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    let mut record = |tag: u16, payload: &[u8]| {
+        out.extend_from_slice(&tag.to_be_bytes());
+        out.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        out.extend_from_slice(payload);
+    };
+
+    let metadata = &image.metadata;
+    if let Some(entry) = metadata.entry {
+        record(TAG_ENTRY, &entry.to_be_bytes());
+    }
+    for device in &metadata.devices {
+        record(TAG_DEVICE, device.as_bytes());
+    }
+    if let Some(isa) = &metadata.isa {
+        record(TAG_ISA, isa.as_bytes());
+    }
+    for (address, name) in &metadata.symbols {
+        record(
+            TAG_SYMBOL,
+            &[&address.to_be_bytes()[..], name.as_bytes()].concat(),
+        );
+    }
+    for (hash, path) in &metadata.sources {
+        record(
+            TAG_SOURCE,
+            &[&hash.to_be_bytes()[..], path.as_bytes()].concat(),
+        );
+    }
+    for capability in &metadata.capabilities {
+        record(TAG_CAPABILITY, capability.as_bytes());
+    }
+    record(TAG_END, &[]);
+
+    out.extend(write_v1(image));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> ObjectImage {
+        ObjectImage {
+            origin: 0x3000,
+            words: vec![0xE002, 0xF022, 0xF025],
+            metadata: Metadata::default(),
+        }
+    }
+
+    #[test]
+    fn v2_keeps_every_record() {
+        let mut image = image();
+        image.metadata = Metadata {
+            entry: Some(0x3001),
+            devices: vec!["timer:irq=1".to_string(), "random".to_string()],
+            isa: Some("lc3".to_string()),
+            symbols: vec![(0x3000, "MAIN".to_string()), (0x3003, "MSG".to_string())],
+            sources: vec![(0x0123_4567_89AB_CDEF, "prog.asm".to_string())],
+            capabilities: vec!["numeric".to_string()],
+        };
+        let bytes = write_v2(&image);
+        assert!(bytes.starts_with(MAGIC));
+        assert_eq!(parse(&bytes).unwrap(), image);
+
+        // without metadata only the magic and the end record come before the v1 body
+        let plain = ObjectImage {
+            metadata: Metadata::default(),
+            ..image
+        };
+        let bytes = write_v2(&plain);
+        assert_eq!(bytes.len(), MAGIC.len() + 4 + write_v1(&plain).len());
+        assert_eq!(parse(&bytes).unwrap(), plain);
+    }
+
+    #[test]
+    fn plain_objects_are_v1() {
+        let bytes = write_v1(&image());
+        assert_eq!(bytes, [0x30, 0x00, 0xE0, 0x02, 0xF0, 0x22, 0xF0, 0x25]);
+        assert_eq!(parse(&bytes).unwrap(), image());
+    }
+
+    #[test]
+    fn unknown_records_are_skipped() {
+        let mut bytes = MAGIC.to_vec();
+        // a tag this loader doesn't know, then an entry point
+        bytes.extend_from_slice(&[0x00, 0x63, 0x00, 0x03, b'n', b'e', b'w']);
+        bytes.extend_from_slice(&[0x00, 0x01, 0x00, 0x02, 0x30, 0x02]);
+        bytes.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+        bytes.extend(write_v1(&image()));
+        let parsed = parse(&bytes).unwrap();
+        assert_eq!(parsed.metadata.entry, Some(0x3002));
+        assert_eq!(parsed.words, image().words);
+    }
+
+    #[test]
+    fn truncated_objects_are_rejected() {
+        let error = |bytes: &[u8]| parse(bytes).unwrap_err().0;
+        let v2 = write_v2(&ObjectImage {
+            metadata: Metadata {
+                entry: Some(0x3000),
+                ..Metadata::default()
+            },
+            ..image()
+        });
+
+        assert_eq!(error(&[0x30]), "object file has no origin");
+        // a body ending halfway through a word, in either format
+        let v1 = write_v1(&image());
+        assert!(error(&v1[..v1.len() - 1]).contains("truncated"));
+        assert!(error(&v2[..v2.len() - 1]).contains("truncated"));
+        // cut off inside the entry record, and before the end record
+        assert_eq!(error(&v2[..MAGIC.len() + 5]), "truncated v2 object header");
+        assert_eq!(error(&v2[..MAGIC.len() + 6]), "truncated v2 object header");
+        // records too short for what they hold
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[0x00, 0x01, 0x00, 0x01, 0x30, 0x00, 0x00, 0x00, 0x00]);
+        bytes.extend(&v1);
+        assert_eq!(error(&bytes), "record 1 is too short");
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[0x00, 0x02, 0x00, 0x01, 0xFF, 0x00, 0x00, 0x00, 0x00]);
+        bytes.extend(&v1);
+        assert_eq!(error(&bytes), "record 2 is not valid UTF-8");
+    }
+}
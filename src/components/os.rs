@@ -0,0 +1,563 @@
+// Software traps (--os): TRAP does what the hardware does instead of the simulator doing the work itself. The PC
+// is saved in R7 (or, in the 2019 revision of the ISA, PSR and PC go on the supervisor stack like an interrupt)
+// and execution jumps through the trap vector table at x0000 to routines running as ordinary LC-3 code, so a
+// program can install its own routines and RET (or RTI) from them.
+//
+// The routines come from an OS image loaded before the program, either an object such as lc3os.obj or the small
+// built-in one below. A vector whose table entry is zero is still done natively, so BKPT and the extension traps
+// keep working next to an OS that doesn't provide them.
+use std::path::Path;
+
+use super::assembler::assemble;
+use super::callstack::{Cause, Handler};
+use super::context::ContextSwitch;
+use super::interrupt;
+use super::iopage::{self, MemoryMappedReg};
+use super::loader::{self, Endian};
+use super::object::{self, Metadata, ObjectImage};
+use super::vm::VM;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapMode {
+    Native,   // the simulator does the trap's work and R7 is left alone
+    Vectored, // R7 = PC, then PC = the table entry; routines return with RET
+    Stack,    // PSR and PC pushed on the supervisor stack; routines return with RTI
+}
+
+// The built-in OS's routines, after the table entries that point at them. Device registers and the return
+// instruction are filled in for the machine it's loaded into. Output matches the native traps, and HALT stops the
+// clock through MCR, clearing R0 to do it.
+const BUILTIN: &str = r#"
+            .ORIG x0200
+T_GETC      LDI R0, KBSR
+            BRzp T_GETC
+            LDI R0, KBDR
+            {RETURN}
+
+T_OUT       ST R1, SAVE1
+OUT_WAIT    LDI R1, DSR
+            BRzp OUT_WAIT
+            STI R0, DDR
+            LD R1, SAVE1
+            {RETURN}
+
+T_PUTS      ST R0, SAVE0
+            ST R1, SAVE1
+            ST R2, SAVE2
+            ADD R1, R0, #0
+PUTS_NEXT   LDR R0, R1, #0
+            BRz PUTS_DONE
+PUTS_WAIT   LDI R2, DSR
+            BRzp PUTS_WAIT
+            STI R0, DDR
+            ADD R1, R1, #1
+            BRnzp PUTS_NEXT
+PUTS_DONE   LD R0, SAVE0
+            LD R1, SAVE1
+            LD R2, SAVE2
+            {RETURN}
+
+T_IN        ST R1, SAVE1
+            ST R2, SAVE2
+            LEA R1, PROMPT
+IN_NEXT     LDR R2, R1, #0
+            BRz IN_READ
+IN_WAIT     LDI R0, DSR
+            BRzp IN_WAIT
+            STI R2, DDR
+            ADD R1, R1, #1
+            BRnzp IN_NEXT
+IN_READ     LDI R0, KBSR
+            BRzp IN_READ
+            LDI R0, KBDR
+IN_ECHO     LDI R1, DSR
+            BRzp IN_ECHO
+            STI R0, DDR
+            LD R2, NEWLINE
+IN_LINE     LDI R1, DSR
+            BRzp IN_LINE
+            STI R2, DDR
+            LD R1, SAVE1
+            LD R2, SAVE2
+            {RETURN}
+
+; two characters a word, low byte first; the high byte is rotated down eight places since there's no shift
+T_PUTSP     ST R0, SAVE0
+            ST R1, SAVE1
+            ST R2, SAVE2
+            ST R3, SAVE3
+            ADD R1, R0, #0
+PUTSP_NEXT  LDR R2, R1, #0
+            BRz PUTSP_DONE
+            LD R3, LOW_BYTE
+            AND R0, R2, R3
+PUTSP_LOW   LDI R3, DSR
+            BRzp PUTSP_LOW
+            STI R0, DDR
+            AND R3, R3, #0
+            ADD R3, R3, #8
+PUTSP_ROT   ADD R2, R2, #0
+            BRn PUTSP_CARRY
+            ADD R2, R2, R2
+            BRnzp PUTSP_COUNT
+PUTSP_CARRY ADD R2, R2, R2
+            ADD R2, R2, #1
+PUTSP_COUNT ADD R3, R3, #-1
+            BRp PUTSP_ROT
+            LD R3, LOW_BYTE
+            AND R0, R2, R3
+            BRz PUTSP_SKIP
+PUTSP_HIGH  LDI R3, DSR
+            BRzp PUTSP_HIGH
+            STI R0, DDR
+PUTSP_SKIP  ADD R1, R1, #1
+            BRnzp PUTSP_NEXT
+PUTSP_DONE  LD R0, SAVE0
+            LD R1, SAVE1
+            LD R2, SAVE2
+            LD R3, SAVE3
+            {RETURN}
+
+T_HALT      AND R0, R0, #0
+            STI R0, MCR
+            BRnzp T_HALT
+
+KBSR        .FILL {KBSR}
+KBDR        .FILL {KBDR}
+DSR         .FILL {DSR}
+DDR         .FILL {DDR}
+MCR         .FILL {MCR}
+LOW_BYTE    .FILL x00FF
+NEWLINE     .FILL x000A
+SAVE0       .BLKW 1
+SAVE1       .BLKW 1
+SAVE2       .BLKW 1
+SAVE3       .BLKW 1
+PROMPT      .STRINGZ "\nInput a character> "
+            .END
+"#;
+
+// Table entries x20-x25, in order
+const ROUTINES: [&str; 6] = ["T_GETC", "T_OUT", "T_PUTS", "T_IN", "T_PUTSP", "T_HALT"];
+const FIRST_VECTOR: u16 = 0x20;
+
+// `--os scheduler`: the built-in OS plus a round-robin scheduler for up to eight threads, on the timer and the
+// context-switch unit (context.rs). The program itself is thread 0. TRAP x30 starts a thread at R0 with its stack
+// at R1 and gives its number in R0 (or -1 when all eight are taken), TRAP x31 gives up the rest of the time slice
+// and TRAP x32 ends the thread; the machine halts once none is left. The timer interrupts after QUANTUM
+// instructions and the next thread runs, unless it came in over a trap routine, which finishes first. Threads run
+// in user mode at priority 0 and TRAPs push PSR and PC, as with --trap-stack.
+const SCHEDULER: &str = r#"
+            .ORIG x0500
+S_SPAWN     ADD R6, R6, #-1
+            STR R2, R6, #0
+            ADD R6, R6, #-1
+            STR R3, R6, #0
+            ADD R6, R6, #-1
+            STR R4, R6, #0
+            AND R2, R2, #0
+            LEA R3, LIVE
+SPAWN_FIND  LDR R4, R3, #0
+            BRz SPAWN_FREE
+            ADD R2, R2, #1
+            ADD R3, R3, #1
+            ADD R4, R2, #-8
+            BRn SPAWN_FIND
+            AND R0, R0, #0
+            ADD R0, R0, #-1
+            BRnzp SPAWN_DONE
+SPAWN_FREE  ADD R4, R4, #1
+            STR R4, R3, #0
+; its context: registers clear, the stack in R6, the entry as PC and a user-mode PSR
+            LEA R3, THREADS
+            ADD R4, R2, #0
+SPAWN_MUL   BRz SPAWN_FILL
+            ADD R3, R3, #10
+            ADD R4, R4, #-1
+            BRnzp SPAWN_MUL
+SPAWN_FILL  AND R4, R4, #0
+            STR R4, R3, #0
+            STR R4, R3, #1
+            STR R4, R3, #2
+            STR R4, R3, #3
+            STR R4, R3, #4
+            STR R4, R3, #5
+            STR R1, R3, #6
+            STR R4, R3, #7
+            STR R0, R3, #8
+            LD R4, USER_PSR
+            STR R4, R3, #9
+            ADD R0, R2, #0
+; the first thread started starts the timer
+            LDI R4, TMCR
+            BRnp SPAWN_DONE
+            LD R4, QUANTUM
+            STI R4, TMCNT
+            LD R4, TIMER_ON
+            STI R4, TMCR
+SPAWN_DONE  LDR R4, R6, #0
+            LDR R3, R6, #1
+            LDR R2, R6, #2
+            ADD R6, R6, #3
+            RTI
+
+S_YIELD     ADD R6, R6, #-1
+            STR R0, R6, #0
+            ADD R6, R6, #-1
+            STR R1, R6, #0
+            ADD R6, R6, #-1
+            STR R2, R6, #0
+            ADD R6, R6, #-1
+            STR R7, R6, #0
+            JSR PICK
+            BRnzp RESTORE
+
+S_EXIT      LDI R0, CSCUR
+            LEA R1, LIVE
+            ADD R1, R1, R0
+            AND R2, R2, #0
+            STR R2, R1, #0
+            JSR PICK
+            LD R0, QUANTUM
+            STI R0, TMCNT
+            RTI
+
+; the timer: acknowledge it, and switch if it came in over a thread (PSR on the stack in user mode)
+S_TICK      ADD R6, R6, #-1
+            STR R0, R6, #0
+            ADD R6, R6, #-1
+            STR R1, R6, #0
+            ADD R6, R6, #-1
+            STR R2, R6, #0
+            ADD R6, R6, #-1
+            STR R7, R6, #0
+            LD R0, TIMER_ON
+            STI R0, TMCR
+            LDR R0, R6, #5
+            BRzp RESTORE
+            JSR PICK
+; the thread returned to gets a whole time slice, whatever picking one cost
+RESTORE     LD R0, QUANTUM
+            STI R0, TMCNT
+            LDR R7, R6, #0
+            LDR R2, R6, #1
+            LDR R1, R6, #2
+            LDR R0, R6, #3
+            ADD R6, R6, #4
+            RTI
+
+; the next live thread after the running one, round robin, for the switch at RTI; with none left, halt
+PICK        LDI R0, CSCUR
+            AND R2, R2, #0
+            ADD R2, R2, #8
+PICK_NEXT   ADD R0, R0, #1
+            ADD R1, R0, #-8
+            BRn PICK_LIVE
+            AND R0, R0, #0
+PICK_LIVE   LEA R1, LIVE
+            ADD R1, R1, R0
+            LDR R1, R1, #0
+            BRp PICK_FOUND
+            ADD R2, R2, #-1
+            BRp PICK_NEXT
+            AND R0, R0, #0
+            STI R0, MCR
+PICK_FOUND  STI R0, CSNEXT
+            RET
+
+CSCUR       .FILL {CSCUR}
+CSNEXT      .FILL {CSNEXT}
+TMCR        .FILL {TMCR}
+TMCNT       .FILL {TMCNT}
+MCR         .FILL {MCR}
+QUANTUM     .FILL #100
+TIMER_ON    .FILL x4001
+USER_PSR    .FILL x8002
+; 1 for a thread that is running or ready, 0 for a free slot
+LIVE        .FILL #1
+            .BLKW #7
+THREADS     .BLKW #80
+            .END
+"#;
+
+// Its TRAPs, from x30 on, and its timer handler
+const SCHEDULER_TRAPS: [&str; 3] = ["S_SPAWN", "S_YIELD", "S_EXIT"];
+const SCHEDULER_VECTOR: u16 = 0x30;
+
+// Load the OS image at `path` (the built-in one for `builtin`, or it with the scheduler for `scheduler`) and send
+// TRAPs through the table from now on
+pub fn load(vm: &mut VM, path: &Path, mode: TrapMode) -> Result<(), String> {
+    let mut mode = mode;
+    if path == Path::new("builtin") {
+        load_builtin(vm, mode)?;
+    } else if path == Path::new("scheduler") {
+        // its routines return with RTI
+        mode = TrapMode::Stack;
+        let timer = vm.io_page.base() + iopage::TIMER;
+        if !vm.devices.iter().any(|d| d.range().contains(&timer)) {
+            return Err("the scheduler needs the timer: add --device timer".to_string());
+        }
+        load_builtin(vm, mode)?;
+        load_scheduler(vm, timer)?;
+    } else {
+        loader::load_object(vm, path, false, Endian::Big)?;
+    }
+    vm.traps = mode;
+    Ok(())
+}
+
+fn load_builtin(vm: &mut VM, mode: TrapMode) -> Result<(), String> {
+    let page = &vm.io_page;
+    let word = |address: u16| format!("x{:04X}", address);
+    let source = BUILTIN
+        .replace("{KBSR}", &word(page.address(MemoryMappedReg::Kbsr)))
+        .replace("{KBDR}", &word(page.address(MemoryMappedReg::Kbdr)))
+        .replace("{DSR}", &word(page.address(MemoryMappedReg::Dsr)))
+        .replace("{DDR}", &word(page.address(MemoryMappedReg::Ddr)))
+        .replace("{MCR}", &word(page.mcr()))
+        .replace(
+            "{RETURN}",
+            if mode == TrapMode::Stack {
+                "RTI"
+            } else {
+                "RET"
+            },
+        );
+    let image = assemble(&source).map_err(|e| format!("built-in OS: {}", e))?;
+    let table = ObjectImage {
+        origin: FIRST_VECTOR,
+        words: addresses(&image, &ROUTINES, "built-in OS")?,
+        metadata: Metadata::default(),
+    };
+    // as images of their own, so a reset puts them back like any other load
+    let name = Path::new("<built-in OS>");
+    loader::load_object_bytes(vm, name, object::write_v2(&table), Endian::Big)?;
+    loader::load_object_bytes(vm, name, object::write_v2(&image), Endian::Big)?;
+    Ok(())
+}
+
+fn load_scheduler(vm: &mut VM, timer: u16) -> Result<(), String> {
+    let page = vm.io_page.clone();
+    let unit = page.base() + iopage::CONTEXT_SWITCH;
+    let word = |address: u16| format!("x{:04X}", address);
+    let source = SCHEDULER
+        .replace("{CSCUR}", &word(unit + 2))
+        .replace("{CSNEXT}", &word(unit + 4))
+        .replace("{TMCR}", &word(timer))
+        .replace("{TMCNT}", &word(timer + 2))
+        .replace("{MCR}", &word(page.mcr()));
+    let image = assemble(&source).map_err(|e| format!("scheduler: {}", e))?;
+    let traps = ObjectImage {
+        origin: SCHEDULER_VECTOR,
+        words: addresses(&image, &SCHEDULER_TRAPS, "scheduler")?,
+        metadata: Metadata::default(),
+    };
+    let tick = ObjectImage {
+        origin: interrupt::TABLE + interrupt::TIMER,
+        words: addresses(&image, &["S_TICK"], "scheduler")?,
+        metadata: Metadata::default(),
+    };
+    let threads = addresses(&image, &["THREADS"], "scheduler")?[0];
+    let name = Path::new("<scheduler>");
+    for part in [&traps, &tick, &image] {
+        loader::load_object_bytes(vm, name, object::write_v2(part), Endian::Big)?;
+    }
+    vm.context = Some(ContextSwitch::new(unit, threads));
+    Ok(())
+}
+
+// Where each of `names` is in an assembled image
+fn addresses(image: &ObjectImage, names: &[&str], what: &str) -> Result<Vec<u16>, String> {
+    names
+        .iter()
+        .map(|name| {
+            image
+                .metadata
+                .symbols
+                .iter()
+                .find(|(_, symbol)| symbol == name)
+                .map(|&(address, _)| address)
+                .ok_or_else(|| format!("{}: no {}", what, name))
+        })
+        .collect()
+}
+
+// Take TRAP `vector` through the table. False when there's no routine for it, leaving it to the simulator.
+pub fn dispatch(vm: &mut VM, vector: u16) -> bool {
+    let routine = vm.memory[vector as usize];
+    if routine == 0 {
+        return false;
+    }
+    let pc = vm.registers.pc;
+    match vm.traps {
+        TrapMode::Native => return false,
+        TrapMode::Vectored => {
+            vm.registers.r7 = pc;
+            let handler = Handler {
+                cause: Cause::Trap(vector),
+                saved_at: None,
+            };
+            vm.call_stack.enter_handler(pc.wrapping_sub(1), routine, pc, handler);
+            vm.registers.pc = routine;
+        }
+        TrapMode::Stack => interrupt::save_context(vm, Cause::Trap(vector), pc.wrapping_sub(1), routine),
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::iodevice::MemoryIo;
+    use crate::components::vm::ExecState;
+
+    fn machine(source: &str, mode: TrapMode, input: &[u8]) -> VM {
+        let image = assemble(source).unwrap();
+        let mut vm = VM::new();
+        if mode != TrapMode::Native {
+            load(&mut vm, Path::new("builtin"), mode).unwrap();
+        }
+        loader::load_object_bytes(
+            &mut vm,
+            Path::new("<test>"),
+            object::write_v1(&image),
+            Endian::Big,
+        )
+        .unwrap();
+        vm.registers.pc = image.origin;
+        vm.record_start();
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.console.io = Box::new(MemoryIo::new(input));
+        vm
+    }
+
+    fn output(vm: &VM) -> String {
+        String::from_utf8_lossy(vm.console.transcript.as_deref().unwrap()).into_owned()
+    }
+
+    const ECHO: &str = "
+            .ORIG x3000
+            LEA R0, HELLO
+            PUTS
+            GETC
+            OUT
+            IN
+            LEA R0, PACKED
+            PUTSP
+            HALT
+    HELLO   .STRINGZ \"hi \"
+    PACKED  .FILL x6261
+            .FILL x0063
+            .FILL 0
+            .END";
+
+    #[test]
+    fn builtin_os_matches_the_native_traps() {
+        for mode in [TrapMode::Native, TrapMode::Vectored, TrapMode::Stack] {
+            let mut vm = machine(ECHO, mode, b"kq");
+            assert_eq!(vm.run(), Ok(ExecState::Halted));
+            assert_eq!(output(&vm), "hi k\nInput a character> q\nabc");
+        }
+    }
+
+    #[test]
+    fn trap_saves_the_pc_and_routines_can_be_replaced() {
+        // the program points OUT at a routine of its own at x4000
+        let mut vm = machine(
+            "
+            .ORIG x3000
+            LD R1, MINE
+            STI R1, VECTOR
+            LD R0, LETTER
+            OUT
+            HALT
+    MINE    .FILL x4000
+    VECTOR  .FILL x0021
+    LETTER  .FILL x7A
+            .END",
+            TrapMode::Vectored,
+            b"",
+        );
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.registers.r7, 0x3004);
+        assert_eq!(vm.registers.pc, 0x4000);
+
+        // the 2019 revision pushes PSR and PC instead and enters supervisor mode
+        let mut vm = machine(ECHO, TrapMode::Stack, b"k");
+        vm.registers.r7 = 0x1234;
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert!(vm.registers.supervisor);
+        assert_eq!(vm.registers.r7, 0x1234);
+        assert_eq!(vm.memory[vm.registers.r6 as usize], 0x3002);
+        // the shadow call stack has the routine as a handler frame until its RTI
+        let (_, frame) = vm.call_stack.handler().unwrap();
+        assert_eq!(
+            frame.handler,
+            Some(Handler {
+                cause: Cause::Trap(0x22),
+                saved_at: Some(vm.registers.r6)
+            })
+        );
+        while vm.registers.pc != 0x3002 {
+            vm.step().unwrap();
+        }
+        assert!(vm.call_stack.frames().is_empty());
+    }
+
+    #[test]
+    fn the_scheduler_takes_turns_between_threads() {
+        // two threads print their letter three times with a delay longer than a time slice between them
+        let image = assemble(
+            "
+            .ORIG x3000
+            LEA R0, WORK_A
+            LD R1, STACK_A
+            TRAP x30
+            ST R0, FIRST
+            LEA R0, WORK_B
+            LD R1, STACK_B
+            TRAP x30
+            TRAP x32
+    WORK_A  LD R0, LETTER_A
+            BRnzp WORK
+    WORK_B  LD R0, LETTER_B
+    WORK    AND R2, R2, #0
+            ADD R2, R2, #3
+    AGAIN   OUT
+            LD R3, DELAY
+    WAIT    ADD R3, R3, #-1
+            BRp WAIT
+            ADD R2, R2, #-1
+            BRp AGAIN
+            TRAP x32
+    LETTER_A .FILL x61
+    LETTER_B .FILL x62
+    DELAY   .FILL #200
+    STACK_A .FILL x5000
+    STACK_B .FILL x6000
+    FIRST   .FILL #0
+            .END",
+        )
+        .unwrap();
+        let mut vm = VM::new();
+        assert!(load(&mut vm, Path::new("scheduler"), TrapMode::Vectored).is_err());
+        let mut registry = lc3_device::Registry::new();
+        crate::components::devices::register_builtin(&mut registry);
+        vm.attach(registry.create("timer").unwrap()).unwrap();
+        load(&mut vm, Path::new("scheduler"), TrapMode::Vectored).unwrap();
+        assert_eq!(vm.traps, TrapMode::Stack);
+        loader::load_object_bytes(&mut vm, Path::new("<test>"), object::write_v2(&image), Endian::Big).unwrap();
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(output(&vm), "ababab");
+        assert_eq!(vm.memory[0x3019], 1);
+        // the last thread to end was B, thread 2
+        assert_eq!(vm.context.as_ref().unwrap().current, 2);
+    }
+}
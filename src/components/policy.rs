@@ -0,0 +1,147 @@
+// Course policy bundles: one `--policy` switch standing for a set of options an instructor would otherwise have to
+// spell out on every run (extension traps, devices, warnings, the seed, strictness). The bundled ones live in
+// policies/*.toml and are compiled in; a path to any other .toml file works the same way.
+//
+// The files use a small subset of TOML: `key = value` lines with strings, integers, booleans and arrays of
+// strings, and `#` comments. Unknown keys are errors so a typo doesn't silently do nothing.
+use std::fs;
+use std::path::Path;
+
+use super::diagnostics::Category;
+
+const BUNDLED: [(&str, &str); 3] = [
+    ("intro", include_str!("../../policies/intro.toml")),
+    ("os-course", include_str!("../../policies/os-course.toml")),
+    (
+        "competition",
+        include_str!("../../policies/competition.toml"),
+    ),
+];
+
+#[derive(Debug, Default, Clone)]
+pub struct Policy {
+    pub description: String,
+    pub capabilities: Vec<String>,
+    pub devices: Vec<String>,
+    pub no_warn: Vec<Category>,
+    // a fixed seed turns off run-to-run randomization
+    pub seed: Option<u64>,
+    pub require_checksum: bool,
+    pub allow_overlap: bool,
+    pub strict: bool,
+}
+
+enum Value {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+impl Value {
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "a string",
+            Value::Int(_) => "an integer",
+            Value::Bool(_) => "a boolean",
+            Value::List(_) => "an array",
+        }
+    }
+}
+
+// A bundled policy by name, or a policy file by path
+pub fn load_policy(name: &str) -> Result<Policy, String> {
+    if let Some((_, text)) = BUNDLED.iter().find(|(n, _)| *n == name) {
+        return parse_policy(text).map_err(|e| format!("policy {}: {}", name, e));
+    }
+    if !name.ends_with(".toml") {
+        let names: Vec<&str> = BUNDLED.iter().map(|(n, _)| *n).collect();
+        return Err(format!(
+            "unknown policy `{}` (bundled: {}, or a path to a .toml file)",
+            name,
+            names.join(", ")
+        ));
+    }
+    let text = fs::read_to_string(Path::new(name))
+        .map_err(|e| format!("couldn't read policy {}: {}", name, e))?;
+    parse_policy(&text).map_err(|e| format!("{}: {}", name, e))
+}
+
+pub fn parse_policy(text: &str) -> Result<Policy, String> {
+    let mut policy = Policy::default();
+    for (n, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let at = |e: String| format!("line {}: {}", n + 1, e);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at(format!("expected `key = value`, got `{}`", line)))?;
+        let key = key.trim();
+        let value = parse_value(value.trim()).map_err(at)?;
+        let mismatch = |value: &Value| at(format!("`{}` can't be {}", key, value.kind()));
+        match (key, value) {
+            ("description", Value::Str(s)) => policy.description = s,
+            ("capabilities", Value::List(list)) => policy.capabilities = list,
+            ("devices", Value::List(list)) => policy.devices = list,
+            ("no_warn", Value::List(list)) => {
+                policy.no_warn = list
+                    .iter()
+                    .map(|c| c.parse())
+                    .collect::<Result<_, _>>()
+                    .map_err(at)?
+            }
+            ("seed", Value::Int(seed)) => policy.seed = Some(seed),
+            ("require_checksum", Value::Bool(b)) => policy.require_checksum = b,
+            ("allow_overlap", Value::Bool(b)) => policy.allow_overlap = b,
+            ("strict", Value::Bool(b)) => policy.strict = b,
+            (
+                "description" | "capabilities" | "devices" | "no_warn" | "seed"
+                | "require_checksum" | "allow_overlap" | "strict",
+                value,
+            ) => return Err(mismatch(&value)),
+            _ => return Err(at(format!("unknown key `{}`", key))),
+        }
+    }
+    Ok(policy)
+}
+
+// `#` starts a comment unless it's inside a string
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    if let Some(inner) = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        return inner
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| match parse_value(item)? {
+                Value::Str(s) => Ok(s),
+                _ => Err(format!("arrays hold strings, got `{}`", item)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::List);
+    }
+    if let Some(inner) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        return Ok(Value::Str(inner.to_string()));
+    }
+    match s {
+        "true" => Ok(Value::Bool(true)),
+        "false" => Ok(Value::Bool(false)),
+        _ => s
+            .parse()
+            .map(Value::Int)
+            .map_err(|_| format!("unsupported value `{}`", s)),
+    }
+}
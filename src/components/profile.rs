@@ -0,0 +1,136 @@
+// Hot-spot report (--profile): after the run, the addresses that executed most, with their disassembly and
+// labels, the same counts totalled per label (the loop or subroutine they belong to), and the opcode mix. Built
+// from the execution statistics, so `--sample-every` makes it a sampling profile.
+use std::fmt::Write as _;
+
+use super::report::opcode_rows;
+use super::stats::ExecutionStats;
+use super::symbols;
+use super::vm::VM;
+
+// hot spots (and labels) listed when the caller has no better idea
+pub const TOP: usize = 20;
+
+pub fn render(vm: &VM, symbols: &[(u16, String)], top: usize) -> String {
+    let empty = ExecutionStats::new();
+    let stats = vm.stats.as_ref().unwrap_or(&empty);
+    let total: u64 = stats.executed.iter().sum();
+    let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+    let mut symbols = symbols.to_vec();
+    symbols.sort();
+
+    let mut out = String::new();
+    if stats.sampled() {
+        writeln!(
+            out,
+            "profile: {} instructions, sampled every {} ({} samples)",
+            stats.steps, stats.sample_every, total
+        )
+        .unwrap();
+    } else {
+        writeln!(out, "profile: {} instructions", stats.steps).unwrap();
+    }
+
+    // hottest first, lower addresses first among equals
+    let mut hot: Vec<(u16, u64)> = (0..=u16::MAX)
+        .map(|address| (address, stats.executed[address as usize]))
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    hot.sort_by_key(|&(address, count)| (std::cmp::Reverse(count), address));
+    out.push_str("  hot spots:\n");
+    for &(address, count) in hot.iter().take(top) {
+        let label = symbols
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map_or(String::new(), |(_, name)| format!("{}: ", name));
+        let word = vm.memory[address as usize];
+        writeln!(
+            out,
+            "    {:>10} {:>5.1}%  x{:04X}  {}{}",
+            count,
+            percent(count),
+            address,
+            label,
+            symbols::name_target(vm.cpu.disassemble(word, address), &symbols)
+        )
+        .unwrap();
+    }
+    if hot.len() > top {
+        writeln!(out, "    ... {} more addresses", hot.len() - top).unwrap();
+    }
+
+    // each address counts towards the nearest label at or before it
+    if !symbols.is_empty() {
+        let mut by_label: Vec<(&str, u64)> = Vec::new();
+        for &(address, count) in &hot {
+            let Some(index) = symbols.iter().rposition(|(a, _)| *a <= address) else {
+                continue;
+            };
+            let name = symbols[index].1.as_str();
+            match by_label.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, total)) => *total += count,
+                None => by_label.push((name, count)),
+            }
+        }
+        by_label.sort_by_key(|&(name, count)| (std::cmp::Reverse(count), name));
+        out.push_str("  by label:\n");
+        for (name, count) in by_label.into_iter().take(top) {
+            writeln!(out, "    {:>10} {:>5.1}%  {}", count, percent(count), name).unwrap();
+        }
+    }
+
+    out.push_str("  opcodes:\n");
+    for (name, count) in opcode_rows(stats) {
+        writeln!(out, "    {:>10} {:>5.1}%  {}", count, percent(count), name).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v2;
+
+    #[test]
+    fn nested_loop_is_the_hot_spot() {
+        // 4 x 5 trips round INNER
+        let image = assemble(
+            "
+            .ORIG x3000
+            AND R1, R1, #0
+            ADD R1, R1, #4
+    OUTER   AND R2, R2, #0
+            ADD R2, R2, #5
+    INNER   ADD R3, R3, #1
+            ADD R2, R2, #-1
+            BRp INNER
+            ADD R1, R1, #-1
+            BRp OUTER
+            HALT
+            .END",
+        )
+        .unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        vm.stats = Some(ExecutionStats::new());
+        vm.run().unwrap();
+
+        let text = render(&vm, &image.metadata.symbols, 3);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "profile: 79 instructions");
+        assert!(
+            lines[2].ends_with("x3004  INNER: ADD R3, R3, #1"),
+            "{}",
+            text
+        );
+        assert!(lines[2].trim_start().starts_with("20  25.3%"), "{}", text);
+        assert!(text.contains("... 7 more addresses"));
+        assert!(text.contains("69  87.3%  INNER"), "{}", text);
+        assert!(
+            text.contains("  opcodes:\n            49  62.0%  ADD\n"),
+            "{}",
+            text
+        );
+    }
+}
@@ -0,0 +1,154 @@
+// Register provenance: for each register, the instruction that last wrote it and a short chain of where that
+// value came from, e.g. `R3 ← MEM[x4002] at x3010 ← ST at x300A ← ADD at x3004`. Chains follow the first source
+// of each instruction and are cut at DEPTH links so tracking stays cheap. Stored words carry a chain too, so a
+// load can continue through the store that put the value there.
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use super::instruction::{sign_extend, trap_name};
+use super::vm::VM;
+
+const DEPTH: usize = 4;
+
+const MNEMONICS: [&str; 16] = [
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR", "RTI", "NOT", "LDI", "STI", "JMP", "RES",
+    "LEA", "TRAP",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub pc: u16,
+    pub step: u64,
+    pub what: String, // `MEM[x4002]` for loads, the mnemonic otherwise
+}
+
+type Chain = Vec<Link>;
+
+#[derive(Default)]
+pub struct Provenance {
+    registers: [Chain; 8],
+    memory: HashMap<u16, Chain>,
+}
+
+// What an instruction is about to write, with the chain each destination will get
+pub struct Plan {
+    registers: Vec<(u16, Chain)>,
+    memory: Option<(u16, Chain)>,
+}
+
+impl Provenance {
+    pub fn new() -> Provenance {
+        Provenance::default()
+    }
+
+    pub fn register(&self, r: u16) -> &[Link] {
+        &self.registers[r as usize]
+    }
+
+    // `R3 ← MEM[x4002] at x3010 (step 57) ← ST at x300A (step 40)`, or `R3 ← (not written)`
+    pub fn describe(&self, r: u16) -> String {
+        let mut out = format!("R{}", r);
+        let chain = self.register(r);
+        if chain.is_empty() {
+            out.push_str(" ← (not written)");
+        }
+        for link in chain {
+            write!(
+                out,
+                " ← {} at x{:04X} (step {})",
+                link.what, link.pc, link.step
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+// A new link in front of an existing chain, keeping at most DEPTH links
+fn extend(link: Link, from: &[Link]) -> Chain {
+    std::iter::once(link)
+        .chain(from.iter().take(DEPTH - 1).cloned())
+        .collect()
+}
+
+// Work out, before the instruction runs, which registers and word it will write and where their values come
+// from. Done up front because executing it may overwrite its own sources (LDR R1, R1, #0).
+pub fn plan(vm: &VM, pc: u16, instruction: u16) -> Option<Plan> {
+    let provenance = vm.provenance.as_ref()?;
+    let opcode = instruction >> 12;
+    let dr = (instruction >> 9) & 0x7;
+    let sr1 = (instruction >> 6) & 0x7;
+    let next = pc.wrapping_add(1);
+    let link = |what: String| Link {
+        pc,
+        step: vm.call_stack.steps() + 1,
+        what,
+    };
+    let register = |r: u16| provenance.registers[r as usize].as_slice();
+    let word = |address: u16| provenance.memory.get(&address).map_or(&[][..], Vec::as_slice);
+    let peek = |address: u16| vm.memory.get(address as usize).copied().unwrap_or(0);
+    let mnemonic = MNEMONICS[opcode as usize].to_string();
+
+    let mut plan = Plan {
+        registers: Vec::new(),
+        memory: None,
+    };
+    match opcode {
+        // ALU results follow their first source register
+        0x1 | 0x5 | 0x9 => plan
+            .registers
+            .push((dr, extend(link(mnemonic), register(sr1)))),
+        0x2 | 0x6 | 0xA => {
+            let address = match opcode {
+                0x2 => next.wrapping_add(sign_extend(instruction & 0x1FF, 9)),
+                0x6 => vm
+                    .registers
+                    .get(sr1)
+                    .wrapping_add(sign_extend(instruction & 0x3F, 6)),
+                _ => peek(next.wrapping_add(sign_extend(instruction & 0x1FF, 9))),
+            };
+            let what = format!("MEM[x{:04X}]", address);
+            plan.registers.push((dr, extend(link(what), word(address))));
+        }
+        0x3 | 0x7 | 0xB => {
+            let address = match opcode {
+                0x3 => next.wrapping_add(sign_extend(instruction & 0x1FF, 9)),
+                0x7 => vm
+                    .registers
+                    .get(sr1)
+                    .wrapping_add(sign_extend(instruction & 0x3F, 6)),
+                _ => peek(next.wrapping_add(sign_extend(instruction & 0x1FF, 9))),
+            };
+            plan.memory = Some((address, extend(link(mnemonic), register(dr))));
+        }
+        0xE => plan.registers.push((dr, vec![link(mnemonic)])),
+        0x4 => plan.registers.push((7, vec![link(mnemonic)])),
+        // traps that hand the program a value
+        0xF => {
+            let vector = instruction & 0xFF;
+            let name = trap_name(vector).unwrap_or("TRAP").to_string();
+            match vector {
+                0x20 | 0x23 | 0x2D => plan.registers.push((0, vec![link(name)])),
+                0x29 => {
+                    plan.registers.push((0, vec![link(name.clone())]));
+                    plan.registers.push((1, vec![link(name)]));
+                }
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+    Some(plan)
+}
+
+// Apply a plan once its instruction has run
+pub fn commit(vm: &mut VM, plan: Plan) {
+    if let Some(provenance) = vm.provenance.as_mut() {
+        for (r, chain) in plan.registers {
+            provenance.registers[r as usize] = chain;
+        }
+        if let Some((address, chain)) = plan.memory {
+            provenance.memory.insert(address, chain);
+        }
+    }
+}
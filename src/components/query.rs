@@ -0,0 +1,100 @@
+// Execution-state queries for editor hovers and inline annotations: given an address or symbol, the word's
+// current value, the instruction that last wrote it, how often it was read and written, and its disassembly.
+use super::access::Access;
+use super::instruction::disassemble_at;
+use super::parse_address;
+use super::results::json_string;
+use super::vm::VM;
+
+pub struct CellInfo {
+    pub address: u16,
+    pub symbol: Option<String>,
+    pub value: u16,
+    pub disassembly: String,
+    pub note: Option<String>,
+    // None when access tracking is off, or the word wasn't touched
+    pub last_write: Option<Access>,
+    pub last_read: Option<Access>,
+    pub reads: Option<u32>,
+    pub writes: Option<u32>,
+}
+
+// An address, or one of the object's symbols
+pub fn resolve(target: &str, symbols: &[(u16, String)]) -> Result<u16, String> {
+    match symbols.iter().find(|(_, name)| name == target.trim()) {
+        Some((address, _)) => Ok(*address),
+        None => parse_address(target)
+            .map_err(|_| format!("`{}` is neither an address nor a symbol", target.trim())),
+    }
+}
+
+// Look at memory without going through the devices, so asking has no side effects
+pub fn query(vm: &VM, symbols: &[(u16, String)], target: &str) -> Result<CellInfo, String> {
+    Ok(cell(vm, symbols, resolve(target, symbols)?))
+}
+
+pub fn cell(vm: &VM, symbols: &[(u16, String)], address: u16) -> CellInfo {
+    let value = vm.memory.get(address as usize).copied().unwrap_or(0);
+    let access = vm.access.as_ref();
+    CellInfo {
+        address,
+        symbol: symbols
+            .iter()
+            .find(|(a, _)| *a == address)
+            .map(|(_, name)| name.clone()),
+        value,
+        disassembly: disassemble_at(value, address),
+        note: vm.regions.notes.get(&address).cloned(),
+        last_write: access.and_then(|a| a.last_write[address as usize]),
+        last_read: access.and_then(|a| a.last_read[address as usize]),
+        reads: access.map(|a| a.reads[address as usize]),
+        writes: access.map(|a| a.writes[address as usize]),
+    }
+}
+
+impl CellInfo {
+    // One JSON object; missing values are null
+    pub fn to_json(&self) -> String {
+        let text = |value: &Option<String>| value.as_deref().map_or("null".to_string(), json_string);
+        let number = |value: Option<u64>| value.map_or("null".to_string(), |v| v.to_string());
+        format!(
+            "{{\"address\": {}, \"symbol\": {}, \"value\": {}, \"disassembly\": {}, \"note\": {}, \"last_writer\": {}, \"last_write_step\": {}, \"last_reader\": {}, \"last_read_step\": {}, \"reads\": {}, \"writes\": {}}}",
+            self.address,
+            text(&self.symbol),
+            self.value,
+            json_string(&self.disassembly),
+            text(&self.note),
+            number(self.last_write.map(|a| a.pc.into())),
+            number(self.last_write.map(|a| a.step)),
+            number(self.last_read.map(|a| a.pc.into())),
+            number(self.last_read.map(|a| a.step)),
+            number(self.reads.map(u64::from)),
+            number(self.writes.map(u64::from))
+        )
+    }
+
+    // `x4000 (SUM): last written by x3010 STR R0, R6, #0 at step 1234; ...`, the answer to "who wrote this?"
+    pub fn who(&self, vm: &VM) -> String {
+        let name = match &self.symbol {
+            Some(symbol) => format!("x{:04X} ({})", self.address, symbol),
+            None => format!("x{:04X}", self.address),
+        };
+        let by = |access: Option<Access>, verb: &str| match access {
+            Some(a) => format!(
+                "last {} by x{:04X} `{}` at step {}",
+                verb,
+                a.pc,
+                disassemble_at(vm.memory[a.pc as usize], a.pc),
+                a.step
+            ),
+            None => format!("not {} since loading", verb),
+        };
+        format!(
+            "{} = x{:04X}: {}; {}",
+            name,
+            self.value,
+            by(self.last_write, "written"),
+            by(self.last_read, "read")
+        )
+    }
+}
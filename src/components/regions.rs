@@ -0,0 +1,145 @@
+// Named memory regions (STACK x6000:x7000, HEAP x7000:x8000, ...) so diagnostics can say where an address lives.
+// Regions come from `--region NAME=START:END` or a file with one `NAME START:END` per line (`#` lines are comments).
+// Single addresses can also carry a free-form note ("loop counter"), shown next to the address wherever it's printed.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use super::{parse_address, parse_range};
+
+pub struct Region {
+    pub name: String,
+    pub range: RangeInclusive<u16>,
+}
+
+#[derive(Default)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+    pub notes: BTreeMap<u16, String>,
+}
+
+// An address shown with its region, e.g. `x6010 (STACK+x10)`
+pub struct Located<'a> {
+    map: &'a MemoryMap,
+    address: u16,
+}
+
+impl fmt::Display for Located<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "x{:04X}", self.address)?;
+        if let Some(region) = self.map.region_of(self.address) {
+            let offset = self.address - region.range.start();
+            if offset == 0 {
+                write!(f, " ({})", region.name)?;
+            } else {
+                write!(f, " ({}+x{:X})", region.name, offset)?;
+            }
+        }
+        if let Some(note) = self.map.notes.get(&self.address) {
+            write!(f, " [{}]", note)?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryMap {
+    pub fn new() -> MemoryMap {
+        MemoryMap::default()
+    }
+
+    pub fn add(&mut self, name: &str, range: RangeInclusive<u16>) {
+        self.regions.push(Region {
+            name: name.to_string(),
+            range,
+        });
+    }
+
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read regions {}: {}", path.display(), e))?;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, range) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                format!(
+                    "{}:{}: expected `NAME START:END`",
+                    path.display(),
+                    number + 1
+                )
+            })?;
+            let range = parse_range(range.trim())
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+            self.add(name, range);
+        }
+        Ok(())
+    }
+
+    pub fn annotate(&mut self, address: u16, note: &str) {
+        self.notes.insert(address, note.to_string());
+    }
+
+    // Notes file: one `ADDRESS note text` per line, where ADDRESS may also be one of the object's symbols
+    pub fn load_notes(&mut self, path: &Path, symbols: &[(u16, String)]) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read annotations {}: {}", path.display(), e))?;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (address, note) = line.split_once(char::is_whitespace).ok_or_else(|| {
+                format!("{}:{}: expected `ADDRESS note`", path.display(), number + 1)
+            })?;
+            let address = symbols
+                .iter()
+                .find(|(_, name)| name == address)
+                .map(|(a, _)| Ok(*a))
+                .unwrap_or_else(|| parse_address(address))
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+            self.annotate(address, note.trim());
+        }
+        Ok(())
+    }
+
+    // The innermost (smallest) region containing the address
+    pub fn region_of(&self, address: u16) -> Option<&Region> {
+        self.regions
+            .iter()
+            .filter(|r| r.range.contains(&address))
+            .min_by_key(|r| r.range.end() - r.range.start())
+    }
+
+    pub fn at(&self, address: u16) -> Located<'_> {
+        Located { map: self, address }
+    }
+
+    // The region a base+offset access escapes: its base points into the region but its target lands outside,
+    // e.g. an LDR off R6 that walks past the end of the stack
+    pub fn escaped(&self, base: u16, address: u16) -> Option<&Region> {
+        self.region_of(base)
+            .filter(|region| !region.range.contains(&address))
+    }
+
+    pub fn describe_access(&self, pc: u16, base: u16, address: u16) -> String {
+        let name = self.escaped(base, address).map_or("", |r| r.name.as_str());
+        format!(
+            "warning: access at {} uses base {} but reaches {}, outside {}",
+            self.at(pc),
+            self.at(base),
+            self.at(address),
+            name
+        )
+    }
+}
+
+// Parse a `--region` argument: NAME=START:END
+pub fn parse_region_arg(s: &str) -> Result<(String, RangeInclusive<u16>), String> {
+    let (name, range) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=START:END, got `{}`", s))?;
+    Ok((name.to_string(), parse_range(range)?))
+}
@@ -1,7 +1,12 @@
 const PC_START: u16 = 0x3000;
+// where the supervisor stack starts until an OS sets its own, just below user programs
+const SSP_START: u16 = 0x3000;
 
 // LC-3 has 10 registers -- 8 general-purpose registers, 1 program counter, and one condition flag.
 // The program counter stores a uint as the memory address of the executed instruction.
+// The condition flags are the low bits of the PSR, whose other fields (privilege and priority) are kept
+// alongside, as are the stack pointers R6 is swapped with on a change of privilege.
+#[derive(Clone, PartialEq, Eq)]
 pub struct Registers {
     pub r0: u16,        // general-purpose register
     pub r1: u16,        // general-purpose register
@@ -13,6 +18,16 @@ pub struct Registers {
     pub r7: u16,        // general-purpose register
     pub pc: u16,        // program counter
     pub cond: u16,      // condition flag
+    pub supervisor: bool, // privilege mode, PSR[15] clear
+    pub priority: u16,  // PSR[10:8]
+    pub saved_ssp: u16, // supervisor stack pointer while in user mode
+    pub saved_usp: u16, // user stack pointer while in supervisor mode
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Registers {
@@ -27,10 +42,26 @@ impl Registers {
             r6: 0,        // general-purpose register
             r7: 0,        // general-purpose register
             pc: PC_START, // program counter
-            cond: 0,      // condition flag
+            cond: ConditionFlag::Zro as u16, // Z at power-on, so BRnzp/BRz work before any flag is set
+            supervisor: false, // programs start in user mode unless --supervisor says otherwise
+            priority: 0,
+            saved_ssp: SSP_START,
+            saved_usp: 0,
         }
     }
 
+    // The processor status register: privilege in bit 15 (1 is user), priority in 10:8, N/Z/P in 2:0
+    pub fn psr(&self) -> u16 {
+        let user = if self.supervisor { 0 } else { 1 << 15 };
+        user | (self.priority & 0x7) << 8 | self.cond & 0x7
+    }
+
+    pub fn set_psr(&mut self, psr: u16) {
+        self.supervisor = psr >> 15 == 0;
+        self.priority = (psr >> 8) & 0x7;
+        self.cond = psr & 0x7;
+    }
+
     pub fn update(&mut self, index: u16, value: u16) {
         match index {
             0 => self.r0 = value,
@@ -43,6 +74,7 @@ impl Registers {
             7 => self.r7 = value,
             8 => self.pc = value,
             9 => self.cond = value,
+            10 => self.set_psr(value),
             _ => panic!("Index out of bound"),
         }
     }
@@ -59,6 +91,7 @@ impl Registers {
             7 => self.r7,
             8 => self.pc,
             9 => self.cond,
+            10 => self.psr(),
             _ => panic!("Index out of bound. "),
         }
     }
@@ -66,19 +99,19 @@ impl Registers {
     // Update the condition register based on the value inside the register `r`.
     pub fn update_r_cond_register(&mut self, r: u16) {
         if self.get(r) == 0 {
-            self.update(9, ConditionFlag::ZRO as u16);
+            self.update(9, ConditionFlag::Zro as u16);
         } else if (self.get(r) >> 15) != 0 {
             // a 1 in the left-most bit indicates negative
-            self.update(9, ConditionFlag::NEG as u16);
+            self.update(9, ConditionFlag::Neg as u16);
         } else {
-            self.update(9, ConditionFlag::POS as u16);
+            self.update(9, ConditionFlag::Pos as u16);
         }
     }
 }
 
 // The RCOND register stores condition flags representing information about the most recent computation.
 enum ConditionFlag {
-    POS = 1 << 0,
-    ZRO = 1 << 1,
-    NEG = 1 << 2,
+    Pos = 1 << 0,
+    Zro = 1 << 1,
+    Neg = 1 << 2,
 }
\ No newline at end of file
@@ -0,0 +1,466 @@
+// Single-file run report (HTML or Markdown): disassembly with coverage, opcode statistics, warnings,
+// trap log, probe markers, final registers and the console transcript. Meant to be attached to feedback or help requests.
+// The JSON form carries the same facts for graders and other scripts: how the run ended, the registers, the
+// instruction count and how often each address ran.
+use std::fmt::Write as _;
+use std::ops::RangeInclusive;
+
+use super::instruction::disassemble_at;
+use super::results::json_string;
+use super::stats::{trap_label, ExecutionStats};
+use super::vm::VM;
+
+const OPCODE_NAMES: [&str; 16] = [
+    "BR", "ADD", "LD", "ST", "JSR", "AND", "LDR", "STR", "RTI", "NOT", "LDI", "STI", "JMP", "RES",
+    "LEA", "TRAP",
+];
+
+const REGISTER_NAMES: [&str; 10] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Html,
+    Markdown,
+    Json,
+}
+
+impl ReportFormat {
+    // `.md`/`.markdown` files get Markdown, `.json` files (and plain `json`) JSON, anything else HTML
+    pub fn from_path(path: &std::path::Path) -> ReportFormat {
+        if path == std::path::Path::new("json") {
+            return ReportFormat::Json;
+        }
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("md") | Some("markdown") => ReportFormat::Markdown,
+            Some("json") => ReportFormat::Json,
+            _ => ReportFormat::Html,
+        }
+    }
+}
+
+pub fn render(vm: &VM, program: RangeInclusive<u16>, format: ReportFormat) -> String {
+    let empty = ExecutionStats::new();
+    let stats = vm.stats.as_ref().unwrap_or(&empty);
+    let transcript = vm
+        .console
+        .transcript
+        .as_ref()
+        .map(|t| String::from_utf8_lossy(t).into_owned())
+        .unwrap_or_default();
+
+    match format {
+        ReportFormat::Html => html(vm, stats, program, &transcript),
+        ReportFormat::Markdown => markdown(vm, stats, program, &transcript),
+        ReportFormat::Json => json(vm, stats, &transcript),
+    }
+}
+
+fn html(vm: &VM, stats: &ExecutionStats, program: RangeInclusive<u16>, transcript: &str) -> String {
+    let mut out = String::new();
+    out.push_str(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><meta name=\"color-scheme\" content=\"light dark\"><title>LC-3 run report</title>\n\
+         <style>body{font-family:sans-serif}table{border-collapse:collapse}\
+         td,th{padding:2px 8px;text-align:left}code,pre,td{font-family:monospace}\
+         tr.cold{color:#999}</style></head><body>\n<h1>LC-3 run report</h1>\n",
+    );
+    writeln!(out, "<p>{}</p>", summary(stats)).unwrap();
+    writeln!(out, "<p>Seed: <code>{}</code></p>", vm.rng.seed()).unwrap();
+
+    out.push_str("<h2>Final state</h2>\n<table>");
+    for (i, name) in REGISTER_NAMES.iter().enumerate() {
+        let value = vm.registers.get(i as u16);
+        writeln!(
+            out,
+            "<tr><th>{}</th><td>x{:04X}</td><td>{}</td></tr>",
+            name, value, value as i16
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+
+    writeln!(
+        out,
+        "<h2>Disassembly</h2>\n<table><tr><th>Address</th><th>Word</th><th>Instruction</th><th>{}</th></tr>",
+        count_heading(stats)
+    )
+    .unwrap();
+    let hottest = program
+        .clone()
+        .map(|a| stats.executed[a as usize])
+        .max()
+        .unwrap_or(0);
+    for address in program {
+        let word = vm.memory[address as usize];
+        let count = stats.executed[address as usize];
+        let row = if count == 0 {
+            "<tr class=\"cold\">".to_string()
+        } else {
+            // shade on a log scale so one hot loop doesn't wash out everything else
+            let heat = (count as f64).ln_1p() / (hottest as f64).ln_1p();
+            format!(
+                "<tr style=\"background:rgba(255,140,0,{:.2})\">",
+                0.1 + 0.6 * heat
+            )
+        };
+        writeln!(
+            out,
+            "{}<td>x{:04X}</td><td>x{:04X}</td><td>{}</td><td>{}</td></tr>",
+            row,
+            address,
+            word,
+            escape(&annotated(vm, word, address)),
+            count
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+
+    out.push_str("<h2>Opcode statistics</h2>\n<table>");
+    for (name, count) in opcode_rows(stats) {
+        writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", name, count).unwrap();
+    }
+    out.push_str("</table>\n");
+
+    out.push_str("<h2>Warnings</h2>\n");
+    if vm.warnings.is_empty() {
+        out.push_str("<p>None.</p>\n");
+    } else {
+        out.push_str("<ul>\n");
+        for warning in &vm.warnings {
+            writeln!(out, "<li><code>{}</code></li>", escape(warning)).unwrap();
+        }
+        out.push_str("</ul>\n");
+    }
+
+    out.push_str(
+        "<h2>Trap log</h2>\n<table><tr><th>Step</th><th>PC</th><th>Trap</th><th>R0</th></tr>\n",
+    );
+    for trap in &stats.traps {
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>x{:04X}</td><td>{}</td><td>x{:04X}</td></tr>",
+            trap.step,
+            trap.pc,
+            trap.name(),
+            trap.r0
+        )
+        .unwrap();
+    }
+    out.push_str("</table>\n");
+    if stats.traps_dropped > 0 {
+        writeln!(out, "<p>{} more traps not shown.</p>", stats.traps_dropped).unwrap();
+    }
+
+    if !stats.markers.is_empty() {
+        out.push_str(
+            "<h2>Markers</h2>\n<table><tr><th>Step</th><th>PC</th><th>Marker</th><th>Instructions until next</th></tr>\n",
+        );
+        for (marker, length) in stats.phases() {
+            writeln!(
+                out,
+                "<tr><td>{}</td><td>x{:04X}</td><td>{}</td><td>{}</td></tr>",
+                marker.step,
+                marker.pc,
+                escape(&marker.label),
+                length
+            )
+            .unwrap();
+        }
+        out.push_str("</table>\n");
+        if stats.markers_dropped > 0 {
+            writeln!(out, "<p>{} more markers not shown.</p>", stats.markers_dropped).unwrap();
+        }
+    }
+
+    out.push_str("<h2>Console transcript</h2>\n");
+    writeln!(out, "<pre>{}</pre>", escape(transcript)).unwrap();
+    out.push_str("</body></html>\n");
+    out
+}
+
+fn markdown(
+    vm: &VM,
+    stats: &ExecutionStats,
+    program: RangeInclusive<u16>,
+    transcript: &str,
+) -> String {
+    let mut out = String::new();
+    out.push_str("# LC-3 run report\n\n");
+    writeln!(out, "{}\n", summary(stats)).unwrap();
+    writeln!(out, "Seed: `{}`\n", vm.rng.seed()).unwrap();
+
+    out.push_str("## Final state\n\n| Register | Hex | Decimal |\n|---|---|---|\n");
+    for (i, name) in REGISTER_NAMES.iter().enumerate() {
+        let value = vm.registers.get(i as u16);
+        writeln!(out, "| {} | x{:04X} | {} |", name, value, value as i16).unwrap();
+    }
+
+    writeln!(
+        out,
+        "\n## Disassembly\n\n| Address | Word | Instruction | {} |\n|---|---|---|---|",
+        count_heading(stats)
+    )
+    .unwrap();
+    for address in program {
+        let word = vm.memory[address as usize];
+        writeln!(
+            out,
+            "| x{:04X} | x{:04X} | {} | {} |",
+            address,
+            word,
+            table_code(&annotated(vm, word, address)),
+            stats.executed[address as usize]
+        )
+        .unwrap();
+    }
+
+    out.push_str("\n## Opcode statistics\n\n| Opcode | Count |\n|---|---|\n");
+    for (name, count) in opcode_rows(stats) {
+        writeln!(out, "| {} | {} |", name, count).unwrap();
+    }
+
+    out.push_str("\n## Warnings\n\n");
+    if vm.warnings.is_empty() {
+        out.push_str("None.\n");
+    }
+    for warning in &vm.warnings {
+        writeln!(out, "- {}", code_span(warning)).unwrap();
+    }
+
+    out.push_str("\n## Trap log\n\n| Step | PC | Trap | R0 |\n|---|---|---|---|\n");
+    for trap in &stats.traps {
+        writeln!(
+            out,
+            "| {} | x{:04X} | {} | x{:04X} |",
+            trap.step,
+            trap.pc,
+            trap.name(),
+            trap.r0
+        )
+        .unwrap();
+    }
+    if stats.traps_dropped > 0 {
+        writeln!(out, "\n{} more traps not shown.", stats.traps_dropped).unwrap();
+    }
+
+    if !stats.markers.is_empty() {
+        out.push_str(
+            "\n## Markers\n\n| Step | PC | Marker | Instructions until next |\n|---|---|---|---|\n",
+        );
+        for (marker, length) in stats.phases() {
+            writeln!(
+                out,
+                "| {} | x{:04X} | {} | {} |",
+                marker.step,
+                marker.pc,
+                table_code(&marker.label),
+                length
+            )
+            .unwrap();
+        }
+        if stats.markers_dropped > 0 {
+            writeln!(out, "\n{} more markers not shown.", stats.markers_dropped).unwrap();
+        }
+    }
+
+    // a fence the transcript can't close early
+    let fence = "`".repeat(longest_backtick_run(transcript).max(2) + 1);
+    writeln!(out, "\n## Console transcript\n\n{}", fence).unwrap();
+    out.push_str(transcript);
+    if !transcript.ends_with('\n') {
+        out.push('\n');
+    }
+    writeln!(out, "{}", fence).unwrap();
+    out
+}
+
+// One member per line. The histogram is `[address, count]` pairs for the addresses that ran at all, lowest first.
+fn json(vm: &VM, stats: &ExecutionStats, transcript: &str) -> String {
+    let exit = match &vm.fault {
+        Some(fault) => format!(
+            "{{\"reason\": \"fault\", \"kind\": \"{}\", \"message\": {}, \"status\": {}}}",
+            fault.kind(),
+            json_string(&fault.describe(&vm.regions)),
+            fault.exit_code()
+        ),
+        None if vm.halted => "{\"reason\": \"halted\"}".to_string(),
+        None if vm.stopped_at.is_some() => "{\"reason\": \"stopped\"}".to_string(),
+        None => "{\"reason\": \"running\"}".to_string(),
+    };
+    let registers: Vec<String> = REGISTER_NAMES
+        .iter()
+        .enumerate()
+        .map(|(i, name)| format!("\"{}\": {}", name, vm.registers.get(i as u16)))
+        .chain(std::iter::once(format!("\"PSR\": {}", vm.registers.psr())))
+        .collect();
+    let histogram: Vec<String> = (0..=u16::MAX)
+        .filter(|&address| stats.executed[address as usize] > 0)
+        .map(|address| format!("[{}, {}]", address, stats.executed[address as usize]))
+        .collect();
+    let opcodes: Vec<String> = opcode_rows(stats)
+        .iter()
+        .map(|(name, count)| format!("\"{}\": {}", name, count))
+        .collect();
+    let traps: Vec<String> = stats
+        .trap_counts
+        .iter()
+        .map(|(&vector, &count)| format!("{}: {}", json_string(&trap_label(vector)), count))
+        .collect();
+    let warnings: Vec<String> = vm.warnings.iter().map(|w| json_string(w)).collect();
+
+    let mut out = String::from("{\n");
+    writeln!(out, "  \"exit\": {},", exit).unwrap();
+    writeln!(out, "  \"instructions\": {},", stats.steps).unwrap();
+    writeln!(out, "  \"sampled_every\": {},", stats.sample_every).unwrap();
+    writeln!(out, "  \"registers\": {{{}}},", registers.join(", ")).unwrap();
+    writeln!(out, "  \"histogram\": [{}],", histogram.join(", ")).unwrap();
+    writeln!(out, "  \"opcodes\": {{{}}},", opcodes.join(", ")).unwrap();
+    writeln!(out, "  \"traps\": {{{}}},", traps.join(", ")).unwrap();
+    writeln!(out, "  \"warnings\": [{}],", warnings.join(", ")).unwrap();
+    writeln!(out, "  \"output\": {},", json_string(transcript)).unwrap();
+    writeln!(out, "  \"seed\": {}", vm.rng.seed()).unwrap();
+    out.push_str("}\n");
+    out
+}
+
+// Disassembly with the address's note as a trailing comment
+fn annotated(vm: &VM, word: u16, address: u16) -> String {
+    match vm.regions.notes.get(&address) {
+        Some(note) => format!("{} ; {}", disassemble_at(word, address), note),
+        None => disassemble_at(word, address),
+    }
+}
+
+fn summary(stats: &ExecutionStats) -> String {
+    if stats.sampled() {
+        format!(
+            "{} instructions executed, sampled every {}; counts below are samples.",
+            stats.steps, stats.sample_every
+        )
+    } else {
+        format!("{} instructions executed.", stats.steps)
+    }
+}
+
+fn count_heading(stats: &ExecutionStats) -> &'static str {
+    if stats.sampled() {
+        "Samples"
+    } else {
+        "Executed"
+    }
+}
+
+// Opcodes that ran at least once, most frequent first
+pub fn opcode_rows(stats: &ExecutionStats) -> Vec<(&'static str, u64)> {
+    let mut rows: Vec<_> = OPCODE_NAMES
+        .iter()
+        .zip(stats.opcodes)
+        .filter(|(_, count)| *count > 0)
+        .map(|(name, count)| (*name, count))
+        .collect();
+    rows.sort_by_key(|row| std::cmp::Reverse(row.1));
+    rows
+}
+
+fn longest_backtick_run(text: &str) -> usize {
+    text.split(|c| c != '`').map(str::len).max().unwrap_or(0)
+}
+
+// Inline code that shows `text` as it is: delimited by more backticks than it has in a row, and padded when it
+// starts or ends with one. Line breaks become spaces, as they would inside the span anyway.
+fn code_span(text: &str) -> String {
+    let text = text.replace(['\r', '\n'], " ");
+    let ticks = "`".repeat(longest_backtick_run(&text) + 1);
+    let pad = if text.starts_with('`') || text.ends_with('`') {
+        " "
+    } else {
+        ""
+    };
+    format!("{}{}{}{}{}", ticks, pad, text, pad, ticks)
+}
+
+// A code span in a table cell, where a `|` would end the cell even inside the span
+fn table_code(text: &str) -> String {
+    code_span(text).replace('|', "\\|")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::callstack::LimitReached;
+    use crate::components::testing::VmBuilder;
+    use crate::components::vm::Fault;
+
+    #[test]
+    fn json_report_says_how_the_run_ended() {
+        // count R1 down from 2, then halt
+        let mut vm = VmBuilder::new()
+            .with_memory(0x3000, &[0x127F, 0x03FE, 0xF025])
+            .with_registers(&[(1, 2)])
+            .with_pc(0x3000)
+            .build();
+        vm.stats = Some(ExecutionStats::new());
+        vm.run().unwrap();
+
+        let text = render(&vm, 0x3000..=0x3002, ReportFormat::Json);
+        assert!(
+            text.contains("\"exit\": {\"reason\": \"halted\"},\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("\"instructions\": 5,\n"), "{}", text);
+        assert!(
+            text.contains("\"histogram\": [[12288, 2], [12289, 2], [12290, 1]],\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("\"R1\": 0, "), "{}", text);
+        assert!(text.contains("\"traps\": {\"HALT\": 1},\n"), "{}", text);
+
+        vm.fault = Some(Fault::Limit(LimitReached {
+            limit: 5,
+            pc: 0x3002,
+        }));
+        let text = render(&vm, 0x3000..=0x3002, ReportFormat::Json);
+        assert!(
+            text.contains("\"reason\": \"fault\", \"kind\": \"limit\""),
+            "{}",
+            text
+        );
+        assert_eq!(
+            ReportFormat::from_path(std::path::Path::new("json")),
+            ReportFormat::Json
+        );
+    }
+
+    #[test]
+    fn markdown_code_holds_backticks() {
+        assert_eq!(code_span("x3000"), "`x3000`");
+        assert_eq!(code_span("use ``` here"), "````use ``` here````");
+        assert_eq!(code_span("`tick"), "`` `tick ``");
+        assert_eq!(table_code("a|b"), "`a\\|b`");
+
+        // a transcript with a fence of its own, and a warning with backticks
+        let mut vm = VmBuilder::new()
+            .with_memory(0x3000, &[0xF025])
+            .with_pc(0x3000)
+            .build();
+        vm.stats = Some(ExecutionStats::new());
+        vm.console.write_str("```\nescaped?\n");
+        vm.warnings.push("label `LOOP` unused".to_string());
+        vm.run().unwrap();
+        let text = render(&vm, 0x3000..=0x3000, ReportFormat::Markdown);
+        assert!(text.contains("- ``label `LOOP` unused``\n"), "{}", text);
+        assert!(
+            text.ends_with("## Console transcript\n\n````\n```\nescaped?\n````\n"),
+            "{}",
+            text
+        );
+    }
+}
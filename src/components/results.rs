@@ -0,0 +1,213 @@
+// Results of the checks a run performs (integrity, budgets, golden frames, ...), kept apart from terminal
+// printing so they can be written as JUnit XML or JSON for LMS pipelines and other tooling.
+use std::fmt::Write as _;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Passed,
+    Failed,
+    Skipped,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Passed => "passed",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+pub struct TestResult {
+    pub name: String,
+    pub status: Status,
+    pub message: Option<String>,
+    pub diff: Option<String>,
+    pub duration: Duration,
+}
+
+pub struct TestSuite {
+    pub name: String,
+    pub results: Vec<TestResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResultsFormat {
+    Junit,
+    Json,
+}
+
+impl ResultsFormat {
+    // `.json` files get JSON, anything else JUnit XML
+    pub fn from_path(path: &std::path::Path) -> ResultsFormat {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => ResultsFormat::Json,
+            _ => ResultsFormat::Junit,
+        }
+    }
+}
+
+impl TestSuite {
+    pub fn new(name: &str) -> TestSuite {
+        TestSuite {
+            name: name.to_string(),
+            results: Vec::new(),
+        }
+    }
+
+    pub fn pass(&mut self, name: &str, duration: Duration) {
+        self.push(name, Status::Passed, None, None, duration);
+    }
+
+    pub fn fail(&mut self, name: &str, message: &str, diff: Option<String>, duration: Duration) {
+        self.push(
+            name,
+            Status::Failed,
+            Some(message.to_string()),
+            diff,
+            duration,
+        );
+    }
+
+    pub fn skip(&mut self, name: &str, reason: &str) {
+        self.push(
+            name,
+            Status::Skipped,
+            Some(reason.to_string()),
+            None,
+            Duration::ZERO,
+        );
+    }
+
+    fn push(
+        &mut self,
+        name: &str,
+        status: Status,
+        message: Option<String>,
+        diff: Option<String>,
+        duration: Duration,
+    ) {
+        self.results.push(TestResult {
+            name: name.to_string(),
+            status,
+            message,
+            diff,
+            duration,
+        });
+    }
+
+    pub fn count(&self, status: Status) -> usize {
+        self.results.iter().filter(|r| r.status == status).count()
+    }
+
+    pub fn passed(&self) -> bool {
+        self.count(Status::Failed) == 0
+    }
+
+    pub fn render(&self, format: ResultsFormat) -> String {
+        match format {
+            ResultsFormat::Junit => self.junit(),
+            ResultsFormat::Json => self.json(),
+        }
+    }
+
+    fn total_time(&self) -> f64 {
+        self.results.iter().map(|r| r.duration.as_secs_f64()).sum()
+    }
+
+    fn junit(&self) -> String {
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        writeln!(
+            out,
+            "<testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            xml_escape(&self.name),
+            self.results.len(),
+            self.count(Status::Failed),
+            self.count(Status::Skipped),
+            self.total_time()
+        )
+        .unwrap();
+        for result in &self.results {
+            write!(
+                out,
+                "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+                xml_escape(&result.name),
+                xml_escape(&self.name),
+                result.duration.as_secs_f64()
+            )
+            .unwrap();
+            let message = xml_escape(result.message.as_deref().unwrap_or(""));
+            match result.status {
+                Status::Passed => out.push_str("/>\n"),
+                Status::Skipped => writeln!(
+                    out,
+                    ">\n    <skipped message=\"{}\"/>\n  </testcase>",
+                    message
+                )
+                .unwrap(),
+                Status::Failed => writeln!(
+                    out,
+                    ">\n    <failure message=\"{}\">{}</failure>\n  </testcase>",
+                    message,
+                    xml_escape(result.diff.as_deref().unwrap_or(""))
+                )
+                .unwrap(),
+            }
+        }
+        out.push_str("</testsuite>\n");
+        out
+    }
+
+    fn json(&self) -> String {
+        let mut out = String::from("{\n");
+        writeln!(out, "  \"suite\": {},", json_string(&self.name)).unwrap();
+        writeln!(out, "  \"passed\": {},", self.passed()).unwrap();
+        writeln!(out, "  \"time\": {:.3},", self.total_time()).unwrap();
+        out.push_str("  \"tests\": [");
+        for (i, result) in self.results.iter().enumerate() {
+            let optional =
+                |value: &Option<String>| value.as_deref().map_or("null".to_string(), json_string);
+            if i > 0 {
+                out.push(',');
+            }
+            write!(
+                out,
+                "\n    {{\"name\": {}, \"status\": \"{}\", \"time\": {:.3}, \"message\": {}, \"diff\": {}}}",
+                json_string(&result.name),
+                result.status.as_str(),
+                result.duration.as_secs_f64(),
+                optional(&result.message),
+                optional(&result.diff)
+            )
+            .unwrap();
+        }
+        out.push_str("\n  ]\n}\n");
+        out
+    }
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
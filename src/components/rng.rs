@@ -0,0 +1,49 @@
+// The one source of randomness for the simulator. Everything random (devices, generated test inputs, ...)
+// draws from a generator seeded here, so a run can be reproduced exactly with `--seed`.
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// splitmix64: tiny, fast, and every seed (including 0) gives a good stream
+#[derive(Debug, Clone)]
+pub struct Rng {
+    seed: u64,
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { seed, state: seed }
+    }
+
+    // Seeded from the clock, for runs that didn't ask for a particular seed
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_entropy() -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Rng::new(nanos)
+    }
+
+    // SystemTime panics in the browser; the page's clock stands in for it
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_entropy() -> Rng {
+        Rng::new(js_sys::Date::now() as u64)
+    }
+
+    // The seed this generator started from, to report so the run can be repeated
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u16(&mut self) -> u16 {
+        (self.next_u64() >> 48) as u16
+    }
+}
@@ -0,0 +1,110 @@
+// Whole-machine snapshots (--save-on-halt, --restore): memory and the register file in a small binary file, to
+// checkpoint a long run and pick it up again later. Everything is big-endian, like object files:
+//
+//   "LC3S"  magic
+//   u16     format version
+//   u16 x12 R0-R7, PC, PSR (privilege, priority and condition codes), saved SSP, saved USP
+//   u32     number of memory words, then the words from x0000
+//
+// Devices, the console and the run's statistics aren't part of a snapshot; a restored run starts those afresh.
+use std::io::{Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use super::vm::VM;
+
+const MAGIC: &[u8; 4] = b"LC3S";
+const VERSION: u16 = 1;
+
+pub fn save<W: Write>(vm: &VM, mut out: W) -> Result<(), String> {
+    let r = &vm.registers;
+    let registers = [
+        r.r0, r.r1, r.r2, r.r3, r.r4, r.r5, r.r6, r.r7, r.pc, r.psr(), r.saved_ssp, r.saved_usp,
+    ];
+    let mut write = || -> std::io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_u16::<BigEndian>(VERSION)?;
+        for value in registers {
+            out.write_u16::<BigEndian>(value)?;
+        }
+        out.write_u32::<BigEndian>(vm.memory.len() as u32)?;
+        for &word in vm.memory.iter() {
+            out.write_u16::<BigEndian>(word)?;
+        }
+        out.flush()
+    };
+    write().map_err(|e| format!("couldn't write snapshot: {}", e))
+}
+
+pub fn load<R: Read>(vm: &mut VM, mut input: R) -> Result<(), String> {
+    let truncated = |e: std::io::Error| format!("snapshot is truncated or unreadable: {}", e);
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic).map_err(truncated)?;
+    if &magic != MAGIC {
+        return Err("not an lc3_sim snapshot".to_string());
+    }
+    let version = input.read_u16::<BigEndian>().map_err(truncated)?;
+    if version != VERSION {
+        return Err(format!(
+            "snapshot format version {} isn't supported (expected {})",
+            version, VERSION
+        ));
+    }
+    let mut registers = [0; 12];
+    for value in registers.iter_mut() {
+        *value = input.read_u16::<BigEndian>().map_err(truncated)?;
+    }
+    let words = input.read_u32::<BigEndian>().map_err(truncated)? as usize;
+    if words != vm.memory.len() {
+        return Err(format!(
+            "snapshot holds {} words of memory, this machine has {}",
+            words,
+            vm.memory.len()
+        ));
+    }
+    // read everything before touching the machine, so a bad file leaves it as it was
+    let mut memory = vec![0; words];
+    input
+        .read_u16_into::<BigEndian>(&mut memory)
+        .map_err(truncated)?;
+
+    vm.memory.copy_from_slice(&memory);
+    let r = &mut vm.registers;
+    let [r0, r1, r2, r3, r4, r5, r6, r7, pc, psr, ssp, usp] = registers;
+    (r.r0, r.r1, r.r2, r.r3, r.r4, r.r5, r.r6, r.r7, r.pc) = (r0, r1, r2, r3, r4, r5, r6, r7, pc);
+    r.set_psr(psr);
+    (r.saved_ssp, r.saved_usp) = (ssp, usp);
+    vm.halted = false;
+    vm.fault = None;
+    vm.stopped_at = None;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut vm = VM::new();
+        vm.memory[0x3000] = 0x1234;
+        vm.memory[0xFFF0] = 0xBEEF;
+        vm.registers.r3 = 7;
+        vm.registers.pc = 0x3005;
+        vm.registers.set_psr(0x0401);
+        vm.registers.saved_usp = 0xF000;
+        let mut bytes = Vec::new();
+        save(&vm, &mut bytes).unwrap();
+
+        let mut restored = VM::new();
+        load(&mut restored, bytes.as_slice()).unwrap();
+        assert_eq!(restored.memory[..], vm.memory[..]);
+        assert_eq!(restored.registers.r3, 7);
+        assert_eq!(restored.registers.pc, 0x3005);
+        assert_eq!(restored.registers.psr(), 0x0401);
+        assert!(restored.registers.supervisor);
+        assert_eq!(restored.registers.saved_usp, 0xF000);
+
+        assert!(load(&mut restored, &bytes[..bytes.len() - 1]).is_err());
+    }
+}
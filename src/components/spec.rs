@@ -0,0 +1,385 @@
+// Scripted tests (`lc3_sim test`): a spec names an object, the keys to type at it, and what the run has to leave
+// behind - console output, registers, memory - within an instruction budget. Specs are TOML or JSON with the
+// same flat keys:
+//
+//   object = "add.obj"               # relative to the spec
+//   input = "3\n4\n"
+//   output = "7\n"
+//   registers = "R0=x0007 R1=#-2"
+//   memory = "x4000=x0007 x4001=#12"
+//   max_instructions = 10000
+//   budget = "MULT:2000 x3100:500"  # instructions per call, subroutines by symbol or address
+//
+// Only `object` is required. A run that doesn't halt within the budget fails, whatever else it did.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::callstack::parse_budget;
+use super::equiv::{self, parse_register, parse_word};
+use super::iodevice::MemoryIo;
+use super::loader::{self, Endian};
+use super::object::{write_v2, ObjectImage};
+use super::parse_address;
+use super::symbols;
+use super::vm::{ExecState, VM};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spec {
+    pub name: String,
+    pub object: PathBuf,
+    pub input: Vec<u8>,
+    pub output: Option<Vec<u8>>,
+    pub registers: Vec<(u16, u16)>,
+    pub memory: Vec<(u16, u16)>,
+    pub max_instructions: u64,
+    pub budgets: Vec<(String, u64)>,
+}
+
+enum Value {
+    Str(String),
+    Int(u64),
+}
+
+pub fn load(path: &Path) -> Result<Spec, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    let json = path.extension().is_some_and(|e| e == "json");
+    let mut spec = parse(&text, json).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if spec.name.is_empty() {
+        spec.name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+    }
+    if let Some(dir) = path.parent() {
+        spec.object = dir.join(&spec.object);
+    }
+    Ok(spec)
+}
+
+pub fn parse(text: &str, json: bool) -> Result<Spec, String> {
+    let fields = if json {
+        json_fields(text)?
+    } else {
+        toml_fields(text)?
+    };
+    let mut spec = Spec {
+        max_instructions: equiv::STEP_LIMIT,
+        ..Spec::default()
+    };
+    for (key, value) in fields {
+        match (key.as_str(), value) {
+            ("name", Value::Str(s)) => spec.name = s,
+            ("object", Value::Str(s)) => spec.object = PathBuf::from(s),
+            ("input", Value::Str(s)) => spec.input = s.into_bytes(),
+            ("output", Value::Str(s)) => spec.output = Some(s.into_bytes()),
+            ("registers", Value::Str(s)) => spec.registers = assignments(&s, parse_register)?,
+            ("memory", Value::Str(s)) => spec.memory = assignments(&s, parse_address)?,
+            ("max_instructions", Value::Int(n)) => spec.max_instructions = n,
+            ("budget", Value::Str(s)) => {
+                spec.budgets = s
+                    .split_whitespace()
+                    .map(parse_budget)
+                    .collect::<Result<_, _>>()?
+            }
+            ("max_instructions", Value::Str(_)) => {
+                return Err("`max_instructions` must be an integer".to_string())
+            }
+            (
+                "name" | "object" | "input" | "output" | "registers" | "memory" | "budget",
+                Value::Int(_),
+            ) => return Err(format!("`{}` must be a string", key)),
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+    }
+    if spec.object.as_os_str().is_empty() {
+        return Err("no `object` to run".to_string());
+    }
+    Ok(spec)
+}
+
+// `TARGET=VALUE` pairs separated by spaces
+fn assignments(
+    text: &str,
+    target: fn(&str) -> Result<u16, String>,
+) -> Result<Vec<(u16, u16)>, String> {
+    text.split_whitespace()
+        .map(|field| {
+            let (left, right) = field
+                .split_once('=')
+                .ok_or_else(|| format!("expected TARGET=VALUE, got `{}`", field))?;
+            Ok((target(left)?, parse_word(right)?))
+        })
+        .collect()
+}
+
+// `key = value` lines, `#` comments
+fn toml_fields(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut fields = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let at = |e: String| format!("line {}: {}", n + 1, e);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at(format!("expected `key = value`, got `{}`", line)))?;
+        let (value, rest) = value_at(value.trim_start()).map_err(at)?;
+        let rest = rest.trim_start();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(at(format!("unexpected `{}` after the value", rest)));
+        }
+        fields.push((key.trim().to_string(), value));
+    }
+    Ok(fields)
+}
+
+// A single object of string and integer members
+fn json_fields(text: &str) -> Result<Vec<(String, Value)>, String> {
+    let mut fields = Vec::new();
+    let mut rest = text
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or("expected a JSON object")?
+        .trim_start();
+    while !rest.is_empty() {
+        let (key, after) = match value_at(rest)? {
+            (Value::Str(key), after) => (key, after),
+            _ => return Err(format!("expected a key at `{}`", rest)),
+        };
+        let after = after
+            .trim_start()
+            .strip_prefix(':')
+            .ok_or_else(|| format!("expected `:` after \"{}\"", key))?;
+        let (value, after) = value_at(after.trim_start())?;
+        fields.push((key, value));
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after.trim_start();
+        } else if !rest.is_empty() {
+            return Err(format!("expected `,` at `{}`", rest));
+        }
+    }
+    Ok(fields)
+}
+
+// A quoted string or an integer at the start of `s`, and what follows it
+fn value_at(s: &str) -> Result<(Value, &str), String> {
+    let Some(quoted) = s.strip_prefix('"') else {
+        let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let n = s[..end]
+            .parse()
+            .map_err(|_| format!("expected a string or an integer at `{}`", s))?;
+        return Ok((Value::Int(n), &s[end..]));
+    };
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((Value::Str(value), &quoted[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some('t') => value.push('\t'),
+                Some(c @ ('"' | '\\' | '/')) => value.push(c),
+                other => return Err(format!("unsupported escape `\\{}`", other.unwrap_or(' '))),
+            },
+            c => value.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+// Load the spec's object into a fresh headless machine and check it
+pub fn run(spec: &Spec) -> Result<Vec<String>, String> {
+    let mut vm = VM::new();
+    let image = loader::load_object(&mut vm, &spec.object, false, Endian::Big)?;
+    if let Some(entry) = image.metadata.entry {
+        vm.registers.pc = entry;
+    }
+    let mut symbols = image.metadata.symbols;
+    symbols::merge(&mut symbols, symbols::for_object(&spec.object, None)?);
+    set_budgets(spec, &symbols, &mut vm)?;
+    vm.record_start();
+    Ok(check(spec, &mut vm))
+}
+
+// Check an image in place of the spec's object, as mutation testing does with each changed program
+pub fn run_image(spec: &Spec, image: &ObjectImage) -> Result<Vec<String>, String> {
+    let mut vm = VM::from_obj_bytes(&write_v2(image))?;
+    set_budgets(spec, &image.metadata.symbols, &mut vm)?;
+    vm.record_start();
+    Ok(check(spec, &mut vm))
+}
+
+// Put the spec's per-call budgets on the machine, naming subroutines from the object's symbols
+pub fn set_budgets(spec: &Spec, symbols: &[(u16, String)], vm: &mut VM) -> Result<(), String> {
+    for (subroutine, max) in &spec.budgets {
+        let entry = symbols::address_of(subroutine, symbols)
+            .map_err(|e| format!("budget {}: {}", subroutine, e))?;
+        vm.call_stack.set_budget(entry, *max);
+    }
+    Ok(())
+}
+
+// Run a loaded machine on the spec's input, returning everything that didn't match
+pub fn check(spec: &Spec, vm: &mut VM) -> Vec<String> {
+    vm.console.echo = false;
+    vm.console.transcript = Some(Vec::new());
+    vm.console.io = Box::new(MemoryIo::new(&spec.input));
+    vm.max_instructions = Some(spec.max_instructions);
+
+    let mut failures = Vec::new();
+    match vm.run() {
+        Ok(ExecState::Halted) => {}
+        Ok(ExecState::Running) => failures.push("stopped without halting".to_string()),
+        Err(e) => failures.push(e.message),
+    }
+    let printed = vm.console.transcript.take().unwrap_or_default();
+    if let Some(output) = spec.output.as_ref().filter(|&output| *output != printed) {
+        failures.push(format!(
+            "output: expected {:?}, got {:?}",
+            String::from_utf8_lossy(output),
+            String::from_utf8_lossy(&printed)
+        ));
+    }
+    for &(register, expected) in &spec.registers {
+        let actual = vm.registers.get(register);
+        if actual != expected {
+            failures.push(format!(
+                "R{}: expected x{:04X}, got x{:04X}",
+                register, expected, actual
+            ));
+        }
+    }
+    for &(address, expected) in &spec.memory {
+        let actual = vm.memory[address as usize];
+        if actual != expected {
+            failures.push(format!(
+                "x{:04X}: expected x{:04X}, got x{:04X}",
+                address, expected, actual
+            ));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v1;
+
+    #[test]
+    fn toml_and_json_say_the_same_thing() {
+        let toml = "# doubling\n\
+                    object = \"double.obj\"\n\
+                    input = \"4\\n\"   # a digit and Enter\n\
+                    registers = \"R0=x0008 R1=#-1\"\n\
+                    max_instructions = 500\n";
+        let json = r#"{"object": "double.obj", "input": "4\n", "registers": "R0=x0008 R1=#-1",
+                       "max_instructions": 500}"#;
+        let spec = parse(toml, false).unwrap();
+        assert_eq!(spec, parse(json, true).unwrap());
+        assert_eq!(spec.input, b"4\n");
+        assert_eq!(spec.registers, [(0, 8), (1, 0xFFFF)]);
+        assert!(parse("input = \"x\"", false).is_err());
+        assert!(parse("object = \"a.obj\"\nbudget = 5", false).is_err());
+    }
+
+    #[test]
+    fn budgets_name_subroutines_by_symbol() {
+        // MULT adds R1 to itself R2 times, 3 instructions per trip
+        let image = assemble(
+            "
+            .ORIG x3000
+            AND R1, R1, #0
+            ADD R1, R1, #3
+            AND R2, R2, #0
+            ADD R2, R2, #4
+            JSR MULT
+            HALT
+    MULT    AND R0, R0, #0
+    AGAIN   ADD R0, R0, R1
+            ADD R2, R2, #-1
+            BRp AGAIN
+            RET
+            .END",
+        )
+        .unwrap();
+        let spec = parse(
+            "object = \"mult.obj\"\nregisters = \"R0=#12\"\nbudget = \"MULT:14\"",
+            false,
+        )
+        .unwrap();
+        assert_eq!(spec.budgets, [("MULT".to_string(), 14)]);
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        set_budgets(&spec, &image.metadata.symbols, &mut vm).unwrap();
+        assert_eq!(check(&spec, &mut vm), Vec::<String>::new());
+
+        let spec = Spec {
+            budgets: vec![("MULT".to_string(), 13)],
+            ..spec
+        };
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        set_budgets(&spec, &image.metadata.symbols, &mut vm).unwrap();
+        let failures = check(&spec, &mut vm);
+        assert!(failures[0].contains("exceeded its budget of 13"), "{:?}", failures);
+
+        let spec = Spec {
+            budgets: vec![("DIV".to_string(), 13)],
+            ..spec
+        };
+        assert!(set_budgets(&spec, &image.metadata.symbols, &mut vm).is_err());
+    }
+
+    #[test]
+    fn reports_every_mismatch() {
+        // read a digit, double it and print the result
+        let image = assemble(
+            "
+            .ORIG x3000
+            GETC
+            ADD R0, R0, #-16
+            ADD R0, R0, #-16
+            ADD R0, R0, #-16
+            ADD R0, R0, R0
+            ST R0, RESULT
+            LD R1, ZERO
+            ADD R0, R0, R1
+            OUT
+            HALT
+    ZERO    .FILL x30
+    RESULT  .BLKW 1
+            .END",
+        )
+        .unwrap();
+        let mut spec = parse(
+            "object = \"double.obj\"\ninput = \"4\"\noutput = \"8\"\nmemory = \"x300B=#8\"",
+            false,
+        )
+        .unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        assert_eq!(check(&spec, &mut vm), Vec::<String>::new());
+
+        spec.output = Some(b"9".to_vec());
+        spec.registers = vec![(1, 0x31)];
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        assert_eq!(
+            check(&spec, &mut vm),
+            [
+                "output: expected \"9\", got \"8\"",
+                "R1: expected x0031, got x0030"
+            ]
+        );
+
+        spec.max_instructions = 3;
+        let mut vm = VM::from_obj_bytes(&write_v1(&image)).unwrap();
+        let failures = check(&spec, &mut vm);
+        assert!(failures[0].starts_with("stopped after 3 instructions"));
+        assert_eq!(failures.len(), 4);
+    }
+}
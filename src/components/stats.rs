@@ -0,0 +1,130 @@
+// Execution statistics gathered while running: per-address execution counts, per-opcode counts, a trap log and
+// the markers the guest wrote to the probe registers.
+// Only collected when something asks for them (e.g. `--report`), since it costs a little on every instruction.
+use std::collections::BTreeMap;
+
+use super::instruction::trap_name;
+
+// Keep the trap log bounded for programs that print in a tight loop
+const TRAP_LOG_LIMIT: usize = 1000;
+const MARKER_LIMIT: usize = 1000;
+
+pub struct TrapRecord {
+    pub step: u64,
+    pub pc: u16,
+    pub vector: u16,
+    pub r0: u16, // argument/character at the time of the call
+}
+
+impl TrapRecord {
+    pub fn name(&self) -> String {
+        trap_label(self.vector)
+    }
+}
+
+// The trap's mnemonic, or `TRAP xNN` for vectors without one
+pub fn trap_label(vector: u16) -> String {
+    trap_name(vector)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("TRAP x{:02X}", vector))
+}
+
+// Written by the guest through the probe registers, e.g. `phase=sort-start`
+pub struct Marker {
+    pub step: u64,
+    pub pc: u16,
+    pub label: String,
+}
+
+pub struct ExecutionStats {
+    pub executed: Vec<u64>, // indexed by address
+    pub opcodes: [u64; 16],
+    pub traps: Vec<TrapRecord>,
+    pub traps_dropped: u64,
+    pub trap_counts: BTreeMap<u16, u64>, // by vector, including calls dropped from the log
+    pub markers: Vec<Marker>,
+    pub markers_dropped: u64,
+    pub steps: u64,
+    // 1 counts every instruction; N > 1 only records the PC and opcode of every Nth (sampling profile)
+    pub sample_every: u64,
+}
+
+impl Default for ExecutionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExecutionStats {
+    pub fn new() -> ExecutionStats {
+        ExecutionStats {
+            executed: vec![0; 1 << 16],
+            opcodes: [0; 16],
+            traps: Vec::new(),
+            traps_dropped: 0,
+            trap_counts: BTreeMap::new(),
+            markers: Vec::new(),
+            markers_dropped: 0,
+            steps: 0,
+            sample_every: 1,
+        }
+    }
+
+    pub fn sampling(sample_every: u64) -> ExecutionStats {
+        ExecutionStats {
+            sample_every: sample_every.max(1),
+            ..ExecutionStats::new()
+        }
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.sample_every > 1
+    }
+
+    pub fn record(&mut self, pc: u16, instruction: u16) {
+        self.steps += 1;
+        if self.sampled() && !self.steps.is_multiple_of(self.sample_every) {
+            return;
+        }
+        self.executed[pc as usize] += 1;
+        self.opcodes[(instruction >> 12) as usize] += 1;
+    }
+
+    pub fn record_trap(&mut self, pc: u16, vector: u16, r0: u16) {
+        *self.trap_counts.entry(vector).or_default() += 1;
+        if self.traps.len() < TRAP_LOG_LIMIT {
+            self.traps.push(TrapRecord {
+                step: self.steps,
+                pc,
+                vector,
+                r0,
+            });
+        } else {
+            self.traps_dropped += 1;
+        }
+    }
+
+    pub fn record_marker(&mut self, pc: u16, label: String) {
+        if self.markers.len() < MARKER_LIMIT {
+            self.markers.push(Marker {
+                step: self.steps,
+                pc,
+                label,
+            });
+        } else {
+            self.markers_dropped += 1;
+        }
+    }
+
+    // Each marker with the number of instructions until the next one (or the end of the run)
+    pub fn phases(&self) -> Vec<(&Marker, u64)> {
+        self.markers
+            .iter()
+            .enumerate()
+            .map(|(i, marker)| {
+                let end = self.markers.get(i + 1).map_or(self.steps, |next| next.step);
+                (marker, end - marker.step)
+            })
+            .collect()
+    }
+}
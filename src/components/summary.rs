@@ -0,0 +1,121 @@
+// What gets printed (to stderr) when the program halts normally, from nothing to a full summary or JSON.
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+use super::results::json_string;
+use super::stats::trap_label;
+use super::vm::VM;
+
+const REGISTER_NAMES: [&str; 10] = ["R0", "R1", "R2", "R3", "R4", "R5", "R6", "R7", "PC", "COND"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryLevel {
+    None,
+    Short, // the classic one-line HALT message
+    Full,  // instructions, traps used, warnings and the final registers
+    Json,
+}
+
+impl FromStr for SummaryLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SummaryLevel::None),
+            "short" => Ok(SummaryLevel::Short),
+            "full" => Ok(SummaryLevel::Full),
+            "json" => Ok(SummaryLevel::Json),
+            _ => Err(format!(
+                "unknown summary `{}` (expected none, short, full or json)",
+                s
+            )),
+        }
+    }
+}
+
+impl SummaryLevel {
+    // Full and JSON summaries list traps, which needs execution statistics
+    pub fn needs_stats(self) -> bool {
+        matches!(self, SummaryLevel::Full | SummaryLevel::Json)
+    }
+}
+
+pub fn render(vm: &VM, level: SummaryLevel) -> String {
+    let steps = vm.call_stack.steps();
+    let traps: Vec<(String, u64)> = vm
+        .stats
+        .iter()
+        .flat_map(|s| s.trap_counts.iter())
+        .map(|(&vector, &count)| (trap_label(vector), count))
+        .collect();
+
+    match level {
+        SummaryLevel::None => String::new(),
+        SummaryLevel::Short => "HALT detected\n".to_string(),
+        SummaryLevel::Full => {
+            let mut out = format!("HALT detected after {} instructions\n", steps);
+            let traps: Vec<String> = traps
+                .iter()
+                .map(|(name, count)| format!("{} x{}", name, count))
+                .collect();
+            writeln!(out, "  traps: {}", none_if_empty(&traps.join(", "))).unwrap();
+            writeln!(out, "  warnings: {}", vm.warnings.len()).unwrap();
+            for warning in &vm.warnings {
+                writeln!(out, "    {}", warning).unwrap();
+            }
+            let registers: Vec<String> = REGISTER_NAMES
+                .iter()
+                .enumerate()
+                .map(|(i, name)| format!("{}=x{:04X}", name, vm.registers.get(i as u16)))
+                .collect();
+            writeln!(out, "  registers: {}", registers.join(" ")).unwrap();
+            if !vm.memo.is_empty() {
+                writeln!(
+                    out,
+                    "  memoized calls: {} hits, {} misses",
+                    vm.memo.hits, vm.memo.misses
+                )
+                .unwrap();
+            }
+            writeln!(out, "  seed: {}", vm.rng.seed()).unwrap();
+            out
+        }
+        SummaryLevel::Json => {
+            let traps: Vec<String> = traps
+                .iter()
+                .map(|(name, count)| format!("{}: {}", json_string(name), count))
+                .collect();
+            let warnings: Vec<String> = vm.warnings.iter().map(|w| json_string(w)).collect();
+            let registers: Vec<String> = REGISTER_NAMES
+                .iter()
+                .enumerate()
+                .map(|(i, name)| format!("\"{}\": {}", name, vm.registers.get(i as u16)))
+                .collect();
+            let memo = if vm.memo.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    ", \"memo\": {{\"hits\": {}, \"misses\": {}}}",
+                    vm.memo.hits, vm.memo.misses
+                )
+            };
+            format!(
+                "{{\"halted\": true, \"instructions\": {}, \"traps\": {{{}}}, \"warnings\": [{}], \"registers\": {{{}}}, \"seed\": {}{}}}\n",
+                steps,
+                traps.join(", "),
+                warnings.join(", "),
+                registers.join(", "),
+                vm.rng.seed(),
+                memo
+            )
+        }
+    }
+}
+
+fn none_if_empty(text: &str) -> &str {
+    if text.is_empty() {
+        "none"
+    } else {
+        text
+    }
+}
@@ -0,0 +1,100 @@
+// Symbol tables in the .sym format the standard LC-3 assembler (lc3as) writes beside each .obj:
+//
+//   // Symbol table
+//   // Scope level 0:
+//   //	Symbol Name       Page Address
+//   //	----------------  ------------
+//   //	LOOP              3002
+//
+// Every line is a comment; the ones that matter hold a name and a hex address without the x. Symbols read here
+// join the ones a v2 object carries, so the debugger, --trace, disasm and --query can all name addresses.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn parse(text: &str) -> Result<Vec<(u16, String)>, String> {
+    let mut symbols = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim_start().strip_prefix("//").unwrap_or(line);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let [name, address] = words[..] else {
+            continue;
+        };
+        // the column headings and their underline
+        if name == "Symbol" || name.starts_with('-') {
+            continue;
+        }
+        let address = u16::from_str_radix(address.trim_start_matches(['x', 'X']), 16)
+            .map_err(|_| format!("line {}: bad address `{}` for {}", n + 1, address, name))?;
+        symbols.push((address, name.to_string()));
+    }
+    Ok(symbols)
+}
+
+pub fn load(path: &Path) -> Result<Vec<(u16, String)>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// The symbols for an object: from `explicit` if given, else from the .sym file next to it if there is one
+pub fn for_object(object: &Path, explicit: Option<&Path>) -> Result<Vec<(u16, String)>, String> {
+    match explicit {
+        Some(path) => load(path),
+        None => match beside(object) {
+            Some(path) => load(&path),
+            None => Ok(Vec::new()),
+        },
+    }
+}
+
+fn beside(object: &Path) -> Option<PathBuf> {
+    let path = object.with_extension("sym");
+    (path != object && path.is_file()).then_some(path)
+}
+
+// Add symbols to a table, keeping the existing entry where a name is already defined, ordered by address
+pub fn merge(table: &mut Vec<(u16, String)>, symbols: Vec<(u16, String)>) {
+    for (address, name) in symbols {
+        if !table.iter().any(|(_, n)| *n == name) {
+            table.push((address, name));
+        }
+    }
+    table.sort();
+}
+
+// The address `target` names: a symbol in the table, else a literal address (x3100, #12544)
+pub fn address_of(target: &str, symbols: &[(u16, String)]) -> Result<u16, String> {
+    match symbols.iter().find(|(_, name)| name == target) {
+        Some(&(address, _)) => Ok(address),
+        None => super::parse_address(target),
+    }
+}
+
+// Disassembly with a target address at the end replaced by its symbol, e.g. `BRp x3003` as `BRp LOOP`
+pub fn name_target(text: String, symbols: &[(u16, String)]) -> String {
+    let named = symbols.iter().find_map(|(address, name)| {
+        let prefix = text.strip_suffix(&format!("x{:04X}", address))?;
+        Some(format!("{}{}", prefix, name))
+    });
+    named.unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lc3as_output() {
+        let text = "// Symbol table\n\
+                    // Scope level 0:\n\
+                    //\tSymbol Name       Page Address\n\
+                    //\t----------------  ------------\n\
+                    //\tLOOP              3002\n\
+                    //\tDONE              3008\n\
+                    \n";
+        assert_eq!(
+            parse(text).unwrap(),
+            [(0x3002, "LOOP".to_string()), (0x3008, "DONE".to_string())]
+        );
+        assert!(parse("//\tLOOP  30G2\n").is_err());
+    }
+}
@@ -0,0 +1,111 @@
+// The run loop as a future (vm.run_async), for hosts on an async runtime. Each poll runs one slice of
+// instructions with run_for; after a slice that used its whole budget the future wakes itself and returns
+// Pending, so the executor gets to run other tasks in between. It resolves to how the run stopped: halted,
+// faulted, at a breakpoint, or waiting for a key the host then queues before awaiting a new one. No runtime is
+// needed beyond a waker, so it works under any executor.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::vm::{PollResult, VM};
+
+pub struct RunTask<'a> {
+    vm: &'a mut VM,
+    slice: u64,
+}
+
+impl<'a> RunTask<'a> {
+    pub fn new(vm: &'a mut VM, slice: u64) -> RunTask<'a> {
+        RunTask {
+            vm,
+            // a slice of nothing would never get anywhere
+            slice: slice.max(1),
+        }
+    }
+}
+
+impl Future for RunTask<'_> {
+    type Output = PollResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<PollResult> {
+        let task = self.get_mut();
+        match task.vm.run_for(task.slice) {
+            PollResult::Yielded => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            outcome => Poll::Ready(outcome),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::iodevice::MemoryIo;
+    use crate::components::object::write_v2;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    // counts how often the task asked to be polled again
+    struct Count(AtomicUsize);
+
+    impl Wake for Count {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn slices_until_the_program_waits_then_halts() {
+        // 1 + 3 x 100 instructions to GETC, then OUT and HALT
+        let image = assemble(
+            "
+            .ORIG x3000
+            LD R1, TRIPS
+    LOOP    ADD R2, R2, #1
+            ADD R1, R1, #-1
+            BRp LOOP
+            GETC
+            OUT
+            HALT
+    TRIPS   .FILL #100
+            .END",
+        )
+        .unwrap();
+        let mut vm = Box::new(VM::from_obj_bytes(&write_v2(&image)).unwrap());
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.console.io = Box::new(MemoryIo::new(b""));
+
+        let count = Arc::new(Count(Default::default()));
+        let waker = count.clone().into();
+        let mut cx = Context::from_waker(&waker);
+        let mut task = vm.run_async(50);
+        let mut polls = 0;
+        let outcome = loop {
+            polls += 1;
+            if let Poll::Ready(outcome) = Pin::new(&mut task).poll(&mut cx) {
+                break outcome;
+            }
+        };
+        assert_eq!(outcome, PollResult::NeedsInput);
+        assert_eq!(polls, 7);
+        assert_eq!(count.0.load(Ordering::Relaxed), 6);
+        assert_eq!(vm.instructions_executed(), 301);
+
+        vm.console.push_input(b"k");
+        let mut task = vm.run_async(50);
+        assert_eq!(
+            Pin::new(&mut task).poll(&mut cx),
+            Poll::Ready(PollResult::Halted)
+        );
+        assert_eq!(vm.console.transcript.as_deref(), Some(&b"k"[..]));
+    }
+}
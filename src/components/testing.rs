@@ -0,0 +1,92 @@
+// Machines for unit tests, built up from memory and registers, and single instructions stepped on them:
+//
+//   let vm = VmBuilder::new()
+//       .with_registers(&[(1, 0x7FFF)])
+//       .step_one(0x1261); // ADD R1, R1, #1
+//   assert_eq!((vm.registers.r1, vm.registers.cond), (0x8000, N));
+//
+// Machines are headless: nothing is echoed, output goes to the transcript and keys come from with_input.
+use std::ops::RangeInclusive;
+
+use super::iodevice::MemoryIo;
+use super::iopage::IoPage;
+use super::vm::VM;
+
+// condition codes as they are kept in vm.registers.cond
+pub const N: u16 = 1 << 2;
+pub const Z: u16 = 1 << 1;
+pub const P: u16 = 1;
+
+// the machine is boxed: moving its 64K words from call to call down a builder chain overflows a test's stack
+pub struct VmBuilder {
+    vm: Box<VM>,
+}
+
+impl Default for VmBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VmBuilder {
+    pub fn new() -> VmBuilder {
+        let mut vm = Box::new(VM::new());
+        vm.quiet = true;
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.console.io = Box::new(MemoryIo::new(b""));
+        VmBuilder { vm }
+    }
+
+    // `words` from `origin` on, wrapping past xFFFF
+    pub fn with_memory(mut self, origin: u16, words: &[u16]) -> VmBuilder {
+        for (offset, &word) in words.iter().enumerate() {
+            self.vm.memory[origin.wrapping_add(offset as u16) as usize] = word;
+        }
+        self
+    }
+
+    // (index, value) pairs as Registers::update takes them: R0-R7, then 8 for the PC, 9 for the condition codes
+    // and 10 for the PSR
+    pub fn with_registers(mut self, registers: &[(u16, u16)]) -> VmBuilder {
+        for &(index, value) in registers {
+            self.vm.registers.update(index, value);
+        }
+        self
+    }
+
+    pub fn with_pc(mut self, pc: u16) -> VmBuilder {
+        self.vm.registers.pc = pc;
+        self
+    }
+
+    // Keys for GETC, IN and the keyboard registers
+    pub fn with_input(mut self, keys: &[u8]) -> VmBuilder {
+        self.vm.console.io = Box::new(MemoryIo::new(keys));
+        self
+    }
+
+    // Move the device registers, e.g. to leave the top of memory as plain words
+    pub fn with_io_page(mut self, range: RangeInclusive<u16>) -> VmBuilder {
+        self.vm.io_page = IoPage::new(range).unwrap();
+        self
+    }
+
+    pub fn build(mut self) -> Box<VM> {
+        self.vm.record_start();
+        self.vm
+    }
+
+    // Put `instruction` at the PC and execute it, and only it
+    pub fn step_one(self, instruction: u16) -> Box<VM> {
+        let pc = self.vm.registers.pc;
+        let mut vm = self.with_memory(pc, &[instruction]).build();
+        let _ = vm.step();
+        vm
+    }
+}
+
+// The text a headless machine has printed
+pub fn output(vm: &VM) -> String {
+    String::from_utf8_lossy(vm.console.transcript.as_deref().unwrap_or_default()).into_owned()
+}
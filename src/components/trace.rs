@@ -0,0 +1,142 @@
+// Instruction trace (--trace): a line per executed instruction with its address, word and disassembly, followed by
+// the registers as the instruction left them, e.g.
+//
+//   x3000  x5260  AND R1, R1, #0        R0=x0000 R1=x0000 R2=x0000 ... R7=x0000 PC=x3001 CC=Z
+//
+// A marker the program writes to the probe registers gets a line of its own after the instruction that wrote it:
+//
+//   x3005  xB00A  STI R0, x3010           R0=x3011 ... R7=x0000 PC=x3006 CC=P
+//   ; marker phase=sort-start
+//
+// Lines go to stderr or a file. A trace is plain text so two runs (a submission and a reference) can be diffed.
+use std::io::{self, Write};
+
+use super::symbols;
+use super::vm::VM;
+
+// disassembly is padded to this width so the registers line up
+const DISASSEMBLY_WIDTH: usize = 22;
+
+pub struct Trace {
+    out: Box<dyn Write>,
+    // branch, call and load targets are written as these names where one fits
+    pub symbols: Vec<(u16, String)>,
+    // written by the instruction being executed, for after its line
+    markers: Vec<String>,
+}
+
+impl Trace {
+    pub fn new(out: Box<dyn Write>) -> Trace {
+        Trace {
+            out,
+            symbols: Vec::new(),
+            markers: Vec::new(),
+        }
+    }
+
+    pub fn to_stderr() -> Trace {
+        Trace::new(Box::new(io::stderr()))
+    }
+
+    // The instruction at `pc` has just executed
+    pub fn record(&mut self, vm: &VM, pc: u16, instruction: u16) {
+        let r = &vm.registers;
+        let registers: Vec<String> = [r.r0, r.r1, r.r2, r.r3, r.r4, r.r5, r.r6, r.r7]
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("R{}=x{:04X}", i, value))
+            .collect();
+        // a trace that can't be written isn't worth stopping the run for
+        let _ = writeln!(
+            self.out,
+            "x{:04X}  x{:04X}  {:<width$}  {} PC=x{:04X} CC={}",
+            pc,
+            instruction,
+            self.disassemble(vm, pc, instruction),
+            registers.join(" "),
+            r.pc,
+            condition(r.cond),
+            width = DISASSEMBLY_WIDTH
+        );
+        for label in self.markers.drain(..) {
+            let _ = writeln!(self.out, "; marker {}", label);
+        }
+    }
+
+    // A probe marker written by the instruction executing now
+    pub fn marker(&mut self, label: &str) {
+        self.markers.push(label.to_string());
+    }
+
+    // The core's disassembly, with a target address at the end replaced by its symbol
+    fn disassemble(&self, vm: &VM, pc: u16, instruction: u16) -> String {
+        symbols::name_target(vm.cpu.disassemble(instruction, pc), &self.symbols)
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+}
+
+fn condition(cond: u16) -> &'static str {
+    match cond & 0x7 {
+        4 => "N",
+        2 => "Z",
+        1 => "P",
+        _ => "?",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v2;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // a writer the test can read back after the trace has it
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn probe_markers_follow_the_instruction_that_wrote_them() {
+        let image = assemble(
+            "
+            .ORIG x3000
+            LEA R0, LABEL
+            STI R0, PROBE
+            AND R1, R1, #0
+            STI R1, CODE
+            HALT
+    PROBE   .FILL xFE18
+    CODE    .FILL xFE1A
+    LABEL   .STRINGZ \"phase=sort-start\"
+            .END",
+        )
+        .unwrap();
+        let mut vm = VM::from_obj_bytes(&write_v2(&image)).unwrap();
+        vm.console.echo = false;
+        let out = Rc::new(RefCell::new(Vec::new()));
+        vm.trace = Some(Trace::new(Box::new(Shared(out.clone()))));
+        for _ in 0..4 {
+            vm.step().unwrap();
+        }
+        let text = String::from_utf8(out.borrow().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[1].starts_with("x3001  xB003  STI R0, x3005"));
+        assert_eq!(lines[2], "; marker phase=sort-start");
+        assert!(lines[4].starts_with("x3003  xB202  STI R1, x3006"));
+        assert_eq!(lines[5], "; marker probe 0");
+    }
+}
@@ -1,49 +1,1181 @@
-// LC-3 has 65536 memory locations, u16
-const MEMORY_SIZE: usize = u16::MAX as usize;
+// KBSR bits
+pub const KBSR_READY: u16 = 1 << 15;
+const KBSR_INTERRUPT_ENABLE: u16 = 1 << 14;
+// the display takes a character at any time
+const DSR_READY: u16 = 1 << 15;
+// MCR bit 15 is the clock; clearing it stops the machine
+const MCR_CLOCK_ENABLE: u16 = 1 << 15;
 
+use super::access::{Access, AccessLog};
+use super::auxconsole::AuxConsole;
+use super::blocks::BlockProfile;
+use super::callstack::{BudgetExceeded, CallStack, LimitReached};
+use super::checkpoint::Marks;
+use super::clock::Clock;
+use super::console::{Console, OutputError};
+use super::context::{self, ContextSwitch};
+use super::control::{self, ControlFlags};
+use super::cpu::{self, Cpu};
+use super::decode::DecodeCache;
+use super::integrity::{Integrity, IntegrityError};
+use super::instruction::UnknownTrap;
+use super::interrupt::{self, Unhandled};
+use super::diagnostics::{Category, Diagnostics, Verdict};
+use super::events::{Divergence, EventLog};
+use super::history::History;
+use super::ident;
+use super::idle::{self, IdleLoop};
+use super::iopage::{IoPage, MemoryMappedReg, Unmapped};
+use super::layout::Layout;
+use super::loader::{self, Endian};
+use super::memo::{Impure, Memo};
+use super::object::ObjectImage;
+use super::os::TrapMode;
+use super::regions::MemoryMap;
+use super::provenance::Provenance;
 use super::register::Registers;
-use std::io::Read;
+use super::rng::Rng;
+use super::snapshot;
+use super::task::RunTask;
+use super::trace::Trace;
+use super::MEMORY_SIZE;
+use super::watch::{HookFn, Hooks, MemoryEvent, Target, Watch, WatchHit, WatchKind};
+use super::stats::ExecutionStats;
+use byteorder::{BigEndian, WriteBytesExt};
+use lc3_device::Device;
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub struct VM {
+    // the core that fetches and executes; LC-3 unless an object or the embedder asks for another
+    pub cpu: &'static dyn Cpu,
     pub memory: [u16; MEMORY_SIZE],
     pub registers: Registers,
+    pub console: Console,
+    pub call_stack: CallStack,
+    pub integrity: Integrity,
+    pub halted: bool,
+    pub regions: MemoryMap,
+    pub aux: AuxConsole,
+    pub marks: Marks,
+    pub stats: Option<ExecutionStats>,
+    pub blocks: Option<BlockProfile>,
+    pub access: Option<AccessLog>,
+    pub provenance: Option<Provenance>,
+    // instructions decoded once per address, see decode.rs; None decodes every one afresh
+    pub decode_cache: Option<DecodeCache>,
+    // recent instructions a debugger can take back, see history.rs
+    pub history: Option<History>,
+    // a line per executed instruction, see trace.rs
+    pub trace: Option<Trace>,
+    // the run faults instead of executing more instructions than this in total
+    pub max_instructions: Option<u64>,
+    // paces the run to an instruction rate, see clock.rs
+    pub clock: Option<Clock>,
+    // skips loops that only poll a device, see idle.rs
+    pub idle: Option<IdleLoop>,
+    // switches threads for a scheduler, see context.rs
+    pub context: Option<ContextSwitch>,
+    pub watches: Vec<Watch>,
+    // callbacks on loads and stores, see add_watch
+    pub hooks: Hooks,
+    // the access a hook paused the run on, until the run goes on
+    pub paused_on: Option<MemoryEvent>,
+    pub memo: Memo,
+    pub warnings: Vec<String>,
+    pub diagnostics: Diagnostics,
+    // device reads being recorded or replayed
+    pub events: Option<EventLog>,
+    pub fault: Option<Fault>,
+    // peripherals on the bus, added with attach; loads, stores and fetches in their ranges go to them instead of
+    // memory or the built-in console registers
+    pub devices: Vec<Box<dyn Device>>,
+    pub layout: Layout,
+    // where the device registers are mapped, xFE00-xFFFF unless configured
+    pub io_page: IoPage,
+    // loads and stores in the I/O page that no register or device answers stop the run (--strict)
+    pub strict: bool,
+    // set by a debugger so BKPT stops the run; without one BKPT is a NOP
+    pub break_on_bkpt: bool,
+    // set by a debugger so a watch (see watch.rs) that triggers pauses the run instead of faulting
+    pub pause_on_watch: bool,
+    // the watch the run paused on, until the run goes on
+    pub watch_hit: Option<WatchHit>,
+    // whether TRAP is done by the simulator or goes through the vector table to an OS image (--os)
+    pub traps: TrapMode,
+    // where the run stopped (a BKPT, a breakpoint, a pause), cleared by whoever resumes it
+    pub stopped_at: Option<u16>,
+    // addresses the run stops before, except where it starts or resumes
+    pub breakpoints: BTreeSet<u16>,
+    // pause/stop requests and breakpoint updates from outside the run loop
+    pub control: Option<Arc<ControlFlags>>,
+    // optional extensions the program may use, from --capability or the object's metadata
+    pub capabilities: Vec<String>,
+    // everything the loader placed in memory, in load order, so a cold reset can put it back
+    pub loaded: Vec<LoadedImage>,
+    // directory TRAP x2E may load objects from; chain loading is refused without one
+    pub load_dir: Option<PathBuf>,
+    // registers as they were when the run started, restored by both kinds of reset
+    pub start: Registers,
+    // warnings are still collected for reports, just not printed
+    pub quiet: bool,
+    // seeded once at startup (--seed); device seeds are drawn from it
+    pub rng: Rng,
+}
+
+pub struct LoadedImage {
+    pub origin: u16,
+    pub words: Vec<u16>,
+}
+
+// Why VM::poll returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollResult {
+    Yielded,          // the time budget ran out, call again
+    NeedsInput,       // the next instruction is GETC/IN and no keystrokes are queued; push some and call again
+    Breakpoint(u16),  // stopped at a BKPT, a breakpoint or a pause request; clear stopped_at to resume
+    Halted,
+    Faulted,          // see `fault`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetKind {
+    Cold,  // clear memory and reload every image
+    Warm,  // keep memory, only restore the registers
+    Empty, // clear memory and forget the images, ready to load another program
+}
+
+// Where an instruction or a run left the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecState {
+    Running, // can go on: between instructions, or stopped at a breakpoint or pause
+    Halted,
+}
+
+// Why the machine can't go on, for callers of execute_program and friends: the fault described against the
+// run's memory map, and the exit status the binary uses for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmError {
+    pub message: String,
+    pub exit_code: i32,
+}
+
+// A check that stopped the run before HALT
+pub enum Fault {
+    Budget(BudgetExceeded),
+    Integrity(IntegrityError),
+    Impure(Impure),
+    Watch(WatchHit),
+    Replay(Divergence),
+    Exception(Unhandled),
+    UnknownTrap(UnknownTrap),
+    Limit(LimitReached),
+    Unmapped(Unmapped),
+    Output(OutputError),
+}
+
+impl Fault {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        match self {
+            Fault::Budget(e) => e.describe(map),
+            Fault::Integrity(e) => e.describe(map),
+            Fault::Impure(e) => e.describe(map),
+            Fault::Watch(e) => e.describe(map),
+            Fault::Replay(e) => e.describe(map),
+            Fault::Exception(e) => e.describe(map),
+            Fault::UnknownTrap(e) => e.describe(map),
+            Fault::Limit(e) => e.describe(map),
+            Fault::Unmapped(e) => e.describe(map),
+            Fault::Output(e) => e.describe(map),
+        }
+    }
+
+    // Short machine-readable name, as in JSON reports
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Fault::Budget(_) => "budget",
+            Fault::Integrity(_) => "integrity",
+            Fault::Impure(_) => "impure",
+            Fault::Watch(_) => "watch",
+            Fault::Replay(_) => "replay",
+            Fault::Exception(_) => "exception",
+            Fault::UnknownTrap(_) => "unknown-trap",
+            Fault::Limit(_) => "limit",
+            Fault::Unmapped(_) => "unmapped",
+            Fault::Output(_) => "output",
+        }
+    }
+
+    // Process exit status used by the binary
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Fault::Budget(_) => 3,
+            Fault::Integrity(_) => 4,
+            Fault::Impure(_) => 6,
+            Fault::Watch(_) => 7,
+            Fault::Replay(_) => 8,
+            Fault::Exception(_) => 9,
+            Fault::UnknownTrap(_) => 10,
+            Fault::Limit(_) => 11,
+            Fault::Unmapped(_) => 12,
+            Fault::Output(_) => 13,
+        }
+    }
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
     pub fn new() -> VM {
         VM {
+            cpu: &cpu::LC3,
             memory: [0; MEMORY_SIZE],
             registers: Registers::new(),
+            console: Console::new(),
+            call_stack: CallStack::new(),
+            integrity: Integrity::new(),
+            halted: false,
+            regions: MemoryMap::new(),
+            aux: AuxConsole::new(),
+            marks: Marks::new(),
+            stats: None,
+            blocks: None,
+            access: None,
+            provenance: None,
+            decode_cache: Some(DecodeCache::new()),
+            history: None,
+            trace: None,
+            max_instructions: None,
+            clock: None,
+            idle: None,
+            context: None,
+            watches: Vec::new(),
+            hooks: Hooks::default(),
+            paused_on: None,
+            memo: Memo::new(),
+            warnings: Vec::new(),
+            diagnostics: Diagnostics::new(),
+            events: None,
+            fault: None,
+            devices: Vec::new(),
+            layout: Layout::new(),
+            io_page: IoPage::default(),
+            strict: false,
+            break_on_bkpt: false,
+            pause_on_watch: false,
+            watch_hit: None,
+            traps: TrapMode::Native,
+            stopped_at: None,
+            breakpoints: BTreeSet::new(),
+            control: None,
+            capabilities: Vec::new(),
+            loaded: Vec::new(),
+            start: Registers::new(),
+            quiet: false,
+            load_dir: None,
+            rng: Rng::from_entropy(),
+        }
+    }
+
+    // A machine with an object already loaded from bytes rather than a file, for hosts that bundle their guest
+    // programs (see include_lc3!)
+    pub fn from_obj_bytes(bytes: &[u8]) -> Result<VM, String> {
+        let mut vm = VM::new();
+        vm.quiet = true;
+        vm.load_object(bytes)?;
+        Ok(vm)
+    }
+
+    // Load a v1 or v2 object from any reader at its origin, big-endian, and start at its entry point when it
+    // records one, like a normal run
+    pub fn load_object<R: Read>(&mut self, mut reader: R) -> Result<ObjectImage, String> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|e| format!("couldn't read object: {}", e))?;
+        let image = loader::load_object_bytes(self, Path::new("<object>"), bytes, Endian::Big)?;
+        if let Some(entry) = image.metadata.entry {
+            self.registers.pc = entry;
+        }
+        self.record_start();
+        Ok(image)
+    }
+
+    // Execute one instruction
+    pub fn step(&mut self) -> Result<ExecState, VmError> {
+        super::step(self)
+    }
+
+    // Run until the program halts, faults or is stopped (see execute_program)
+    pub fn run(&mut self) -> Result<ExecState, VmError> {
+        super::execute_program(self)
+    }
+
+    // Instructions executed since the run started (or was last reset)
+    pub fn instructions_executed(&self) -> u64 {
+        self.call_stack.steps()
+    }
+
+    // Halted, faulted or still able to run
+    pub fn state(&self) -> Result<ExecState, VmError> {
+        if let Some(fault) = &self.fault {
+            return Err(VmError {
+                message: fault.describe(&self.regions),
+                exit_code: fault.exit_code(),
+            });
+        }
+        Ok(if self.halted {
+            ExecState::Halted
+        } else {
+            ExecState::Running
+        })
+    }
+
+    // Write memory and registers as a snapshot (see snapshot.rs)
+    pub fn save_state<W: Write>(&self, writer: W) -> Result<(), String> {
+        snapshot::save(self, writer)
+    }
+
+    // Replace memory and registers with a snapshot's, ready to run on from where it was taken
+    pub fn load_state<R: Read>(&mut self, reader: R) -> Result<(), String> {
+        snapshot::load(self, reader)
+    }
+
+    // Remember the current registers as the state a reset returns to, once loading has set PC and friends
+    pub fn record_start(&mut self) {
+        self.start = self.registers.clone();
+    }
+
+    // Get back to the start of the run without reloading from disk. Devices, breakpoints, marks,
+    // budgets and statistics are left alone; only the machine state and the run's outcome are reset.
+    //
+    // An Empty reset goes further, for hosts that run one program after another on the same machine (a grading
+    // server): nothing the last program loaded, printed, warned about or left in a device carries over. The
+    // machine's configuration (devices attached, watches, breakpoints, budgets, limits, console device and
+    // settings) stays, and statistics collectors are the host's to replace. Load the next program and run it.
+    pub fn reset(&mut self, kind: ResetKind) {
+        match kind {
+            ResetKind::Cold => {
+                self.memory = [0; MEMORY_SIZE];
+                for image in &self.loaded {
+                    let start = image.origin as usize;
+                    self.memory[start..start + image.words.len()].copy_from_slice(&image.words);
+                }
+            }
+            ResetKind::Warm => {}
+            ResetKind::Empty => {
+                self.memory = [0; MEMORY_SIZE];
+                self.loaded.clear();
+                self.layout.segments.clear();
+                self.start = Registers::new();
+                self.capabilities.clear();
+                self.load_dir = None;
+                self.warnings.clear();
+                self.diagnostics.clear();
+                self.memo.clear();
+                self.console.clear();
+                self.paused_on = None;
+                for device in &mut self.devices {
+                    device.reset();
+                }
+            }
+        }
+        self.registers = self.start.clone();
+        self.call_stack.reset();
+        self.memo.reset();
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
         }
+        if let Some(idle) = self.idle.as_mut() {
+            idle.reset();
+        }
+        if let Some(context) = self.context.as_mut() {
+            context.reset();
+        }
+        // the step numbers they record start over with the call stack's count
+        if self.access.is_some() {
+            self.access = Some(AccessLog::new());
+        }
+        if self.provenance.is_some() {
+            self.provenance = Some(Provenance::new());
+        }
+        self.halted = false;
+        self.fault = None;
+        self.stopped_at = None;
+    }
+
+    // Run for at most about `budget` and return, for hosts that drive the simulator from their own event loop.
+    // Never blocks: keyboard input comes only from console.push_input, and a KBSR read with nothing queued
+    // just reports no key.
+    pub fn poll(&mut self, budget: Duration) -> PollResult {
+        let start = Instant::now();
+        let mut count = 0u64;
+        // checking the clock every instruction would cost more than the instructions themselves, unless a slow
+        // clock would run far past the budget between checks
+        let clocked = self.clock.is_some();
+        self.run_until(|_| {
+            count += 1;
+            (clocked || count.is_multiple_of(control::CONTROL_INTERVAL as u64)) && start.elapsed() >= budget
+        })
+    }
+
+    // Run at most `instructions` instructions and return, as poll does. Counting instructions rather than time,
+    // it works where there's no clock (wasm32) and slices a run the same way on every machine.
+    pub fn run_for(&mut self, instructions: u64) -> PollResult {
+        let mut left = instructions;
+        self.run_until(|_| match left {
+            0 => true,
+            _ => {
+                left -= 1;
+                false
+            }
+        })
+    }
+
+    // Run until `done` says so, asked before each instruction (Yielded then), or until the program halts,
+    // faults, stops or waits for a key. Never blocks, as with poll. A breakpoint at the PC it starts from is
+    // passed over, so calling it again after a Breakpoint resumes the run.
+    pub fn run_until(&mut self, mut done: impl FnMut(&VM) -> bool) -> PollResult {
+        self.console.push_input(&[]);
+        let mut first = true;
+        let mut count = 0u64;
+        loop {
+            if !super::running(self) || (!first && super::at_breakpoint(self)) {
+                return self.outcome();
+            }
+            if self.needs_input() {
+                return PollResult::NeedsInput;
+            }
+            if done(self) {
+                return PollResult::Yielded;
+            }
+            first = false;
+            if super::step(self).is_err() {
+                return PollResult::Faulted;
+            }
+            count += 1;
+            if count.is_multiple_of(control::CONTROL_INTERVAL as u64) {
+                control::check(self);
+            }
+        }
+    }
+
+    // run_for in slices as a future, for hosts on an async runtime (see task.rs)
+    pub fn run_async(&mut self, slice: u64) -> RunTask<'_> {
+        RunTask::new(self, slice)
+    }
+
+    fn outcome(&self) -> PollResult {
+        if let Some(address) = self.stopped_at {
+            PollResult::Breakpoint(address)
+        } else if self.fault.is_some() {
+            PollResult::Faulted
+        } else {
+            PollResult::Halted
+        }
+    }
+
+    // The instruction at PC reads a keystroke (GETC, IN) or a line (GETD) that hasn't been queued yet
+    pub fn needs_input(&self) -> bool {
+        let instruction = self.memory[self.cpu.word_index(self.registers.pc) as usize];
+        if instruction >> 12 != 0xF {
+            return false;
+        }
+        match instruction & 0xFF {
+            0x20 | 0x23 => self.console.input_exhausted(),
+            0x2D if self.has_capability("numeric") => self.console.line_pending(),
+            _ => false,
+        }
+    }
+
+    // Call `callback` on every load (Read), store (Write) or both (Access) the program makes in `range`. A
+    // callback returning true pauses the run after the instruction, as a breakpoint would, with the access in
+    // paused_on. Returns an id for remove_watch.
+    pub fn add_watch(&mut self, range: RangeInclusive<u16>, kind: WatchKind, callback: HookFn) -> usize {
+        self.hooks.add(range, kind, callback)
+    }
+
+    pub fn remove_watch(&mut self, id: usize) -> bool {
+        self.hooks.remove(id)
     }
 
+    // A load by the running program
     pub fn read_memory(&mut self, address: u16) -> u16 {
-        if address == MemoryMappedReg::Kbsr as u16 {
-            self.handle_keyboard();
+        let by = self.accessor();
+        if self.strict && self.unmapped(address) {
+            self.fault.get_or_insert(Fault::Unmapped(Unmapped {
+                address,
+                pc: by.pc,
+                store: false,
+            }));
+        }
+        if let Some(access) = self.access.as_mut() {
+            access.read(address, by);
+        }
+        let value = self.fetch(address);
+        if self.idle.is_some() {
+            idle::read(self, address, value);
+        }
+        if !self.hooks.is_empty() {
+            self.notify_hooks(WatchKind::Read, address, value, by.pc);
+        }
+        value
+    }
+
+    // A fault that ends the run, unless a debugger asked to stop at the prompt; the PC it stops at is set once the
+    // instruction is done
+    pub fn watch_triggered(&mut self, hit: WatchHit) {
+        if self.pause_on_watch {
+            self.watch_hit.get_or_insert(hit);
+        } else {
+            self.fault.get_or_insert(Fault::Watch(hit));
+        }
+    }
+
+    fn notify_hooks(&mut self, kind: WatchKind, address: u16, value: u16, pc: u16) {
+        let event = MemoryEvent {
+            kind,
+            address,
+            value,
+            pc,
+        };
+        if self.hooks.notify(&event) {
+            self.paused_on = Some(event);
+            self.stopped_at = Some(self.registers.pc);
+        }
+    }
+
+    // A read that isn't the program's own load, such as an instruction fetch. Devices still see it.
+    pub fn fetch(&mut self, address: u16) -> u16 {
+        if self.device_at(address).is_some() {
+            return self.read_device(address);
+        }
+        if address == self.io_page.mcr() {
+            self.memory[address as usize] = MCR_CLOCK_ENABLE;
+        }
+        if let Some(value) = self.context.as_ref().and_then(|c| c.read(address)) {
+            self.memory[address as usize] = value;
+        }
+        match self.io_page.register_at(address) {
+            Some(MemoryMappedReg::Kbsr) => self.handle_keyboard(),
+            Some(MemoryMappedReg::Kbdr) => {
+                // taking the key clears the ready bit
+                let kbsr = self.io_page.address(MemoryMappedReg::Kbsr) as usize;
+                self.memory[kbsr] &= !KBSR_READY;
+            }
+            Some(MemoryMappedReg::Dsr) => self.memory[address as usize] = DSR_READY,
+            Some(MemoryMappedReg::AuxKbsr) => self.memory[address as usize] = self.aux.status(),
+            Some(MemoryMappedReg::AuxKbdr) => self.memory[address as usize] = self.aux.data(),
+            Some(MemoryMappedReg::AuxDsr) => {
+                self.memory[address as usize] = self.aux.display_status()
+            }
+            Some(register) => {
+                if let Some(value) = ident::read(self, register) {
+                    self.memory[address as usize] = value;
+                }
+            }
+            None => {}
         }
         self.memory[address as usize]
     }
 
+    // The instruction being executed, which is the one making any access (the PC has already moved past it)
+    fn accessor(&self) -> Access {
+        Access {
+            pc: self.registers.pc.wrapping_sub(1),
+            step: self.call_stack.steps() + 1,
+        }
+    }
+
+    // Ask the device at this address, or the event log when replaying one
+    fn read_device(&mut self, address: u16) -> u16 {
+        let step = self.call_stack.steps() + 1;
+        if let Some(replayed) = self.events.as_mut().and_then(|log| log.replay(step, address)) {
+            return match replayed {
+                Ok(value) => value,
+                Err(divergence) => {
+                    self.fault.get_or_insert(Fault::Replay(divergence));
+                    0
+                }
+            };
+        }
+        let value = self.device_at(address).map_or(0, |device| device.read(address));
+        if let Some(log) = self.events.as_mut() {
+            log.observe(step, address, value);
+        }
+        value
+    }
+
+    // In the I/O page, but neither a register nor a device's
+    fn unmapped(&mut self, address: u16) -> bool {
+        self.io_page.contains(address)
+            && self.io_page.register_at(address).is_none()
+            && address != self.io_page.mcr()
+            && self.device_at(address).is_none()
+    }
+
+    // A registered device claiming this address, if any
+    fn device_at(&mut self, address: u16) -> Option<&mut Box<dyn Device>> {
+        self.devices
+            .iter_mut()
+            .find(|d| d.range().contains(&address))
+    }
+
+    // Put a device on the bus. Its range can't overlap another device's, but it can cover the console registers
+    // to take them over from the built-in keyboard and display.
+    pub fn attach(&mut self, device: Box<dyn Device>) -> Result<(), String> {
+        let range = device.range();
+        let clash = self.devices.iter().find(|other| {
+            let other = other.range();
+            other.start() <= range.end() && range.start() <= other.end()
+        });
+        if let Some(other) = clash {
+            return Err(format!(
+                "device `{}` at x{:04X}-x{:04X} overlaps `{}` at x{:04X}-x{:04X}",
+                device.name(),
+                range.start(),
+                range.end(),
+                other.name(),
+                other.range().start(),
+                other.range().end()
+            ));
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    // A KBSR read. A key already in KBDR stays there until KBDR is read; otherwise take a key if one has been
+    // typed. Never waits: with no key the ready bit stays clear and the program polls again.
     fn handle_keyboard(&mut self) {
-        let mut buffer = [0; 1];
-        std::io::stdin().read_exact(&mut buffer).unwrap();
-        if buffer[0] != 0 {
-            self.write_memory(MemoryMappedReg::Kbsr as usize, 1 << 15);
-            self.write_memory(MemoryMappedReg::Kbdr as usize, buffer[0] as u16);
+        let kbsr = self.io_page.address(MemoryMappedReg::Kbsr) as usize;
+        if self.memory[kbsr] & KBSR_READY != 0 {
+            return;
+        }
+        if !self.key_ready() {
+            // the program is waiting for a key, so show what it printed so far
+            self.console.prompt();
+            return;
+        }
+        let key = self.read_byte();
+        self.latch_key(key);
+    }
+
+    // Whether a key is waiting, without waiting for one. When replaying, whether the recording has one for this
+    // instruction: a poll that found nothing isn't logged, only the key it eventually found.
+    pub fn key_ready(&mut self) -> bool {
+        let kbdr = self.io_page.address(MemoryMappedReg::Kbdr);
+        let step = self.call_stack.steps() + 1;
+        match self.events.as_ref() {
+            Some(log) if log.replaying() => log.has_next(step, kbdr),
+            _ => self.console.key_ready(),
+        }
+    }
+
+    // The next key for the program, however it asks (KBDR, GETC, IN, GETD); x00 once queued input is used up.
+    // With an event log the key is recorded or replayed like a device read of KBDR.
+    pub fn read_byte(&mut self) -> u8 {
+        let kbdr = self.io_page.address(MemoryMappedReg::Kbdr);
+        let step = self.call_stack.steps() + 1;
+        if let Some(replayed) = self.events.as_mut().and_then(|log| log.replay(step, kbdr)) {
+            return match replayed {
+                Ok(key) => key as u8,
+                Err(divergence) => {
+                    self.fault.get_or_insert(Fault::Replay(divergence));
+                    0
+                }
+            };
+        }
+        let key = self.console.read_byte();
+        if let Some(log) = self.events.as_mut() {
+            log.observe(step, kbdr, key as u16);
+        }
+        key
+    }
+
+    // A keystroke for IN, which echoes what it reads. Keys that arrive as escape sequences (arrows, function keys:
+    // ESC [ ... or ESC O x) are skipped rather than handed over a byte at a time; a lone ESC is still a key.
+    pub fn read_key(&mut self) -> u8 {
+        loop {
+            let key = self.read_byte();
+            if key != 0x1B || !self.key_ready() {
+                return key;
+            }
+            match self.read_byte() {
+                b'[' => loop {
+                    match self.read_byte() {
+                        0 => return 0,
+                        0x40..=0x7E => break,
+                        _ => {}
+                    }
+                },
+                b'O' => {
+                    self.read_byte();
+                }
+                // Alt with a key sends ESC and then the key
+                key => return key,
+            }
+        }
+    }
+
+    // The keyboard updates its own registers, these aren't stores by the program. The interrupt enable bit is
+    // the program's and is kept.
+    fn latch_key(&mut self, key: u8) {
+        let kbsr = self.io_page.address(MemoryMappedReg::Kbsr) as usize;
+        let kbdr = self.io_page.address(MemoryMappedReg::Kbdr) as usize;
+        if key != 0 {
+            self.memory[kbsr] |= KBSR_READY;
+            self.memory[kbdr] = key as u16;
         } else {
-            self.write_memory(MemoryMappedReg::Kbsr as usize, 0);
+            self.memory[kbsr] &= !KBSR_READY;
+        }
+    }
+
+    // With KBSR's interrupt enable bit set, a typed key interrupts the program (vector x80) instead of waiting
+    // to be polled for, as long as the program runs below the keyboard's priority. Checked before each
+    // instruction.
+    pub fn keyboard_interrupt(&mut self) {
+        let kbsr = self.io_page.address(MemoryMappedReg::Kbsr) as usize;
+        // a device that took KBSR over is the keyboard now
+        if self.memory[kbsr] & KBSR_INTERRUPT_ENABLE == 0 || self.device_at(kbsr as u16).is_some() {
+            return;
+        }
+        if self.memory[kbsr] & KBSR_READY == 0 && self.key_ready() {
+            let key = self.read_byte();
+            self.latch_key(key);
+        }
+        if self.memory[kbsr] & KBSR_READY != 0 && self.registers.priority < interrupt::KEYBOARD_PRIORITY {
+            interrupt::enter(self, interrupt::KEYBOARD, Some(interrupt::KEYBOARD_PRIORITY));
+        }
+    }
+
+    // Let the devices count the instruction about to run, then take the most urgent interrupt one asks for if
+    // the program runs below its priority. Checked before each instruction, after the keyboard.
+    pub fn device_interrupts(&mut self) {
+        if self.devices.is_empty() {
+            return;
+        }
+        for device in &mut self.devices {
+            device.tick();
+        }
+        let request = self
+            .devices
+            .iter()
+            .filter_map(|device| device.interrupt())
+            .max_by_key(|request| request.priority);
+        if let Some(request) = request.filter(|r| r.priority as u16 > self.registers.priority) {
+            interrupt::enter(self, request.vector as u16, Some(request.priority as u16));
+        }
+    }
+
+    // Write a region of memory as a loadable .obj image: the origin followed by the words, big-endian
+    pub fn write_image<W: Write>(&self, out: &mut W, range: RangeInclusive<u16>) -> io::Result<()> {
+        out.write_u16::<BigEndian>(*range.start())?;
+        for address in range {
+            out.write_u16::<BigEndian>(self.memory[address as usize])?;
+        }
+        Ok(())
+    }
+
+    // Warn when a base+offset access leaves the named region its base register points into. The warning is
+    // only formatted when the diagnostics channel will keep it, since a loop can repeat the access many times.
+    pub fn check_region_access(&mut self, base: u16, address: u16) {
+        if self.regions.escaped(base, address).is_none() {
+            return;
+        }
+        let pc = self.registers.pc.wrapping_sub(1);
+        let verdict = self
+            .diagnostics
+            .admit(Category::Region, pc, self.call_stack.steps());
+        if verdict != Verdict::Drop {
+            let warning = self.regions.describe_access(pc, base, address);
+            self.emit(verdict, warning);
+        }
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|c| c == name)
+    }
+
+    // Report a warning through the diagnostics channel, keyed by its category and the PC (or load address) it
+    // is about; what the channel keeps is also listed in the end-of-run report
+    pub fn warn(&mut self, category: Category, pc: u16, warning: String) {
+        let verdict = self.diagnostics.admit(category, pc, self.call_stack.steps());
+        self.emit(verdict, warning);
+    }
+
+    fn emit(&mut self, verdict: Verdict, warning: String) {
+        if verdict == Verdict::Drop {
+            return;
+        }
+        if verdict == Verdict::Show && !self.quiet {
+            eprintln!("{}", warning);
+        }
+        self.warnings.push(warning);
+    }
+
+    pub fn write_memory(&mut self, address: u16, value: u16) {
+        let index = address as usize;
+        if self.strict && self.unmapped(address) {
+            // refuse the store and stop the run
+            self.fault.get_or_insert(Fault::Unmapped(Unmapped {
+                address,
+                pc: self.registers.pc.wrapping_sub(1),
+                store: true,
+            }));
+            return;
+        }
+        if self.integrity.continuous
+            && self.memory[index] != value
+            && self.integrity.covers(address)
+        {
+            // refuse the store and stop the run
+            self.fault.get_or_insert(Fault::Integrity(IntegrityError::Overwritten {
+                address,
+                pc: self.registers.pc.wrapping_sub(1),
+                old: self.memory[index],
+                new: value,
+            }));
+            return;
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.stored(address, self.memory[index]);
+        }
+        let by = self.accessor();
+        if let Some(access) = self.access.as_mut() {
+            access.write(address, by);
         }
+        if !self.hooks.is_empty() {
+            self.notify_hooks(WatchKind::Write, address, value, by.pc);
+        }
+        if let Some(watch) = self
+            .watches
+            .iter()
+            .find(|w| w.target == Target::Memory(address) && w.triggers(self.memory[index], value))
+        {
+            // the store still happens, so the state shows what tripped it
+            let hit = watch.hit(by.pc, self.memory[index], value);
+            self.watch_triggered(hit);
+        }
+        if let Some(cache) = self.decode_cache.as_mut() {
+            cache.invalidate(address);
+        }
+        if let Some(device) = self.device_at(address) {
+            device.write(address, value);
+            return;
+        }
+        if self.context.as_ref().is_some_and(|c| c.range().contains(&address)) {
+            context::store(self, address, value);
+            return;
+        }
+        if address == self.io_page.mcr() {
+            // an OS image's HALT routine stops the clock rather than trapping to the simulator
+            if value & MCR_CLOCK_ENABLE == 0 {
+                self.console.flush();
+                self.halted = true;
+            }
+            return;
+        }
+        match self.io_page.register_at(address) {
+            Some(MemoryMappedReg::Kbsr) => {
+                // only the interrupt enable bit is the program's to set
+                let ready = self.memory[index] & KBSR_READY;
+                self.memory[index] = ready | value & KBSR_INTERRUPT_ENABLE;
+                return;
+            }
+            Some(MemoryMappedReg::Ddr) => self.console.write_byte(value as u8),
+            Some(MemoryMappedReg::Dsr) => return,
+            Some(MemoryMappedReg::AuxDdr) => self.aux.write(value),
+            Some(MemoryMappedReg::Probe) => {
+                let label = self.read_string(value, PROBE_LABEL_LIMIT);
+                self.probe(label);
+            }
+            Some(MemoryMappedReg::ProbeCode) => self.probe(format!("probe {}", value)),
+            Some(
+                MemoryMappedReg::IdMagic
+                | MemoryMappedReg::IdVersion
+                | MemoryMappedReg::IdExtensions
+                | MemoryMappedReg::IdIsa,
+            ) => return,
+            _ => {}
+        }
+        self.memory[index] = value;
     }
 
-    pub fn write_memory(&mut self, address: usize, value: u16) {
-        self.memory[address] = value;
+    // A null-terminated string in guest memory, one character per word, cut short after `limit` characters
+    pub fn read_string(&self, address: u16, limit: usize) -> String {
+        (address..=u16::MAX)
+            .map(|a| self.memory.get(a as usize).copied().unwrap_or(0))
+            .take_while(|&word| word != 0)
+            .take(limit)
+            .map(|word| (word & 0xFF) as u8 as char)
+            .collect()
+    }
+
+    // Views of memory for hosts and tests setting up inputs and checking results. They go straight to memory:
+    // no devices, watches, hooks or integrity checks, and nothing the debugger can undo. A range running past
+    // xFFFF is an error rather than wrapping round.
+
+    // `len` words from `origin`
+    pub fn read_slice(&self, origin: u16, len: usize) -> Result<&[u16], String> {
+        let range = span(origin, len)?;
+        Ok(&self.memory[range])
+    }
+
+    pub fn write_slice(&mut self, origin: u16, words: &[u16]) -> Result<(), String> {
+        let range = span(origin, words.len())?;
+        self.memory[range].copy_from_slice(words);
+        Ok(())
+    }
+
+    // The null-terminated string at `address`, one character per word. A word that isn't a character (above
+    // xFF) or a string with no terminator before the end of memory is an error.
+    pub fn read_string_z(&self, address: u16) -> Result<String, String> {
+        let mut text = String::new();
+        for a in address as usize..MEMORY_SIZE {
+            match self.memory[a] {
+                0 => return Ok(text),
+                word @ 1..=0xFF => text.push(word as u8 as char),
+                word => {
+                    return Err(format!(
+                        "x{:04X} holds x{:04X}, which isn't a character",
+                        a, word
+                    ))
+                }
+            }
+        }
+        Err(format!("the string at x{:04X} runs off the end of memory", address))
+    }
+
+    // `text` at `address`, one character per word, and the terminating zero. Only ASCII fits a word the way the
+    // traps read it.
+    pub fn write_string_z(&mut self, address: u16, text: &str) -> Result<(), String> {
+        if !text.is_ascii() {
+            return Err(format!("{:?} isn't ASCII", text));
+        }
+        let words: Vec<u16> = text.bytes().map(u16::from).chain([0]).collect();
+        self.write_slice(address, &words)
+    }
+
+    // Markers only have somewhere to go when statistics are being collected or the run is traced
+    fn probe(&mut self, label: String) {
+        let pc = self.registers.pc.wrapping_sub(1);
+        if let Some(trace) = self.trace.as_mut() {
+            trace.marker(&label);
+        }
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record_marker(pc, label);
+        }
+    }
+}
+
+const PROBE_LABEL_LIMIT: usize = 80;
+
+// The indices of `len` words from `origin`, if they're all in memory
+fn span(origin: u16, len: usize) -> Result<std::ops::Range<usize>, String> {
+    let start = origin as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= MEMORY_SIZE => Ok(start..end),
+        _ => Err(format!(
+            "{} words from x{:04X} run past the end of memory",
+            len, origin
+        )),
     }
 }
 
-pub enum MemoryMappedReg {
-    // key presses
-    Kbsr = 0xFE00,
-    
-    // identify key
-    Kbdr = 0xFE02,
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::iodevice::MemoryIo;
+    use crate::components::object::write_v2;
+
+    fn object(source: &str) -> Vec<u8> {
+        write_v2(&assemble(source).unwrap())
+    }
+
+    #[test]
+    fn empty_reset_leaves_nothing_for_the_next_program() {
+        let mut vm = VM::new();
+        vm.quiet = true;
+        vm.console.echo = false;
+        vm.console.transcript = Some(Vec::new());
+        vm.max_instructions = Some(100);
+
+        // the first program leaves a word at x4000, a register and some output behind
+        let first = object(
+            "
+            .ORIG x3000
+            LD R3, WORD
+            STI R3, TARGET
+            LEA R0, TEXT
+            PUTS
+            HALT
+    WORD    .FILL x1234
+    TARGET  .FILL x4000
+    TEXT    .STRINGZ \"one\"
+            .END",
+        );
+        vm.load_object(&first[..]).unwrap();
+        vm.console.io = Box::new(MemoryIo::new(b""));
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.memory[0x4000], 0x1234);
+
+        vm.reset(ResetKind::Empty);
+        assert!(vm.loaded.is_empty());
+        assert_eq!(vm.registers.r3, 0);
+        assert_eq!(vm.registers.pc, 0x3000);
+        assert_eq!(vm.console.transcript.as_deref(), Some(&[][..]));
+
+        // the same origin loads again without an overlap, and sees none of the first program
+        let second = object(
+            "
+            .ORIG x3000
+            LDI R1, TARGET
+            LEA R0, TEXT
+            PUTS
+            HALT
+    TARGET  .FILL x4000
+    TEXT    .STRINGZ \"two\"
+            .END",
+        );
+        vm.load_object(&second[..]).unwrap();
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.registers.r1, 0);
+        assert_eq!(vm.registers.r3, 0);
+        assert_eq!(vm.console.transcript.as_deref(), Some(&b"two"[..]));
+        // configuration carries over
+        assert_eq!(vm.max_instructions, Some(100));
+    }
+
+    #[test]
+    fn strict_stops_at_an_unmapped_io_address() {
+        let program = object(
+            "
+            .ORIG x3000
+            LDI R0, DSR
+            LDI R1, HOLE
+            ADD R2, R2, #1
+            HALT
+    DSR     .FILL xFE04
+    HOLE    .FILL xFE08
+            .END",
+        );
+        let mut vm = VM::new();
+        vm.console.echo = false;
+        vm.load_object(&program[..]).unwrap();
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+
+        vm.reset(ResetKind::Cold);
+        vm.strict = true;
+        let error = vm.run().unwrap_err();
+        assert_eq!(error.exit_code, 12);
+        assert!(
+            error.message.starts_with("load from unmapped I/O address xFE08 at x3001"),
+            "{}",
+            error.message
+        );
+        assert_eq!(vm.registers.r2, 0);
+
+        // a store is refused
+        vm.reset(ResetKind::Cold);
+        vm.registers.r0 = 0x0041;
+        vm.registers.pc = 0x3000;
+        vm.memory[0x3000] = 0xB004; // STI R0, x3005
+        vm.memory[0x3005] = 0xFE0A;
+        assert!(vm.run().is_err());
+        assert_eq!(vm.memory[0xFE0A], 0);
+    }
+
+    #[test]
+    fn slices_and_strings_are_bounds_checked() {
+        // the program upper-cases the string at x4000 in place
+        let program = object(
+            "
+            .ORIG x3000
+            LD R1, TEXT
+            LD R3, CASE
+    NEXT    LDR R2, R1, #0
+            BRz DONE
+            ADD R2, R2, R3
+            STR R2, R1, #0
+            ADD R1, R1, #1
+            BRnzp NEXT
+    DONE    HALT
+    TEXT    .FILL x4000
+    CASE    .FILL #-32
+            .END",
+        );
+        let mut vm = VM::new();
+        vm.console.echo = false;
+        vm.load_object(&program[..]).unwrap();
+        vm.write_string_z(0x4000, "lc").unwrap();
+        assert_eq!(vm.read_slice(0x4000, 3), Ok(&[0x6C, 0x63, 0][..]));
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert_eq!(vm.read_string_z(0x4000), Ok("LC".to_string()));
+
+        vm.write_slice(0xFFFE, &[0x41, 0x42]).unwrap();
+        assert!(vm.read_string_z(0xFFFE).is_err());
+        assert!(vm.read_slice(0xFFFF, 2).is_err());
+        assert!(vm.write_slice(0xFFFF, &[1, 2]).is_err());
+        assert!(vm.write_string_z(0x4000, "caf\u{e9}").is_err());
+        vm.memory[0x4001] = 0x1234;
+        assert!(vm.read_string_z(0x4000).is_err());
+    }
+
+    #[test]
+    fn a_budget_overrun_keeps_the_fault_its_instruction_raised() {
+        use crate::components::testing::VmBuilder;
+        use crate::components::watch::parse_watch;
+
+        // the subroutine's only instruction both trips the watch and runs over its budget
+        let mut vm = VmBuilder::new()
+            .with_pc(0x3000)
+            .with_memory(0x3000, &[0x4800, 0x3005]) // JSR x3001; ST R0, x3007
+            .build();
+        vm.watches.push(parse_watch("MEM[x3007]").unwrap());
+        vm.call_stack.set_budget(0x3001, 1);
+        let _ = vm.step();
+        let _ = vm.step();
+        assert!(matches!(vm.fault, Some(Fault::Watch(_))));
+    }
+
+    #[test]
+    fn a_watch_keeps_an_earlier_fault() {
+        use crate::components::testing::VmBuilder;
+        use crate::components::watch::parse_watch;
+
+        let mut vm = VmBuilder::new().build();
+        vm.watches.push(parse_watch("MEM[x4000]").unwrap());
+        vm.fault = Some(Fault::Limit(LimitReached { limit: 1, pc: 0x3000 }));
+        vm.write_memory(0x4000, 1);
+        assert!(matches!(vm.fault, Some(Fault::Limit(_))));
+    }
+
+    #[test]
+    fn a_refused_store_leaves_no_trace() {
+        use crate::components::access::AccessLog;
+        use crate::components::integrity::ProtectedRegion;
+        use crate::components::testing::VmBuilder;
+
+        let mut vm = VmBuilder::new()
+            .with_pc(0x3000)
+            .with_memory(0x3000, &[0x3005]) // ST R0, x3006
+            .with_registers(&[(0, 0x1234)])
+            .build();
+        vm.integrity.regions.push(ProtectedRegion { range: 0x3006..=0x3006, hash: 0 });
+        vm.integrity.continuous = true;
+        vm.access = Some(AccessLog::new());
+        let _ = vm.step();
+        assert!(matches!(vm.fault, Some(Fault::Integrity(_))));
+        assert_eq!(vm.memory[0x3006], 0);
+        assert_eq!(vm.access.as_ref().unwrap().last_write[0x3006], None);
+    }
+}
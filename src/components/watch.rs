@@ -0,0 +1,295 @@
+// Data breakpoints: stop the run when a word of memory or a register is written, optionally only when the new
+// value crosses a threshold (`MEM[x5000] when new > x0100`, `R6 when new < x6000`). Memory watches are checked
+// in the store path and register watches after each instruction, so the run stops at the exact instruction.
+//
+// Memory hooks (VM::add_watch) are the library's version: a callback on every load or store in a range, which
+// can pause the run (the debugger's `watch`) or just look.
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use super::equiv::parse_register;
+use super::parse_address;
+use super::regions::MemoryMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Memory(u16),
+    Register(u16),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::Memory(address) => write!(f, "x{:04X}", address),
+            Target::Register(r) => write!(f, "R{}", r),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub target: Target,
+    condition: Option<(Comparison, u16)>,
+    text: String,
+}
+
+// Raised when a watch triggers; stops the run like any other fault, or pauses it under a debugger
+#[derive(Debug)]
+pub struct WatchHit {
+    pub watch: String,
+    pub target: Target,
+    pub pc: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+impl WatchHit {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        // the word's note, if it has one
+        let note = match self.target {
+            Target::Memory(address) => map.notes.get(&address).map(|note| format!(" [{}]", note)),
+            Target::Register(_) => None,
+        };
+        format!(
+            "watch `{}`{} triggered by the instruction at {}: x{:04X} -> x{:04X}",
+            self.watch,
+            note.unwrap_or_default(),
+            map.at(self.pc),
+            self.old,
+            self.new
+        )
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.describe(&MemoryMap::new()))
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl Watch {
+    // Whether writing `new` over `old` triggers it. Unconditional watches trigger on every write; conditional ones
+    // only when the condition becomes true, so a value that stays past the threshold stops the run once.
+    pub fn triggers(&self, old: u16, new: u16) -> bool {
+        match self.condition {
+            None => true,
+            Some((comparison, threshold)) => {
+                let holds = |value: u16| match comparison {
+                    Comparison::Less => value < threshold,
+                    Comparison::LessOrEqual => value <= threshold,
+                    Comparison::Greater => value > threshold,
+                    Comparison::GreaterOrEqual => value >= threshold,
+                    Comparison::Equal => value == threshold,
+                    Comparison::NotEqual => value != threshold,
+                };
+                holds(new) && !holds(old)
+            }
+        }
+    }
+
+    pub fn hit(&self, pc: u16, old: u16, new: u16) -> WatchHit {
+        WatchHit {
+            watch: self.text.clone(),
+            target: self.target,
+            pc,
+            old,
+            new,
+        }
+    }
+}
+
+// Parse a `--watch` argument: `MEM[ADDRESS]` or `Rn`, optionally followed by `when new OP VALUE` where OP is one
+// of < <= > >= == != and values compare as unsigned words
+pub fn parse_watch(s: &str) -> Result<Watch, String> {
+    let text = s.split_whitespace().collect::<Vec<_>>().join(" ");
+    let (target, condition) = match text.split_once(" when ") {
+        Some((target, condition)) => (target, Some(condition)),
+        None => (text.as_str(), None),
+    };
+
+    let target = match target
+        .strip_prefix("MEM[")
+        .or_else(|| target.strip_prefix("mem["))
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        Some(address) => Target::Memory(parse_address(address)?),
+        None => Target::Register(parse_register(target)?),
+    };
+
+    let condition = match condition {
+        None => None,
+        Some(condition) => {
+            let fields: Vec<&str> = condition.split_whitespace().collect();
+            let [subject, op, value] = fields[..] else {
+                return Err(format!("expected `when new OP VALUE`, got `when {}`", condition));
+            };
+            if subject != "new" {
+                return Err(format!("conditions are on `new`, got `{}`", subject));
+            }
+            let comparison = match op {
+                "<" => Comparison::Less,
+                "<=" => Comparison::LessOrEqual,
+                ">" => Comparison::Greater,
+                ">=" => Comparison::GreaterOrEqual,
+                "==" => Comparison::Equal,
+                "!=" => Comparison::NotEqual,
+                _ => return Err(format!("unknown comparison `{}`", op)),
+            };
+            Some((comparison, parse_address(value)?))
+        }
+    };
+
+    Ok(Watch {
+        target,
+        condition,
+        text,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access, // either
+}
+
+// A load or store by the program, as a hook sees it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEvent {
+    pub kind: WatchKind, // Read or Write
+    pub address: u16,
+    pub value: u16, // the word loaded, or the word stored
+    pub pc: u16,    // the instruction making the access
+}
+
+impl MemoryEvent {
+    pub fn describe(&self, map: &MemoryMap) -> String {
+        let (what, direction) = match self.kind {
+            WatchKind::Write => ("store", "to"),
+            _ => ("load", "from"),
+        };
+        format!(
+            "{} of x{:04X} {} {} by the instruction at {}",
+            what,
+            self.value,
+            direction,
+            map.at(self.address),
+            map.at(self.pc)
+        )
+    }
+}
+
+// Called with each matching access; returning true pauses the run after the instruction
+pub type HookFn = Box<dyn FnMut(&MemoryEvent) -> bool>;
+
+struct Hook {
+    id: usize,
+    range: RangeInclusive<u16>,
+    kind: WatchKind,
+    callback: HookFn,
+}
+
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    next_id: usize,
+}
+
+impl Hooks {
+    // Returns an id for remove
+    pub fn add(&mut self, range: RangeInclusive<u16>, kind: WatchKind, callback: HookFn) -> usize {
+        self.next_id += 1;
+        self.hooks.push(Hook {
+            id: self.next_id,
+            range,
+            kind,
+            callback,
+        });
+        self.next_id
+    }
+
+    pub fn remove(&mut self, id: usize) -> bool {
+        let before = self.hooks.len();
+        self.hooks.retain(|h| h.id != id);
+        self.hooks.len() != before
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    // Run every hook the access matches; true if any of them asks to pause
+    pub fn notify(&mut self, event: &MemoryEvent) -> bool {
+        let mut pause = false;
+        for hook in self.hooks.iter_mut() {
+            let kind = hook.kind == WatchKind::Access || hook.kind == event.kind;
+            if kind && hook.range.contains(&event.address) {
+                pause |= (hook.callback)(event);
+            }
+        }
+        pause
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::assembler::assemble;
+    use crate::components::object::write_v1;
+    use crate::components::vm::{ExecState, VM};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn hooks_see_accesses_and_can_pause() {
+        let mut vm = VM::from_obj_bytes(&write_v1(
+            &assemble(".ORIG x3000\nLD R0, N\nADD R0, R0, #1\nST R0, N\nHALT\nN .FILL #4\n.END").unwrap(),
+        ))
+        .unwrap();
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let log = seen.clone();
+        vm.add_watch(0x3004..=0x3004, WatchKind::Access, Box::new(move |e| {
+            log.borrow_mut().push(*e);
+            false
+        }));
+        vm.add_watch(0x3004..=0x3004, WatchKind::Write, Box::new(|_| true));
+
+        assert_eq!(vm.run(), Ok(ExecState::Running));
+        assert_eq!(vm.stopped_at, Some(0x3003));
+        let store = vm.paused_on.unwrap();
+        assert_eq!((store.kind, store.value, store.pc), (WatchKind::Write, 5, 0x3002));
+        assert_eq!(seen.borrow().len(), 2);
+        assert_eq!(seen.borrow()[0].kind, WatchKind::Read);
+
+        vm.stopped_at = None;
+        assert_eq!(vm.run(), Ok(ExecState::Halted));
+        assert!(vm.paused_on.is_none());
+    }
+
+    #[test]
+    fn a_hit_shows_the_note_on_its_word() {
+        let mut map = MemoryMap::new();
+        map.annotate(0x4000, "loop counter");
+        let hit = parse_watch("MEM[x4000] when new > 3").unwrap().hit(0x3002, 3, 4);
+        assert_eq!(
+            hit.describe(&map),
+            "watch `MEM[x4000] when new > 3` [loop counter] triggered by the instruction at x3002: x0003 -> x0004"
+        );
+    }
+}
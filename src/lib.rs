@@ -0,0 +1,9 @@
+//! The LC-3 virtual machine as a library: create a [`VM`], load an object into it, then step or run it.
+//! The `lc3_sim` binary is a command-line front end over this crate; terminal handling lives there, not here.
+
+pub mod components;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+pub use components::loader::Endian;
+pub use components::vm::{Fault, PollResult, VM};
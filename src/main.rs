@@ -1,58 +1,1492 @@
-pub mod components;
-use components::vm::VM;
+mod terminal;
+#[cfg(feature = "tui")]
+mod tui;
 
-use termios::*;
+use lc3_sim::components;
+use components::access::AccessLog;
+use components::assembler;
+use components::blocks::{BlockProfile, GraphFormat};
+use components::bundle::Bundle;
+use components::callstack;
+use components::capture::{FrameCapture, FrameTrigger};
+use components::console::AnsiPolicy;
+use components::context::ContextSwitch;
+use components::cpu::{self, Cpu};
+use components::checksum;
+use components::clock::{self, Clock};
+use components::diagnostics::Category;
+use components::hints;
+use components::history::{self, History};
+use components::idle::IdleLoop;
+use components::devices;
+use components::equiv::{self, Contract};
+use components::events::EventLog;
+use components::info::{self, Topic};
+use components::inspect;
+use components::instruction::{disassemble_at, listing};
+use components::integrity::hash_words;
+use components::iopage::{self, IoPage};
+use components::lesson::{self, Lesson};
+use components::loader::{self, Endian, Format};
+use components::memo;
+use components::mutate;
+use components::object;
+use components::os::{self, TrapMode};
+use components::regions;
+use components::policy::{self, Policy};
+use components::profile;
+use components::provenance::Provenance;
+use components::query;
+use components::report::{self, ReportFormat};
+use components::results::{ResultsFormat, Status, TestSuite};
+use components::rng::Rng;
+use components::spec;
+use components::stats::ExecutionStats;
+use components::summary::{self, SummaryLevel};
+use components::symbols;
+use components::trace::Trace;
+use components::vm::{ExecState, VM};
+use components::watch::{self, Target, Watch, WatchKind};
+use terminal::{Terminal, TerminalMode};
 
-use byteorder::{BigEndian, ReadBytesExt};
+use lc3_device::Registry;
 
-use std::{fs::File, io::BufReader};
+use std::io::{BufWriter, IsTerminal, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+use std::fs::File;
+use structopt::clap::ErrorKind;
 use structopt::StructOpt;
 
+// `lc3_sim <subcommand> ...`, where a bare `lc3_sim prog.obj [OPTIONS]` is `run` (see `command`)
+#[derive(StructOpt)]
+#[structopt(
+    name = "lc3_sim",
+    after_help = "`lc3_sim prog.obj [OPTIONS]` with no subcommand is the same as `lc3_sim run prog.obj [OPTIONS]`."
+)]
+enum Command {
+    #[structopt(about = "Run a program")]
+    Run(Cli),
+    #[structopt(about = "Run a program under the debugger prompt, like run --debug")]
+    Debug(Cli),
+    #[structopt(about = "Assemble a source file into an object")]
+    Asm(AsmCli),
+    #[structopt(about = "List an object as addresses, words and instructions")]
+    Disasm(DisasmCli),
+    #[structopt(about = "Run scripted tests from TOML or JSON specs")]
+    Test(TestCli),
+    #[structopt(about = "Write a checksum sidecar (prog.obj.sum) for an object")]
+    Sign(SignCli),
+    #[structopt(about = "Run a --bundle again as it was recorded")]
+    Replay(ReplayCli),
+    #[structopt(about = "Walk through a lesson: its program under the debugger, with checkpoints to reach")]
+    Lesson(LessonCli),
+}
+
 #[derive(StructOpt)]
 struct Cli {
     // The path to the file to read
     #[structopt(parse(from_os_str))]
     path: std::path::PathBuf,
+
+    // How to set up the terminal: raw (byte-at-a-time input without echo, the default) or off to leave it as it is
+    #[structopt(long, default_value = "raw")]
+    terminal_mode: TerminalMode,
+
+    // Leave the terminal in cooked mode, for piped or scripted input (the same as --terminal-mode off)
+    #[structopt(long)]
+    no_raw: bool,
+
+    // What to do with ANSI escape sequences in guest output: pass, strip or escape.
+    // Defaults to pass when stdout is a terminal and strip otherwise.
+    #[structopt(long)]
+    ansi: Option<AnsiPolicy>,
+
+    // Write captured console frames to this file at HALT
+    #[structopt(long, parse(from_os_str))]
+    capture_frames: Option<std::path::PathBuf>,
+
+    // Compare captured frames against this golden file at HALT, exiting with status 2 on mismatch
+    #[structopt(long, parse(from_os_str))]
+    golden_frames: Option<std::path::PathBuf>,
+
+    // When to cut a frame: halt, prompt, or every N instructions
+    #[structopt(long, default_value = "halt")]
+    frame_on: FrameTrigger,
+
+    // Stop when a word or register is written, optionally only when the new value crosses a threshold:
+    // 'MEM[x5000] when new > x0100', 'R6 when new < x6000'. Repeatable.
+    #[structopt(long = "watch", parse(try_from_str = watch::parse_watch))]
+    watches: Vec<Watch>,
+
+    // Instruction budget per call for a subroutine, as SUBROUTINE:MAX where the subroutine is a symbol or an
+    // address (e.g. MULT:2000, x3100:2000). Repeatable.
+    #[structopt(long = "budget", parse(try_from_str = callstack::parse_budget))]
+    budgets: Vec<(String, u64)>,
+
+    // Manifest of protected ranges and their hashes, verified after loading (exit status 4 on mismatch)
+    #[structopt(long, parse(from_os_str))]
+    integrity: Option<std::path::PathBuf>,
+
+    // Also stop as soon as a store modifies a protected range
+    #[structopt(long)]
+    integrity_continuous: bool,
+
+    // Print a manifest line for this range after loading and exit. Repeatable.
+    #[structopt(long = "hash-region", parse(try_from_str = components::parse_range))]
+    hash_regions: Vec<std::ops::RangeInclusive<u16>>,
+
+    // Save a region of memory after the run as a loadable object: --save-image out.obj x3000:x30FF
+    #[structopt(long, number_of_values = 2, value_names = &["FILE", "RANGE"])]
+    save_image: Vec<String>,
+
+    // Write a region of memory after the run: --dump-range x3000:x30FF=out.hex. .hex and .bin files get text that
+    // --format reads back, anything else an object. Repeatable.
+    #[structopt(long = "dump-range", parse(try_from_str = loader::parse_dump_arg))]
+    dump_ranges: Vec<(std::ops::RangeInclusive<u16>, std::path::PathBuf)>,
+
+    // Start executing here instead of at the object's entry point (or x3000)
+    #[structopt(long, alias = "pc", parse(try_from_str = components::parse_address))]
+    entry: Option<u16>,
+
+    // Place a data file in memory before running: --load-data table.csv@x5000. Repeatable.
+    #[structopt(long, parse(try_from_str = loader::parse_data_arg))]
+    load_data: Vec<(std::path::PathBuf, u16)>,
+
+    // Name a memory region for diagnostics: --region STACK=x6000:x7000. Repeatable.
+    #[structopt(long = "region", parse(try_from_str = regions::parse_region_arg))]
+    regions: Vec<(String, std::ops::RangeInclusive<u16>)>,
+
+    // File of named regions, one `NAME START:END` per line
+    #[structopt(long = "regions", parse(from_os_str))]
+    regions_file: Option<std::path::PathBuf>,
+
+    // Treat a subroutine (address or symbol) as pure over the given inputs and cache its results:
+    // SUBROUTINE=R0,R1,x4000:x40FF->R0 (outputs default to R0). Experimental. Repeatable.
+    #[structopt(long = "pure", parse(try_from_str = memo::parse_pure_arg))]
+    pure: Vec<(String, memo::Signature)>,
+
+    // Run memoized calls anyway and stop when a cached result is wrong, to check --pure declarations
+    #[structopt(long)]
+    pure_verify: bool,
+
+    // Notes for addresses, one `ADDRESS note` per line (ADDRESS may be a symbol), shown in diagnostics, diffs and reports
+    #[structopt(long, parse(from_os_str))]
+    annotations: Option<std::path::PathBuf>,
+
+    // File read through the auxiliary console input registers (xFE10/xFE12)
+    #[structopt(long, parse(from_os_str))]
+    aux_in: Option<std::path::PathBuf>,
+
+    // File written through the auxiliary console output registers (xFE14/xFE16)
+    #[structopt(long, parse(from_os_str))]
+    aux_out: Option<std::path::PathBuf>,
+
+    // Record a state checkpoint the first time the PC reaches an address: --mark A@x3100. Repeatable.
+    #[structopt(long = "mark", parse(try_from_str = parse_mark))]
+    marks: Vec<(String, u16)>,
+
+    // After the run, print what changed between two checkpoints: --diff A:B. Repeatable.
+    #[structopt(long = "diff")]
+    diffs: Vec<String>,
+
+    // Write a run report after the run: HTML, Markdown for .md files or JSON for .json files; `json` prints it to stderr
+    #[structopt(long, parse(from_os_str))]
+    report: Option<std::path::PathBuf>,
+
+    // After the run, print the most executed addresses, labels and opcodes (a hot-spot profile)
+    #[structopt(long)]
+    profile: bool,
+
+    // Profile the report (or --profile) by sampling the PC every N instructions instead of counting every one
+    #[structopt(long, value_name = "N")]
+    sample_every: Option<u64>,
+
+    // Profile basic blocks and write the weighted control-flow graph after the run: DOT, or JSON for .json files
+    #[structopt(long, parse(from_os_str))]
+    cfg: Option<std::path::PathBuf>,
+
+    // After the run, point out patterns in hot loops worth a second look (constants reloaded every iteration,
+    // multiplication by repeated ADD, strings printed one OUT at a time)
+    #[structopt(long)]
+    hints: bool,
+
+    // Print every executed instruction (address, word, disassembly) and the registers after it to stderr
+    #[structopt(long)]
+    trace: bool,
+
+    // Write the --trace to a file instead of stderr (implies --trace)
+    #[structopt(long, parse(from_os_str))]
+    trace_file: Option<std::path::PathBuf>,
+
+    // Stop the run (exit status 11) if it executes this many instructions without halting
+    #[structopt(long, value_name = "N")]
+    max_instructions: Option<u64>,
+
+    // Stop the run (exit status 12) at a load or store in the I/O page that no register or device answers
+    #[structopt(long)]
+    strict: bool,
+
+    // Execute at most this many instructions a second, for programs that time things with delay loops or the
+    // timer device
+    #[structopt(long, value_name = "HZ", parse(try_from_str = clock::parse_hz))]
+    clock_hz: Option<u64>,
+
+    // Skip loops that only poll a device, up to the next device event, counting the skipped instructions as run
+    #[structopt(long)]
+    fast_forward: bool,
+
+    // When the program halts, save memory and registers to this file, to continue later with --restore
+    #[structopt(long, parse(from_os_str))]
+    save_on_halt: Option<std::path::PathBuf>,
+
+    // Start from a snapshot written by --save-on-halt instead of the object's initial state. The object is still
+    // loaded first, for its symbols and metadata.
+    #[structopt(long, parse(from_os_str))]
+    restore: Option<std::path::PathBuf>,
+
+    // Symbol table (.sym) to name addresses with; by default the .sym next to the object is used if there is one
+    #[structopt(long, parse(from_os_str))]
+    sym: Option<std::path::PathBuf>,
+
+    // After the run, print what is known about an address or symbol as one JSON line on stderr: value, last
+    // writer, read/write counts and disassembly (for editor hovers). Repeatable.
+    #[structopt(long = "query", value_name = "TARGET")]
+    queries: Vec<String>,
+
+    // After the run, say which instruction last wrote and last read an address or symbol, and at which step.
+    // Repeatable.
+    #[structopt(long = "who", value_name = "TARGET")]
+    who: Vec<String>,
+
+    // After the run, show where each register's final value came from, as a short chain of the instructions
+    // that produced it
+    #[structopt(long)]
+    provenance: bool,
+
+    // Log every value devices return to the program and every key it reads, keyed to the instruction that read it
+    #[structopt(long, parse(from_os_str))]
+    record_events: Option<std::path::PathBuf>,
+
+    // Answer device and keyboard reads from a log written by --record-events, stopping with status 8 if the run
+    // diverges
+    #[structopt(long, parse(from_os_str), conflicts_with = "record-events")]
+    replay_events: Option<std::path::PathBuf>,
+
+    // Type the keystrokes in this file instead of reading the keyboard; once they run out, reads see x00
+    #[structopt(long, parse(from_os_str))]
+    input: Option<std::path::PathBuf>,
+
+    // Write everything needed to repeat this run to one file: the command line and the files it names, the
+    // keystrokes read, the seed and the simulator version. `lc3_sim replay FILE` repeats it.
+    #[structopt(long, parse(from_os_str))]
+    bundle: Option<std::path::PathBuf>,
+
+    // After the run, print the device registers decoded field by field
+    #[structopt(long)]
+    inspect_devices: bool,
+
+    // Start stopped at the entry point with a debugger prompt (break, step, continue, regs, mem); BKPT stops there too
+    #[structopt(long)]
+    debug: bool,
+
+    // With --debug, start with the breakpoints, watches and notes this file lists and write them back to it when
+    // the session ends
+    #[structopt(long, parse(from_os_str))]
+    session: Option<std::path::PathBuf>,
+
+    // With --debug, walk through this lesson's checkpoints at the prompt: explanations, predictions to make and
+    // points to reach (see `lc3_sim lesson`)
+    #[structopt(long, parse(from_os_str))]
+    lesson: Option<std::path::PathBuf>,
+
+    // Run in a full-screen view of the registers, the code around the PC and the console, with keys to step, run
+    // and reset. Needs a build with `--features tui`.
+    #[structopt(long, conflicts_with = "debug")]
+    tui: bool,
+
+    // The instruction set: lc3, or lc3b for the byte-addressed LC-3b (LDB/STB, LDW/STW, SHF, XOR)
+    #[structopt(long, default_value = "lc3", parse(try_from_str = cpu::parse_isa))]
+    isa: &'static dyn Cpu,
+
+    // Start in supervisor mode (PSR[15] clear) with R6 on the supervisor stack, as an OS image expects
+    #[structopt(long)]
+    supervisor: bool,
+
+    // Load this OS image (an object, `builtin`, or `scheduler` for it with a thread scheduler) before the program and do TRAPs as the hardware does: save the
+    // PC in R7 and jump through the vector table at x0000 to the OS's routines
+    #[structopt(long, parse(from_os_str))]
+    os: Option<std::path::PathBuf>,
+
+    // With --os, TRAP pushes PSR and PC on the supervisor stack and routines return with RTI, as in the 2019
+    // revision of the ISA
+    #[structopt(long, requires = "os")]
+    trap_stack: bool,
+
+    // Add the context-switch unit at xFE28 (offset x28 into the I/O page) for a thread library
+    #[structopt(long)]
+    context_switch: bool,
+
+    // What to print to stderr when the program halts: none, short, full or json (default short, none with --quiet)
+    #[structopt(long)]
+    summary: Option<SummaryLevel>,
+
+    // Don't report warnings of this category: region, load or endian. Repeatable.
+    #[structopt(long = "no-warn")]
+    no_warn: Vec<Category>,
+
+    // Silence the simulator's own messages (load confirmation, HALT summary, warnings); errors are still reported
+    #[structopt(long, short)]
+    quiet: bool,
+
+    // Write the results of the run's checks: JSON for .json files, JUnit XML otherwise
+    #[structopt(long, parse(from_os_str))]
+    results: Option<std::path::PathBuf>,
+
+    // Attach a memory-mapped device by name, with optional arguments: --device random:xFE20. Repeatable.
+    #[structopt(long = "device")]
+    devices: Vec<String>,
+
+    // Where the memory-mapped device registers live, START:END (default xFE00:xFFFF). At least x100 words;
+    // registers and device defaults keep their offsets from START.
+    #[structopt(long, parse(try_from_str = iopage::parse_io_page))]
+    io_page: Option<IoPage>,
+
+    // Entry for the read-only config ROM at xFE40, for OS images to query at boot: --config-rom rows=24. Repeatable.
+    #[structopt(long = "config-rom", parse(try_from_str = devices::parse_config_entry))]
+    config_rom: Vec<(String, String)>,
+
+    // Course policy: a bundled one (intro, os-course, competition) or a .toml file. It sets capabilities, devices,
+    // silenced warnings, the seed and strictness; options given on the command line add to it.
+    #[structopt(long)]
+    policy: Option<String>,
+
+    // Enable an optional extension: screen (cursor traps x27-x29), numeric (number traps x2A-x2D), chain (TRAP x2E
+    // loads and runs another object). Repeatable.
+    #[structopt(long = "capability", possible_values = CAPABILITIES)]
+    capabilities: Vec<String>,
+
+    // Byte order of a plain object's words: big (the standard), little, or auto to guess from the contents
+    #[structopt(long, default_value = "big")]
+    endian: Endian,
+
+    // Program file format: obj, hex or bin (lc3as text, a word per line after the origin), raw (a memory image
+    // from x0000 in the --endian order), or auto to go by the extension
+    #[structopt(long, default_value = "auto")]
+    format: Format,
+
+    // Refuse to run an object without a checksum sidecar (prog.obj.sum)
+    #[structopt(long)]
+    require_checksum: bool,
+
+    // Load files even when they overlap each other
+    #[structopt(long)]
+    allow_overlap: bool,
+
+    // Print the address-space layout after loading, as text or json (to stderr)
+    #[structopt(long, possible_values = &["text", "json"])]
+    layout: Option<String>,
+
+    // Seed for everything random (devices, generated inputs), so a run can be repeated exactly.
+    // Shown in reports and full summaries; taken from the clock when not given.
+    #[structopt(long)]
+    seed: Option<u64>,
+
+    // Check another object for the same observable behavior as this one and exit (status 5 on a difference)
+    #[structopt(long, parse(from_os_str))]
+    equiv: Option<std::path::PathBuf>,
+
+    // Cases for --equiv, one per line: R0=x5 R1=#-2 input=text. Random register values when omitted.
+    #[structopt(long, parse(from_os_str))]
+    equiv_inputs: Option<std::path::PathBuf>,
+
+    // Register compared by --equiv besides console output, R0 by default. Repeatable.
+    #[structopt(long = "equiv-register", parse(try_from_str = equiv::parse_register))]
+    equiv_registers: Vec<u16>,
+
+    // Memory compared by --equiv: --equiv-region x4000:x40FF. Repeatable.
+    #[structopt(long = "equiv-region", parse(try_from_str = components::parse_range))]
+    equiv_regions: Vec<std::ops::RangeInclusive<u16>>,
+}
+
+// `lc3_sim asm prog.asm -o prog.obj`
+#[derive(StructOpt)]
+struct AsmCli {
+    // The assembly source to read
+    #[structopt(parse(from_os_str))]
+    source: std::path::PathBuf,
+
+    // Where to write the object, next to the source with a .obj extension unless given
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<std::path::PathBuf>,
+
+    // Write a v2 object, which keeps the labels as symbols (for --debug, --query and --annotate) and a hash of
+    // the source, instead of a plain .obj
+    #[structopt(long)]
+    v2: bool,
+}
+
+// `lc3_sim disasm prog.obj`
+#[derive(StructOpt)]
+struct DisasmCli {
+    // The object to list, v1 or v2
+    #[structopt(parse(from_os_str))]
+    path: std::path::PathBuf,
+}
+
+// `lc3_sim test add.toml sub.json ...`
+#[derive(StructOpt)]
+struct TestCli {
+    // Specs to run, TOML or (with a .json extension) JSON
+    #[structopt(parse(from_os_str), required = true)]
+    specs: Vec<std::path::PathBuf>,
+
+    // Write the results as well: JSON for .json files, JUnit XML otherwise
+    #[structopt(long, parse(from_os_str))]
+    results: Option<std::path::PathBuf>,
+
+    // Only print failures
+    #[structopt(short, long)]
+    quiet: bool,
+
+    // Mutation testing: run the specs against every single-instruction change to this reference solution (LC-3
+    // source) in place of their objects, and report the changes none of them fails on
+    #[structopt(long, parse(from_os_str))]
+    mutate: Option<std::path::PathBuf>,
+}
+
+// `lc3_sim sign prog.obj`
+#[derive(StructOpt)]
+struct SignCli {
+    // The object to write prog.obj.sum for
+    #[structopt(parse(from_os_str))]
+    path: std::path::PathBuf,
+
+    // Don't say where the sidecar was written
+    #[structopt(short, long)]
+    quiet: bool,
+}
+
+// `lc3_sim replay run.lc3bundle`
+#[derive(StructOpt)]
+struct ReplayCli {
+    // A bundle written by --bundle
+    #[structopt(parse(from_os_str))]
+    bundle: std::path::PathBuf,
+}
+
+// `lc3_sim lesson intro.lesson`
+#[derive(StructOpt)]
+struct LessonCli {
+    // A lesson file, which names the program to run
+    #[structopt(parse(from_os_str))]
+    lesson: std::path::PathBuf,
+}
+
+// Extensions a program can ask for with --capability or in its v2 metadata
+const CAPABILITIES: &[&str] = &["screen", "numeric", "chain"];
+
+// Merge a policy into the options: lists are added to, switches turned on, and the seed used unless one was given
+fn apply_policy(cli: &mut Cli, policy: Policy) -> Result<(), String> {
+    for capability in policy.capabilities {
+        if !CAPABILITIES.contains(&capability.as_str()) {
+            return Err(format!("policy enables unknown capability {}", capability));
+        }
+        if !cli.capabilities.contains(&capability) {
+            cli.capabilities.push(capability);
+        }
+    }
+    for spec in policy.devices {
+        if !cli.devices.contains(&spec) {
+            cli.devices.push(spec);
+        }
+    }
+    cli.no_warn.extend(policy.no_warn);
+    cli.seed = cli.seed.or(policy.seed);
+    cli.require_checksum |= policy.require_checksum;
+    cli.allow_overlap |= policy.allow_overlap;
+    cli.strict |= policy.strict;
+    Ok(())
+}
+
+fn parse_mark(s: &str) -> Result<(String, u16), String> {
+    let (name, address) = s
+        .split_once('@')
+        .ok_or_else(|| format!("expected NAME@ADDRESS, got `{}`", s))?;
+    Ok((name.to_string(), components::parse_address(address)?))
+}
+
+// Names that start a subcommand rather than a bare run, as structopt spells the variants of Command
+const SUBCOMMANDS: [&str; 9] = ["run", "debug", "asm", "disasm", "test", "sign", "replay", "lesson", "help"];
+
+// The subcommand from the command line. Anything that isn't one, such as a program or an option, starts a bare
+// `lc3_sim prog.obj [OPTIONS]`, which is parsed as `run`. A mistake after a subcommand's name is reported by
+// that subcommand.
+fn command() -> Command {
+    match Command::from_iter_safe(std::env::args_os()) {
+        Ok(command) => command,
+        Err(e)
+            if matches!(e.kind, ErrorKind::UnknownArgument | ErrorKind::UnrecognizedSubcommand)
+                && !std::env::args().nth(1).is_some_and(|a| SUBCOMMANDS.contains(&a.as_str())) =>
+        {
+            Command::Run(Cli::from_args())
+        }
+        Err(e) => e.exit(),
+    }
 }
 
 fn main() {
-    let stdin = 0;
-    let termios = termios::Termios::from_fd(stdin).unwrap();
+    let status = match command() {
+        Command::Asm(cli) => assemble_file(&cli).map(|()| 0),
+        Command::Disasm(cli) => list_object(&cli)
+            .map(|()| 0)
+            .map_err(|e| format!("{}: {}", cli.path.display(), e)),
+        Command::Test(cli) => match &cli.mutate {
+            Some(source) => mutation_test(&cli, source),
+            None => Ok(if run_specs(&cli) { 0 } else { 1 }),
+        },
+        Command::Sign(cli) => checksum::sign(&cli.path).map(|path| {
+            if !cli.quiet {
+                eprintln!("wrote {}", path.display());
+            }
+            0
+        }),
+        Command::Replay(replay) => replay_cli(&replay).and_then(run),
+        Command::Lesson(lesson) => lesson::program(&lesson.lesson).and_then(|program| {
+            let mut cli = Cli::from_iter([std::ffi::OsStr::new("lc3_sim"), program.as_os_str()]);
+            cli.debug = true;
+            cli.lesson = Some(lesson.lesson);
+            run(cli)
+        }),
+        Command::Run(cli) => run(cli),
+        Command::Debug(cli) if cli.tui => Err("--tui can't be used with `lc3_sim debug`".to_string()),
+        Command::Debug(mut cli) => {
+            cli.debug = true;
+            run(cli)
+        }
+    };
+    match status {
+        // a clean HALT
+        Ok(0) => {}
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Run a program as the command line asks, returning the exit status. A setup failure (a file that can't be read,
+// a bad option value) is the error and exits with status 1.
+fn run(mut cli: Cli) -> Result<i32, String> {
+    if let Some(name) = cli.policy.clone() {
+        policy::load_policy(&name).and_then(|p| apply_policy(&mut cli, p))?;
+    }
+
+    if cli.tui && !cfg!(feature = "tui") {
+        let rebuild = "rebuild it with `cargo build --features tui`";
+        return Err(format!("this lc3_sim was built without --tui; {}", rebuild));
+    }
+
+    if cli.session.is_some() && !cli.debug {
+        return Err("--session needs --debug".to_string());
+    }
+    if cli.lesson.is_some() && !cli.debug {
+        return Err("--lesson needs --debug".to_string());
+    }
+
+    if let Some(other) = &cli.equiv {
+        return check_equivalence(&cli, other);
+    }
+
+    // before the run, so the bundle holds the files as the run found them
+    let bundle = cli.bundle.clone().map(|path| (path, Bundle::capture(&bundled_args())));
+
+    let terminal = Terminal::enter(if cli.no_raw { TerminalMode::Off } else { cli.terminal_mode });
 
-    let mut new_termios = termios.clone();
-    new_termios.c_iflag &= IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | ICRNL | IXON;
-    new_termios.c_lflag &= !(ICANON | ECHO);
+    // an internal bug must not leave the terminal raw; the guest state is reported where the run is caught
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        terminal.restore();
+        default_hook(info);
+    }));
 
-    tcsetattr(stdin, TCSANOW, &mut new_termios).unwrap();
+    let status = run_with_terminal(cli, &terminal, bundle);
+    // reset stdin
+    terminal.restore();
+    status
+}
 
+// Everything from building the machine to the last report, with the terminal set up for the program
+fn run_with_terminal(
+    cli: Cli,
+    terminal: &Terminal,
+    bundle: Option<(std::path::PathBuf, Bundle)>,
+) -> Result<i32, String> {
     let mut vm = VM::new();
+    vm.cpu = cli.isa;
+    if let Some(path) = &cli.input {
+        let keys = std::fs::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        vm.console.push_input(&keys);
+    }
+    if bundle.is_some() && cli.input.is_none() {
+        vm.console.recording = Some(Vec::new());
+    }
+
+    // Passing sequences through is only safe while the terminal is raw and we own stdout
+    vm.console.policy = cli.ansi.unwrap_or(if std::io::stdout().is_terminal() {
+        AnsiPolicy::Pass
+    } else {
+        AnsiPolicy::Strip
+    });
+
+    if cli.capture_frames.is_some() || cli.golden_frames.is_some() {
+        let mut capture = FrameCapture::new(cli.frame_on);
+        capture.output = cli.capture_frames;
+        capture.golden = cli.golden_frames;
+        vm.console.capture = Some(capture);
+    }
+
+    if let Some(path) = &cli.regions_file {
+        vm.regions.load(path)?;
+    }
+    for (name, range) in &cli.regions {
+        vm.regions.add(name, range.clone());
+    }
+
+    if let Some(path) = &cli.aux_in {
+        vm.aux
+            .attach_input(path)
+            .map_err(|e| format!("couldn't open {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &cli.aux_out {
+        vm.aux
+            .attach_output(path)
+            .map_err(|e| format!("couldn't create {}: {}", path.display(), e))?;
+    }
+
+    if let Some(seed) = cli.seed {
+        vm.rng = Rng::new(seed);
+    }
+
+    if let Some(io_page) = &cli.io_page {
+        vm.io_page = io_page.clone();
+    }
 
-    let cli = Cli::from_args();
+    let mut registry = Registry::new();
+    registry.set_seed(vm.rng.next_u64());
+    registry.set_io_page(vm.io_page.range.clone());
+    devices::register_all(&mut registry);
+    for spec in &cli.devices {
+        registry.create(spec).and_then(|device| vm.attach(device))?;
+    }
 
-    let f = File::open(cli.path).expect("couldn't open file");
-    let mut f = BufReader::new(f);
+    vm.capabilities = cli.capabilities.clone();
 
-    // reading through binary
-    let base_address = f.read_u16::<BigEndian>().expect("error");
+    if !cli.config_rom.is_empty() {
+        devices::ConfigRom::new(&cli.config_rom, vm.io_page.base()).and_then(|rom| vm.attach(Box::new(rom)))?;
+    }
 
-    // utilize memory
-    let mut address = base_address as usize;
+    for (name, address) in &cli.marks {
+        vm.marks.arm(name, *address);
+    }
 
-    while let Ok(instruction) = f.read_u16::<BigEndian>() {
-        vm.write_memory(address, instruction);
-        address += 1;
+    let summary_level = cli.summary.unwrap_or(if cli.quiet {
+        SummaryLevel::None
+    } else {
+        SummaryLevel::Short
+    });
+    vm.quiet = cli.quiet;
+    vm.diagnostics.silenced = cli.no_warn.iter().copied().collect();
+
+    if cli.report.is_some() || cli.profile || summary_level.needs_stats() {
+        vm.stats = Some(ExecutionStats::sampling(cli.sample_every.unwrap_or(1)));
+    }
+    if cli.cfg.is_some() || cli.hints {
+        vm.blocks = Some(BlockProfile::new());
     }
-    
-    if let Err(e) = f.read_u16::<BigEndian>() {
-        if e.kind() == std::io::ErrorKind::UnexpectedEof {
-            println!("checked!");
+    if cli.report.is_some() {
+        vm.console.transcript = Some(Vec::new());
+    }
+    if let Some(path) = &cli.trace_file {
+        let file = std::fs::File::create(path)
+            .map_err(|e| format!("couldn't create trace file {}: {}", path.display(), e))?;
+        vm.trace = Some(Trace::new(Box::new(BufWriter::new(file))));
+    } else if cli.trace {
+        vm.trace = Some(Trace::to_stderr());
+    }
+
+    vm.watches = cli.watches.clone();
+    vm.max_instructions = cli.max_instructions;
+    vm.clock = cli.clock_hz.map(Clock::new);
+    if cli.fast_forward {
+        vm.idle = Some(IdleLoop::new());
+    }
+
+    if cli.record_events.is_some() {
+        vm.events = Some(EventLog::record());
+    }
+    if let Some(path) = &cli.replay_events {
+        vm.events = Some(EventLog::load(path)?);
+    }
+
+    let mut results = TestSuite::new(&cli.path.display().to_string());
+
+    vm.layout.allow_overlap = cli.allow_overlap;
+    vm.load_dir = cli.path.parent().map(std::path::Path::to_path_buf);
+
+    if cli.context_switch {
+        vm.context = Some(ContextSwitch::new(vm.io_page.base() + iopage::CONTEXT_SWITCH, 0));
+    }
+    if let Some(path) = &cli.os {
+        let mode = if cli.trap_stack { TrapMode::Stack } else { TrapMode::Vectored };
+        os::load(&mut vm, path, mode).map_err(|e| format!("--os {}: {}", path.display(), e))?;
+    }
+    let mut image = loader::load_program(&mut vm, &cli.path, cli.require_checksum, cli.endian, cli.format)?;
+    symbols::merge(&mut image.metadata.symbols, symbols::for_object(&cli.path, cli.sym.as_deref())?);
+    if let Some(trace) = vm.trace.as_mut() {
+        trace.symbols = image.metadata.symbols.clone();
+    }
+    if !cli.quiet {
+        eprintln!("checked!");
+    }
+
+    if let Some(entry) = cli.entry.or(image.metadata.entry) {
+        vm.registers.pc = entry;
+    }
+    // after loading, so an image placed in the I/O page still loads (with its warning)
+    vm.strict = cli.strict;
+    // devices the object asks for, unless the same spec was already given on the command line
+    for spec in &image.metadata.devices {
+        if cli.devices.contains(spec) {
+            continue;
+        }
+        registry
+            .create(spec)
+            .and_then(|device| vm.attach(device))
+            .map_err(|e| format!("{} requires device {}: {}", cli.path.display(), spec, e))?;
+    }
+
+    for capability in &image.metadata.capabilities {
+        if !CAPABILITIES.contains(&capability.as_str()) {
+            return Err(format!("{} requires unknown capability {}", cli.path.display(), capability));
+        }
+        if !vm.has_capability(capability) {
+            vm.capabilities.push(capability.clone());
+        }
+    }
+
+    for (path, address) in &cli.load_data {
+        loader::load_data(&mut vm, path, *address)?;
+    }
+
+    if let Some(path) = &cli.annotations {
+        vm.regions.load_notes(path, &image.metadata.symbols)?;
+    }
+
+    for (subroutine, signature) in &cli.pure {
+        let entry = image
+            .metadata
+            .symbols
+            .iter()
+            .find(|(_, name)| name == subroutine)
+            .map(|(a, _)| Ok(*a))
+            .unwrap_or_else(|| components::parse_address(subroutine));
+        let entry = entry.map_err(|e| format!("--pure {}: {}", subroutine, e))?;
+        vm.memo.declare(entry, signature.clone());
+    }
+    vm.memo.verify = cli.pure_verify;
+
+    for (subroutine, max) in &cli.budgets {
+        let entry = symbols::address_of(subroutine, &image.metadata.symbols)
+            .map_err(|e| format!("--budget {}: {}", subroutine, e))?;
+        vm.call_stack.set_budget(entry, *max);
+    }
+
+    if cli.supervisor {
+        vm.registers.supervisor = true;
+        vm.registers.r6 = vm.registers.saved_ssp;
+    }
+    if let Some(path) = &cli.restore {
+        let restored = std::fs::File::open(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| vm.load_state(std::io::BufReader::new(file)));
+        restored.map_err(|e| format!("--restore {}: {}", path.display(), e))?;
+    }
+    vm.record_start();
+    // after loading, so only the program's own loads and stores are counted
+    if !cli.queries.is_empty() || !cli.who.is_empty() {
+        vm.access = Some(AccessLog::new());
+    }
+    if cli.provenance {
+        vm.provenance = Some(Provenance::new());
+    }
+
+    match cli.layout.as_deref() {
+        Some("json") => eprint!("{}", vm.layout.render_json(vm.registers.pc)),
+        Some(_) => eprint!("{}", vm.layout.render_text(vm.registers.pc)),
+        None => {}
+    }
+
+    if !cli.hash_regions.is_empty() {
+        for range in cli.hash_regions {
+            let words = &vm.memory[*range.start() as usize..=*range.end() as usize];
+            println!(
+                "x{:04X}:x{:04X} {:016x}",
+                range.start(),
+                range.end(),
+                hash_words(words)
+            );
+        }
+        return Ok(0);
+    }
+
+    // exit status for a failed check, the run is skipped if starter code was already modified
+    let mut status = None;
+
+    if let Some(manifest) = &cli.integrity {
+        if let Err(e) = vm.integrity.load_manifest(manifest) {
+            eprintln!("{}", e);
+            return Ok(4);
+        }
+        match vm.integrity.verify(&vm.memory) {
+            Ok(()) => results.pass("integrity", Duration::ZERO),
+            Err(e) => {
+                let message = e.describe(&vm.regions);
+                eprintln!("{}", message);
+                results.fail("integrity", &message, None, Duration::ZERO);
+                status = Some(4);
+            }
+        }
+        vm.integrity.continuous = cli.integrity_continuous;
+    }
+
+    if status.is_none() {
+        let start = Instant::now();
+        let symbols = &image.metadata.symbols;
+        let lesson = cli.lesson.as_deref().map(|path| lesson::load(path, symbols)).transpose()?;
+        let run = panic::catch_unwind(AssertUnwindSafe(|| {
+            if cli.debug {
+                debug_session(&mut vm, terminal, symbols, cli.session.as_deref(), lesson);
+                vm.state()
+            } else if cli.tui {
+                #[cfg(feature = "tui")]
+                if let Err(e) = tui::run(&mut vm, symbols) {
+                    eprintln!("{}", e);
+                }
+                vm.state()
+            } else {
+                components::execute_program(&mut vm)
+            }
+        }));
+        let Ok(state) = run else {
+            crash_report(&vm);
+            // the trace up to the crash is the most useful part of it
+            if let Some(trace) = vm.trace.as_mut() {
+                trace.flush();
+            }
+            // a crash is exactly what a bundle is for
+            save_bundle(bundle.as_ref(), &vm);
+            return Ok(101);
+        };
+        match state {
+            Err(e) => {
+                eprintln!("{}", e.message);
+                results.fail("run", &e.message, None, start.elapsed());
+                status = Some(e.exit_code);
+            }
+            Ok(ExecState::Halted) => results.pass("run", start.elapsed()),
+            Ok(ExecState::Running) => results.skip("run", "stopped in the debugger"),
+        }
+    } else {
+        results.skip("run", "starter code integrity check failed");
+    }
+
+    if vm.halted {
+        eprint!("{}", summary::render(&vm, summary_level));
+    }
+    if let Some(held_back) = vm.diagnostics.summary().filter(|_| !cli.quiet) {
+        eprintln!("{}", held_back);
+    }
+
+    for target in &cli.queries {
+        match query::query(&vm, &image.metadata.symbols, target) {
+            Ok(info) => eprintln!("{}", info.to_json()),
+            Err(e) => eprintln!("--query {}: {}", target, e),
+        }
+    }
+    // the debugger tracks provenance for whence too, without asking for the report
+    if let (true, Some(provenance)) = (cli.provenance, &vm.provenance) {
+        for r in 0..8 {
+            eprintln!("{}", provenance.describe(r));
+        }
+    }
+    if cli.inspect_devices {
+        eprint!("{}", inspect::render(&vm));
+    }
+    for target in &cli.who {
+        match query::query(&vm, &image.metadata.symbols, target) {
+            Ok(info) => eprintln!("{}", info.who(&vm)),
+            Err(e) => eprintln!("--who {}: {}", target, e),
+        }
+    }
+
+    vm.aux.flush();
+    if let (Some(path), true) = (&cli.save_on_halt, vm.halted) {
+        let saved = std::fs::File::create(path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| vm.save_state(BufWriter::new(file)));
+        if let Err(e) = saved {
+            eprintln!("--save-on-halt {}: {}", path.display(), e);
+            status = status.or(Some(1));
+        }
+    }
+    if let Some(trace) = vm.trace.as_mut() {
+        trace.flush();
+    }
+
+    if let Some(capture) = vm.console.capture.as_mut() {
+        let start = Instant::now();
+        match capture.finish() {
+            Ok(()) if capture.golden.is_some() => results.pass("golden-frames", start.elapsed()),
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!("{}", e);
+                let (message, diff) = e.split_once('\n').unwrap_or((&e, ""));
+                results.fail("golden-frames", message, Some(diff.to_string()), start.elapsed());
+                status = status.or(Some(2));
+            }
+        }
+    }
+
+    for pair in &cli.diffs {
+        let (a, b) = pair.split_once(':').unwrap_or((pair, ""));
+        match vm.marks.diff(a, b) {
+            Ok(changes) => {
+                eprintln!("changes from {} to {}:", a, b);
+                for change in changes {
+                    eprintln!("  {}", change.describe(&vm.regions));
+                }
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+
+    if let Some(path) = &cli.report {
+        let end = image.origin as usize + image.words.len().max(1) - 1;
+        let program = image.origin..=end as u16;
+        let text = report::render(&vm, program, ReportFormat::from_path(path));
+        // `--report json` goes with the other machine-readable output on stderr, clear of the program's
+        if path == std::path::Path::new("json") {
+            eprint!("{}", text);
+        } else if let Err(e) = std::fs::write(path, text) {
+            eprintln!("couldn't write report {}: {}", path.display(), e);
+        }
+    }
+
+    if let (Some(path), Some(log)) = (&cli.record_events, &vm.events) {
+        if let Err(e) = log.save(path) {
+            eprintln!("{}", e);
+        }
+    }
+
+    if let (Some(path), Some(blocks)) = (&cli.cfg, &vm.blocks) {
+        let text = blocks.render(&vm.memory, GraphFormat::from_path(path));
+        if let Err(e) = std::fs::write(path, text) {
+            eprintln!("couldn't write control-flow graph {}: {}", path.display(), e);
+        }
+    }
+
+    if cli.profile {
+        eprint!("{}", profile::render(&vm, &image.metadata.symbols, profile::TOP));
+    }
+
+    if let (true, Some(blocks)) = (cli.hints, &vm.blocks) {
+        for hint in hints::hints(&blocks.graph(), &vm.memory, &image.metadata.symbols) {
+            eprintln!("hint: {}", hint);
+        }
+    }
+
+    save_bundle(bundle.as_ref(), &vm);
+
+    if let Some(path) = &cli.results {
+        let text = results.render(ResultsFormat::from_path(path));
+        if let Err(e) = std::fs::write(path, text) {
+            eprintln!("couldn't write results {}: {}", path.display(), e);
+        }
+    }
+
+    for image in cli.save_image.chunks(2) {
+        components::parse_range(&image[1]).and_then(|range| save_image(&vm, &image[0], range))?;
+    }
+
+    for (range, path) in &cli.dump_ranges {
+        dump_range(&vm, range.clone(), path)?;
+    }
+
+    // a clean HALT is exit status 0
+    Ok(status.unwrap_or(0))
+}
+// The --debug prompt. The terminal is back in its normal mode while the prompt reads a line and raw again
+// while the guest runs, so the program sees the same keyboard it would without the debugger.
+fn debug_session(
+    vm: &mut VM,
+    terminal: &Terminal,
+    symbols: &[(u16, String)],
+    session: Option<&std::path::Path>,
+    mut lesson: Option<Lesson>,
+) {
+    use components::debugger::{self, Command};
+
+    vm.break_on_bkpt = true;
+    // a --watch stops at the prompt rather than ending the run
+    vm.pause_on_watch = true;
+    vm.history = Some(History::new(history::CAPACITY));
+    // for whowrote and whence; only what the program does from here on counts
+    if vm.access.is_none() {
+        vm.access = Some(AccessLog::new());
+    }
+    if vm.provenance.is_none() {
+        vm.provenance = Some(Provenance::new());
+    }
+    let mut last = None;
+    // instruction count before the last step, next, finish or continue, for undo
+    let mut before_last = None;
+    // address -> hook id
+    let mut watches = std::collections::BTreeMap::new();
+    if let Some(path) = session {
+        restore_session(vm, &mut watches, path, symbols);
+    }
+    // memory as of the last diff
+    let mut snapshot = vm.memory.to_vec();
+    eprintln!("{}", debugger::location(vm));
+    loop {
+        terminal.restore();
+        if let Some(lesson) = lesson.as_mut() {
+            let said = lesson.advance(vm);
+            if !said.is_empty() {
+                eprintln!("{}", said);
+            }
+        }
+        // a lesson's question takes the next line as its answer
+        let question = lesson.as_ref().and_then(|l| l.question());
+        eprint!("{}", question.as_deref().unwrap_or("(lc3) "));
+        let Some(line) = components::iodevice::read_line() else {
+            break;
+        };
+        if let (Some(lesson), Some(_)) = (lesson.as_mut(), &question) {
+            eprintln!("{}", lesson.answer(vm, &line));
+            continue;
+        }
+        let command = if line.trim().is_empty() {
+            match last.clone() {
+                Some(command) => Ok(command),
+                None => continue,
+            }
         } else {
-            println!("fails: {}", e);
+            debugger::parse_command(&line, symbols)
+        };
+        match command {
+            Err(e) => eprintln!("{}", e),
+            Ok(Command::Break(address)) => {
+                vm.breakpoints.insert(address);
+                eprintln!("breakpoint at x{:04X}", address);
+            }
+            Ok(Command::Delete(address)) => {
+                if !vm.breakpoints.remove(&address) {
+                    eprintln!("no breakpoint at x{:04X}", address);
+                }
+            }
+            Ok(Command::Breakpoints) => {
+                let hooks: Vec<u16> = watches.keys().copied().collect();
+                eprintln!("{}", info::render(vm, Topic::Breakpoints, false, symbols, &hooks));
+            }
+            Ok(Command::Watch(address)) => {
+                watch_access(vm, &mut watches, address);
+                eprintln!("watching x{:04X}", address);
+            }
+            Ok(Command::WatchValue(watch)) => {
+                eprintln!("watching {}", watch);
+                if !vm.watches.contains(&watch) {
+                    vm.watches.push(watch);
+                }
+            }
+            Ok(Command::Unwatch(target)) => {
+                let hook = match target {
+                    Target::Memory(address) => watches.remove(&address).map(|id| vm.remove_watch(id)),
+                    Target::Register(_) => None,
+                };
+                if !debugger::unwatch(vm, target) && hook.is_none() {
+                    eprintln!("no watch on {}", target);
+                }
+            }
+            Ok(Command::Finish) if vm.call_stack.frames().is_empty() => {
+                eprintln!("not in a subroutine")
+            }
+            Ok(Command::FinishHandler) if vm.call_stack.handler().is_none() => eprintln!("not in a handler"),
+            Ok(
+                command @ (Command::Step(_)
+                | Command::Next(_)
+                | Command::Finish
+                | Command::FinishHandler
+                | Command::Continue),
+            ) => {
+                vm.stopped_at = None;
+                if !components::running(vm) {
+                    eprintln!("{}", debugger::stop_reason(vm));
+                    continue;
+                }
+                before_last = Some(vm.instructions_executed());
+                terminal.resume();
+                match command {
+                    Command::Step(n) => {
+                        for _ in 0..n {
+                            if !components::running(vm) || vm.step().is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Command::Next(n) => {
+                        for _ in 0..n {
+                            debugger::next(vm);
+                        }
+                    }
+                    Command::Finish => {
+                        debugger::finish(vm);
+                    }
+                    Command::FinishHandler => {
+                        debugger::finish_handler(vm);
+                    }
+                    // stop_reason below says how the run ended
+                    _ => {
+                        let _ = vm.run();
+                    }
+                }
+                vm.console.flush();
+                terminal.restore();
+                eprintln!("{}", debugger::stop_reason(vm));
+                last = Some(command);
+            }
+            Ok(Command::Regs) => eprintln!("{}", debugger::registers(vm)),
+            Ok(Command::Info(topic, json)) => {
+                let hooks: Vec<u16> = watches.keys().copied().collect();
+                eprintln!("{}", info::render(vm, topic, json, symbols, &hooks));
+            }
+            Ok(Command::ReverseStep(n)) => {
+                before_last = None;
+                eprintln!("{}", debugger::reverse(vm, n as usize));
+            }
+            Ok(Command::Undo) => match before_last.take() {
+                Some(before) => {
+                    let count = vm.instructions_executed().saturating_sub(before);
+                    eprintln!("{}", debugger::reverse(vm, count as usize));
+                }
+                None => eprintln!("nothing to undo"),
+            },
+            Ok(Command::Restart(kind)) => {
+                eprintln!("{}", debugger::restart(vm, kind));
+                before_last = None;
+                snapshot.copy_from_slice(&vm.memory);
+                last = None;
+            }
+            Ok(Command::Backtrace) => eprintln!("{}", debugger::backtrace(vm, symbols)),
+            Ok(Command::Handler) => eprintln!("{}", debugger::handler(vm, symbols)),
+            Ok(Command::Mem(address, count)) => eprintln!("{}", debugger::memory(vm, address, count)),
+            Ok(Command::WhoWrote(address)) => eprintln!("{}", debugger::who_wrote(vm, address, symbols)),
+            Ok(Command::Whence(register)) => eprintln!("{}", debugger::whence(vm, register)),
+            Ok(Command::Diff) => {
+                eprintln!("{}", debugger::diff(&snapshot, vm));
+                snapshot.copy_from_slice(&vm.memory);
+            }
+            Ok(Command::Mark(name)) => eprintln!("{}", debugger::mark(vm, &name)),
+            Ok(Command::DiffMarks(a, b)) => match debugger::diff_marks(vm, &a, &b, symbols) {
+                Ok(changes) => eprintln!("{}", changes),
+                Err(e) => eprintln!("{}", e),
+            },
+            Ok(Command::Help) => eprintln!("{}", debugger::HELP),
+            Ok(Command::Note(address, note)) => vm.regions.annotate(address, &note),
+            Ok(Command::Unnote(address)) => {
+                if vm.regions.notes.remove(&address).is_none() {
+                    eprintln!("no note on x{:04X}", address);
+                }
+            }
+            Ok(Command::Notes) => eprintln!("{}", debugger::notes(vm, symbols)),
+            Ok(Command::Quit) => break,
+        }
+    }
+    if let Some(path) = session {
+        let hooks: Vec<u16> = watches.keys().copied().collect();
+        if let Err(e) = std::fs::write(path, debugger::session(vm, &hooks)) {
+            eprintln!("couldn't save the session to {}: {}", path.display(), e);
         }
-    }    
+    }
+}
 
-    components::execute_program(&mut vm);
+// Stop after any load or store at `address`, once however often it's asked for
+fn watch_access(vm: &mut VM, watches: &mut std::collections::BTreeMap<u16, usize>, address: u16) {
+    watches
+        .entry(address)
+        .or_insert_with(|| vm.add_watch(address..=address, WatchKind::Access, Box::new(|_| true)));
+}
 
-    // reset stdin
-    tcsetattr(stdin, TCSANOW, &termios).unwrap();
+// Set the breakpoints, watches and notes a session file lists. A file that isn't there yet is an empty session;
+// the end of this one writes it.
+fn restore_session(
+    vm: &mut VM,
+    watches: &mut std::collections::BTreeMap<u16, usize>,
+    path: &std::path::Path,
+    symbols: &[(u16, String)],
+) {
+    use components::debugger::{self, Command};
+
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            eprintln!("couldn't read the session {}: {}", path.display(), e);
+            return;
+        }
+    };
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        match debugger::parse_command(line, symbols) {
+            Ok(Command::Break(address)) => {
+                vm.breakpoints.insert(address);
+            }
+            Ok(Command::Watch(address)) => watch_access(vm, watches, address),
+            Ok(Command::WatchValue(watch)) if vm.watches.contains(&watch) => {}
+            Ok(Command::WatchValue(watch)) => vm.watches.push(watch),
+            Ok(Command::Note(address, note)) => vm.regions.annotate(address, &note),
+            Ok(_) => eprintln!("{}:{}: a session only sets breakpoints, watches and notes", path.display(), number + 1),
+            Err(e) => eprintln!("{}:{}: {}", path.display(), number + 1, e),
+        }
+    }
+}
+
+
+
+// The command line for a bundle, without --bundle itself
+fn bundled_args() -> Vec<String> {
+    let mut args = Vec::new();
+    let mut skip = false;
+    // the bundle replays without a subcommand
+    let mut given: Vec<String> = std::env::args().skip(1).collect();
+    match given.first().map(String::as_str) {
+        Some("run") => drop(given.remove(0)),
+        Some("debug") => given[0] = "--debug".to_string(),
+        _ => {}
+    }
+    for arg in given {
+        if std::mem::take(&mut skip) || arg.starts_with("--bundle=") {
+            continue;
+        }
+        skip = arg == "--bundle";
+        if !skip {
+            args.push(arg);
+        }
+    }
+    args
+}
+
+// Add what the run decided for itself (keystrokes read, the seed drawn) so the replay does the same
+fn save_bundle(bundle: Option<&(std::path::PathBuf, Bundle)>, vm: &VM) {
+    let Some((path, bundle)) = bundle else {
+        return;
+    };
+    let mut bundle = bundle.clone();
+    // recorded unless the keys already came from an --input file, which the bundle holds
+    if let Some(keys) = &vm.console.recording {
+        bundle.attach("--input", "keys", keys.clone());
+    }
+    if !bundle.args.iter().any(|a| a == "--seed" || a.starts_with("--seed=")) {
+        bundle.args.extend(["--seed".to_string(), vm.rng.seed().to_string()]);
+    }
+    if let Err(e) = bundle.save(path) {
+        eprintln!("{}", e);
+    }
+}
+
+// Unpack a bundle next to the other temporary files and read its command line as if it had been typed
+fn replay_cli(replay: &ReplayCli) -> Result<Cli, String> {
+    let bundle = Bundle::load(&replay.bundle)?;
+    if bundle.version != env!("CARGO_PKG_VERSION") {
+        eprintln!(
+            "warning: bundle written by lc3_sim {}, replaying with {}",
+            bundle.version,
+            env!("CARGO_PKG_VERSION")
+        );
+    }
+    let dir = std::env::temp_dir().join(format!("lc3_sim-replay-{}", std::process::id()));
+    let args = bundle.unpack(&dir)?;
+    Ok(Cli::from_iter(std::iter::once("lc3_sim".to_string()).chain(args)))
+}
+
+fn list_object(cli: &DisasmCli) -> Result<(), String> {
+    let bytes = std::fs::read(&cli.path).map_err(|e| e.to_string())?;
+    let mut image = object::parse(&bytes).map_err(|e| e.to_string())?;
+    symbols::merge(&mut image.metadata.symbols, symbols::for_object(&cli.path, None)?);
+    print!("{}", listing(image.origin, &image.words, &image.metadata.symbols));
+    Ok(())
+}
+
+fn assemble_file(cli: &AsmCli) -> Result<(), String> {
+    let source = std::fs::read_to_string(&cli.source)
+        .map_err(|e| format!("couldn't read {}: {}", cli.source.display(), e))?;
+    let mut image = assembler::assemble(&source).map_err(|errors| {
+        let name = cli.source.display();
+        errors.lines().map(|e| format!("{}: {}", name, e)).collect::<Vec<_>>().join("\n")
+    })?;
+    let output = cli.output.clone().unwrap_or_else(|| cli.source.with_extension("obj"));
+    let bytes = if cli.v2 {
+        let hash = assembler::source_hash(&source);
+        image.metadata.sources.push((hash, cli.source.display().to_string()));
+        object::write_v2(&image)
+    } else {
+        object::write_v1(&image)
+    };
+    std::fs::write(&output, bytes).map_err(|e| format!("couldn't write {}: {}", output.display(), e))
+}
+
+// Headless like --equiv. Whether every spec passed.
+fn run_specs(cli: &TestCli) -> bool {
+    let mut results = TestSuite::new("lc3_sim test");
+    for path in &cli.specs {
+        let start = Instant::now();
+        let (name, failures) = match spec::load(path) {
+            Ok(spec) => (spec.name.clone(), spec::run(&spec).unwrap_or_else(|e| vec![e])),
+            Err(e) => (path.display().to_string(), vec![e]),
+        };
+        if failures.is_empty() {
+            if !cli.quiet {
+                println!("PASS {}", name);
+            }
+            results.pass(&name, start.elapsed());
+        } else {
+            println!("FAIL {}", name);
+            for failure in &failures {
+                println!("  {}", failure);
+            }
+            results.fail(&name, &failures[0], Some(failures.join("\n")), start.elapsed());
+        }
+    }
+    if let Some(path) = &cli.results {
+        if let Err(e) = std::fs::write(path, results.render(ResultsFormat::from_path(path))) {
+            eprintln!("couldn't write results {}: {}", path.display(), e);
+        }
+    }
+    if !cli.quiet {
+        println!(
+            "{} passed, {} failed",
+            results.count(Status::Passed),
+            results.count(Status::Failed)
+        );
+    }
+    results.passed()
+}
+
+// `lc3_sim test --mutate`. The exit status is 1 if a mutant survived; a reference that fails its own specs is
+// the error.
+fn mutation_test(cli: &TestCli, source: &std::path::Path) -> Result<i32, String> {
+    let text = std::fs::read_to_string(source)
+        .map_err(|e| format!("couldn't read {}: {}", source.display(), e))?;
+    let (image, instructions) = assembler::assemble_listing(&text).map_err(|errors| {
+        let name = source.display();
+        errors.lines().map(|e| format!("{}: {}", name, e)).collect::<Vec<_>>().join("\n")
+    })?;
+    let specs = cli.specs.iter().map(|path| spec::load(path)).collect::<Result<Vec<_>, _>>()?;
+    let outcomes = mutate::run(&image, &instructions, &specs)?;
+
+    let mut results = TestSuite::new("lc3_sim test --mutate");
+    for outcome in &outcomes {
+        let name = outcome.mutant.describe();
+        match &outcome.killed_by {
+            Some(spec) => {
+                if !cli.quiet {
+                    println!("KILLED {} (by {})", name, spec);
+                }
+                results.pass(&name, outcome.elapsed);
+            }
+            None => {
+                println!("SURVIVED {}", name);
+                results.fail(&name, "no spec failed on this change", None, outcome.elapsed);
+            }
+        }
+    }
+    if let Some(path) = &cli.results {
+        if let Err(e) = std::fs::write(path, results.render(ResultsFormat::from_path(path))) {
+            eprintln!("couldn't write results {}: {}", path.display(), e);
+        }
+    }
+    let survived = results.count(Status::Failed);
+    if !cli.quiet {
+        println!("{} mutants, {} killed, {} survived", outcomes.len(), outcomes.len() - survived, survived);
+    }
+    Ok(if survived == 0 { 0 } else { 1 })
+}
+
+// Runs headless, so the terminal is left alone. The exit status is 5 when the programs differ.
+fn check_equivalence(cli: &Cli, other: &std::path::Path) -> Result<i32, String> {
+    let cases = match &cli.equiv_inputs {
+        Some(path) => equiv::load_cases(path),
+        // a fixed default keeps "equivalent on 100 cases" meaning the same 100 cases
+        None => Ok(equiv::generate_cases(100, &mut Rng::new(cli.seed.unwrap_or(0)))),
+    };
+    let contract = Contract {
+        registers: if cli.equiv_registers.is_empty() {
+            vec![0]
+        } else {
+            cli.equiv_registers.clone()
+        },
+        regions: cli.equiv_regions.clone(),
+    };
+    let cases = cases?;
+    match equiv::check(&cli.path, other, &cases, &contract)? {
+        None if !cli.quiet => eprintln!("equivalent on {} cases", cases.len()),
+        None => {}
+        Some(difference) => {
+            eprintln!("{}", difference);
+            return Ok(5);
+        }
+    }
+    Ok(0)
+}
+
+// After an internal panic: the guest state at the time, and all of memory as a loadable object for the bug report
+fn crash_report(vm: &VM) {
+    const CORE_FILE: &str = "lc3_sim-core.obj";
+
+    let pc = vm.registers.pc.wrapping_sub(1);
+    let instruction = vm.memory[pc as usize];
+    eprintln!("internal error while executing x{:04X} at {}", instruction, vm.regions.at(pc));
+    eprintln!("  {}", disassemble_at(instruction, pc));
+    let registers: Vec<String> = (0..8)
+        .map(|r| format!("R{}=x{:04X}", r, vm.registers.get(r)))
+        .collect();
+    eprintln!("  {} COND=x{:04X}", registers.join(" "), vm.registers.cond);
+    match save_image(vm, CORE_FILE, 0x0000..=0xFFFF) {
+        Ok(()) => eprintln!("memory written to {}", CORE_FILE),
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+fn dump_range(vm: &VM, range: std::ops::RangeInclusive<u16>, path: &std::path::Path) -> Result<(), String> {
+    let radix = match Format::Auto.for_path(path) {
+        Format::Hex => 16,
+        Format::Bin => 2,
+        _ => return save_image(vm, &path.to_string_lossy(), range),
+    };
+    let words = &vm.memory[*range.start() as usize..=*range.end() as usize];
+    std::fs::write(path, loader::format_text(*range.start(), words, radix))
+        .map_err(|e| format!("couldn't write {}: {}", path.display(), e))
+}
+
+fn save_image(vm: &VM, path: &str, range: std::ops::RangeInclusive<u16>) -> Result<(), String> {
+    let mut out = BufWriter::new(
+        File::create(path).map_err(|e| format!("couldn't create {}: {}", path, e))?,
+    );
+    vm.write_image(&mut out, range)
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("couldn't write {}: {}", path, e))
 }
\ No newline at end of file
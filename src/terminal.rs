@@ -0,0 +1,120 @@
+// Terminal setup for a run. Raw mode is built flag by flag from the saved settings instead of masking them, so
+// macOS/BSD and Linux terminals end up configured the same way whatever their defaults were, and the original
+// settings come back on every way out. `--terminal-mode off` leaves the terminal alone for environments where
+// changing it misbehaves.
+//
+// termios is Unix only; elsewhere (Windows) crossterm switches the console into raw mode instead. Either way stdin
+// that isn't a terminal (a pipe, a file) is left as it is and read as plain bytes.
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+#[cfg(unix)]
+use termios::*;
+
+#[cfg(unix)]
+const STDIN: i32 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalMode {
+    Raw,
+    Off,
+}
+
+impl FromStr for TerminalMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(TerminalMode::Raw),
+            "off" => Ok(TerminalMode::Off),
+            _ => Err(format!(
+                "unknown terminal mode `{}` (expected raw or off)",
+                s
+            )),
+        }
+    }
+}
+
+// The terminal as it was before the run, when we changed it
+#[cfg(unix)]
+#[derive(Clone, Copy)]
+pub struct Terminal {
+    saved: Option<Termios>,
+}
+
+#[cfg(unix)]
+impl Terminal {
+    // Switch stdin to the mode. Stdin that isn't a terminal is left as it is.
+    pub fn enter(mode: TerminalMode) -> Terminal {
+        let saved = match mode {
+            TerminalMode::Raw if std::io::stdin().is_terminal() => {
+                Termios::from_fd(STDIN).ok().filter(|saved| {
+                    let mut raw = *saved;
+                    configure_raw(&mut raw);
+                    tcsetattr(STDIN, TCSANOW, &raw).is_ok()
+                })
+            }
+            _ => None,
+        };
+        Terminal { saved }
+    }
+
+    pub fn restore(&self) {
+        if let Some(saved) = &self.saved {
+            let _ = tcsetattr(STDIN, TCSANOW, saved);
+        }
+    }
+
+    // Back to raw mode after a restore, e.g. when the debugger hands the terminal back to the guest
+    pub fn resume(&self) {
+        if let Some(saved) = &self.saved {
+            let mut raw = *saved;
+            configure_raw(&mut raw);
+            let _ = tcsetattr(STDIN, TCSANOW, &raw);
+        }
+    }
+}
+
+// Whether we put the console into raw mode. crossterm keeps the original console mode and puts it back itself.
+#[cfg(not(unix))]
+#[derive(Clone, Copy)]
+pub struct Terminal {
+    raw: bool,
+}
+
+#[cfg(not(unix))]
+impl Terminal {
+    pub fn enter(mode: TerminalMode) -> Terminal {
+        let raw = mode == TerminalMode::Raw
+            && std::io::stdin().is_terminal()
+            && crossterm::terminal::enable_raw_mode().is_ok();
+        Terminal { raw }
+    }
+
+    pub fn restore(&self) {
+        if self.raw {
+            let _ = crossterm::terminal::disable_raw_mode();
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.raw {
+            let _ = crossterm::terminal::enable_raw_mode();
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn configure_raw(t: &mut Termios) {
+    // input bytes arrive as typed: no break or parity handling, no stripping to 7 bits, no XON/XOFF flow control.
+    // CR is still turned into NL so Enter reads as x0A, which is what LC-3 programs compare against.
+    t.c_iflag &= !(IGNBRK | BRKINT | PARMRK | ISTRIP | INLCR | IGNCR | IXON | IXOFF);
+    t.c_iflag |= ICRNL;
+    // a byte at a time and no echo (the guest echoes what it wants). IEXTEN goes too: on macOS it makes ^O
+    // discard output and ^V quote the next key. Signals stay so ^C still stops the simulator.
+    t.c_lflag &= !(ICANON | ECHO | ECHONL | IEXTEN);
+    t.c_lflag |= ISIG;
+    // a read returns as soon as one byte is there, without a timer
+    t.c_cc[VMIN] = 1;
+    t.c_cc[VTIME] = 0;
+}
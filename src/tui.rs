@@ -0,0 +1,637 @@
+// Full-screen front end (--tui, built with `--features tui`): the register file, a disassembly view around the PC
+// and the console in panes on the alternate screen, like the lc3tools GUI but in the terminal. The machine runs
+// through VM::poll in short slices between redraws, so the screen and the keyboard stay live while it runs.
+//
+// While the program runs, keys go to it and Esc pauses. While paused, keys are commands: s steps, r runs,
+// a auto-steps (runs at a human pace, 1 to 1000 instructions a second, set with the left and right arrows), x
+// resets, up, down, PgUp and PgDn scroll the disassembly, `.` goes back to the PC and q quits. d swaps the
+// disassembly for the device pane, every device register decoded bit by bit; there up and down pick a register,
+// [ and ] a bit, and t flips it.
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use lc3_sim::components::console::AnsiPolicy;
+use lc3_sim::components::iodevice::MemoryIo;
+use lc3_sim::components::vm::{PollResult, ResetKind, VM};
+use lc3_sim::components::{self, debugger, inspect};
+
+// machine time per slice between redraws and key checks
+const SLICE: Duration = Duration::from_millis(15);
+const REGISTERS_WIDTH: u16 = 24;
+const HELP: &str =
+    "s step  r run  a auto-step  \u{2190}\u{2192} speed  Esc pause  x reset  \u{2191}\u{2193} PgUp PgDn scroll  . PC  d devices  q quit";
+const DEVICES_HELP: &str =
+    "s step  r run  a auto-step  \u{2190}\u{2192} speed  Esc pause  x reset  \u{2191}\u{2193} register  [ ] bit  t toggle  d memory  q quit";
+// auto-step speeds in instructions a second
+const RATES: [u64; 10] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000];
+
+// Auto-step: instructions fall due at the rate from when it started and run a few at a time between redraws,
+// so the panes show every step at low rates without the keyboard waiting on a sleep
+struct Pace {
+    start: Instant,
+    done: u64,
+}
+
+impl Pace {
+    fn new(now: Instant) -> Pace {
+        Pace { start: now, done: 0 }
+    }
+
+    // How many instructions are due by `now`, the first one straight away
+    fn due(&self, rate: u64, now: Instant) -> u64 {
+        let elapsed = now.duration_since(self.start).as_secs_f64();
+        ((elapsed * rate as f64) as u64 + 1).saturating_sub(self.done)
+    }
+}
+
+struct Screen {
+    running: bool,
+    // running at RATES[rate] rather than flat out
+    pace: Option<Pace>,
+    rate: usize,
+    // first address of the disassembly view when scrolled away from the PC
+    scroll: Option<u16>,
+    // the device pane in place of the disassembly, with the register (counting down all the devices) and bit
+    // picked in it
+    devices: bool,
+    register: usize,
+    bit: u8,
+    // keys from --input, queued again on every reset
+    input: Vec<u8>,
+    message: String,
+    // wipe the screen before the next draw, after a resize
+    clear: bool,
+}
+
+// Leaves the alternate screen however the session ends, a panic included
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub fn run(vm: &mut VM, symbols: &[(u16, String)]) -> Result<(), String> {
+    let input: Vec<u8> = vm
+        .console
+        .io
+        .queued()
+        .map(|keys| keys.iter().copied().collect())
+        .unwrap_or_default();
+    vm.console.io = Box::new(MemoryIo::new(&input));
+    // a pane can't show cursor movement or colors
+    vm.console.policy = AnsiPolicy::Strip;
+
+    terminal::enable_raw_mode().map_err(|e| format!("--tui needs a terminal: {}", e))?;
+    let guard = Guard;
+    execute!(io::stdout(), EnterAlternateScreen, Hide).map_err(|e| e.to_string())?;
+
+    let mut screen = Screen::new(input);
+    // redrawn after every slice while running, and after a key or a resize while paused
+    let mut changed = true;
+    loop {
+        if screen.running && screen.slice(vm) {
+            changed = true;
+        }
+        if std::mem::take(&mut changed) {
+            screen.draw(vm, symbols).map_err(|e| e.to_string())?;
+        }
+        let wait = if screen.running && screen.pace.is_none() && screen.message.is_empty() {
+            Duration::ZERO
+        } else {
+            SLICE
+        };
+        if !event::poll(wait).map_err(|e| e.to_string())? {
+            continue;
+        }
+        match event::read().map_err(|e| e.to_string())? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => {
+                if !screen.key(vm, key) {
+                    break;
+                }
+            }
+            Event::Resize(..) => screen.clear = true,
+            _ => continue,
+        }
+        changed = true;
+    }
+    drop(guard);
+
+    // the console pane is gone with the alternate screen; leave what the program printed behind
+    if let Some(memory) = vm.console.memory() {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&memory.output);
+        let _ = stdout.flush();
+    }
+    // quitting part way through is like quitting the debugger
+    if components::running(vm) {
+        vm.stopped_at = Some(vm.registers.pc);
+    }
+    Ok(())
+}
+
+impl Screen {
+    fn new(input: Vec<u8>) -> Screen {
+        Screen {
+            running: false,
+            pace: None,
+            rate: 3,
+            scroll: None,
+            devices: false,
+            register: 0,
+            bit: 15,
+            input,
+            message: String::new(),
+            clear: true,
+        }
+    }
+
+    // Run flat out for a slice, or the instructions auto-step has due. False when nothing ran.
+    fn slice(&mut self, vm: &mut VM) -> bool {
+        let result = match &mut self.pace {
+            None => vm.poll(SLICE),
+            Some(pace) => {
+                let due = pace.due(RATES[self.rate], Instant::now());
+                if due == 0 {
+                    return false;
+                }
+                let before = vm.instructions_executed();
+                let result = vm.run_for(due);
+                pace.done += vm.instructions_executed() - before;
+                result
+            }
+        };
+        self.message.clear();
+        match result {
+            PollResult::Yielded => {}
+            PollResult::NeedsInput => {
+                self.message = "waiting for a key".to_string();
+                // the steps missed while waiting aren't made up in a burst afterwards
+                if self.pace.is_some() {
+                    self.pace = Some(Pace::new(Instant::now()));
+                }
+            }
+            _ => {
+                self.running = false;
+                self.pace = None;
+                self.message = debugger::stop_reason(vm);
+            }
+        }
+        true
+    }
+
+    // Change the auto-step speed by `by` steps of RATES, starting the count afresh at the new rate
+    fn speed(&mut self, by: isize) {
+        self.rate = self.rate.saturating_add_signed(by).min(RATES.len() - 1);
+        if self.pace.is_some() {
+            self.pace = Some(Pace::new(Instant::now()));
+        }
+        self.message = format!("auto-step {}/s", RATES[self.rate]);
+    }
+
+    // Whether to carry on
+    fn key(&mut self, vm: &mut VM, key: KeyEvent) -> bool {
+        if self.running {
+            match key.code {
+                KeyCode::Esc => {
+                    self.running = false;
+                    self.pace = None;
+                    self.message = format!("paused at {}", debugger::location(vm));
+                }
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.running = false;
+                    self.pace = None;
+                }
+                KeyCode::Left => self.speed(-1),
+                KeyCode::Right => self.speed(1),
+                KeyCode::Char(c) if c.is_ascii() => vm.console.push_input(&[c as u8]),
+                KeyCode::Enter => vm.console.push_input(b"\n"),
+                KeyCode::Backspace => vm.console.push_input(&[0x08]),
+                KeyCode::Tab => vm.console.push_input(b"\t"),
+                _ => {}
+            }
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('s') | KeyCode::Char(' ') => self.step(vm),
+            KeyCode::Char(c @ ('r' | 'c' | 'a')) => {
+                vm.stopped_at = None;
+                self.scroll = None;
+                self.running = components::running(vm);
+                if !self.running {
+                    self.message = debugger::stop_reason(vm);
+                } else if c == 'a' {
+                    self.pace = Some(Pace::new(Instant::now()));
+                    self.message = format!("auto-step {}/s", RATES[self.rate]);
+                }
+            }
+            KeyCode::Char('x') => {
+                vm.reset(ResetKind::Cold);
+                vm.console.io = Box::new(MemoryIo::new(&self.input));
+                self.scroll = None;
+                self.message = "reset".to_string();
+            }
+            KeyCode::Char('.') => self.scroll = None,
+            KeyCode::Char('d') => self.devices = !self.devices,
+            KeyCode::Left => self.speed(-1),
+            KeyCode::Right => self.speed(1),
+            KeyCode::Up if self.devices => self.register = self.register.saturating_sub(1),
+            KeyCode::Down if self.devices => {
+                let count = inspect::groups(vm).iter().map(|(_, _, r)| r.len()).sum::<usize>();
+                self.register = (self.register + 1).min(count.saturating_sub(1));
+            }
+            KeyCode::Char('[') if self.devices => self.bit = (self.bit + 1).min(15),
+            KeyCode::Char(']') if self.devices => self.bit = self.bit.saturating_sub(1),
+            KeyCode::Char('t') if self.devices => self.toggle(vm),
+            KeyCode::Up => self.scroll_by(vm, -1),
+            KeyCode::Down => self.scroll_by(vm, 1),
+            KeyCode::PageUp => self.scroll_by(vm, -16),
+            KeyCode::PageDown => self.scroll_by(vm, 16),
+            _ => {}
+        }
+        true
+    }
+
+    fn step(&mut self, vm: &mut VM) {
+        vm.stopped_at = None;
+        self.scroll = None;
+        if !components::running(vm) {
+            self.message = debugger::stop_reason(vm);
+        } else if vm.needs_input() {
+            // a step can't wait for a key; running can
+            self.message = "the next instruction reads a key: run (r) and type it".to_string();
+        } else {
+            let _ = vm.step();
+            self.message = debugger::stop_reason(vm);
+        }
+    }
+
+    // Flip the picked bit of the picked register
+    fn toggle(&mut self, vm: &mut VM) {
+        let picked = inspect::groups(vm)
+            .into_iter()
+            .flat_map(|(_, _, registers)| registers)
+            .nth(self.register);
+        let Some((info, _)) = picked else {
+            self.message = "no device registers".to_string();
+            return;
+        };
+        self.message = match inspect::toggle(vm, info.address, self.bit) {
+            Ok(value) => format!("{} = x{:04X}", info.name, value),
+            Err(e) => e,
+        };
+    }
+
+    fn scroll_by(&mut self, vm: &VM, lines: i32) {
+        let top = self.scroll.unwrap_or(vm.registers.pc.saturating_sub(8));
+        self.scroll = Some((top as i32 + lines).clamp(0, u16::MAX as i32) as u16);
+    }
+
+    fn draw(&mut self, vm: &VM, symbols: &[(u16, String)]) -> io::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let console_rows = (rows / 3).max(5);
+        let top_rows = rows.saturating_sub(console_rows + 3);
+        let code_width = columns.saturating_sub(REGISTERS_WIDTH + 1);
+        let mut out = io::stdout();
+        if std::mem::take(&mut self.clear) {
+            queue!(out, Clear(ClearType::All))?;
+        }
+
+        let state = match (&self.pace, self.running) {
+            (Some(_), true) => format!("auto-step {}/s", RATES[self.rate]),
+            (None, true) => "running".to_string(),
+            _ => "paused".to_string(),
+        };
+        title(
+            &mut out,
+            0,
+            0,
+            columns,
+            &format!("lc3_sim  {}  {}", state, self.message),
+        )?;
+
+        // registers, left
+        title(&mut out, 0, 1, REGISTERS_WIDTH, "Registers")?;
+        let names = vm.cpu.register_names();
+        for row in 0..top_rows as usize {
+            let line = match names.get(row) {
+                Some(name) => {
+                    let value = vm.cpu.register(vm, row);
+                    format!("{:<5}x{:04X}  {:>6}", name, value, value as i16)
+                }
+                None if row == names.len() + 1 => {
+                    format!("{} instructions", vm.instructions_executed())
+                }
+                None => String::new(),
+            };
+            line_at(&mut out, 0, 2 + row as u16, REGISTERS_WIDTH, &line)?;
+        }
+
+        if self.devices {
+            title(&mut out, REGISTERS_WIDTH + 1, 1, code_width, "Devices")?;
+            let rows = device_rows(vm, self.register, self.bit);
+            // keep the picked register on screen
+            let picked = rows.iter().position(|row| row.picked).unwrap_or(0);
+            let skip = (picked + 2).saturating_sub(top_rows as usize);
+            for row in 0..top_rows as usize {
+                let y = 2 + row as u16;
+                match rows.get(skip + row) {
+                    Some(row) => row.draw(&mut out, REGISTERS_WIDTH + 1, y, code_width)?,
+                    None => line_at(&mut out, REGISTERS_WIDTH + 1, y, code_width, "")?,
+                }
+            }
+        } else {
+            self.draw_memory(&mut out, vm, symbols, top_rows, code_width)?;
+        }
+
+        // console, bottom, showing the end of the output
+        let console_top = rows.saturating_sub(console_rows + 1);
+        title(&mut out, 0, console_top, columns, "Console")?;
+        let output = vm
+            .console
+            .memory()
+            .map_or(&[][..], |memory| &memory.output[..]);
+        let text = printable(output);
+        let lines: Vec<&str> = text.split('\n').collect();
+        let shown = &lines[lines.len().saturating_sub(console_rows as usize - 1)..];
+        for row in 0..console_rows - 1 {
+            let line = shown.get(row as usize).copied().unwrap_or("");
+            line_at(&mut out, 0, console_top + 1 + row, columns, line)?;
+        }
+
+        let help = if self.devices { DEVICES_HELP } else { HELP };
+        queue!(out, SetAttribute(Attribute::Dim))?;
+        line_at(&mut out, 0, rows.saturating_sub(1), columns, help)?;
+        queue!(out, SetAttribute(Attribute::Reset))?;
+        out.flush()
+    }
+
+    // The disassembly, right, with the PC a few lines from the top unless scrolled
+    fn draw_memory(
+        &self,
+        out: &mut impl Write,
+        vm: &VM,
+        symbols: &[(u16, String)],
+        top_rows: u16,
+        code_width: u16,
+    ) -> io::Result<()> {
+        title(out, REGISTERS_WIDTH + 1, 1, code_width, "Memory")?;
+        let top = self.scroll.unwrap_or(vm.registers.pc.saturating_sub(8));
+        for (row, address) in (top..=u16::MAX).take(top_rows as usize).enumerate() {
+            let word = vm.memory.get(address as usize).copied().unwrap_or(0);
+            let label = symbols
+                .iter()
+                .find(|(a, _)| *a == address)
+                .map_or("", |(_, name)| name.as_str());
+            let marker = match (
+                address == vm.registers.pc,
+                vm.breakpoints.contains(&address),
+            ) {
+                (true, _) => '>',
+                (false, true) => '*',
+                _ => ' ',
+            };
+            // a label column only when there are labels to show
+            let label = if symbols.is_empty() {
+                String::new()
+            } else {
+                format!("{:<12}", label)
+            };
+            let line = format!(
+                "{} x{:04X}  x{:04X}  {}{}",
+                marker,
+                address,
+                word,
+                label,
+                vm.cpu.disassemble(word, address)
+            );
+            if address == vm.registers.pc {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            line_at(
+                out,
+                REGISTERS_WIDTH + 1,
+                2 + row as u16,
+                code_width,
+                &line,
+            )?;
+            queue!(out, SetAttribute(Attribute::Reset))?;
+        }
+
+        Ok(())
+    }
+}
+
+// A line of the device pane. The picked register's bit line shows its picked bit in reverse video.
+struct Row {
+    text: String,
+    picked: bool,
+    // character to highlight
+    highlight: Option<usize>,
+}
+
+impl Row {
+    fn draw(&self, out: &mut impl Write, x: u16, y: u16, width: u16) -> io::Result<()> {
+        line_at(out, x, y, width, &self.text)?;
+        if let Some(column) = self.highlight.filter(|&c| c < width as usize) {
+            let bit: String = self.text.chars().skip(column).take(1).collect();
+            queue!(
+                out,
+                MoveTo(x + column as u16, y),
+                SetAttribute(Attribute::Reverse),
+                Print(bit),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Each device's heading, then two lines a register: its value with the fields decoded, e.g.
+// `> KBSR     xFE00 = x8000  READY=1  IE=0`, and its sixteen bits high bit first. The picked register is marked
+// and says which field its picked bit belongs to.
+fn device_rows(vm: &VM, picked: usize, bit: u8) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let mut index = 0;
+    for (name, range, registers) in inspect::groups(vm) {
+        let text = match range {
+            Some(range) => format!("{} (x{:04X}-x{:04X})", name, range.start(), range.end()),
+            None => name,
+        };
+        rows.push(Row { text, picked: false, highlight: None });
+        for (info, value) in registers {
+            let is_picked = index == picked;
+            index += 1;
+            let mut text = format!(
+                "{} {:<8} x{:04X} = ",
+                if is_picked { '>' } else { ' ' },
+                info.name,
+                info.address
+            );
+            let Some(value) = value else {
+                text.push_str("?????  (reading it has side effects)");
+                rows.push(Row { text, picked: is_picked, highlight: None });
+                continue;
+            };
+            text.push_str(&format!("x{:04X}", value));
+            for field in &info.fields {
+                if field.high == field.low {
+                    text.push_str(&format!("  {}={}", field.name, field.extract(value)));
+                } else {
+                    text.push_str(&format!("  {}=x{:X}", field.name, field.extract(value)));
+                }
+            }
+            rows.push(Row { text, picked: is_picked, highlight: None });
+
+            // bits in groups of four under the value
+            let indent = 11;
+            let mut bits = " ".repeat(indent);
+            for b in (0..16).rev() {
+                bits.push(if value & (1 << b) != 0 { '1' } else { '0' });
+                if b % 4 == 0 && b > 0 {
+                    bits.push(' ');
+                }
+            }
+            let highlight = is_picked.then(|| indent + (15 - bit as usize) + (15 - bit as usize) / 4);
+            if is_picked {
+                let field = info
+                    .fields
+                    .iter()
+                    .find(|f| (f.low..=f.high).contains(&bit))
+                    .map_or("", |f| f.name);
+                bits.push_str(format!("  bit {} {}", bit, field).trim_end());
+            }
+            rows.push(Row { text: bits, picked: false, highlight });
+        }
+    }
+    rows
+}
+
+// Cut to the width or padded out to it, so whatever was there before is covered
+fn line_at(out: &mut impl Write, x: u16, y: u16, width: u16, text: &str) -> io::Result<()> {
+    let text: String = text.chars().take(width as usize).collect();
+    queue!(
+        out,
+        MoveTo(x, y),
+        Print(format!("{:<width$}", text, width = width as usize))
+    )
+}
+
+fn title(out: &mut impl Write, x: u16, y: u16, width: u16, text: &str) -> io::Result<()> {
+    queue!(out, SetAttribute(Attribute::Bold))?;
+    line_at(
+        out,
+        x,
+        y,
+        width,
+        &format!(
+            "{:\u{2500}<width$}",
+            format!("\u{2500} {} ", text),
+            width = width as usize
+        ),
+    )?;
+    queue!(out, SetAttribute(Attribute::Reset))
+}
+
+// Guest output as pane text: backspace takes a character back, other control characters are dropped
+fn printable(output: &[u8]) -> String {
+    let mut text = String::new();
+    for &byte in output {
+        match byte {
+            b'\n' => text.push('\n'),
+            0x08 | 0x7F if !text.ends_with('\n') => {
+                text.pop();
+            }
+            b'\t' => text.push_str("    "),
+            0x20..=0x7E => text.push(byte as char),
+            _ => {}
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lc3_device::Registry;
+    use lc3_sim::components::devices;
+
+    fn press(screen: &mut Screen, vm: &mut VM, code: KeyCode) {
+        assert!(screen.key(vm, KeyEvent::new(code, KeyModifiers::NONE)));
+    }
+
+    fn with_timer() -> VM {
+        let mut vm = VM::new();
+        let mut registry = Registry::new();
+        devices::register_builtin(&mut registry);
+        vm.attach(registry.create("timer").unwrap()).unwrap();
+        vm
+    }
+
+    #[test]
+    fn the_device_pane_decodes_each_register_bit_by_bit() {
+        let mut vm = with_timer();
+        vm.memory[0xFE00] = 0x8000;
+        let rows = device_rows(&vm, 0, 14);
+        let text: Vec<&str> = rows.iter().map(|r| r.text.as_str()).collect();
+        assert_eq!(text[0], "console");
+        assert_eq!(text[1], "> KBSR     xFE00 = x8000  READY=1  IE=0");
+        assert_eq!(text[2], "           1000 0000 0000 0000  bit 14 IE");
+        assert_eq!(rows[2].highlight, Some(12));
+        assert!(rows[1].picked);
+        assert_eq!(text[3], "  KBDR     xFE02 = x0000  CHAR=x0");
+        assert_eq!(text[4], "           0000 0000 0000 0000");
+        assert!(text.contains(&"timer (xFE24-xFE26)"));
+        assert!(text.contains(&"  TMCR     xFE24 = x0000  DONE=0  IE=0  ENABLE=0"));
+    }
+
+    #[test]
+    fn keys_pick_a_bit_and_toggle_it() {
+        let mut vm = with_timer();
+        let mut screen = Screen::new(Vec::new());
+        press(&mut screen, &mut vm, KeyCode::Char('d'));
+        assert!(screen.devices);
+        // KBSR's interrupt enable, one bit below READY
+        press(&mut screen, &mut vm, KeyCode::Char(']'));
+        press(&mut screen, &mut vm, KeyCode::Char('t'));
+        assert_eq!(vm.memory[0xFE00], 0x4000);
+        assert_eq!(screen.message, "KBSR = x4000");
+        // down past the seven console registers to the timer's control register, and set DONE
+        for _ in 0..7 {
+            press(&mut screen, &mut vm, KeyCode::Down);
+        }
+        press(&mut screen, &mut vm, KeyCode::Char('['));
+        press(&mut screen, &mut vm, KeyCode::Char('t'));
+        assert_eq!(screen.message, "TMCR = x8000");
+        assert_eq!(vm.devices[0].peek(0xFE24), Some(0x8000));
+        // the last register is as far down as it goes
+        for _ in 0..5 {
+            press(&mut screen, &mut vm, KeyCode::Down);
+        }
+        assert_eq!(screen.register, 8);
+        press(&mut screen, &mut vm, KeyCode::Char('d'));
+        assert!(!screen.devices);
+    }
+
+    #[test]
+    fn auto_step_runs_what_has_fallen_due() {
+        let start = Instant::now();
+        let mut pace = Pace::new(start);
+        // the first step right away, then one every fifth of a second at 5/s
+        assert_eq!(pace.due(5, start), 1);
+        pace.done = 1;
+        assert_eq!(pace.due(5, start + Duration::from_millis(100)), 0);
+        assert_eq!(pace.due(5, start + Duration::from_millis(200)), 1);
+        // a slow redraw catches up on everything due, and no more
+        assert_eq!(pace.due(1000, start + Duration::from_millis(50)), 50);
+    }
+}
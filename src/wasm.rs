@@ -0,0 +1,154 @@
+// The simulator for a web page (wasm32-unknown-unknown, built with --no-default-features): one machine per page,
+// driven from JavaScript through wasm-bindgen.
+//
+//   init(byte => term.write(String.fromCharCode(byte)));
+//   load_program(new Uint8Array(await (await fetch("prog.obj")).arrayBuffer()));
+//   document.onkeydown = e => key_event(e.key.charCodeAt(0));
+//   (function tick() {
+//     const status = run(10000);
+//     if (status == Status.Running || status == Status.NeedsInput) requestAnimationFrame(tick);
+//   })();
+//
+// There's no clock to budget a run by time here (VM::poll would panic asking for one), so `run` counts
+// instructions instead.
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+
+use js_sys::Function;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsValue;
+
+use crate::components::iodevice::IoDevice;
+use crate::components::vm::{PollResult, ResetKind, VM};
+
+thread_local! {
+    static MACHINE: RefCell<Option<VM>> = const { RefCell::new(None) };
+}
+
+// Where the machine is after a step or a run
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,    // call step or run again
+    NeedsInput, // the next instruction reads a key; call key_event, then carry on
+    Halted,
+    Stopped, // at a BKPT
+    Faulted, // see error()
+}
+
+// Keys from key_event in, printed bytes out through the page's callback
+struct PageIo {
+    keys: VecDeque<u8>,
+    output: Function,
+}
+
+impl IoDevice for PageIo {
+    fn read_char(&mut self) -> Option<u8> {
+        self.keys.pop_front()
+    }
+
+    fn write_char(&mut self, byte: u8) -> io::Result<()> {
+        // nothing useful to do if the page's callback throws
+        let _ = self.output.call1(&JsValue::NULL, &JsValue::from(byte));
+        Ok(())
+    }
+
+    fn queued(&self) -> Option<&VecDeque<u8>> {
+        Some(&self.keys)
+    }
+
+    fn queue(&mut self, keys: &[u8]) {
+        self.keys.extend(keys);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+fn with_machine<T>(f: impl FnOnce(&mut VM) -> T) -> Result<T, JsError> {
+    MACHINE.with(|machine| match machine.borrow_mut().as_mut() {
+        Some(vm) => Ok(f(vm)),
+        None => Err(JsError::new("call init first")),
+    })
+}
+
+// A fresh machine whose console output goes to `output`, called with each byte the program prints. The callback
+// runs in the middle of a step, so it mustn't call back into the simulator.
+#[wasm_bindgen]
+pub fn init(output: Function) {
+    let mut vm = VM::new();
+    // warnings would go to a stderr nobody sees
+    vm.quiet = true;
+    vm.console.io = Box::new(PageIo {
+        keys: VecDeque::new(),
+        output,
+    });
+    MACHINE.with(|machine| *machine.borrow_mut() = Some(vm));
+}
+
+// Load an object (v1 or v2) and point the PC at its entry point
+#[wasm_bindgen]
+pub fn load_program(bytes: &[u8]) -> Result<(), JsError> {
+    with_machine(|vm| vm.load_object(bytes).map(|_| ()))?.map_err(|e| JsError::new(&e))
+}
+
+// A keystroke for GETC, IN or KBSR/KBDR
+#[wasm_bindgen]
+pub fn key_event(key: u8) -> Result<(), JsError> {
+    with_machine(|vm| vm.console.push_input(&[key]))
+}
+
+#[wasm_bindgen]
+pub fn step() -> Result<Status, JsError> {
+    run(1)
+}
+
+// Up to `instructions` instructions, returning early when the program halts, stops or waits for a key
+#[wasm_bindgen]
+pub fn run(instructions: u32) -> Result<Status, JsError> {
+    with_machine(|vm| {
+        // carry on past a BKPT
+        vm.stopped_at = None;
+        match vm.run_for(instructions as u64) {
+            PollResult::Yielded => Status::Running,
+            PollResult::NeedsInput => Status::NeedsInput,
+            PollResult::Breakpoint(_) => Status::Stopped,
+            PollResult::Halted => Status::Halted,
+            PollResult::Faulted => Status::Faulted,
+        }
+    })
+}
+
+// R0-R7, PC, the condition codes and the PSR
+#[wasm_bindgen]
+pub fn registers() -> Result<Vec<u16>, JsError> {
+    with_machine(|vm| {
+        (0..vm.cpu.register_names().len())
+            .map(|i| vm.cpu.register(vm, i))
+            .collect()
+    })
+}
+
+// A word of memory, read without going through devices
+#[wasm_bindgen]
+pub fn memory(address: u16) -> Result<u16, JsError> {
+    with_machine(|vm| vm.memory.get(address as usize).copied().unwrap_or(0))
+}
+
+// What stopped the run, after Faulted
+#[wasm_bindgen]
+pub fn error() -> Result<Option<String>, JsError> {
+    with_machine(|vm| vm.fault.as_ref().map(|fault| fault.describe(&vm.regions)))
+}
+
+#[wasm_bindgen]
+pub fn reset() -> Result<(), JsError> {
+    with_machine(|vm| vm.reset(ResetKind::Cold))
+}
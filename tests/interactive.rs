@@ -0,0 +1,172 @@
+// End-to-end tests of keyboard and terminal behavior, with the simulator running under a pseudo-terminal
+#![cfg(unix)]
+
+mod pty;
+
+use pty::{object, Pty};
+
+// GETC, OUT, and loop until the character was a newline
+const ECHO_LINE: [u16; 5] = [0xF020, 0xF021, 0x1236, 0x0BFC, 0xF025];
+
+// HALT is exit status 0
+const HALTED: i32 = 0;
+
+#[test]
+fn keys_arrive_one_at_a_time_without_terminal_echo() {
+    let path = object("echo", 0x3000, &ECHO_LINE);
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet"]);
+    pty.wait_for_raw();
+    pty.send("a");
+    // in canonical mode nothing would be read before Enter
+    pty.expect("a");
+    pty.send("b\r");
+    let status = pty.wait();
+    // each key shows once, from the guest's OUT; the terminal doesn't echo it too
+    assert_eq!(pty.output, "ab\r\n");
+    assert_eq!(status.code(), Some(HALTED));
+}
+
+#[test]
+fn enter_reads_as_newline() {
+    let path = object("enter", 0x3000, &ECHO_LINE);
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet"]);
+    pty.wait_for_raw();
+    pty.send("\r");
+    // the loop only ends on x0A, so a CR reaching the guest would keep it waiting
+    assert_eq!(pty.wait().code(), Some(HALTED));
+}
+
+#[test]
+fn kbsr_polling_sees_a_key() {
+    // LDI R0, KBSR / BRzp back / LDI R0, KBDR / OUT / HALT
+    let path = object(
+        "kbsr",
+        0x3000,
+        &[0xA004, 0x07FE, 0xA003, 0xF021, 0xF025, 0xFE00, 0xFE02],
+    );
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet"]);
+    pty.wait_for_raw();
+    pty.send("k");
+    pty.expect("k");
+    assert_eq!(pty.wait().code(), Some(HALTED));
+}
+
+#[test]
+fn kbsr_reads_dont_wait_for_a_key() {
+    // R1 starts at 1 and is cleared each time KBSR isn't ready yet; the key is only echoed if that happened:
+    // AND R1, R1, #0 / ADD R1, R1, #1 / LDI R0, KBSR / BRn +2 / AND R1, R1, #0 / BR back /
+    // LDI R0, KBDR / ADD R1, R1, #0 / BRp +1 / OUT / HALT
+    let path = object(
+        "kbsr-poll",
+        0x3000,
+        &[
+            0x5260, 0x1261, 0xA008, 0x0802, 0x5260, 0x0FFC, 0xA005, 0x1260, 0x0201, 0xF021, 0xF025,
+            0xFE00, 0xFE02,
+        ],
+    );
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet"]);
+    pty.wait_for_raw();
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    pty.send("k");
+    pty.expect("k");
+    assert_eq!(pty.wait().code(), Some(HALTED));
+}
+
+#[test]
+fn halt_summary_goes_to_the_terminal() {
+    let path = object("halt", 0x3000, &[0xF025]);
+    let mut pty = Pty::spawn(&[path.to_str().unwrap()]);
+    pty.expect("HALT detected");
+    assert_eq!(pty.wait().code(), Some(HALTED));
+}
+
+#[test]
+fn terminal_settings_come_back_after_the_run() {
+    let path = object("restore", 0x3000, &ECHO_LINE);
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet"]);
+    assert_ne!(
+        pty.initial_flags & libc::ICANON,
+        0,
+        "a new terminal should start canonical"
+    );
+    pty.wait_for_raw();
+    // raw while the program runs
+    assert_eq!(pty.local_flags() & (libc::ICANON | libc::ECHO), 0);
+    pty.send("x\r");
+    pty.wait();
+    assert_eq!(pty.local_flags(), pty.initial_flags);
+}
+
+#[test]
+fn terminal_settings_come_back_after_an_unknown_trap() {
+    // GETC / TRAP xFF
+    let path = object("unknown-trap", 0x3000, &[0xF020, 0xF0FF]);
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet"]);
+    pty.wait_for_raw();
+    pty.send("x");
+    let status = pty.wait();
+    assert!(pty.output.contains("unknown trap xFF at x3001"));
+    assert_eq!(status.code(), Some(10));
+    assert_eq!(pty.local_flags(), pty.initial_flags);
+}
+
+#[test]
+fn terminal_mode_off_leaves_the_terminal_alone() {
+    let path = object("off", 0x3000, &ECHO_LINE);
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet", "--terminal-mode", "off"]);
+    pty.send("z\r");
+    pty.wait();
+    // the terminal echoed the line itself and then the guest printed it again
+    assert_eq!(
+        pty.output.matches('z').count(),
+        2,
+        "output: {:?}",
+        pty.output
+    );
+    assert_eq!(pty.local_flags(), pty.initial_flags);
+}
+
+#[test]
+fn debugger_stops_at_a_breakpoint_and_resumes() {
+    let path = object("debug", 0x3000, &ECHO_LINE);
+    let mut pty = Pty::spawn(&[path.to_str().unwrap(), "--quiet", "--debug"]);
+    pty.expect("(lc3) ");
+    // the prompt reads a whole line in the terminal's own mode
+    assert_ne!(pty.local_flags() & libc::ICANON, 0);
+    pty.send("break x3001\r");
+    pty.expect("breakpoint at x3001");
+    pty.send("continue\r");
+    pty.wait_for_raw();
+    pty.send("q");
+    pty.expect("breakpoint at x3001: OUT");
+    pty.send("regs\r");
+    pty.expect("R0=x0071");
+    pty.send("quit\r");
+    pty.wait();
+    assert_eq!(pty.local_flags(), pty.initial_flags);
+}
+
+#[test]
+fn piped_input_runs_out_instead_of_panicking() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    // GETC / OUT / GETC / OUT / HALT, with one key on stdin: the second GETC finds the pipe closed and reads x00
+    let path = object("piped", 0x3000, &[0xF020, 0xF021, 0xF020, 0xF021, 0xF025]);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_lc3_sim"))
+        .args([path.to_str().unwrap(), "--quiet"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"a").unwrap();
+    let run = child.wait_with_output().unwrap();
+    assert_eq!(run.stdout, b"a\0");
+    assert_eq!(
+        run.status.code(),
+        Some(HALTED),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+}
@@ -0,0 +1,161 @@
+// Runs the simulator under a pseudo-terminal so tests see exactly what a user at a terminal would: raw mode,
+// echo, KBSR polling and the terminal being put back afterwards. Keystrokes are written to the master side and
+// everything the simulator prints (stdout and stderr share the terminal) is read back from it with timeouts.
+#![allow(dead_code)]
+
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Pty {
+    master: File,
+    child: Child,
+    // everything read from the terminal so far
+    pub output: String,
+    // local-mode flags of the terminal before the simulator started
+    pub initial_flags: libc::tcflag_t,
+}
+
+impl Pty {
+    // Start the simulator with these arguments, its stdin, stdout and stderr all on a new terminal
+    pub fn spawn(args: &[&str]) -> Pty {
+        let (mut master, mut slave) = (0, 0);
+        let opened = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        assert_eq!(opened, 0, "openpty failed");
+        let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_lc3_sim"));
+        command
+            .args(args)
+            .stdin(Stdio::from(slave.try_clone().unwrap()))
+            .stdout(Stdio::from(slave.try_clone().unwrap()))
+            .stderr(Stdio::from(slave));
+        // make the terminal the child's controlling terminal, as a shell would
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                libc::ioctl(0, libc::TIOCSCTTY as _, 0);
+                Ok(())
+            });
+        }
+        let initial_flags = local_flags(&master);
+        let child = command.spawn().expect("couldn't start lc3_sim");
+        Pty {
+            master,
+            child,
+            output: String::new(),
+            initial_flags,
+        }
+    }
+
+    pub fn send(&mut self, keys: &str) {
+        self.master.write_all(keys.as_bytes()).unwrap();
+    }
+
+    // Read until the output contains `text`, failing the test with what was seen if it doesn't in time
+    pub fn expect(&mut self, text: &str) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !self.output.contains(text) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            assert!(
+                !left.is_zero() && self.read_some(left),
+                "timed out waiting for {:?}; output so far: {:?}",
+                text,
+                self.output
+            );
+        }
+    }
+
+    // Wait for the simulator to exit, collecting the rest of its output
+    pub fn wait(&mut self) -> ExitStatus {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                while self.read_some(Duration::from_millis(50)) {}
+                return status;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "lc3_sim didn't exit; output so far: {:?}",
+                self.output
+            );
+            self.read_some(Duration::from_millis(50));
+        }
+    }
+
+    // The terminal's current local-mode flags (ECHO, ICANON, ...)
+    pub fn local_flags(&self) -> libc::tcflag_t {
+        local_flags(&self.master)
+    }
+
+    // Wait for the simulator to switch the terminal to raw mode, so keys sent next aren't echoed or
+    // line-buffered by the terminal itself
+    pub fn wait_for_raw(&self) {
+        let deadline = Instant::now() + TIMEOUT;
+        while self.local_flags() & libc::ICANON != 0 {
+            assert!(Instant::now() < deadline, "terminal never went raw");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // Whether anything was read within `timeout`
+    fn read_some(&mut self, timeout: Duration) -> bool {
+        let mut poll = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            return false;
+        }
+        let mut buffer = [0; 1024];
+        match self.master.read(&mut buffer) {
+            // Linux reports EIO once the last process on the terminal has gone
+            Ok(0) | Err(_) => false,
+            Ok(n) => {
+                self.output.push_str(&String::from_utf8_lossy(&buffer[..n]));
+                true
+            }
+        }
+    }
+}
+
+impl Drop for Pty {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn local_flags(terminal: &File) -> libc::tcflag_t {
+    let mut settings: libc::termios = unsafe { std::mem::zeroed() };
+    let got = unsafe { libc::tcgetattr(terminal.as_raw_fd(), &mut settings) };
+    assert_eq!(got, 0, "tcgetattr failed");
+    settings.c_lflag
+}
+
+// Write a plain object (origin, then the words) to a fresh file for one test
+pub fn object(name: &str, origin: u16, words: &[u16]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("lc3_sim-{}-{}.obj", std::process::id(), name));
+    let bytes: Vec<u8> = std::iter::once(origin)
+        .chain(words.iter().copied())
+        .flat_map(u16::to_be_bytes)
+        .collect();
+    fs::write(&path, bytes).unwrap();
+    path
+}